@@ -1,5 +1,6 @@
 use crate::internal::cell::CellError;
 use crate::internal::column::Column as InternalColumn;
+use crate::internal::data_value::ValueError;
 use crate::internal::id::Identifier;
 use crate::type_::DataType;
 use crate::value::Value;
@@ -34,16 +35,26 @@ impl Column {
     }
 
     pub fn push(&mut self, value: Value) -> Result<(), Error> {
-        // Check if the value is of the correct type
+        // Check if the value is of the correct type, or can be cast to it without
+        // going out of range
         if value.conforms_to(&self.type_) {
             self.cells.push(value);
-            Ok(())
-        } else {
-            Err(Error::CellError(CellError::IncompatibleType {
-                column_name: self.name.clone(),
-                expected: self.type_.clone().as_internal_data_type(),
-                got: value.as_internal_data_type(),
-            }))
+            return Ok(());
+        }
+
+        match value.cast_to(&self.type_) {
+            Ok(value) => {
+                self.cells.push(value);
+                Ok(())
+            }
+            Err(Error::ValueError(ValueError::IncompatibleCast { .. })) => {
+                Err(Error::CellError(CellError::IncompatibleType {
+                    column_name: self.name.clone(),
+                    expected: self.type_.clone().as_internal_data_type(),
+                    got: value.as_internal_data_type(),
+                }))
+            }
+            Err(err) => Err(err),
         }
     }
 
@@ -0,0 +1,24 @@
+//! Entry points for the fuzz targets in `fuzz/`, which can't reach the
+//! crate's internals otherwise. Only built with the `fuzzing` feature.
+
+use crate::internal::database::Database;
+use crate::internal::limits::DecodeLimits;
+use crate::internal::stream::StreamReader;
+
+// small enough that a hostile input can't make a fuzz run slow or run out of memory
+const LIMITS: DecodeLimits = DecodeLimits {
+    max_sheets: 256,
+    max_columns: 256,
+    max_cells: 1 << 16,
+    max_string_length: 1 << 16,
+    max_total_bytes: 1 << 26,
+};
+
+/// Decodes `bytes` every way the crate can, ignoring the results. Anything
+/// other than returning (a panic, an abort, a hang) is a bug.
+pub fn deserialize(bytes: &[u8]) {
+    let _ = Database::deserialize_bytes_limited(bytes, &LIMITS);
+    let _ = Database::deserialize_bytes_lossy(bytes, &LIMITS);
+    let _ = Database::verify(bytes);
+    let _ = StreamReader::new(bytes).count();
+}
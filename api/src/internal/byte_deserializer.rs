@@ -1,4 +1,5 @@
 use crate::internal::errors::Error;
+use crate::internal::format::Format;
 
 #[derive(Debug, Clone)]
 pub(crate) struct ByteDeserializer<'a> {
@@ -12,7 +13,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_u8(&mut self) -> Result<u8, Error> {
-        if self.pos + 1 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 1 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -25,7 +26,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_u16(&mut self) -> Result<u16, Error> {
-        if self.pos + 2 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 2 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -38,7 +39,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_u32(&mut self) -> Result<u32, Error> {
-        if self.pos + 4 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 4 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -56,7 +57,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_u64(&mut self) -> Result<u64, Error> {
-        if self.pos + 8 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 8 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -78,7 +79,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_i8(&mut self) -> Result<i8, Error> {
-        if self.pos + 1 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 1 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -91,7 +92,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_i16(&mut self) -> Result<i16, Error> {
-        if self.pos + 2 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 2 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -104,7 +105,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_i32(&mut self) -> Result<i32, Error> {
-        if self.pos + 4 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 4 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -122,7 +123,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_i64(&mut self) -> Result<i64, Error> {
-        if self.pos + 8 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 8 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -143,8 +144,23 @@ impl ByteDeserializer<'_> {
         Ok(value)
     }
 
+    pub(crate) fn read_i128(&mut self) -> Result<i128, Error> {
+        if self.bytes.len() - self.pos < 16 {
+            return Err(Error::ByteError(ByteError::OutOfBoundsError {
+                pos: self.pos,
+                len: self.bytes.len(),
+            }));
+        }
+
+        let mut buf = [0; 16];
+        buf.copy_from_slice(&self.bytes[self.pos..self.pos + 16]);
+        let value = i128::from_be_bytes(buf);
+        self.pos += 16;
+        Ok(value)
+    }
+
     pub(crate) fn read_f32(&mut self) -> Result<f32, Error> {
-        if self.pos + 4 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 4 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -162,7 +178,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_f64(&mut self) -> Result<f64, Error> {
-        if self.pos + 8 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 8 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -184,7 +200,7 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_bool(&mut self) -> Result<bool, Error> {
-        if self.pos + 1 > self.bytes.len() {
+        if self.bytes.len() - self.pos < 1 {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -202,20 +218,40 @@ impl ByteDeserializer<'_> {
     }
 
     pub(crate) fn read_string(&mut self, length: usize) -> Result<String, Error> {
-        if self.pos + length > self.bytes.len() {
+        if self.bytes.len() - self.pos < length {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
             }));
         }
 
-        let value = String::from_utf8_lossy(&self.bytes[self.pos..self.pos + length]).to_string();
+        let bytes = &self.bytes[self.pos..self.pos + length];
+        let value = String::from_utf8(bytes.to_vec()).map_err(|_| {
+            Error::ByteError(ByteError::InvalidUtf8 {
+                pos: self.pos,
+                bytes: bytes.to_vec(),
+            })
+        })?;
         self.pos += length;
         Ok(value)
     }
 
+    /// Like read_string, but replaces invalid UTF-8 with U+FFFD instead of failing
+    pub(crate) fn read_string_lossy(&mut self, length: usize) -> Result<String, Error> {
+        let bytes = self.read_bytes(length)?;
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    // names are strict unless the format says to salvage them
+    pub(crate) fn read_name(&mut self, length: usize, format: &Format) -> Result<String, Error> {
+        match format.lossy_names {
+            true => self.read_string_lossy(length),
+            false => self.read_string(length),
+        }
+    }
+
     pub(crate) fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>, Error> {
-        if self.pos + length > self.bytes.len() {
+        if self.bytes.len() - self.pos < length {
             return Err(Error::ByteError(ByteError::OutOfBoundsError {
                 pos: self.pos,
                 len: self.bytes.len(),
@@ -230,6 +266,10 @@ impl ByteDeserializer<'_> {
     pub(crate) fn remaining_bytes(&self) -> &[u8] {
         &self.bytes[self.pos..]
     }
+
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
 }
 
 /* -- ERRORS -- */
@@ -238,6 +278,9 @@ impl ByteDeserializer<'_> {
 pub(crate) enum ByteError {
     OutOfBoundsError { pos: usize, len: usize },
     BadBoolError { value: u8 },
+    // pos is where the string starts in the bytes being decoded; decoders of
+    // enclosing blocks shift it (see Error::offset_by) until it is a file offset
+    InvalidUtf8 { pos: usize, bytes: Vec<u8> },
 }
 
 impl std::fmt::Display for ByteError {
@@ -250,6 +293,10 @@ impl std::fmt::Display for ByteError {
             ByteError::BadBoolError { value } => {
                 write!(f, "Bad bool error: value {} is not 0 or 1", value)
             }
+
+            ByteError::InvalidUtf8 { pos, bytes } => {
+                write!(f, "Invalid UTF-8 at byte {}: {:02x?}", pos, bytes)
+            }
         }
     }
 }
@@ -17,7 +17,7 @@ impl Cell {
     }
 
     pub(crate) fn adopt(&mut self, column: &Column) {
-        self.column = Some(Rc::new(column.clone()));
+        self.column = Some(Rc::new(column.without_cells()));
     }
 
     pub(crate) fn get_value(&self) -> &Value {
@@ -27,20 +27,9 @@ impl Cell {
     pub(crate) fn set_value(&mut self, value: Value) -> Result<(), Error> {
         // If the cell isn't yet adopted, return an error
         match &self.column {
-            Some(_) => {
-                if self
-                    .value
-                    .conforms_to(&self.column.as_ref().unwrap().value_type)
-                {
-                    self.value = value;
-                    Ok(())
-                } else {
-                    Err(Error::CellError(CellError::IncompatibleType {
-                        column_name: self.column.as_ref().unwrap().name.clone(),
-                        expected: self.column.as_ref().unwrap().value_type,
-                        got: value.get_type(),
-                    }))
-                }
+            Some(column) => {
+                self.value = column.coerce_value(value)?;
+                Ok(())
             }
             None => Err(Error::CellError(CellError::IsNotAdopted)),
         }
@@ -0,0 +1,421 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::errors::Error;
+use crate::internal::format::{Features, Format};
+use crate::internal::index::Index;
+use crate::internal::length_table::{self, LengthTable};
+use crate::internal::segment;
+
+// With Features::CHECKSUMS every column block, every sheet block and the whole
+// file end with a BE u32 CRC32C of the bytes before it (within that block).
+
+pub(crate) fn append(bytes: &mut Vec<u8>) {
+    let checksum = crc32c::crc32c(bytes);
+    bytes.extend_from_slice(&checksum.to_be_bytes());
+}
+
+/// Splits a block into its body and trailing checksum, returning the body if the
+/// checksum matches. `region` is only called to describe a mismatch.
+pub(crate) fn checked_body(bytes: &[u8], region: impl FnOnce() -> Region) -> Result<&[u8], Error> {
+    match split(bytes) {
+        Some((body, stored, computed)) if stored == computed => Ok(body),
+        Some((_, stored, computed)) => Err(Error::ChecksumError(ChecksumError::Mismatch {
+            region: region(),
+            stored,
+            computed,
+        })),
+        None => Err(Error::ChecksumError(ChecksumError::Mismatch {
+            region: region(),
+            stored: 0,
+            computed: crc32c::crc32c(bytes),
+        })),
+    }
+}
+
+// (body, stored checksum, computed checksum)
+fn split(bytes: &[u8]) -> Option<(&[u8], u32, u32)> {
+    if bytes.len() < 4 {
+        return None;
+    }
+
+    let (body, tail) = bytes.split_at(bytes.len() - 4);
+    let stored = u32::from_be_bytes([tail[0], tail[1], tail[2], tail[3]]);
+    Some((body, stored, crc32c::crc32c(body)))
+}
+
+/// Best effort name of a sheet or column block, both of which start with a
+/// 16 byte id and a length-prefixed name. Used to describe corrupted blocks,
+/// so it never fails on bad UTF-8.
+pub(crate) fn peek_name(bytes: &[u8]) -> Option<String> {
+    peek_name_and_end(bytes).map(|(name, _)| name)
+}
+
+// the name and the offset of the first byte after it
+fn peek_name_and_end(bytes: &[u8]) -> Option<(String, usize)> {
+    let mut deserializer = ByteDeserializer::new(bytes);
+    deserializer.read_bytes(16).ok()?;
+    let name_length = deserializer.read_u32().ok()? as usize;
+    let name = deserializer.read_bytes(name_length).ok()?;
+    Some((
+        String::from_utf8_lossy(&name).to_string(),
+        deserializer.position(),
+    ))
+}
+
+/// Checks every checksum in a serialized database, without stopping at the
+/// first failure. Only fails outright if the header itself can't be read.
+pub(crate) fn verify(bytes: &[u8]) -> Result<VerifyReport, Error> {
+    let mut deserializer = ByteDeserializer::new(bytes);
+    let format = Format::read_header(&mut deserializer)?;
+    let header_length = deserializer.position();
+
+    let mut report = VerifyReport {
+        checksummed: format.has(Features::CHECKSUMS),
+        corruptions: Vec::new(),
+    };
+    if !report.checksummed {
+        return Ok(report);
+    }
+
+    // appended segments have checksums of their own
+    let (file, segments) = match format.has(Features::SEGMENTS) {
+        true => match segment::base_length_of(bytes, header_length, &format) {
+            Ok(length) => bytes.split_at(length),
+            Err(_) => (bytes, &[][..]),
+        },
+        false => (bytes, &[][..]),
+    };
+
+    let body = match split(file) {
+        Some((body, stored, computed)) => {
+            if stored != computed {
+                report.corruptions.push(Corruption {
+                    region: Region::File,
+                    offset: 0,
+                    length: file.len(),
+                    kind: CorruptionKind::ChecksumMismatch { stored, computed },
+                });
+            }
+            body
+        }
+        None => file,
+    };
+
+    let sheets = match LengthTable::entries(&body[header_length.min(body.len())..], &format) {
+        Ok(sheets) => sheets,
+        Err(_) => {
+            report.corruptions.push(Corruption {
+                region: Region::File,
+                offset: header_length,
+                length: body.len().saturating_sub(header_length),
+                kind: CorruptionKind::Malformed,
+            });
+            return Ok(report);
+        }
+    };
+
+    for range in sheets {
+        let offset = header_length + range.start;
+        let sheet_bytes = &body[offset..header_length + range.end];
+        let sheet_name = peek_name(sheet_bytes);
+        verify_sheet(sheet_bytes, offset, sheet_name, &format, &mut report);
+    }
+
+    if format.has(Features::INDEX) {
+        verify_index(body, header_length, &format, &mut report);
+    }
+    verify_segments(segments, file.len(), &format, &mut report);
+
+    Ok(report)
+}
+
+fn verify_segments(bytes: &[u8], start: usize, format: &Format, report: &mut VerifyReport) {
+    let mut deserializer = ByteDeserializer::new(bytes);
+    while let Ok(length) = deserializer.read_u32() {
+        let offset = deserializer.position() - 4;
+        if deserializer.read_bytes(length as usize).is_err() {
+            // cut short by an interrupted append, and not part of the file
+            break;
+        }
+        let segment = &bytes[offset..deserializer.position()];
+        if segment::intact(segment).is_err()
+            && !segment::any_intact(&bytes[offset + segment.len()..])
+        {
+            // torn by an interrupted append, and not part of the file either
+            break;
+        }
+
+        let (body, kind) = match split(segment) {
+            Some((body, stored, computed)) if stored == computed => (body, None),
+            Some((body, stored, computed)) => (
+                body,
+                Some(CorruptionKind::ChecksumMismatch { stored, computed }),
+            ),
+            None => (segment, Some(CorruptionKind::Malformed)),
+        };
+        let sheets = LengthTable::entries(&body[4.min(body.len())..], format);
+        let kind = match (kind, &sheets) {
+            (None, Err(_)) => Some(CorruptionKind::Malformed),
+            (kind, _) => kind,
+        };
+        if let Some(kind) = kind {
+            report.corruptions.push(Corruption {
+                region: Region::Segment,
+                offset: start + offset,
+                length: segment.len(),
+                kind,
+            });
+        }
+
+        for range in sheets.unwrap_or_default() {
+            let sheet_offset = offset + 4 + range.start;
+            let sheet_bytes = &segment[4 + range.start..4 + range.end];
+            let sheet_name = peek_name(sheet_bytes);
+            verify_sheet(
+                sheet_bytes,
+                start + sheet_offset,
+                sheet_name,
+                format,
+                report,
+            );
+        }
+    }
+}
+
+fn verify_index(body: &[u8], header_length: usize, format: &Format, report: &mut VerifyReport) {
+    let mut deserializer = ByteDeserializer::new(&body[header_length..]);
+    let located = deserializer.read_bytes(4).ok().and_then(|raw| {
+        let sheets_length = format.read_length_table_u32([raw[0], raw[1], raw[2], raw[3]]);
+        let offset = usize::try_from(Index::offset(header_length, sheets_length)).ok()?;
+        let mut deserializer = ByteDeserializer::new(body.get(offset..)?);
+        let length = deserializer.read_u32().ok()? as usize;
+        let start = offset + 4;
+        Some((start, body.get(start..start.checked_add(length)?)?))
+    });
+
+    let (offset, length, kind) = match located {
+        Some((offset, bytes)) => match split(bytes) {
+            Some((_, stored, computed)) if stored == computed => return,
+            Some((_, stored, computed)) => (
+                offset,
+                bytes.len(),
+                CorruptionKind::ChecksumMismatch { stored, computed },
+            ),
+            None => (offset, bytes.len(), CorruptionKind::Malformed),
+        },
+        None => (
+            header_length,
+            body.len() - header_length,
+            CorruptionKind::Malformed,
+        ),
+    };
+    report.corruptions.push(Corruption {
+        region: Region::Index,
+        offset,
+        length,
+        kind,
+    });
+}
+
+fn verify_sheet(
+    bytes: &[u8],
+    offset: usize,
+    name: Option<String>,
+    format: &Format,
+    report: &mut VerifyReport,
+) {
+    let region = Region::Sheet { name: name.clone() };
+    let body = match split(bytes) {
+        Some((body, stored, computed)) => {
+            if stored != computed {
+                report.corruptions.push(Corruption {
+                    region,
+                    offset,
+                    length: bytes.len(),
+                    kind: CorruptionKind::ChecksumMismatch { stored, computed },
+                });
+            }
+            body
+        }
+        None => {
+            report.corruptions.push(Corruption {
+                region,
+                offset,
+                length: bytes.len(),
+                kind: CorruptionKind::Malformed,
+            });
+            return;
+        }
+    };
+
+    // id + name length + name, any triggers and row ids, then the columns table
+    let located = peek_name_and_end(body).and_then(|(_, name_end)| {
+        let triggers = format.has(Features::TRIGGERS);
+        let triggers_end =
+            name_end + length_table::block_length(&body[name_end..], triggers).ok()?;
+        let row_ids = format.has(Features::ROW_IDS);
+        let columns_start =
+            triggers_end + length_table::block_length(body.get(triggers_end..)?, row_ids).ok()?;
+        LengthTable::entries(body.get(columns_start..)?, format)
+            .ok()
+            .map(|columns| (columns_start, columns))
+    });
+    let (columns_start, columns) = match located {
+        Some(located) => located,
+        None => {
+            report.corruptions.push(Corruption {
+                region: Region::Sheet { name },
+                offset,
+                length: bytes.len(),
+                kind: CorruptionKind::Malformed,
+            });
+            return;
+        }
+    };
+
+    for range in columns {
+        let column_offset = columns_start + range.start;
+        let column_bytes = &body[column_offset..columns_start + range.end];
+        let region = Region::Column {
+            sheet: name.clone(),
+            name: peek_name(column_bytes),
+        };
+        let kind = match split(column_bytes) {
+            Some((_, stored, computed)) if stored == computed => continue,
+            Some((_, stored, computed)) => CorruptionKind::ChecksumMismatch { stored, computed },
+            None => CorruptionKind::Malformed,
+        };
+        report.corruptions.push(Corruption {
+            region,
+            offset: offset + column_offset,
+            length: column_bytes.len(),
+            kind,
+        });
+    }
+}
+
+/// The result of `Database::verify`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VerifyReport {
+    // false for files written without checksums, which can't be verified
+    pub(crate) checksummed: bool,
+    pub(crate) corruptions: Vec<Corruption>,
+}
+
+impl VerifyReport {
+    pub(crate) fn is_ok(&self) -> bool {
+        self.corruptions.is_empty()
+    }
+}
+
+/// A damaged part of a file. Offsets are from the start of the file.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Corruption {
+    pub(crate) region: Region,
+    pub(crate) offset: usize,
+    pub(crate) length: usize,
+    pub(crate) kind: CorruptionKind,
+}
+
+// Names are read from the damaged bytes, so they may themselves be garbled,
+// or missing if they couldn't be read at all.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Region {
+    File,
+    Index,
+    Sheet {
+        name: Option<String>,
+    },
+    Column {
+        sheet: Option<String>,
+        name: Option<String>,
+    },
+    // rows appended with Database::append
+    Segment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CorruptionKind {
+    ChecksumMismatch { stored: u32, computed: u32 },
+    // too damaged to locate the blocks inside it
+    Malformed,
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = |name: &Option<String>| name.clone().unwrap_or_else(|| "?".to_string());
+        match self {
+            Region::File => write!(f, "file"),
+            Region::Index => write!(f, "index"),
+            Region::Segment => write!(f, "appended segment"),
+            Region::Sheet { name: sheet } => write!(f, "sheet '{}'", name(sheet)),
+            Region::Column {
+                sheet,
+                name: column,
+            } => {
+                write!(f, "column '{}' of sheet '{}'", name(column), name(sheet))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Corruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            CorruptionKind::ChecksumMismatch { stored, computed } => write!(
+                f,
+                "{} at bytes {}..{}: checksum {:#010x} does not match {:#010x}",
+                self.region,
+                self.offset,
+                self.offset + self.length,
+                stored,
+                computed
+            ),
+            CorruptionKind::Malformed => write!(
+                f,
+                "{} at bytes {}..{} is malformed",
+                self.region,
+                self.offset,
+                self.offset + self.length
+            ),
+        }
+    }
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum ChecksumError {
+    Mismatch {
+        region: Region,
+        stored: u32,
+        computed: u32,
+    },
+    Corrupted {
+        corruptions: Vec<Corruption>,
+    },
+}
+
+impl std::fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksumError::Mismatch {
+                region,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "Checksum mismatch in {}: stored {:#010x}, computed {:#010x}",
+                region, stored, computed
+            ),
+            ChecksumError::Corrupted { corruptions } => {
+                write!(f, "File is corrupted")?;
+                for corruption in corruptions {
+                    write!(f, "\n  {}", corruption)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChecksumError {}
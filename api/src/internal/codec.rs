@@ -0,0 +1,236 @@
+use crate::internal::columnar::{self, malformed, Input};
+use crate::internal::data_type::Type;
+use crate::internal::data_value::{Number, Value, ValueError};
+use crate::internal::errors::Error;
+use crate::internal::limits::Budget;
+use std::collections::HashMap;
+
+// Compressed layouts for the non-Nil values of a column. Each one is written
+// after the row count and null bitmap (see columnar::encode), and is read a
+// value at a time by columnar::Decoder.
+
+// unsigned LEB128: 7 bits per byte, least significant group first, high bit
+// set on every byte but the last
+pub(crate) fn write_varint(mut value: u128, bytes: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+pub(crate) fn varint_length(mut value: u128) -> usize {
+    let mut length = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        length += 1;
+    }
+    length
+}
+
+pub(crate) fn read_varint(input: &mut impl Input) -> Result<u128, Error> {
+    let mut value = 0u128;
+    let mut shift = 0;
+    loop {
+        let byte = input.read_u8()?;
+        if shift > 126 || (shift == 126 && byte & 0x7f > 0x03) {
+            return Err(malformed("varint is too long"));
+        }
+        value |= ((byte & 0x7f) as u128) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn read_count(input: &mut impl Input, max: usize) -> Result<usize, Error> {
+    let count = read_varint(input)?;
+    if count > max as u128 {
+        return Err(malformed("count is larger than the column"));
+    }
+    Ok(count as usize)
+}
+
+// Runs compare the stored bits, so NaNs with different payloads or 0.0 and
+// -0.0 don't merge into one run and come back changed.
+fn identical(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Flt(a), Value::Flt(b)) => a.to_bits() == b.to_bits(),
+        (Value::F32(a), Value::F32(b)) => a.to_bits() == b.to_bits(),
+        _ => a.get_type() == b.get_type() && a == b,
+    }
+}
+
+// repeated: varint run_length, then the value's payload (Value::write_payload)
+pub(crate) fn write_runs(values: &[&Value], bytes: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < values.len() {
+        let mut run = 1;
+        while i + run < values.len() && identical(values[i], values[i + run]) {
+            run += 1;
+        }
+        write_varint(run as u128, bytes);
+        values[i].write_payload(bytes);
+        i += run;
+    }
+}
+
+pub(crate) fn runs_length(values: &[&Value]) -> usize {
+    let mut length = 0;
+    let mut i = 0;
+    while i < values.len() {
+        let mut run = 1;
+        while i + run < values.len() && identical(values[i], values[i + run]) {
+            run += 1;
+        }
+        length += varint_length(run as u128) + values[i].payload_length();
+        i += run;
+    }
+    length
+}
+
+// one run, of at most `left` values: how long it is and what it repeats. A
+// string is charged for every time it's repeated before any copy is made; the
+// cells themselves were charged for by the caller.
+pub(crate) fn read_run(
+    input: &mut impl Input,
+    value_type: Type,
+    left: usize,
+    budget: &mut Budget,
+) -> Result<(usize, Value), Error> {
+    let run = read_count(input, left)?;
+    if run == 0 {
+        return Err(malformed("empty run"));
+    }
+    let value = columnar::read_payload(input, value_type)?;
+    if let Value::Str(s) = &value {
+        budget.charge(s.len() as u64 * run as u64)?;
+    }
+    Ok((run, value))
+}
+
+fn zigzag(value: i128) -> u128 {
+    ((value << 1) ^ (value >> 127)) as u128
+}
+
+fn unzigzag(value: u128) -> i128 {
+    ((value >> 1) as i128) ^ -((value & 1) as i128)
+}
+
+// repeated: zigzag varint of the difference from the previous value (the
+// first value is relative to 0). Differences wrap, so even i128 extremes
+// round trip exactly.
+pub(crate) fn write_deltas(values: &[&Value], bytes: &mut Vec<u8>) {
+    let mut previous = 0i128;
+    for value in values {
+        if let Some(Number::Int(current)) = value.as_number() {
+            write_varint(zigzag(current.wrapping_sub(previous)), bytes);
+            previous = current;
+        }
+    }
+}
+
+pub(crate) fn deltas_length(values: &[&Value]) -> usize {
+    let mut previous = 0i128;
+    let mut length = 0;
+    for value in values {
+        if let Some(Number::Int(current)) = value.as_number() {
+            length += varint_length(zigzag(current.wrapping_sub(previous)));
+            previous = current;
+        }
+    }
+    length
+}
+
+// the value after `previous`, which it then replaces
+pub(crate) fn read_delta(
+    input: &mut impl Input,
+    value_type: Type,
+    previous: &mut i128,
+) -> Result<Value, Error> {
+    let current = previous.wrapping_add(unzigzag(read_varint(input)?));
+    *previous = current;
+    Value::from_i128(current, value_type)
+        .ok_or_else(|| malformed("delta decoded to a value out of range"))
+}
+
+// varint dictionary_length
+// repeated dictionary_length times: varint length, UTF-8 bytes
+// repeated for each value: varint index into the dictionary
+pub(crate) fn write_dictionary(values: &[&Value], bytes: &mut Vec<u8>) {
+    let mut dictionary: Vec<&str> = Vec::new();
+    let mut indexes: HashMap<&str, usize> = HashMap::new();
+    let mut encoded = Vec::new();
+
+    for value in values {
+        if let Value::Str(s) = value {
+            let index = *indexes.entry(s.as_str()).or_insert_with(|| {
+                dictionary.push(s.as_str());
+                dictionary.len() - 1
+            });
+            write_varint(index as u128, &mut encoded);
+        }
+    }
+
+    write_varint(dictionary.len() as u128, bytes);
+    for entry in dictionary {
+        write_varint(entry.len() as u128, bytes);
+        bytes.extend_from_slice(entry.as_bytes());
+    }
+    bytes.extend_from_slice(&encoded);
+}
+
+pub(crate) fn dictionary_length(values: &[&Value]) -> usize {
+    let mut indexes: HashMap<&str, usize> = HashMap::new();
+    let mut length = 0;
+    for value in values {
+        if let Value::Str(s) = value {
+            let next = indexes.len();
+            let index = *indexes.entry(s.as_str()).or_insert_with(|| {
+                length += varint_length(s.len() as u128) + s.len();
+                next
+            });
+            length += varint_length(index as u128);
+        }
+    }
+    varint_length(indexes.len() as u128) + length
+}
+
+// the dictionary ahead of `count` indexes
+pub(crate) fn read_dictionary(input: &mut impl Input, count: usize) -> Result<Vec<String>, Error> {
+    let dictionary_length = read_count(input, count)?;
+    let mut dictionary = Vec::new();
+    for _ in 0..dictionary_length {
+        let remaining = input.remaining();
+        let length = read_count(input, remaining)?;
+        let bytes = input.read_bytes(length)?;
+        let entry = String::from_utf8(bytes).map_err(|err| {
+            Error::ValueError(ValueError::InvalidUtf8Str {
+                bytes: err.into_bytes(),
+            })
+        })?;
+        dictionary.push(entry);
+    }
+    Ok(dictionary)
+}
+
+// one index into the dictionary, charged for the string it expands to before
+// it's copied
+pub(crate) fn read_index(
+    input: &mut impl Input,
+    dictionary: &[String],
+    budget: &mut Budget,
+) -> Result<Value, Error> {
+    let index = read_varint(input)?;
+    let entry = usize::try_from(index)
+        .ok()
+        .and_then(|index| dictionary.get(index))
+        .ok_or_else(|| malformed("dictionary index out of range"))?;
+    budget.charge(entry.len() as u64)?;
+    Ok(Value::Str(entry.clone()))
+}
@@ -1,12 +1,20 @@
 use crate::internal::byte_deserializer::ByteDeserializer;
-use crate::internal::cell::Cell;
+use crate::internal::cell::{Cell, CellError};
+use crate::internal::checksum::{self, Region};
+use crate::internal::columnar::{self, Encoding, PINNED};
+use crate::internal::computed;
 use crate::internal::data_type::Type;
-use crate::internal::data_value::Value;
+use crate::internal::data_value::{Value, ValueError};
 use crate::internal::errors::Error;
+use crate::internal::expr::Expr;
+use crate::internal::format::{Features, Format};
 use crate::internal::id::Identifier;
 use crate::internal::length_table::LengthTable;
+use crate::internal::limits::{self, Budget, Limited};
 use crate::internal::sheet::Sheet;
+use crate::internal::stream::StreamWriter;
 use crate::internal::traits::{PrettyPrintable, Serializable};
+use std::io::Write;
 use std::rc::Rc;
 
 #[derive(Debug, Clone)]
@@ -16,6 +24,10 @@ pub(crate) struct Column {
     pub(crate) value_type: Type,
     pub(crate) cells: Vec<Cell>,
     pub(crate) sheet: Option<Rc<Sheet>>,
+    // None lets the writer pick the smallest encoding
+    pub(crate) codec: Option<Encoding>,
+    // the expression its values are computed from, see computed.rs
+    pub(crate) computed: Option<Expr>,
 }
 
 impl Column {
@@ -26,6 +38,8 @@ impl Column {
             value_type,
             cells: Vec::new(),
             sheet,
+            codec: None,
+            computed: None,
         }
     }
 
@@ -41,6 +55,8 @@ impl Column {
             value_type,
             cells: Vec::new(),
             sheet,
+            codec: None,
+            computed: None,
         }
     }
 
@@ -58,7 +74,21 @@ impl Column {
     }
 
     pub(crate) fn adopt(&mut self, sheet: &Sheet) {
-        self.sheet = Some(Rc::new(sheet.clone()));
+        self.sheet = Some(Rc::new(sheet.without_columns()));
+    }
+
+    /// A copy of everything but the cells, for cells to refer back to. Copying
+    /// the cells too would make filling (or decoding) a column quadratic.
+    pub(crate) fn without_cells(&self) -> Column {
+        Column {
+            id: self.id,
+            name: self.name.clone(),
+            value_type: self.value_type,
+            cells: Vec::new(),
+            sheet: self.sheet.clone(),
+            codec: self.codec,
+            computed: self.computed.clone(),
+        }
     }
 
     pub(crate) fn get_row_count(&self) -> usize {
@@ -78,6 +108,163 @@ impl Column {
         let cell = Cell::new(value, None);
         self.adopt_cell(&cell);
     }
+
+    /// Casts a value to the column's type, e.g. an Int literal into a U8 column.
+    /// Fails if the value has an incompatible type or doesn't fit.
+    pub(crate) fn coerce_value(&self, value: Value) -> Result<Value, Error> {
+        if value.conforms_to(&self.value_type) {
+            return Ok(value);
+        }
+
+        let got = value.get_type();
+        value.cast_to(self.value_type).map_err(|err| match err {
+            Error::ValueError(ValueError::IncompatibleCast { .. }) => {
+                Error::CellError(CellError::IncompatibleType {
+                    column_name: self.name.clone(),
+                    expected: self.value_type,
+                    got,
+                })
+            }
+            err => err,
+        })
+    }
+
+    /// Pins the encoding used when the column is serialized, or with None goes
+    /// back to picking the smallest one automatically. Fails if the encoding
+    /// can't hold values of the column's type.
+    pub(crate) fn set_codec(&mut self, codec: Option<Encoding>) -> Result<(), Error> {
+        if let Some(encoding) = codec {
+            if !encoding.applies_to(self.value_type) {
+                return Err(Error::ColumnError(ColumnError::IncompatibleEncoding {
+                    column_name: self.name.clone(),
+                    encoding,
+                    value_type: self.value_type,
+                }));
+            }
+        }
+        self.codec = codec;
+        Ok(())
+    }
+
+    /// Works out the encoding and serialized length without keeping any of
+    /// the serialized bytes around
+    pub(crate) fn plan(&self, format: &Format) -> ColumnPlan {
+        // a pinned codec is still subject to the format, e.g. it can't be used
+        // when writing a file without Features::COMPRESSION
+        let candidates: Vec<Encoding> = match self.codec {
+            Some(codec) if codec.is_compressed() && !format.has(Features::COMPRESSION) => vec![],
+            Some(codec) if format.has(Features::COLUMNAR) => vec![codec],
+            _ => Encoding::candidates(format),
+        };
+        let pinned = match self.codec {
+            Some(codec) if candidates == [codec] => PINNED,
+            _ => 0,
+        };
+
+        let (encoding, body_length) =
+            match columnar::smallest(&self.cells, self.value_type, &candidates) {
+                Some(smallest) => smallest,
+                None => (
+                    Encoding::Cells,
+                    LengthTable::table_length(&self.cell_lengths(format)),
+                ),
+            };
+
+        let mut length = 16 + 4 + self.name.len() + 1 + body_length;
+        if format.has(Features::COMPUTED) {
+            length += 4 + self.expression().len();
+        }
+        if format.has(Features::COLUMNAR) {
+            length += 1;
+        }
+        if format.has(Features::CHECKSUMS) {
+            length += 4;
+        }
+
+        ColumnPlan {
+            length,
+            encoding,
+            pinned,
+        }
+    }
+
+    // the source of the computed expression, empty if the column isn't computed
+    fn expression(&self) -> String {
+        self.computed
+            .as_ref()
+            .map_or_else(String::new, |expression| expression.to_string())
+    }
+
+    fn cell_lengths(&self, format: &Format) -> Vec<usize> {
+        self.cells
+            .iter()
+            .map(|cell| cell.serialized_bytes_with(format).len())
+            .collect()
+    }
+
+    /// Writes the column as laid out by `plan`, which must come from `self.plan(format)`
+    pub(crate) fn write_to<W: Write>(
+        &self,
+        writer: &mut StreamWriter<W>,
+        plan: &ColumnPlan,
+        format: &Format,
+    ) -> Result<(), Error> {
+        let start = writer.position();
+        if format.has(Features::CHECKSUMS) {
+            writer.begin_checksum();
+        }
+
+        writer.write(&self.id.serialized_bytes())?;
+        writer.write_u32(self.name.len() as u32)?;
+        writer.write(self.name.as_bytes())?;
+        writer.write(&self.value_type.serialized_bytes())?;
+        if format.has(Features::COLUMNAR) {
+            writer.write(&[plan.encoding.as_byte() | plan.pinned])?;
+        }
+        if format.has(Features::COMPUTED) {
+            let expression = self.expression();
+            writer.write_u32(expression.len() as u32)?;
+            writer.write(expression.as_bytes())?;
+        }
+
+        match plan.encoding {
+            Encoding::Cells => {
+                // LengthTable for cells
+                let lengths = self.cell_lengths(format);
+                writer.write(&LengthTable::header_bytes(&lengths, format))?;
+                for cell in &self.cells {
+                    writer.write(&cell.serialized_bytes_with(format))?;
+                }
+            }
+            encoding => {
+                let body = columnar::encode(&self.cells, self.value_type, encoding)
+                    .expect("the plan only picks encodings that apply");
+                writer.write(&body)?;
+            }
+        }
+
+        if format.has(Features::CHECKSUMS) {
+            writer.end_checksum()?;
+        }
+        debug_assert_eq!(writer.position() - start, plan.length as u64);
+        Ok(())
+    }
+
+    /// Like insert_value, but coerces the value to the column's type first
+    pub(crate) fn push_value(&mut self, value: Value) -> Result<(), Error> {
+        let value = self.coerce_value(value)?;
+        self.insert_value(value);
+        Ok(())
+    }
+}
+
+/// What serializing a column writes, worked out before writing any of it so
+/// enclosing length tables can be written first. See Column::plan.
+#[derive(Debug, Clone)]
+pub(crate) struct ColumnPlan {
+    pub(crate) length: usize,
+    encoding: Encoding,
+    pinned: u8,
 }
 
 impl Serializable<Column> for Column {
@@ -86,32 +273,105 @@ impl Serializable<Column> for Column {
     // u32 name_length: 4 bytes, length of the name of the column
     // [u8; name_length] name: name_length bytes, name of the column
     // u8 value_type: 1 byte, type of the column
-    // length_table<Cell> cells: cells serialized
+    // with Features::COLUMNAR:
+    //   u8 encoding: 1 byte, columnar::Encoding, | columnar::PINNED if set_codec chose it
+    // with Features::COMPUTED:
+    //   u32 expression_length, u8[expression_length] expression: as Expr's
+    //   Display writes it, empty if the column isn't computed
+    // the cells, with Features::COLUMNAR:
+    //   Cells: length_table<Cell>, others: see columnar::encode
+    // otherwise:
+    //   length_table<Cell> cells: cells serialized
+    // with Features::CHECKSUMS: u32 crc32c of everything above
 
     fn serialized_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(&self.id.serialized_bytes());
-        bytes.extend_from_slice(&(self.name.len() as u32).to_be_bytes());
-        bytes.extend_from_slice(self.name.as_bytes());
-        bytes.push(self.value_type.serialized_bytes()[0]);
+        self.serialized_bytes_with(&Format::current())
+    }
 
-        // LengthTable for cells
-        let table_bytes = LengthTable::serialize(&self.cells);
+    fn deserialize_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Self::deserialize_bytes_with(bytes, &Format::current())
+    }
 
-        bytes.extend_from_slice(&table_bytes);
+    fn serialized_bytes_with(&self, format: &Format) -> Vec<u8> {
+        let plan = self.plan(format);
+        let mut writer = StreamWriter::new(Vec::with_capacity(plan.length));
+        self.write_to(&mut writer, &plan, format)
+            .expect("writing to a Vec can't fail");
+        writer.into_inner()
+    }
 
-        bytes
+    fn deserialize_bytes_with(bytes: &[u8], format: &Format) -> Result<Self, Error> {
+        Self::decode(bytes, format, &mut Budget::new(&format.limits))
     }
+}
+
+impl Column {
+    /// Decodes a column, charging what it allocates to `budget`
+    pub(crate) fn decode(
+        bytes: &[u8],
+        format: &Format,
+        budget: &mut Budget,
+    ) -> Result<Self, Error> {
+        let bytes = if format.has(Features::CHECKSUMS) {
+            checksum::checked_body(bytes, || Region::Column {
+                sheet: None,
+                name: checksum::peek_name(bytes),
+            })?
+        } else {
+            bytes
+        };
 
-    fn deserialize_bytes(bytes: &[u8]) -> Result<Self, Error> {
         let mut deserializer = ByteDeserializer::new(bytes);
         let id = Identifier::deserialize_bytes(&deserializer.read_bytes(16)?)?;
         let name_length = deserializer.read_u32()?;
-        let name = deserializer.read_string(name_length as usize)?;
+        format.limits.check_string(name_length as usize)?;
+        budget.charge(limits::column_size(name_length as usize))?;
+        let name = deserializer.read_name(name_length as usize, format)?;
         let value_type = Type::deserialize_bytes(&[deserializer.read_u8()?])?;
 
-        // Pass the remaining bytes to the LengthTable deserializer
-        let cells = LengthTable::deserialize(deserializer.remaining_bytes())?;
+        let (encoding, pinned) = match format.has(Features::COLUMNAR) {
+            true => {
+                let byte = deserializer.read_u8()?;
+                (Encoding::from_byte(byte & !PINNED)?, byte & PINNED != 0)
+            }
+            false => (Encoding::Cells, false),
+        };
+        let computed = computed::read(&mut deserializer, format)?;
+        if encoding.is_compressed() && !format.has(Features::COMPRESSION) {
+            return Err(Error::ColumnError(ColumnError::UnknownEncoding {
+                encoding: encoding.as_byte(),
+            }));
+        }
+
+        let values: Vec<Value> = match encoding {
+            // Pass the remaining bytes to the LengthTable deserializer
+            Encoding::Cells => LengthTable::deserialize_limited(
+                deserializer.remaining_bytes(),
+                format,
+                Some(Limited::Cells),
+                budget,
+                |bytes, format, budget| {
+                    // a cell's bytes are at least as long as its string
+                    budget.charge(limits::cell_size(bytes.len()))?;
+                    Cell::deserialize_bytes_with(bytes, format)
+                },
+            )?
+            .into_iter()
+            .map(|cell| cell.value)
+            .collect(),
+            _ => columnar::decode(
+                deserializer.remaining_bytes(),
+                value_type,
+                encoding,
+                &format.limits,
+                budget,
+            )?,
+        };
+        format.limits.check_strings(&values)?;
+        let cells = values
+            .into_iter()
+            .map(|value| Cell::new(value, None))
+            .collect();
 
         // Initialize the column with an empty vec of cells
         let mut column = Column {
@@ -120,6 +380,8 @@ impl Serializable<Column> for Column {
             value_type,
             cells: Vec::new(),
             sheet: None,
+            codec: pinned.then_some(encoding),
+            computed,
         };
 
         // Adopt the cells
@@ -134,7 +396,13 @@ impl PrettyPrintable for Column {
     fn pretty_print(&self, indent: usize) -> String {
         let mut result = String::new();
         let indent_str = " ".repeat(indent);
-        result.push_str(&format!("{}Column: {}\n", indent_str, self.name));
+        match &self.computed {
+            Some(expression) => result.push_str(&format!(
+                "{}Column: {} = {}\n",
+                indent_str, self.name, expression
+            )),
+            None => result.push_str(&format!("{}Column: {}\n", indent_str, self.name)),
+        }
         for cell in &self.cells {
             result.push_str(&cell.pretty_print(indent + 2));
         }
@@ -154,6 +422,17 @@ pub(crate) enum ColumnError {
         column_id: Identifier,
         column_name: String,
     },
+    UnknownEncoding {
+        encoding: u8,
+    },
+    MalformedBody {
+        reason: String,
+    },
+    IncompatibleEncoding {
+        column_name: String,
+        encoding: Encoding,
+        value_type: Type,
+    },
 }
 
 impl std::fmt::Display for ColumnError {
@@ -175,8 +454,209 @@ impl std::fmt::Display for ColumnError {
                 "Column with id {} and name {} is not adopted",
                 column_id, column_name
             ),
+            ColumnError::UnknownEncoding { encoding } => {
+                write!(f, "Unknown column encoding: {}", encoding)
+            }
+            ColumnError::MalformedBody { reason } => {
+                write!(f, "Malformed column body: {}", reason)
+            }
+            ColumnError::IncompatibleEncoding {
+                column_name,
+                encoding,
+                value_type,
+            } => write!(
+                f,
+                "Column {} can't use the {:?} encoding for {:?} values",
+                column_name, encoding, value_type
+            ),
         }
     }
 }
 
 impl std::error::Error for ColumnError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column_with(value_type: Type, values: Vec<Value>) -> Column {
+        let mut column = Column::new("c".to_string(), value_type, None);
+        for value in values {
+            column.insert_value(value);
+        }
+        column
+    }
+
+    fn values(column: &Column) -> Vec<Value> {
+        column.cells.iter().map(|cell| cell.value.clone()).collect()
+    }
+
+    #[test]
+    fn columnar_round_trip() {
+        let columns = vec![
+            column_with(
+                Type::Bool,
+                vec![
+                    Value::Bool(true),
+                    Value::Nil,
+                    Value::Bool(false),
+                    Value::Bool(true),
+                ],
+            ),
+            column_with(
+                Type::Int,
+                vec![Value::Int(-1), Value::Nil, Value::Int(i64::MAX)],
+            ),
+            column_with(Type::Flt, vec![Value::Nil, Value::Flt(2.5)]),
+            column_with(
+                Type::Str,
+                vec![
+                    Value::Str("".to_string()),
+                    Value::Nil,
+                    Value::Str("héllo".to_string()),
+                ],
+            ),
+            column_with(Type::U16, vec![Value::U16(7); 9]),
+            column_with(Type::Str, vec![]),
+        ];
+
+        for column in columns {
+            let bytes = column.serialized_bytes();
+            let decoded = Column::deserialize_bytes(&bytes).unwrap();
+            assert_eq!(values(&decoded), values(&column));
+        }
+    }
+
+    #[test]
+    fn columnar_is_smaller() {
+        let column = column_with(Type::Int, (0..100).map(Value::Int).collect());
+        let columnar = column.serialized_bytes();
+        let cells = column.serialized_bytes_with(&Format::v1());
+        // 8 bytes per value plus the bitmap, instead of 13 per cell
+        assert!(columnar.len() < cells.len() * 2 / 3);
+    }
+
+    // a few values of each type, with repeats and nils
+    fn sample_values(value_type: Type) -> Vec<Value> {
+        let samples = match value_type {
+            Type::Bool => vec![Value::Bool(true), Value::Bool(false)],
+            Type::Int => vec![Value::Int(i64::MIN), Value::Int(i64::MAX), Value::Int(0)],
+            Type::Flt => vec![Value::Flt(-0.0), Value::Flt(f64::NAN), Value::Flt(1.5)],
+            Type::Str => vec![Value::Str("".to_string()), Value::Str("ünï".to_string())],
+            Type::I8 => vec![Value::I8(i8::MIN), Value::I8(i8::MAX)],
+            Type::I16 => vec![Value::I16(i16::MIN), Value::I16(i16::MAX)],
+            Type::I32 => vec![Value::I32(i32::MIN), Value::I32(i32::MAX)],
+            Type::U8 => vec![Value::U8(0), Value::U8(u8::MAX)],
+            Type::U16 => vec![Value::U16(0), Value::U16(u16::MAX)],
+            Type::U32 => vec![Value::U32(0), Value::U32(u32::MAX)],
+            Type::U64 => vec![Value::U64(0), Value::U64(u64::MAX)],
+            Type::I128 => vec![Value::I128(i128::MIN), Value::I128(i128::MAX)],
+            Type::F32 => vec![Value::F32(f32::NAN), Value::F32(-0.0), Value::F32(3.25)],
+            Type::Unknown => vec![],
+        };
+
+        let mut values = vec![Value::Nil];
+        for sample in samples {
+            values.push(sample.clone());
+            values.push(sample.clone());
+            values.push(Value::Nil);
+            values.push(sample);
+        }
+        values
+    }
+
+    // same type and same bits, which is stricter than ==
+    fn assert_identical(decoded: &[Value], expected: &[Value]) {
+        assert_eq!(decoded.len(), expected.len());
+        for (a, b) in decoded.iter().zip(expected) {
+            assert_eq!(a.get_type(), b.get_type());
+            assert_eq!(a.serialized_bytes(), b.serialized_bytes());
+        }
+    }
+
+    #[test]
+    fn every_codec_round_trips_every_type() {
+        let types = [
+            Type::Bool,
+            Type::Int,
+            Type::Flt,
+            Type::Str,
+            Type::I8,
+            Type::I16,
+            Type::I32,
+            Type::U8,
+            Type::U16,
+            Type::U32,
+            Type::U64,
+            Type::I128,
+            Type::F32,
+        ];
+        let encodings = [
+            Encoding::Cells,
+            Encoding::Columnar,
+            Encoding::RunLength,
+            Encoding::Delta,
+            Encoding::Dictionary,
+        ];
+
+        for value_type in types {
+            let column = column_with(value_type, sample_values(value_type));
+            let decoded = Column::deserialize_bytes(&column.serialized_bytes()).unwrap();
+            assert_identical(&values(&decoded), &values(&column));
+            assert_eq!(decoded.codec, None);
+
+            for encoding in encodings {
+                let mut pinned = column.clone();
+                if pinned.set_codec(Some(encoding)).is_err() {
+                    assert!(!encoding.applies_to(value_type));
+                    continue;
+                }
+
+                // measured without encoding, to the byte
+                assert_eq!(
+                    columnar::smallest(&column.cells, value_type, &[encoding]),
+                    columnar::encode(&column.cells, value_type, encoding)
+                        .map(|body| (encoding, body.len()))
+                );
+                let bytes = pinned.serialized_bytes();
+                let decoded = Column::deserialize_bytes(&bytes).unwrap();
+                assert_identical(&values(&decoded), &values(&column));
+                assert_eq!(decoded.codec, Some(encoding));
+            }
+        }
+    }
+
+    #[test]
+    fn automatic_codec_picks_the_smallest() {
+        let encoding_of = |column: &Column| {
+            let bytes = column.serialized_bytes();
+            // id, name length, name, value type
+            Encoding::from_byte(bytes[16 + 4 + column.name.len() + 1]).unwrap()
+        };
+
+        let ids = column_with(Type::Int, (1000..2000).map(Value::Int).collect());
+        assert_eq!(encoding_of(&ids), Encoding::Delta);
+
+        let flags = column_with(Type::Bool, vec![Value::Bool(true); 500]);
+        assert_eq!(encoding_of(&flags), Encoding::RunLength);
+
+        let statuses = ["open", "closed", "pending"]
+            .iter()
+            .cycle()
+            .take(300)
+            .map(|s| Value::Str(s.to_string()))
+            .collect();
+        assert_eq!(
+            encoding_of(&column_with(Type::Str, statuses)),
+            Encoding::Dictionary
+        );
+    }
+
+    #[test]
+    fn mistyped_cells_fall_back_to_cells() {
+        let column = column_with(Type::Int, vec![Value::Int(1), Value::Str("x".to_string())]);
+        let bytes = column.serialized_bytes();
+        let decoded = Column::deserialize_bytes(&bytes).unwrap();
+        assert_eq!(values(&decoded), values(&column));
+    }
+}
@@ -0,0 +1,436 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::cell::Cell;
+use crate::internal::codec;
+use crate::internal::column::ColumnError;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::{Value, ValueError};
+use crate::internal::errors::Error;
+use crate::internal::format::{Features, Format};
+use crate::internal::limits::{self, Budget, DecodeLimits, Limited};
+
+// set on the encoding byte when the encoding was chosen explicitly with
+// Column::set_codec rather than automatically
+pub(crate) const PINNED: u8 = 0x80;
+
+/// How a column's cells are laid out. Stored as one byte after the column's
+/// value type when Features::COLUMNAR is set; without it, columns are always
+/// `Cells`. The compressed encodings need Features::COMPRESSION.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    // length_table<Cell>, every cell carrying its own type byte
+    Cells,
+    // null bitmap followed by the packed values, see encode
+    Columnar,
+    // runs of equal values, see codec::write_runs
+    RunLength,
+    // integers as varint differences from the previous value, see codec::write_deltas
+    Delta,
+    // strings as indexes into a table of distinct strings, see codec::write_dictionary
+    Dictionary,
+}
+
+impl Encoding {
+    pub(crate) fn as_byte(&self) -> u8 {
+        match self {
+            Encoding::Cells => 0,
+            Encoding::Columnar => 1,
+            Encoding::RunLength => 2,
+            Encoding::Delta => 3,
+            Encoding::Dictionary => 4,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Result<Self, Error> {
+        match byte {
+            0 => Ok(Encoding::Cells),
+            1 => Ok(Encoding::Columnar),
+            2 => Ok(Encoding::RunLength),
+            3 => Ok(Encoding::Delta),
+            4 => Ok(Encoding::Dictionary),
+            _ => Err(Error::ColumnError(ColumnError::UnknownEncoding {
+                encoding: byte,
+            })),
+        }
+    }
+
+    pub(crate) fn is_compressed(&self) -> bool {
+        matches!(
+            self,
+            Encoding::RunLength | Encoding::Delta | Encoding::Dictionary
+        )
+    }
+
+    pub(crate) fn applies_to(&self, value_type: Type) -> bool {
+        match self {
+            Encoding::Cells => true,
+            Encoding::Columnar | Encoding::RunLength => value_type != Type::Unknown,
+            Encoding::Delta => value_type.is_integer(),
+            Encoding::Dictionary => value_type == Type::Str,
+        }
+    }
+
+    /// The encodings a writer picks from automatically, smallest output wins
+    pub(crate) fn candidates(format: &Format) -> Vec<Encoding> {
+        let mut candidates = Vec::new();
+        if format.has(Features::COLUMNAR) {
+            candidates.push(Encoding::Columnar);
+        }
+        if format.has(Features::COLUMNAR) && format.has(Features::COMPRESSION) {
+            candidates.extend([Encoding::RunLength, Encoding::Delta, Encoding::Dictionary]);
+        }
+        candidates
+    }
+}
+
+// all numbers are BE
+// u32 row_count
+// [u8; ceil(row_count / 8)] null bitmap: bit i (LSB first) is set if row i is not Nil
+// then, for the non-Nil rows only, in the encoding's layout. For Columnar:
+//   Bool: one bit per value, LSB first, padded to a whole byte
+//   Str: [u32; n + 1] offsets into the data buffer, then the UTF-8 data buffer
+//   everything else: the values back to back at their fixed size
+/// Encodes a column's cells, or returns None if the encoding doesn't apply to
+/// the column's type or some cell doesn't have the column's type (those
+/// columns have to stay in the Cells encoding).
+pub(crate) fn encode(cells: &[Cell], value_type: Type, encoding: Encoding) -> Option<Vec<u8>> {
+    if !encodes(cells, value_type, encoding) {
+        return None;
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(cells.len() as u32).to_be_bytes());
+
+    let mut bitmap = vec![0u8; cells.len().div_ceil(8)];
+    for (i, cell) in cells.iter().enumerate() {
+        if !matches!(cell.value, Value::Nil) {
+            bitmap[i / 8] |= 1 << (i % 8);
+        }
+    }
+    bytes.extend_from_slice(&bitmap);
+
+    let present = present(cells);
+    match encoding {
+        Encoding::RunLength => codec::write_runs(&present, &mut bytes),
+        Encoding::Delta => codec::write_deltas(&present, &mut bytes),
+        Encoding::Dictionary => codec::write_dictionary(&present, &mut bytes),
+        _ => write_packed(&present, value_type, &mut bytes),
+    }
+
+    Some(bytes)
+}
+
+/// The candidate that encodes to the fewest bytes and how many, preferring
+/// earlier candidates on a tie. Only lengths are worked out, so nothing the
+/// size of the column is built.
+pub(crate) fn smallest(
+    cells: &[Cell],
+    value_type: Type,
+    candidates: &[Encoding],
+) -> Option<(Encoding, usize)> {
+    let present = present(cells);
+    candidates
+        .iter()
+        .filter(|encoding| encodes(cells, value_type, **encoding))
+        .map(|encoding| {
+            let length = body_length(&present, cells.len(), value_type, *encoding);
+            (*encoding, length)
+        })
+        .reduce(|best, next| match next.1 < best.1 {
+            true => next,
+            false => best,
+        })
+}
+
+fn encodes(cells: &[Cell], value_type: Type, encoding: Encoding) -> bool {
+    encoding != Encoding::Cells
+        && encoding.applies_to(value_type)
+        && cells.iter().all(|c| c.value.conforms_to(&value_type))
+}
+
+// the non-Nil values, which are all the body holds after the bitmap
+fn present(cells: &[Cell]) -> Vec<&Value> {
+    cells
+        .iter()
+        .map(|cell| &cell.value)
+        .filter(|value| !matches!(value, Value::Nil))
+        .collect()
+}
+
+// row count, bitmap and values, as encode lays them out
+fn body_length(present: &[&Value], rows: usize, value_type: Type, encoding: Encoding) -> usize {
+    let values = match encoding {
+        Encoding::RunLength => codec::runs_length(present),
+        Encoding::Delta => codec::deltas_length(present),
+        Encoding::Dictionary => codec::dictionary_length(present),
+        _ => match value_type {
+            Type::Bool => present.len().div_ceil(8),
+            Type::Str => {
+                let data: usize = present.iter().map(|value| value.payload_length() - 4).sum();
+                4 * (present.len() + 1) + data
+            }
+            _ => present.iter().map(|value| value.payload_length()).sum(),
+        },
+    };
+    4 + rows.div_ceil(8) + values
+}
+
+fn write_packed(present: &[&Value], value_type: Type, bytes: &mut Vec<u8>) {
+    match value_type {
+        Type::Bool => {
+            let mut bits = Vec::new();
+            for (i, value) in present.iter().enumerate() {
+                if i % 8 == 0 {
+                    bits.push(0);
+                }
+                if let Value::Bool(true) = value {
+                    bits[i / 8] |= 1 << (i % 8);
+                }
+            }
+            bytes.extend_from_slice(&bits);
+        }
+        Type::Str => {
+            let mut offsets = vec![0u32];
+            let mut data = Vec::new();
+            for value in present {
+                if let Value::Str(s) = value {
+                    data.extend_from_slice(s.as_bytes());
+                }
+                offsets.push(data.len() as u32);
+            }
+            for offset in offsets {
+                bytes.extend_from_slice(&offset.to_be_bytes());
+            }
+            bytes.extend_from_slice(&data);
+        }
+        _ => {
+            for value in present {
+                value.write_payload(bytes);
+            }
+        }
+    }
+}
+
+/// Decodes a column's cells. Every row is charged against the limits before
+/// any are decoded, and encodings that expand (runs, dictionaries) are
+/// charged for what they expand to before expanding.
+pub(crate) fn decode(
+    bytes: &[u8],
+    value_type: Type,
+    encoding: Encoding,
+    limits: &DecodeLimits,
+    budget: &mut Budget,
+) -> Result<Vec<Value>, Error> {
+    let mut input = ByteDeserializer::new(bytes);
+    let mut decoder = Decoder::new(&mut input, value_type, encoding, limits, budget)?;
+    let mut values = Vec::with_capacity(decoder.rows());
+    while let Some(value) = decoder.next(&mut input, budget)? {
+        values.push(value);
+    }
+    if !input.remaining_bytes().is_empty() {
+        return Err(malformed("trailing bytes after the values"));
+    }
+    Ok(values)
+}
+
+/// Where a column body is decoded from: bytes already in memory, or a stream
+/// read only as far as the values asked for so far (see stream.rs)
+pub(crate) trait Input {
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>, Error>;
+
+    /// How many bytes of the body are left to read
+    fn remaining(&self) -> usize;
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        ByteDeserializer::new(&self.read_bytes(4)?).read_u32()
+    }
+}
+
+impl Input for ByteDeserializer<'_> {
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>, Error> {
+        ByteDeserializer::read_bytes(self, length)
+    }
+
+    fn remaining(&self) -> usize {
+        self.remaining_bytes().len()
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        ByteDeserializer::read_u8(self)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        ByteDeserializer::read_u32(self)
+    }
+}
+
+/// Value::read_payload, from any input
+pub(crate) fn read_payload(input: &mut impl Input, value_type: Type) -> Result<Value, Error> {
+    let bytes = match value_type {
+        Type::Str => {
+            let mut bytes = input.read_bytes(4)?;
+            let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            bytes.extend(input.read_bytes(length as usize)?);
+            bytes
+        }
+        _ => input.read_bytes(value_type.fixed_size().unwrap_or(0))?,
+    };
+    Value::read_payload(value_type, &mut ByteDeserializer::new(&bytes))
+}
+
+/// Decodes a column body a value at a time. All it holds on to is what the
+/// layout puts ahead of the values: the null bitmap, a bit a row, and the
+/// offsets of packed strings or the entries of a dictionary.
+pub(crate) struct Decoder {
+    value_type: Type,
+    bitmap: Vec<u8>,
+    rows: usize,
+    row: usize,
+    // non-Nil values still to be read
+    present: usize,
+    values: Values,
+}
+
+// what an encoding carries from one value to the next
+enum Values {
+    Packed,
+    // the byte holding the next bool, and how many have been read
+    Bits { byte: u8, read: usize },
+    // the length of each string still to be read
+    Strings { lengths: std::vec::IntoIter<u32> },
+    // the value of the run being read, and how many more of it are left
+    Runs { value: Value, left: usize },
+    Deltas { previous: i128 },
+    Dictionary { entries: Vec<String> },
+}
+
+impl Decoder {
+    /// Reads a body up to its first value. Every row is charged against the
+    /// limits here, before any are decoded.
+    pub(crate) fn new(
+        input: &mut impl Input,
+        value_type: Type,
+        encoding: Encoding,
+        limits: &DecodeLimits,
+        budget: &mut Budget,
+    ) -> Result<Self, Error> {
+        if encoding == Encoding::Cells || !encoding.applies_to(value_type) {
+            return Err(malformed("encoding doesn't apply to the column's type"));
+        }
+
+        let rows = input.read_u32()? as usize;
+        limits.check_count(Limited::Cells, rows as u64)?;
+        budget.charge(rows as u64 * limits::cell_size(0))?;
+
+        let bitmap = input.read_bytes(rows.div_ceil(8))?;
+        let present = (0..rows).filter(|i| is_set(&bitmap, *i)).count();
+        let values = match encoding {
+            Encoding::RunLength => Values::Runs {
+                value: Value::Nil,
+                left: 0,
+            },
+            Encoding::Delta => Values::Deltas { previous: 0 },
+            Encoding::Dictionary => Values::Dictionary {
+                entries: codec::read_dictionary(input, present)?,
+            },
+            _ => match value_type {
+                Type::Bool => Values::Bits { byte: 0, read: 0 },
+                Type::Str => Values::Strings {
+                    lengths: read_offsets(input, present)?.into_iter(),
+                },
+                _ => Values::Packed,
+            },
+        };
+
+        Ok(Decoder {
+            value_type,
+            bitmap,
+            rows,
+            row: 0,
+            present,
+            values,
+        })
+    }
+
+    pub(crate) fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// The next row's value, or None after the last row
+    pub(crate) fn next(
+        &mut self,
+        input: &mut impl Input,
+        budget: &mut Budget,
+    ) -> Result<Option<Value>, Error> {
+        if self.row == self.rows {
+            return Ok(None);
+        }
+        self.row += 1;
+        if !is_set(&self.bitmap, self.row - 1) {
+            return Ok(Some(Value::Nil));
+        }
+
+        self.present -= 1;
+        let value = match &mut self.values {
+            Values::Packed => read_payload(input, self.value_type)?,
+            Values::Bits { byte, read } => {
+                if *read % 8 == 0 {
+                    *byte = input.read_u8()?;
+                }
+                *read += 1;
+                Value::Bool(*byte >> ((*read - 1) % 8) & 1 == 1)
+            }
+            Values::Strings { lengths } => {
+                let length = lengths.next().unwrap_or_default();
+                let bytes = input.read_bytes(length as usize)?;
+                String::from_utf8(bytes).map(Value::Str).map_err(|err| {
+                    Error::ValueError(ValueError::InvalidUtf8Str {
+                        bytes: err.into_bytes(),
+                    })
+                })?
+            }
+            Values::Runs { value, left } => {
+                if *left == 0 {
+                    // this value and the ones after it
+                    let run = codec::read_run(input, self.value_type, self.present + 1, budget)?;
+                    (*left, *value) = run;
+                }
+                *left -= 1;
+                value.clone()
+            }
+            Values::Deltas { previous } => codec::read_delta(input, self.value_type, previous)?,
+            Values::Dictionary { entries } => codec::read_index(input, entries, budget)?,
+        };
+        Ok(Some(value))
+    }
+}
+
+fn is_set(bitmap: &[u8], i: usize) -> bool {
+    bitmap[i / 8] >> (i % 8) & 1 == 1
+}
+
+// the offsets ahead of `count` packed strings, as the length of each string;
+// any bytes before the first offset are skipped
+fn read_offsets(input: &mut impl Input, count: usize) -> Result<Vec<u32>, Error> {
+    let mut offsets = Vec::new();
+    for _ in 0..=count {
+        offsets.push(input.read_u32()?);
+    }
+    if offsets.windows(2).any(|window| window[0] > window[1]) {
+        return Err(malformed("string offsets are out of order"));
+    }
+    input.read_bytes(offsets[0] as usize)?;
+    Ok(offsets
+        .windows(2)
+        .map(|window| window[1] - window[0])
+        .collect())
+}
+
+pub(crate) fn malformed(reason: &str) -> Error {
+    Error::ColumnError(ColumnError::MalformedBody {
+        reason: reason.to_string(),
+    })
+}
@@ -0,0 +1,354 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::column::Column;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::Value;
+use crate::internal::database::Database;
+use crate::internal::errors::Error;
+use crate::internal::expr::Expr;
+use crate::internal::format::{Features, Format};
+use crate::internal::id::Identifier;
+use crate::internal::sheet::Sheet;
+use crate::internal::wal::{Operation, WalError};
+
+// A computed column holds values worked out from the other columns of the
+// same row, and its `rowid`, by an Expr, e.g. `total = price * qty`. Its type is the type of
+// the expression, checked against the columns it reads when it is added.
+//
+// The values are stored like any other column's, and recomputed whenever a
+// row is inserted or one of its cells is updated, so reading one costs
+// nothing. They can't be set directly: a value given for a computed column
+// when inserting a row is ignored, and updating its cells fails. A computed
+// column can only read columns before it, so they are computed left to right.
+
+// all numbers are BE
+// u32 expression_length, u8[expression_length] expression: as Expr's Display
+// writes it, empty for columns that aren't computed
+// Only written with Features::COMPUTED, see Column's layout.
+
+/// Reads the expression of a column, leaving the deserializer after it
+pub(crate) fn read(
+    deserializer: &mut ByteDeserializer,
+    format: &Format,
+) -> Result<Option<Expr>, Error> {
+    if !format.has(Features::COMPUTED) {
+        return Ok(None);
+    }
+    let length = deserializer.read_u32()? as usize;
+    format.limits.check_string(length)?;
+    match deserializer.read_string(length)?.as_str() {
+        "" => Ok(None),
+        source => Expr::parse(source).map(Some),
+    }
+}
+
+impl Sheet {
+    /// Works out the computed columns of `row` again from its other cells and
+    /// its id. Leaves the row untouched if any of them fails.
+    pub(crate) fn recompute(&mut self, row: usize) -> Result<(), Error> {
+        let mut computed: Vec<(usize, Value)> = Vec::new();
+        for (index, column) in self.columns.iter().enumerate() {
+            let Some(expression) = &column.computed else {
+                continue;
+            };
+            let value = expression.evaluate(&|name| {
+                if self.is_row_id(name) {
+                    return Some(self.row_id(row).map_or(Value::Nil, Value::U64));
+                }
+                let index = self.get_column_index(name)?;
+                match computed.iter().find(|(computed, _)| *computed == index) {
+                    Some((_, value)) => Some(value.clone()),
+                    None => self.columns[index]
+                        .get_cell(row)
+                        .map(|cell| cell.get_value().clone()),
+                }
+            })?;
+            computed.push((index, column.coerce_value(value)?));
+        }
+        for (index, value) in computed {
+            if let Some(cell) = self.columns[index].get_cell_mut(row) {
+                cell.value = value;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if a computed column reads the column named `name`, so it can't
+    /// be removed
+    pub(crate) fn check_removable(&self, name: &str) -> Result<(), Error> {
+        let reader = self.columns.iter().find(|column| {
+            column
+                .computed
+                .as_ref()
+                .is_some_and(|expression| expression.columns().contains(&name))
+        });
+        match reader {
+            Some(reader) => Err(Error::ComputedError(ComputedError::InUse {
+                column_name: name.to_string(),
+                computed_name: reader.name.clone(),
+            })),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Column {
+    /// Fails if the column can't be set directly because it's computed
+    pub(crate) fn check_settable(&self) -> Result<(), Error> {
+        match self.computed {
+            Some(_) => Err(Error::ComputedError(ComputedError::IsComputed {
+                column_name: self.name.clone(),
+            })),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Adds a computed column to the end of a sheet and computes it for every
+/// existing row, for Operation::AddComputedColumn
+pub(crate) fn add(
+    database: &mut Database,
+    sheet: &Identifier,
+    id: &Identifier,
+    name: &str,
+    expression: &Expr,
+) -> Result<(), Error> {
+    let sheet = database
+        .get_sheet_mut_by_id(sheet)
+        .ok_or(Error::WalError(WalError::SheetNotFound { id: *sheet }))?;
+    let value_type = expression.check_in(sheet)?;
+    if value_type == Type::Unknown {
+        return Err(Error::ComputedError(ComputedError::AlwaysNil {
+            column_name: name.to_string(),
+        }));
+    }
+
+    let rows = sheet.columns.first().map_or(0, Column::get_row_count);
+    let mut column = Column::new_with_set_id(*id, name.to_string(), value_type, None);
+    column.computed = Some(expression.clone());
+    for _ in 0..rows {
+        column.insert_value(Value::Nil);
+    }
+    sheet.adopt_column(&column);
+    for row in 0..rows {
+        if let Err(err) = sheet.recompute(row) {
+            sheet.columns.pop();
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+impl Database {
+    /// Adds a column computed from `source`, e.g. `price * qty`, to the end of
+    /// a sheet and returns its id. Shorthand for applying
+    /// Operation::AddComputedColumn in a transaction.
+    pub(crate) fn add_computed_column(
+        &mut self,
+        sheet: &Identifier,
+        name: &str,
+        source: &str,
+    ) -> Result<Identifier, Error> {
+        let (id, expression) = (Identifier::new(), Expr::parse(source)?);
+        self.transaction(|tx| {
+            tx.apply(Operation::AddComputedColumn {
+                sheet: *sheet,
+                id,
+                name: name.to_string(),
+                expression,
+            })
+        })?;
+        Ok(id)
+    }
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum ComputedError {
+    IsComputed {
+        column_name: String,
+    },
+    InUse {
+        column_name: String,
+        computed_name: String,
+    },
+    AlwaysNil {
+        column_name: String,
+    },
+}
+
+impl std::fmt::Display for ComputedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComputedError::IsComputed { column_name } => {
+                write!(f, "Column {} is computed and can't be set", column_name)
+            }
+            ComputedError::InUse {
+                column_name,
+                computed_name,
+            } => write!(
+                f,
+                "Column {} is used by computed column {}",
+                column_name, computed_name
+            ),
+            ComputedError::AlwaysNil { column_name } => {
+                write!(f, "Computed column {} would always be nil", column_name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ComputedError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::expr::ExprError;
+    use crate::internal::history::History;
+    use crate::internal::stream::StreamReader;
+    use crate::internal::traits::{PrettyPrintable, Serializable};
+
+    // orders with a price and a quantity
+    fn orders() -> (Database, Identifier, Identifier, Identifier) {
+        let mut database = Database::new_empty();
+        let (sheet, columns) = database
+            .transaction(|tx| {
+                let (sheet, columns) = tx.add_sheet(
+                    "orders",
+                    &[("item", Type::Str), ("price", Type::U32), ("qty", Type::U8)],
+                )?;
+                tx.apply(Operation::InsertRow {
+                    sheet,
+                    values: vec![Value::Str("tea".to_string()), Value::Int(3), Value::Int(2)],
+                })?;
+                Ok((sheet, columns))
+            })
+            .unwrap();
+        (database, sheet, columns[1], columns[2])
+    }
+
+    fn totals(database: &Database) -> Vec<Value> {
+        let column = database.columns[0].get_column_by_name("total").unwrap();
+        column.cells.iter().map(|cell| cell.value.clone()).collect()
+    }
+
+    #[test]
+    fn computed_columns_follow_their_sources_and_are_saved() {
+        let (mut database, sheet, _, qty) = orders();
+        database
+            .add_computed_column(&sheet, "total", "price * qty")
+            .unwrap();
+        assert_eq!(totals(&database), vec![Value::Int(6)]);
+        // U32 and U8 widen to U32
+        assert_eq!(database.columns[0].columns[3].value_type, Type::U32);
+
+        database
+            .transaction(|tx| {
+                tx.apply(Operation::InsertRow {
+                    sheet,
+                    // the value given for the computed column is ignored
+                    values: vec![
+                        Value::Str("cake".to_string()),
+                        Value::Int(5),
+                        Value::Nil,
+                        Value::Int(1000),
+                    ],
+                })?;
+                tx.apply(Operation::UpdateCell {
+                    sheet,
+                    column: qty,
+                    row: 0,
+                    value: Value::Int(4),
+                })
+            })
+            .unwrap();
+        assert_eq!(totals(&database), vec![Value::Int(12), Value::Nil]);
+
+        let bytes = database.serialized_bytes();
+        assert!(Database::verify(&bytes).unwrap().corruptions.is_empty());
+        let loaded = Database::deserialize_bytes(&bytes).unwrap();
+        assert_eq!(loaded.pretty_print(0), database.pretty_print(0));
+        let streamed = StreamReader::new(&bytes[..]).next_sheet().unwrap().unwrap();
+        assert_eq!(
+            streamed.columns[3].computed,
+            Some(Expr::parse("price * qty").unwrap())
+        );
+
+        let mut history = History::new(loaded);
+        let before = history.database().pretty_print(0);
+        history
+            .apply(Operation::UpdateCell {
+                sheet,
+                column: qty,
+                row: 1,
+                value: Value::Int(2),
+            })
+            .unwrap();
+        assert_eq!(totals(history.database())[1], Value::Int(10));
+        history.undo();
+        assert_eq!(history.database().pretty_print(0), before);
+    }
+
+    #[test]
+    fn computed_columns_can_read_the_row_id() {
+        let (mut database, sheet, _, _) = orders();
+        database
+            .add_computed_column(&sheet, "total", "rowid + 1")
+            .unwrap();
+        let tea = vec![
+            Value::Str("tea".to_string()),
+            Value::Int(3),
+            Value::Int(2),
+            Value::Nil,
+        ];
+        database.insert_row(&sheet, tea.clone()).unwrap();
+        database.delete_row(&sheet, 0).unwrap();
+        database.insert_row(&sheet, tea).unwrap();
+        assert_eq!(totals(&database), vec![Value::U64(2), Value::U64(3)]);
+    }
+
+    #[test]
+    fn computed_columns_are_checked_and_protected() {
+        let (mut database, sheet, price, _) = orders();
+        assert!(matches!(
+            database.add_computed_column(&sheet, "total", "price * item"),
+            Err(Error::ExprError(ExprError::TypeMismatch { .. }))
+        ));
+        assert!(matches!(
+            database.add_computed_column(&sheet, "total", "prise * qty"),
+            Err(Error::ExprError(ExprError::UnknownColumn { span, .. })) if span == (0..5)
+        ));
+        assert!(matches!(
+            database.add_computed_column(&sheet, "total", "nil + nil"),
+            Err(Error::ComputedError(ComputedError::AlwaysNil { .. }))
+        ));
+        // fails for the existing row, so nothing is added
+        assert!(matches!(
+            database.add_computed_column(&sheet, "total", "price / (qty - 2)"),
+            Err(Error::ValueError(_))
+        ));
+        assert_eq!(database.columns[0].columns.len(), 3);
+
+        let total = database
+            .add_computed_column(&sheet, "total", "qty - price * 2")
+            .unwrap();
+        assert_eq!(totals(&database), vec![Value::Int(-4)]);
+
+        assert!(matches!(
+            database.transaction(|tx| tx.apply(Operation::UpdateCell {
+                sheet,
+                column: total,
+                row: 0,
+                value: Value::Int(1),
+            })),
+            Err(Error::ComputedError(ComputedError::IsComputed { .. }))
+        ));
+        assert!(matches!(
+            database.transaction(|tx| tx.apply(Operation::RemoveColumn {
+                sheet,
+                column: price,
+            })),
+            Err(Error::ComputedError(ComputedError::InUse { .. }))
+        ));
+    }
+}
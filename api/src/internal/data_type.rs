@@ -1,13 +1,117 @@
 use crate::internal::errors::Error;
 use crate::internal::traits::Serializable;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+// Int and Flt are the 64-bit types; the sized variants were added later, so
+// they come after Unknown to keep the original type bytes stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum Type {
     Bool,
     Int,
     Flt,
     Str,
     Unknown,
+    I8,
+    I16,
+    I32,
+    U8,
+    U16,
+    U32,
+    U64,
+    I128,
+    F32,
+}
+
+impl Type {
+    pub(crate) fn is_integer(&self) -> bool {
+        self.integer_width().is_some()
+    }
+
+    pub(crate) fn is_float(&self) -> bool {
+        matches!(self, Type::F32 | Type::Flt)
+    }
+
+    pub(crate) fn is_numeric(&self) -> bool {
+        self.is_integer() || self.is_float()
+    }
+
+    pub(crate) fn is_signed(&self) -> bool {
+        matches!(
+            self,
+            Type::I8 | Type::I16 | Type::I32 | Type::Int | Type::I128 | Type::F32 | Type::Flt
+        )
+    }
+
+    // width in bits of an integer type
+    pub(crate) fn integer_width(&self) -> Option<u32> {
+        match self {
+            Type::I8 | Type::U8 => Some(8),
+            Type::I16 | Type::U16 => Some(16),
+            Type::I32 | Type::U32 => Some(32),
+            Type::Int | Type::U64 => Some(64),
+            Type::I128 => Some(128),
+            _ => None,
+        }
+    }
+
+    /// Size in bytes of a value of this type, if every value has the same size.
+    pub(crate) fn fixed_size(&self) -> Option<usize> {
+        match self {
+            Type::Bool => Some(1),
+            Type::F32 => Some(4),
+            Type::Flt => Some(8),
+            Type::Str | Type::Unknown => None,
+            _ => self.integer_width().map(|bits| bits as usize / 8),
+        }
+    }
+
+    fn signed_of_width(bits: u32) -> Option<Type> {
+        match bits {
+            8 => Some(Type::I8),
+            16 => Some(Type::I16),
+            32 => Some(Type::I32),
+            64 => Some(Type::Int),
+            128 => Some(Type::I128),
+            _ => None,
+        }
+    }
+
+    /// The type both operands of an arithmetic operation are widened to.
+    ///
+    /// Integers widen to the smallest type that holds every value of both sides,
+    /// so mixing signed and unsigned picks the next larger signed type. Mixing an
+    /// integer with a float gives F32 for integers of up to 16 bits and Flt
+    /// otherwise. Non-numeric types only "widen" to themselves.
+    pub(crate) fn widen(a: Type, b: Type) -> Option<Type> {
+        if a == b {
+            return Some(a);
+        }
+
+        match (a.integer_width(), b.integer_width()) {
+            (Some(wa), Some(wb)) => {
+                if a.is_signed() == b.is_signed() {
+                    return Some(if wa >= wb { a } else { b });
+                }
+                let (signed, unsigned) = if a.is_signed() { (wa, wb) } else { (wb, wa) };
+                if signed > unsigned {
+                    Type::signed_of_width(signed)
+                } else {
+                    Type::signed_of_width(unsigned * 2)
+                }
+            }
+            (Some(width), None) if b.is_float() => Some(Type::widen_float(b, width)),
+            (None, Some(width)) if a.is_float() => Some(Type::widen_float(a, width)),
+            (None, None) if a.is_float() && b.is_float() => Some(Type::Flt),
+            _ => None,
+        }
+    }
+
+    fn widen_float(float: Type, integer_width: u32) -> Type {
+        if float == Type::F32 && integer_width <= 16 {
+            Type::F32
+        } else {
+            Type::Flt
+        }
+    }
 }
 
 impl Serializable<Type> for Type {
@@ -18,6 +122,15 @@ impl Serializable<Type> for Type {
             Type::Flt => 2,
             Type::Str => 3,
             Type::Unknown => 4,
+            Type::I8 => 5,
+            Type::I16 => 6,
+            Type::I32 => 7,
+            Type::U8 => 8,
+            Type::U16 => 9,
+            Type::U32 => 10,
+            Type::U64 => 11,
+            Type::I128 => 12,
+            Type::F32 => 13,
         }]
     }
 
@@ -37,6 +150,15 @@ impl Serializable<Type> for Type {
             2 => Ok(Type::Flt),
             3 => Ok(Type::Str),
             4 => Ok(Type::Unknown),
+            5 => Ok(Type::I8),
+            6 => Ok(Type::I16),
+            7 => Ok(Type::I32),
+            8 => Ok(Type::U8),
+            9 => Ok(Type::U16),
+            10 => Ok(Type::U32),
+            11 => Ok(Type::U64),
+            12 => Ok(Type::I128),
+            13 => Ok(Type::F32),
             _ => Err(Error::TypeError(TypeError::InvalidType { got: bytes[0] })),
         }
     }
@@ -1,24 +1,46 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
 use crate::internal::data_type::Type;
 use crate::internal::errors::Error;
 use crate::internal::traits::Serializable;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Clone, PartialEq)]
+// Values have a total order so they can be sorted, deduplicated and used as keys:
+// Nil < Bool < numbers < Str. Numbers compare by numeric value regardless of
+// their variant, NaN sorts above every other number and equals itself, and
+// strings compare bytewise.
+#[derive(Debug, Clone)]
 pub(crate) enum Value {
     Bool(bool),
     Int(i64),
     Flt(f64),
     Str(String),
     Nil,
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I128(i128),
+    F32(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
 }
 
 impl Value {
     pub(crate) fn conforms_to(&self, other: &Type) -> bool {
         match self {
-            Value::Bool(_) => *other == Type::Bool,
-            Value::Int(_) => *other == Type::Int,
-            Value::Flt(_) => *other == Type::Flt,
-            Value::Str(_) => *other == Type::Str,
             Value::Nil => true,
+            _ => self.get_type() == *other,
         }
     }
 
@@ -29,37 +51,348 @@ impl Value {
             Value::Flt(_) => Type::Flt,
             Value::Str(_) => Type::Str,
             Value::Nil => Type::Unknown,
+            Value::I8(_) => Type::I8,
+            Value::I16(_) => Type::I16,
+            Value::I32(_) => Type::I32,
+            Value::U8(_) => Type::U8,
+            Value::U16(_) => Type::U16,
+            Value::U32(_) => Type::U32,
+            Value::U64(_) => Type::U64,
+            Value::I128(_) => Type::I128,
+            Value::F32(_) => Type::F32,
         }
     }
-}
 
-impl Serializable<Value> for Value {
-    fn serialized_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+    pub(crate) fn as_number(&self) -> Option<Number> {
+        match self {
+            Value::Int(i) => Some(Number::Int(*i as i128)),
+            Value::I8(i) => Some(Number::Int(*i as i128)),
+            Value::I16(i) => Some(Number::Int(*i as i128)),
+            Value::I32(i) => Some(Number::Int(*i as i128)),
+            Value::U8(i) => Some(Number::Int(*i as i128)),
+            Value::U16(i) => Some(Number::Int(*i as i128)),
+            Value::U32(i) => Some(Number::Int(*i as i128)),
+            Value::U64(i) => Some(Number::Int(*i as i128)),
+            Value::I128(i) => Some(Number::Int(*i)),
+            Value::Flt(f) => Some(Number::Flt(*f)),
+            Value::F32(f) => Some(Number::Flt(*f as f64)),
+            _ => None,
+        }
+    }
 
+    // position of the variant's family in the total order
+    pub(crate) fn order_rank(&self) -> u8 {
         match self {
-            Value::Bool(b) => {
-                bytes.push(Type::Bool as u8);
-                bytes.push(*b as u8);
+            Value::Nil => 0,
+            Value::Bool(_) => 1,
+            Value::Str(_) => 3,
+            _ => 2,
+        }
+    }
+
+    /// Builds an integer value of the given type, or None if it doesn't fit.
+    pub(crate) fn from_i128(value: i128, type_: Type) -> Option<Value> {
+        Some(match type_ {
+            Type::I8 => Value::I8(i8::try_from(value).ok()?),
+            Type::I16 => Value::I16(i16::try_from(value).ok()?),
+            Type::I32 => Value::I32(i32::try_from(value).ok()?),
+            Type::Int => Value::Int(i64::try_from(value).ok()?),
+            Type::I128 => Value::I128(value),
+            Type::U8 => Value::U8(u8::try_from(value).ok()?),
+            Type::U16 => Value::U16(u16::try_from(value).ok()?),
+            Type::U32 => Value::U32(u32::try_from(value).ok()?),
+            Type::U64 => Value::U64(u64::try_from(value).ok()?),
+            _ => return None,
+        })
+    }
+
+    /// Converts the value to `target`, failing instead of truncating or wrapping.
+    ///
+    /// Integers convert between widths when the value is in range, integers
+    /// convert to floats, and floats convert to integers only when they are
+    /// integral and in range. Nil casts to every type.
+    pub(crate) fn cast_to(&self, target: Type) -> Result<Value, Error> {
+        let source = self.get_type();
+        if source == target || source == Type::Unknown {
+            return Ok(self.clone());
+        }
+
+        let out_of_range = || {
+            Error::ValueError(ValueError::OutOfRange {
+                value: self.clone(),
+                target,
+            })
+        };
+
+        match self.as_number() {
+            Some(Number::Int(i)) if target.is_integer() => {
+                Value::from_i128(i, target).ok_or_else(out_of_range)
+            }
+            Some(Number::Int(i)) if target == Type::F32 => Ok(Value::F32(i as f32)),
+            Some(Number::Int(i)) if target == Type::Flt => Ok(Value::Flt(i as f64)),
+            Some(Number::Flt(f)) if target.is_integer() => {
+                if f.fract() != 0.0 || f.abs() >= I128_BOUND {
+                    return Err(out_of_range());
+                }
+                Value::from_i128(f as i128, target).ok_or_else(out_of_range)
             }
-            Value::Int(i) => {
-                bytes.push(Type::Int as u8);
-                bytes.extend_from_slice(&i.to_be_bytes());
+            Some(Number::Flt(f)) if target == Type::F32 => {
+                if f.is_finite() && f.abs() > f32::MAX as f64 {
+                    return Err(out_of_range());
+                }
+                Ok(Value::F32(f as f32))
+            }
+            Some(Number::Flt(f)) if target == Type::Flt => Ok(Value::Flt(f)),
+            _ => Err(Error::ValueError(ValueError::IncompatibleCast {
+                from: source,
+                to: target,
+            })),
+        }
+    }
+
+    /// Applies an arithmetic operator after widening both operands to a common
+    /// type (see `Type::widen`). Nil on either side gives Nil. Integer overflow
+    /// and integer division by zero are errors rather than wrapping or panicking.
+    pub(crate) fn arith(&self, op: ArithOp, other: &Value) -> Result<Value, Error> {
+        if let (Value::Nil, _) | (_, Value::Nil) = (self, other) {
+            return Ok(Value::Nil);
+        }
+
+        let (left, right) = (self.get_type(), other.get_type());
+        let target =
+            Type::widen(left, right)
+                .filter(|t| t.is_numeric())
+                .ok_or(Error::ValueError(ValueError::IncompatibleOperands {
+                    op,
+                    left,
+                    right,
+                }))?;
+
+        match (self.as_number(), other.as_number()) {
+            (Some(Number::Int(a)), Some(Number::Int(b))) => {
+                if b == 0 && matches!(op, ArithOp::Div | ArithOp::Rem) {
+                    return Err(Error::ValueError(ValueError::DivisionByZero));
+                }
+                let result = match op {
+                    ArithOp::Add => a.checked_add(b),
+                    ArithOp::Sub => a.checked_sub(b),
+                    ArithOp::Mul => a.checked_mul(b),
+                    ArithOp::Div => a.checked_div(b),
+                    ArithOp::Rem => a.checked_rem(b),
+                };
+                result
+                    .and_then(|r| Value::from_i128(r, target))
+                    .ok_or(Error::ValueError(ValueError::Overflow { op, target }))
             }
-            Value::Flt(f) => {
-                bytes.push(Type::Flt as u8);
-                bytes.extend_from_slice(&f.to_be_bytes());
+            (Some(a), Some(b)) => {
+                let (a, b) = (a.as_f64(), b.as_f64());
+                let result = match op {
+                    ArithOp::Add => a + b,
+                    ArithOp::Sub => a - b,
+                    ArithOp::Mul => a * b,
+                    ArithOp::Div => a / b,
+                    ArithOp::Rem => a % b,
+                };
+                Ok(match target {
+                    Type::F32 => Value::F32(result as f32),
+                    _ => Value::Flt(result),
+                })
             }
+            _ => unreachable!("widened to a numeric type, so both sides are numbers"),
+        }
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.as_bytes().cmp(b.as_bytes()),
+            _ => match (self.as_number(), other.as_number()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                _ => self.order_rank().cmp(&other.order_rank()),
+            },
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.order_rank().hash(state);
+        match self {
+            Value::Bool(b) => b.hash(state),
+            Value::Str(s) => s.as_bytes().hash(state),
+            Value::Nil => {}
+            _ => self.as_number().hash(state),
+        }
+    }
+}
+
+/// A numeric value widened so that any two numbers can be compared exactly.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Number {
+    Int(i128),
+    Flt(f64),
+}
+
+// 2^127 is exactly representable as an f64; every i128 is strictly below it.
+const I128_BOUND: f64 = 170141183460469231731687303715884105728.0;
+
+impl Number {
+    pub(crate) fn as_f64(&self) -> f64 {
+        match *self {
+            Number::Int(i) => i as f64,
+            Number::Flt(f) => f,
+        }
+    }
+
+    fn cmp_flt(a: f64, b: f64) -> Ordering {
+        match (a.is_nan(), b.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            // neither is NaN, so this is a total comparison (and -0.0 == 0.0)
+            (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        }
+    }
+
+    fn cmp_int_flt(i: i128, f: f64) -> Ordering {
+        if f.is_nan() || f >= I128_BOUND {
+            return Ordering::Less;
+        }
+        if f < -I128_BOUND {
+            return Ordering::Greater;
+        }
+
+        // |f| < 2^127 here, so its integral part converts to i128 exactly
+        let whole = f.trunc();
+        match i.cmp(&(whole as i128)) {
+            Ordering::Equal => Self::cmp_flt(whole, f),
+            ordering => ordering,
+        }
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (*self, *other) {
+            (Number::Int(a), Number::Int(b)) => a.cmp(&b),
+            (Number::Flt(a), Number::Flt(b)) => Self::cmp_flt(a, b),
+            (Number::Int(a), Number::Flt(b)) => Self::cmp_int_flt(a, b),
+            (Number::Flt(a), Number::Int(b)) => Self::cmp_int_flt(b, a).reverse(),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl Hash for Number {
+    // Numbers that compare equal must hash equally, so integral floats hash
+    // like the integer they are equal to and every NaN hashes the same.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Number::Int(i) => i.hash(state),
+            Number::Flt(f) if f.is_nan() => f64::NAN.to_bits().hash(state),
+            Number::Flt(f) if f.fract() == 0.0 && f.abs() < I128_BOUND => (f as i128).hash(state),
+            Number::Flt(f) => f.to_bits().hash(state),
+        }
+    }
+}
+
+impl Value {
+    // The bytes after the type byte. Str payloads are length-prefixed.
+    pub(crate) fn write_payload(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Value::Bool(b) => bytes.push(*b as u8),
+            Value::Int(i) => bytes.extend_from_slice(&i.to_be_bytes()),
+            Value::Flt(f) => bytes.extend_from_slice(&f.to_be_bytes()),
             Value::Str(s) => {
-                bytes.push(Type::Str as u8);
                 bytes.extend_from_slice(&(s.len() as u32).to_be_bytes());
                 bytes.extend_from_slice(s.as_bytes());
             }
-            Value::Nil => {
-                bytes.push(Type::Unknown as u8);
+            Value::Nil => {}
+            Value::I8(i) => bytes.extend_from_slice(&i.to_be_bytes()),
+            Value::I16(i) => bytes.extend_from_slice(&i.to_be_bytes()),
+            Value::I32(i) => bytes.extend_from_slice(&i.to_be_bytes()),
+            Value::U8(i) => bytes.extend_from_slice(&i.to_be_bytes()),
+            Value::U16(i) => bytes.extend_from_slice(&i.to_be_bytes()),
+            Value::U32(i) => bytes.extend_from_slice(&i.to_be_bytes()),
+            Value::U64(i) => bytes.extend_from_slice(&i.to_be_bytes()),
+            Value::I128(i) => bytes.extend_from_slice(&i.to_be_bytes()),
+            Value::F32(f) => bytes.extend_from_slice(&f.to_be_bytes()),
+        }
+    }
+
+    /// How many bytes write_payload writes
+    pub(crate) fn payload_length(&self) -> usize {
+        match self {
+            Value::Str(s) => 4 + s.len(),
+            value => value.get_type().fixed_size().unwrap_or(0),
+        }
+    }
+
+    /// Reads a payload written by write_payload
+    pub(crate) fn read_payload(
+        value_type: Type,
+        deserializer: &mut ByteDeserializer,
+    ) -> Result<Value, Error> {
+        match value_type {
+            Type::Bool => Ok(Value::Bool(deserializer.read_u8()? != 0)),
+            Type::Int => Ok(Value::Int(deserializer.read_i64()?)),
+            Type::Flt => Ok(Value::Flt(deserializer.read_f64()?)),
+            Type::I8 => Ok(Value::I8(deserializer.read_i8()?)),
+            Type::I16 => Ok(Value::I16(deserializer.read_i16()?)),
+            Type::I32 => Ok(Value::I32(deserializer.read_i32()?)),
+            Type::U8 => Ok(Value::U8(deserializer.read_u8()?)),
+            Type::U16 => Ok(Value::U16(deserializer.read_u16()?)),
+            Type::U32 => Ok(Value::U32(deserializer.read_u32()?)),
+            Type::U64 => Ok(Value::U64(deserializer.read_u64()?)),
+            Type::I128 => Ok(Value::I128(deserializer.read_i128()?)),
+            Type::F32 => Ok(Value::F32(deserializer.read_f32()?)),
+            Type::Str => {
+                let length = deserializer.read_u32()? as usize;
+                let bytes = deserializer.read_bytes(length)?;
+                String::from_utf8(bytes).map(Value::Str).map_err(|err| {
+                    Error::ValueError(ValueError::InvalidUtf8Str {
+                        bytes: err.into_bytes(),
+                    })
+                })
             }
+            Type::Unknown => Ok(Value::Nil),
         }
+    }
+}
 
+impl Serializable<Value> for Value {
+    fn serialized_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.get_type() as u8];
+        self.write_payload(&mut bytes);
         bytes
     }
 
@@ -67,7 +400,7 @@ impl Serializable<Value> for Value {
     where
         Self: Sized,
     {
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(Error::ValueError(ValueError::InvalidSize {
                 got: bytes.len(),
             }));
@@ -75,52 +408,18 @@ impl Serializable<Value> for Value {
 
         let value_type = Type::deserialize_bytes(&bytes[0..1])?;
 
-        // if value_type is not in Type enum, it is an error
-        if value_type != Type::Bool
-            && value_type != Type::Int
-            && value_type != Type::Flt
-            && value_type != Type::Str
-            && value_type != Type::Unknown
-        {
-            return Err(Error::ValueError(ValueError::InvalidType {
-                got: value_type as u8,
-            }));
-        }
-
-        match value_type {
-            Type::Bool => {
-                if bytes.len() != 2 {
-                    return Err(Error::ValueError(ValueError::InvalidSize {
-                        got: bytes.len(),
-                    }));
-                }
-
-                Ok(Value::Bool(bytes[1] != 0))
-            }
-            Type::Int => {
-                if bytes.len() != 9 {
-                    return Err(Error::ValueError(ValueError::InvalidSize {
-                        got: bytes.len(),
-                    }));
-                }
-
-                let mut int_bytes = [0; 8];
-                int_bytes.copy_from_slice(&bytes[1..9]);
-
-                Ok(Value::Int(i64::from_be_bytes(int_bytes)))
+        // fixed size payloads must match exactly
+        if let Some(size) = value_type.fixed_size() {
+            if bytes.len() != 1 + size {
+                return Err(Error::ValueError(ValueError::InvalidSize {
+                    got: bytes.len(),
+                }));
             }
-            Type::Flt => {
-                if bytes.len() != 9 {
-                    return Err(Error::ValueError(ValueError::InvalidSize {
-                        got: bytes.len(),
-                    }));
-                }
+        }
 
-                let mut flt_bytes = [0; 8];
-                flt_bytes.copy_from_slice(&bytes[1..9]);
+        let mut deserializer = ByteDeserializer::new(&bytes[1..]);
 
-                Ok(Value::Flt(f64::from_be_bytes(flt_bytes)))
-            }
+        match value_type {
             Type::Str => {
                 if bytes.len() < 5 {
                     return Err(Error::ValueError(ValueError::InvalidSize {
@@ -128,9 +427,7 @@ impl Serializable<Value> for Value {
                     }));
                 }
 
-                let mut str_len_bytes = [0; 4];
-                str_len_bytes.copy_from_slice(&bytes[1..5]);
-                let str_len = u32::from_be_bytes(str_len_bytes) as usize;
+                let str_len = deserializer.read_u32()? as usize;
 
                 if bytes.len() != 5 + str_len {
                     return Err(Error::ValueError(ValueError::InvalidSize {
@@ -148,6 +445,7 @@ impl Serializable<Value> for Value {
                 }
             }
             Type::Unknown => Ok(Value::Nil),
+            _ => Value::read_payload(value_type, &mut deserializer),
         }
     }
 }
@@ -156,9 +454,33 @@ impl Serializable<Value> for Value {
 
 #[derive(Debug)]
 pub(crate) enum ValueError {
-    InvalidSize { got: usize },
-    InvalidType { got: u8 },
-    InvalidUtf8Str { bytes: Vec<u8> },
+    InvalidSize {
+        got: usize,
+    },
+    InvalidType {
+        got: u8,
+    },
+    InvalidUtf8Str {
+        bytes: Vec<u8>,
+    },
+    OutOfRange {
+        value: Value,
+        target: Type,
+    },
+    IncompatibleCast {
+        from: Type,
+        to: Type,
+    },
+    IncompatibleOperands {
+        op: ArithOp,
+        left: Type,
+        right: Type,
+    },
+    Overflow {
+        op: ArithOp,
+        target: Type,
+    },
+    DivisionByZero,
 }
 
 impl std::fmt::Display for ValueError {
@@ -173,8 +495,141 @@ impl std::fmt::Display for ValueError {
             ValueError::InvalidUtf8Str { bytes } => {
                 write!(f, "Invalid UTF-8 string: {:?}", bytes)
             }
+            ValueError::OutOfRange { value, target } => {
+                write!(f, "Value {:?} is out of range for {:?}", value, target)
+            }
+            ValueError::IncompatibleCast { from, to } => {
+                write!(f, "Cannot cast {:?} to {:?}", from, to)
+            }
+            ValueError::IncompatibleOperands { op, left, right } => {
+                write!(f, "Cannot apply {:?} to {:?} and {:?}", op, left, right)
+            }
+            ValueError::Overflow { op, target } => {
+                write!(f, "{:?} overflowed {:?}", op, target)
+            }
+            ValueError::DivisionByZero => write!(f, "Division by zero"),
         }
     }
 }
 
 impl std::error::Error for ValueError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn values_have_a_total_order() {
+        let mut values = vec![
+            Value::Str("b".to_string()),
+            Value::Flt(f64::NAN),
+            Value::Int(2),
+            Value::Flt(1.5),
+            Value::Bool(true),
+            Value::Nil,
+            Value::Str("a".to_string()),
+            Value::Flt(f64::NEG_INFINITY),
+            Value::Bool(false),
+        ];
+        values.sort();
+
+        assert_eq!(
+            values,
+            vec![
+                Value::Nil,
+                Value::Bool(false),
+                Value::Bool(true),
+                Value::Flt(f64::NEG_INFINITY),
+                Value::Flt(1.5),
+                Value::Int(2),
+                Value::Flt(f64::NAN),
+                Value::Str("a".to_string()),
+                Value::Str("b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ints_and_floats_compare_exactly() {
+        assert_eq!(Value::Int(1), Value::Flt(1.0));
+        assert_eq!(Value::Int(0), Value::Flt(-0.0));
+        assert!(Value::Int(-2) < Value::Flt(-1.5));
+        assert!(Value::Int(i64::MAX) < Value::Flt(9223372036854775808.0));
+        assert!(Value::Int(i64::MAX - 1) < Value::Int(i64::MAX));
+        assert_eq!(Value::Flt(f64::NAN), Value::Flt(f64::NAN));
+    }
+
+    #[test]
+    fn sized_numbers_compare_by_value() {
+        assert_eq!(Value::U8(7), Value::I128(7));
+        assert!(Value::U64(u64::MAX) > Value::Int(i64::MAX));
+        assert_eq!(Value::F32(0.5), Value::Flt(0.5));
+    }
+
+    #[test]
+    fn casts_are_range_checked() {
+        assert_eq!(Value::Int(255).cast_to(Type::U8).unwrap(), Value::U8(255));
+        assert!(Value::Int(256).cast_to(Type::U8).is_err());
+        assert!(Value::I8(-1).cast_to(Type::U64).is_err());
+        assert_eq!(Value::Flt(3.0).cast_to(Type::I16).unwrap(), Value::I16(3));
+        assert!(Value::Flt(3.5).cast_to(Type::I16).is_err());
+        assert!(Value::Flt(1e300).cast_to(Type::F32).is_err());
+        assert!(Value::Str("1".to_string()).cast_to(Type::Int).is_err());
+    }
+
+    #[test]
+    fn arithmetic_widens_operands() {
+        let sum = Value::U8(200).arith(ArithOp::Add, &Value::I8(100)).unwrap();
+        assert_eq!(sum.get_type(), Type::I16);
+        assert_eq!(sum, Value::I16(300));
+
+        let big = Value::U64(u64::MAX)
+            .arith(ArithOp::Add, &Value::Int(1))
+            .unwrap();
+        assert_eq!(big, Value::I128(u64::MAX as i128 + 1));
+
+        let mixed = Value::I32(1).arith(ArithOp::Div, &Value::F32(2.0)).unwrap();
+        assert_eq!(mixed.get_type(), Type::Flt);
+
+        assert!(Value::U8(255).arith(ArithOp::Add, &Value::U8(1)).is_err());
+        assert!(Value::Int(1).arith(ArithOp::Div, &Value::Int(0)).is_err());
+    }
+
+    #[test]
+    fn sized_values_round_trip() {
+        let values = vec![
+            Value::I8(-5),
+            Value::I16(-300),
+            Value::I32(70000),
+            Value::U8(200),
+            Value::U16(60000),
+            Value::U32(4000000000),
+            Value::U64(u64::MAX),
+            Value::I128(i128::MIN),
+            Value::F32(1.25),
+        ];
+        for value in values {
+            let bytes = value.serialized_bytes();
+            let decoded = Value::deserialize_bytes(&bytes).unwrap();
+            assert_eq!(decoded.get_type(), value.get_type());
+            assert_eq!(decoded, value);
+        }
+    }
+
+    #[test]
+    fn equal_values_hash_equally() {
+        let set: HashSet<Value> = [
+            Value::Int(3),
+            Value::Flt(3.0),
+            Value::Flt(f64::NAN),
+            Value::Flt(-f64::NAN),
+            Value::Flt(0.0),
+            Value::Flt(-0.0),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(set.len(), 3);
+    }
+}
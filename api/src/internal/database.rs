@@ -1,25 +1,66 @@
-use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::byte_deserializer::{ByteDeserializer, ByteError};
+use crate::internal::checksum::{self, ChecksumError, Region, VerifyReport};
 use crate::internal::errors::Error;
+use crate::internal::format::{Features, Format};
+use crate::internal::id::Identifier;
+use crate::internal::index::{ColumnEntry, Index, SheetEntry};
 use crate::internal::length_table::LengthTable;
-use crate::internal::sheet::Sheet;
+use crate::internal::limits::{Budget, DecodeLimits, Limited};
+use crate::internal::observer::Observers;
+use crate::internal::segment::{self, Saved};
+use crate::internal::sheet::{Sheet, SheetPlan};
+use crate::internal::stream::StreamWriter;
 use crate::internal::traits::{PrettyPrintable, Serializable};
+use crate::internal::transaction::{Constraint, ConstraintIndex};
+use crate::internal::view::{self, View};
+use std::cell::RefCell;
+use std::io::Write;
+use std::ops::Range;
 
 #[derive(Debug, Clone)]
 pub(crate) struct Database {
     pub(crate) columns: Vec<Sheet>,
+    // checked when a transaction commits, see transaction.rs. Not serialized.
+    pub(crate) constraints: Vec<Constraint>,
+    pub(crate) constrained: ConstraintIndex,
+    // see observer.rs. Not serialized, and not copied by clone.
+    pub(crate) observers: Observers,
+    // see view.rs
+    pub(crate) views: Vec<View>,
+    // the file last saved or loaded, for appending to, see segment.rs. Not
+    // serialized.
+    pub(crate) saved: RefCell<Option<Saved>>,
 }
 
 impl Database {
     pub(crate) fn new(columns: Vec<Sheet>) -> Self {
-        Self { columns }
+        Self {
+            columns,
+            constraints: Vec::new(),
+            constrained: ConstraintIndex::default(),
+            observers: Observers::default(),
+            views: Vec::new(),
+            saved: RefCell::new(None),
+        }
     }
 
     pub(crate) fn new_empty() -> Self {
         Self {
             columns: Vec::new(),
+            constraints: Vec::new(),
+            constrained: ConstraintIndex::default(),
+            observers: Observers::default(),
+            views: Vec::new(),
+            saved: RefCell::new(None),
         }
     }
 
+    /// An empty database for sheets to refer back to. Nothing is read through
+    /// that reference, so none of this one is copied into it.
+    pub(crate) fn without_sheets(&self) -> Database {
+        Database::new_empty()
+    }
+
     pub(crate) fn adopt_sheet(&mut self, sheet: &Sheet) {
         let mut sheet = sheet.clone();
         sheet.adopt(self);
@@ -32,40 +73,252 @@ impl Database {
         }
     }
 
+    /// Checks every checksum in a serialized database and reports each damaged
+    /// sheet and column, rather than stopping at the first one like
+    /// deserialize_bytes does.
+    pub(crate) fn verify(bytes: &[u8]) -> Result<VerifyReport, Error> {
+        checksum::verify(bytes)
+    }
+
+    /// Serializes the database straight into `writer`, producing the same bytes
+    /// as serialized_bytes_with without holding them all in memory. Lengths are
+    /// worked out in a first pass, then everything is written in a second one.
+    /// Returns the writer, flushed.
+    pub(crate) fn write_to<W: Write>(&self, writer: W, format: &Format) -> Result<W, Error> {
+        let plans: Vec<SheetPlan> = self
+            .columns
+            .iter()
+            .map(|sheet| sheet.plan(format))
+            .collect();
+        let mut writer = StreamWriter::new(writer);
+
+        if format.has(Features::CHECKSUMS) {
+            writer.begin_checksum();
+        }
+        writer.write(&format.header_bytes())?;
+
+        let sheets_start = writer.position() as usize;
+        let lengths: Vec<usize> = plans.iter().map(|plan| plan.length).collect();
+        writer.write(&LengthTable::header_bytes(&lengths, format))?;
+        for (sheet, plan) in self.columns.iter().zip(&plans) {
+            sheet.write_to(&mut writer, plan, format)?;
+        }
+
+        if format.has(Features::INDEX) {
+            let index = self.index(&plans, sheets_start);
+            let index_bytes = index.serialized_bytes_with(format);
+            writer.write_u32(index_bytes.len() as u32)?;
+            writer.write(&index_bytes)?;
+        }
+        writer.write(&view::serialized_bytes(&self.views, format))?;
+
+        if format.has(Features::CHECKSUMS) {
+            writer.end_checksum()?;
+        }
+        writer.flush()?;
+        Ok(writer.into_inner())
+    }
+
+    // the index of a file whose sheets table starts at `sheets_start`
+    fn index(&self, plans: &[SheetPlan], sheets_start: usize) -> Index {
+        let lengths: Vec<usize> = plans.iter().map(|plan| plan.length).collect();
+        let absolute = |range: Range<usize>, start: usize| {
+            (start + range.start) as u64..(start + range.end) as u64
+        };
+
+        let sheets = self
+            .columns
+            .iter()
+            .zip(plans)
+            .zip(LengthTable::ranges(&lengths))
+            .map(|((sheet, plan), range)| {
+                let sheet_start = sheets_start + range.start;
+                SheetEntry {
+                    id: sheet.id,
+                    name: sheet.name.clone(),
+                    range: absolute(range, sheets_start),
+                    columns: sheet
+                        .columns
+                        .iter()
+                        .zip(plan.column_ranges())
+                        .map(|(column, range)| ColumnEntry {
+                            id: column.id,
+                            name: column.name.clone(),
+                            range: absolute(range, sheet_start),
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Index { sheets }
+    }
+
+    /// Decodes a database, enforcing `limits` on what the decoded data may
+    /// take. Use this rather than deserialize_bytes for untrusted bytes.
+    pub(crate) fn deserialize_bytes_limited(
+        bytes: &[u8],
+        limits: &DecodeLimits,
+    ) -> Result<Database, Error> {
+        Ok(Self::decode(bytes, limits, false)?.0)
+    }
+
+    /// Decodes a database whose sheet or column names may not be valid UTF-8,
+    /// for salvaging damaged files. Invalid bytes become U+FFFD, and every
+    /// name that was changed is returned alongside the database.
+    pub(crate) fn deserialize_bytes_lossy(
+        bytes: &[u8],
+        limits: &DecodeLimits,
+    ) -> Result<(Database, Vec<RenamedName>), Error> {
+        let (database, _) = Self::decode(bytes, limits, true)?;
+
+        let mut deserializer = ByteDeserializer::new(bytes);
+        let format = Format {
+            lossy_names: true,
+            ..Format::read_header(&mut deserializer)?
+        };
+        let index = Index::locate(bytes, deserializer.position(), &format)?;
+
+        // every block starts with a 16 byte id and a u32 name length
+        let renamed_at = |range: &Range<u64>, name: &str, sheet: Option<&str>| {
+            let start = range.start as usize + 20;
+            let raw = &bytes[start - 4..start];
+            let raw = &bytes
+                [start..start + u32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]) as usize];
+            match std::str::from_utf8(raw) {
+                Ok(_) => None,
+                Err(_) => Some(RenamedName {
+                    offset: start as u64,
+                    bytes: raw.to_vec(),
+                    name: name.to_string(),
+                    sheet: sheet.map(str::to_string),
+                }),
+            }
+        };
+
+        let mut renamed = Vec::new();
+        for sheet in &index.sheets {
+            renamed.extend(renamed_at(&sheet.range, &sheet.name, None));
+            for column in &sheet.columns {
+                renamed.extend(renamed_at(&column.range, &column.name, Some(&sheet.name)));
+            }
+        }
+
+        Ok((database, renamed))
+    }
+
+    /// Decodes a database, and says where the last of its bytes ends: the end
+    /// of the file, but for what's left of an interrupted append.
+    pub(crate) fn decode(
+        bytes: &[u8],
+        limits: &DecodeLimits,
+        lossy_names: bool,
+    ) -> Result<(Database, usize), Error> {
+        let mut deserializer = ByteDeserializer::new(bytes);
+        let mut format = Format::read_header(&mut deserializer)?;
+        format.limits = *limits;
+        format.lossy_names = lossy_names;
+        let header_length = deserializer.position();
+
+        // rows appended after the file was written follow it in segments
+        let (file, segments) = match format.has(Features::SEGMENTS) {
+            true => bytes.split_at(segment::base_length_of(bytes, header_length, &format)?),
+            false => (bytes, &[][..]),
+        };
+
+        // Check the whole file before decoding anything, and on a mismatch work
+        // out exactly which sheets and columns are damaged
+        let body = if format.has(Features::CHECKSUMS) {
+            checksum::checked_body(file, || Region::File).map_err(|_| {
+                match checksum::verify(bytes) {
+                    Ok(report) => Error::ChecksumError(ChecksumError::Corrupted {
+                        corruptions: report.corruptions,
+                    }),
+                    Err(err) => err,
+                }
+            })?
+        } else {
+            file
+        };
+
+        // one budget for everything the file decodes to, appended rows included
+        let mut budget = Budget::new(limits);
+        let sheets = LengthTable::deserialize_limited(
+            body.get(header_length..).unwrap_or_default(),
+            &format,
+            Some(Limited::Sheets),
+            &mut budget,
+            Sheet::decode,
+        )
+        .map_err(|err| err.offset_by(header_length))?;
+
+        let mut database = Database::new(Vec::new());
+        database.adopt_sheets(sheets);
+        if format.has(Features::VIEWS) {
+            let out_of_bounds = |pos: u64| {
+                Error::ByteError(ByteError::OutOfBoundsError {
+                    pos: pos as usize,
+                    len: body.len(),
+                })
+            };
+            let offset = view::offset(header_length, &format, |offset| {
+                match body.get(offset as usize..).and_then(|rest| rest.get(..4)) {
+                    Some(raw) => Ok([raw[0], raw[1], raw[2], raw[3]]),
+                    None => Err(out_of_bounds(offset)),
+                }
+            })?;
+            let views = body.get(offset as usize..).ok_or(out_of_bounds(offset))?;
+            database.views = view::read(&mut ByteDeserializer::new(views), &format)
+                .map_err(|err| err.offset_by(offset as usize))?;
+            view::check(&database)?;
+        }
+
+        let mut end = file.len();
+        if format.has(Features::SEGMENTS) {
+            let (appended, length) =
+                segment::read_segments(segments, file.len(), &format, &mut budget)?;
+            segment::merge(&mut database, appended, &format)?;
+            end += length;
+        }
+
+        Ok((database, end))
+    }
+
     pub(crate) fn get_sheet_mut(&mut self, name: &str) -> Option<&mut Sheet> {
         self.columns.iter_mut().find(|sheet| sheet.name == name)
     }
+
+    pub(crate) fn get_sheet_mut_by_id(&mut self, id: &Identifier) -> Option<&mut Sheet> {
+        self.columns.iter_mut().find(|sheet| sheet.id == *id)
+    }
 }
 
 impl Serializable<Database> for Database {
     // all numbers are BE
+    // header: see Format::header_bytes
     // length_table<Sheet> sheets: sheets serialized
+    // with Features::INDEX: u32 index_length, then the Index (see index::Index)
+    // with Features::VIEWS: the views, see view.rs
+    // with Features::CHECKSUMS: u32 crc32c of everything above, header included
+    // with Features::SEGMENTS: any rows appended since, see segment.rs
 
     fn serialized_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        // ascii string magic bytes: "baseboredv1"
-        bytes.extend_from_slice(&[98, 97, 115, 101, 98, 111, 114, 101, 100, 118, 49]);
-
-        let sheets_bytes = LengthTable::serialize(&self.columns);
-        bytes.extend(sheets_bytes);
-        bytes
+        self.serialized_bytes_with(&Format::current())
     }
 
+    // The format is read from the header, so this accepts every supported version
     fn deserialize_bytes(bytes: &[u8]) -> Result<Database, Error> {
-        let mut deserializer = ByteDeserializer::new(bytes);
-
-        // check magic bytes
-        let magic_bytes = deserializer.read_bytes(11)?;
-        if magic_bytes != [98, 97, 115, 101, 98, 111, 114, 101, 100, 118, 49] {
-            return Err(Error::DatabaseError(DatabaseError::InvalidMagicBytes));
-        }
-
-        let sheets = LengthTable::deserialize(deserializer.remaining_bytes())?;
+        Self::deserialize_bytes_limited(bytes, &DecodeLimits::default())
+    }
 
-        let mut database = Database::new(Vec::new());
-        database.adopt_sheets(sheets);
+    fn serialized_bytes_with(&self, format: &Format) -> Vec<u8> {
+        self.write_to(Vec::new(), format)
+            .expect("writing to a Vec can't fail")
+    }
 
-        Ok(database)
+    fn deserialize_bytes_with(bytes: &[u8], _format: &Format) -> Result<Database, Error> {
+        // the header is authoritative
+        Self::deserialize_bytes(bytes)
     }
 }
 
@@ -77,23 +330,333 @@ impl PrettyPrintable for Database {
         for sheet in &self.columns {
             result.push_str(&sheet.pretty_print(indent + 2));
         }
+        for view in &self.views {
+            result.push_str(&format!(
+                "{}  View {}: {}\n",
+                indent_str, view.name, view.query
+            ));
+        }
         result
     }
 }
 
+/// A sheet or column name that wasn't valid UTF-8, and what
+/// deserialize_bytes_lossy decoded it as
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RenamedName {
+    // of the name's first byte in the file
+    pub(crate) offset: u64,
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) name: String,
+    // the sheet a renamed column is in
+    pub(crate) sheet: Option<String>,
+}
+
 /* -- ERRORS -- */
 
 #[derive(Debug)]
 pub(crate) enum DatabaseError {
     InvalidMagicBytes,
+    SheetNotFound { sheet_name: String },
 }
 
 impl std::fmt::Display for DatabaseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             DatabaseError::InvalidMagicBytes => write!(f, "Invalid magic bytes"),
+            DatabaseError::SheetNotFound { sheet_name } => {
+                write!(f, "Sheet with name {} not found", sheet_name)
+            }
         }
     }
 }
 
 impl std::error::Error for DatabaseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::byte_deserializer::ByteError;
+    use crate::internal::column::Column;
+    use crate::internal::data_type::Type;
+    use crate::internal::data_value::Value;
+    use crate::internal::format::FormatError;
+    use crate::internal::limits::LimitError;
+    use crate::internal::stream::StreamReader;
+
+    fn sample_database() -> Database {
+        let mut sheet = Sheet::new("people".to_string(), Vec::new(), None);
+        sheet.adopt_column(&Column::new("name".to_string(), Type::Str, None));
+        sheet.adopt_column(&Column::new("age".to_string(), Type::U8, None));
+        sheet
+            .insert_row(vec![Value::Str("ada".to_string()), Value::Int(36)])
+            .unwrap();
+        sheet
+            .insert_row(vec![Value::Str("alan".to_string()), Value::Nil])
+            .unwrap();
+
+        let mut database = Database::new_empty();
+        database.adopt_sheet(&sheet);
+        database
+    }
+
+    fn cells(database: &Database) -> Vec<Vec<Value>> {
+        database.columns[0]
+            .columns
+            .iter()
+            .map(|column| column.cells.iter().map(|c| c.value.clone()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn writes_v2_header() {
+        let bytes = sample_database().serialized_bytes();
+        assert_eq!(&bytes[..11], b"baseboredv2");
+        assert_eq!(u16::from_be_bytes([bytes[11], bytes[12]]), 17);
+    }
+
+    #[test]
+    fn reads_v1_files() {
+        let database = sample_database();
+        let v1 = database.serialized_bytes_with(&Format::v1());
+        assert_eq!(&v1[..11], b"baseboredv1");
+
+        let decoded = Database::deserialize_bytes(&v1).unwrap();
+        assert_eq!(cells(&decoded), cells(&database));
+
+        // and round trips through v2
+        let decoded = Database::deserialize_bytes(&decoded.serialized_bytes()).unwrap();
+        assert_eq!(cells(&decoded), cells(&database));
+    }
+
+    #[test]
+    fn verify_reports_every_damaged_column() {
+        let mut database = sample_database();
+        let mut other = database.columns[0].clone();
+        other.name = "pets".to_string();
+        database.adopt_sheet(&other);
+
+        let clean = database.serialized_bytes();
+        assert!(Database::verify(&clean).unwrap().is_ok());
+
+        // flip the type byte of the last cell in each sheet's "name" column
+        let mut damaged = clean.clone();
+        for (at, _) in clean.windows(4).enumerate().filter(|(_, w)| *w == b"alan") {
+            damaged[at - 5] ^= 0xff;
+        }
+
+        let report = Database::verify(&damaged).unwrap();
+        let regions: Vec<Region> = report
+            .corruptions
+            .iter()
+            .map(|c| c.region.clone())
+            .collect();
+        assert!(regions.contains(&Region::File));
+        for sheet in ["people", "pets"] {
+            assert!(regions.contains(&Region::Sheet {
+                name: Some(sheet.to_string())
+            }));
+        }
+        assert_eq!(
+            regions
+                .iter()
+                .filter(|r| matches!(r, Region::Column { .. }))
+                .count(),
+            2
+        );
+
+        match Database::deserialize_bytes(&damaged) {
+            Err(Error::ChecksumError(ChecksumError::Corrupted { corruptions })) => {
+                assert_eq!(corruptions, report.corruptions)
+            }
+            other => panic!("expected a checksum error, got {:?}", other),
+        }
+    }
+
+    // accepts at most 3 bytes per write, then fails once it has taken `limit` bytes
+    struct Trickle {
+        bytes: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.bytes.len() >= self.limit {
+                return Err(std::io::Error::other("disk full"));
+            }
+            let n = buf.len().min(3);
+            self.bytes.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_to_streams_the_same_bytes() {
+        let database = sample_database();
+        for format in [Format::current(), Format::v1()] {
+            let expected = database.serialized_bytes_with(&format);
+            let written = database
+                .write_to(
+                    Trickle {
+                        bytes: Vec::new(),
+                        limit: usize::MAX,
+                    },
+                    &format,
+                )
+                .unwrap();
+            assert_eq!(written.bytes, expected);
+        }
+
+        let full = Trickle {
+            bytes: Vec::new(),
+            limit: 20,
+        };
+        assert!(matches!(
+            database.write_to(full, &Format::current()),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn damaged_files_fail_without_panicking() {
+        let mut database = sample_database();
+        database.columns[0]
+            .insert_row(vec![Value::Str("ada".to_string()), Value::Int(7)])
+            .unwrap();
+        let unchecked = Format {
+            features: Features::KNOWN.without(Features::CHECKSUMS),
+            ..Format::current()
+        };
+
+        for format in [Format::current(), unchecked, Format::v1()] {
+            let bytes = database.serialized_bytes_with(&format);
+            let mut inputs: Vec<Vec<u8>> = (0..bytes.len()).map(|n| bytes[..n].to_vec()).collect();
+            for at in 0..bytes.len() {
+                for byte in [0x00, 0x01, 0x7f, 0x80, 0xff] {
+                    let mut damaged = bytes.clone();
+                    damaged[at] = byte;
+                    inputs.push(damaged);
+                }
+            }
+
+            for input in inputs {
+                let _ = Database::deserialize_bytes(&input);
+                let _ = Database::verify(&input);
+                let _ = StreamReader::new(&input[..]).count();
+            }
+        }
+    }
+
+    #[test]
+    fn enforces_decode_limits() {
+        let bytes = sample_database().serialized_bytes();
+        let decode =
+            |limits: DecodeLimits| match Database::deserialize_bytes_limited(&bytes, &limits) {
+                Err(Error::LimitError(err)) => err,
+                other => panic!("expected a limit error, got {:?}", other),
+            };
+
+        let defaults = DecodeLimits::default();
+        assert!(matches!(
+            decode(DecodeLimits {
+                max_sheets: 0,
+                ..defaults
+            }),
+            LimitError::TooMany {
+                what: Limited::Sheets,
+                ..
+            }
+        ));
+        assert!(matches!(
+            decode(DecodeLimits {
+                max_cells: 1,
+                ..defaults
+            }),
+            LimitError::TooMany {
+                what: Limited::Cells,
+                count: 2,
+                ..
+            }
+        ));
+        assert!(matches!(
+            decode(DecodeLimits {
+                max_string_length: 3,
+                ..defaults
+            }),
+            LimitError::StringTooLong { length: 6, .. }
+        ));
+        assert!(matches!(
+            decode(DecodeLimits {
+                max_total_bytes: 64,
+                ..defaults
+            }),
+            LimitError::TooLarge { limit: 64 }
+        ));
+    }
+
+    #[test]
+    fn names_must_be_valid_utf8() {
+        let unchecked = Format {
+            features: Features::KNOWN.without(Features::CHECKSUMS),
+            ..Format::current()
+        };
+        let mut bytes = sample_database().serialized_bytes_with(&unchecked);
+        let sheet_at = bytes.windows(6).position(|w| w == b"people").unwrap();
+        let column_at = bytes.windows(3).position(|w| w == b"age").unwrap();
+        bytes[sheet_at] = 0xff;
+        bytes[column_at + 2] = 0xc3;
+
+        match Database::deserialize_bytes(&bytes) {
+            Err(Error::ByteError(ByteError::InvalidUtf8 { pos, bytes })) => {
+                assert_eq!(pos, sheet_at);
+                assert_eq!(bytes, b"\xffeople");
+            }
+            other => panic!("expected invalid UTF-8, got {:?}", other),
+        }
+
+        let (database, renamed) =
+            Database::deserialize_bytes_lossy(&bytes, &DecodeLimits::default()).unwrap();
+        assert_eq!(database.columns[0].name, "\u{fffd}eople");
+        assert_eq!(database.columns[0].columns[1].name, "ag\u{fffd}");
+        assert_eq!(
+            renamed,
+            vec![
+                RenamedName {
+                    offset: sheet_at as u64,
+                    bytes: b"\xffeople".to_vec(),
+                    name: "\u{fffd}eople".to_string(),
+                    sheet: None,
+                },
+                RenamedName {
+                    offset: column_at as u64,
+                    bytes: b"ag\xc3".to_vec(),
+                    name: "ag\u{fffd}".to_string(),
+                    sheet: Some("\u{fffd}eople".to_string()),
+                },
+            ]
+        );
+
+        assert!(StreamReader::new(&bytes[..]).any(|event| event.is_err()));
+        let mut stream = StreamReader::limited(&bytes[..], DecodeLimits::default(), true);
+        let sheet = stream.next_sheet().unwrap().unwrap();
+        assert_eq!(sheet.name, "\u{fffd}eople");
+        assert_eq!(sheet.columns[1].name, "ag\u{fffd}");
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut bytes = sample_database().serialized_bytes();
+        bytes[10] = b'3';
+
+        match Database::deserialize_bytes(&bytes) {
+            Err(Error::FormatError(FormatError::UnsupportedVersion { version })) => {
+                assert_eq!(version, b'3')
+            }
+            other => panic!("expected an unsupported version error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use crate::internal::byte_deserializer::{ByteDeserializer, ByteError};
+use crate::internal::checksum::{ChecksumError, Region};
+use crate::internal::column::Column;
+use crate::internal::database::{Database, DatabaseError};
+use crate::internal::errors::Error;
+use crate::internal::format::{Features, Format, MAGIC};
+use crate::internal::index::{Index, SheetEntry};
+use crate::internal::limits::Budget;
+use crate::internal::segment::{self, SegmentError};
+use crate::internal::sheet::{Sheet, SheetError};
+use crate::internal::traits::Serializable;
+use crate::internal::view::{self, View};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+/// A database file opened for random access. Only the header and the index are
+/// read up front; sheets and columns are read and decoded when asked for.
+///
+/// Files written without Features::INDEX are still readable, but have to be
+/// scanned once on open to build the index.
+pub(crate) struct DatabaseFile {
+    source: Source,
+    format: Format,
+    index: Index,
+    // rows appended after the file was written (see segment.rs), decoded on
+    // open since they aren't indexed
+    appended: Vec<Sheet>,
+    // read on open, they're small
+    views: Vec<View>,
+}
+
+enum Source {
+    File(RefCell<File>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl Source {
+    fn len(&self) -> Result<u64, Error> {
+        match self {
+            Source::File(file) => Ok(file.borrow().metadata().map_err(Error::Io)?.len()),
+            #[cfg(feature = "mmap")]
+            Source::Mapped(map) => Ok(map.len() as u64),
+        }
+    }
+
+    fn read_at(&self, range: Range<u64>) -> Result<Vec<u8>, Error> {
+        let length = self.len()?;
+        if range.start > range.end || range.end > length {
+            return Err(Error::ByteError(ByteError::OutOfBoundsError {
+                pos: range.end as usize,
+                len: length as usize,
+            }));
+        }
+
+        match self {
+            Source::File(file) => {
+                let mut file = file.borrow_mut();
+                let mut bytes = vec![0; (range.end - range.start) as usize];
+                file.seek(SeekFrom::Start(range.start)).map_err(Error::Io)?;
+                file.read_exact(&mut bytes).map_err(Error::Io)?;
+                Ok(bytes)
+            }
+            #[cfg(feature = "mmap")]
+            Source::Mapped(map) => Ok(map[range.start as usize..range.end as usize].to_vec()),
+        }
+    }
+
+    fn read_u32_at(&self, offset: u64) -> Result<[u8; 4], Error> {
+        let bytes = self.read_at(offset..offset + 4)?;
+        Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+}
+
+impl DatabaseFile {
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<DatabaseFile, Error> {
+        let file = File::open(path).map_err(Error::Io)?;
+        Self::from_source(Source::File(RefCell::new(file)))
+    }
+
+    /// Like `open`, but maps the file into memory instead of seeking and
+    /// reading. The file must not be modified while it's open.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn open_mapped(path: impl AsRef<Path>) -> Result<DatabaseFile, Error> {
+        let file = File::open(path).map_err(Error::Io)?;
+        // SAFETY: memmap2 can't stop other processes from changing the file
+        // under us; the caller promises not to
+        let map = unsafe { memmap2::Mmap::map(&file).map_err(Error::Io)? };
+        Self::from_source(Source::Mapped(map))
+    }
+
+    fn from_source(source: Source) -> Result<DatabaseFile, Error> {
+        let file_length = source.len()?;
+
+        // v1 headers end after the version byte; v2 headers say how long they are
+        let prefix_length = (MAGIC.len() as u64 + 3).min(file_length);
+        let prefix = source.read_at(0..prefix_length)?;
+        let header_length = match prefix.get(MAGIC.len()) {
+            Some(b'1') | None => prefix.len() as u64,
+            Some(_) if prefix.len() < MAGIC.len() + 3 => prefix.len() as u64,
+            Some(_) => u16::from_be_bytes([prefix[11], prefix[12]]) as u64,
+        };
+        let header = source.read_at(0..header_length.min(file_length))?;
+        let mut deserializer = ByteDeserializer::new(&header);
+        let format = Format::read_header(&mut deserializer)?;
+        let header_length = deserializer.position();
+
+        let index = if format.has(Features::INDEX) {
+            let sheets_length =
+                format.read_length_table_u32(source.read_u32_at(header_length as u64)?);
+            let offset = Index::offset(header_length, sheets_length);
+            let index_length = u32::from_be_bytes(source.read_u32_at(offset)?) as u64;
+            let index_bytes = source.read_at(offset + 4..offset + 4 + index_length)?;
+            Index::deserialize_bytes_with(&index_bytes, &format)
+                .map_err(|err| err.offset_by(offset as usize + 4))?
+        } else {
+            let bytes = source.read_at(0..file_length)?;
+            Index::locate(&bytes, header_length, &format)?
+        };
+        index.check_bounds(file_length)?;
+
+        let appended = match format.has(Features::SEGMENTS) {
+            true => {
+                let base = segment::base_length(header_length, &format, |offset| {
+                    source.read_u32_at(offset)
+                })?;
+                let segments = source.read_at(base..file_length)?;
+                let mut budget = Budget::new(&format.limits);
+                segment::read_segments(&segments, base as usize, &format, &mut budget)?.0
+            }
+            false => Vec::new(),
+        };
+        for sheet in &appended {
+            let entry = index
+                .sheets
+                .iter()
+                .find(|entry| entry.id == sheet.id)
+                .ok_or(Error::SegmentError(SegmentError::UnknownSheet {
+                    id: sheet.id,
+                }))?;
+            let ids = entry.columns.iter().map(|column| column.id);
+            if !ids.eq(sheet.columns.iter().map(|column| column.id)) {
+                return Err(Error::SegmentError(SegmentError::SchemaMismatch {
+                    sheet_name: entry.name.clone(),
+                }));
+            }
+        }
+
+        let views = match format.has(Features::VIEWS) {
+            true => {
+                let offset =
+                    view::offset(header_length, &format, |offset| source.read_u32_at(offset))?;
+                let length = u32::from_be_bytes(source.read_u32_at(offset)?) as u64;
+                let bytes = source.read_at(offset..offset + 4 + length)?;
+                let views = view::read(&mut ByteDeserializer::new(&bytes), &format)
+                    .map_err(|err| err.offset_by(offset as usize))?;
+                // the columns' types are only read with the sheets
+                view::check_names(&views, |name| index.sheet(name).is_some())?;
+                views
+            }
+            false => Vec::new(),
+        };
+
+        Ok(DatabaseFile {
+            source,
+            format,
+            index,
+            appended,
+            views,
+        })
+    }
+
+    pub(crate) fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /// The names of the sheets, then of the views
+    pub(crate) fn sheet_names(&self) -> Vec<&str> {
+        let sheets = self.index.sheets.iter().map(|sheet| sheet.name.as_str());
+        sheets
+            .chain(self.views.iter().map(|view| view.name.as_str()))
+            .collect()
+    }
+
+    /// Reads the rows of a view, loading only the sheet it reads
+    pub(crate) fn view(&self, name: &str) -> Result<Sheet, Error> {
+        let mut database = Database::new_empty();
+        database.views = self.views.clone();
+        // views only read sheets or views before them, so the chain ends
+        let mut reads = name.to_string();
+        for _ in 0..=self.views.len() {
+            match self.views.iter().find(|view| view.name == reads) {
+                Some(view) => reads = view.query.sheet.clone(),
+                None => {
+                    database.adopt_sheet(&self.sheet(&reads)?.load()?);
+                    break;
+                }
+            }
+        }
+        Ok(database.read(name)?.into_owned())
+    }
+
+    pub(crate) fn sheet(&self, name: &str) -> Result<SheetFile<'_>, Error> {
+        let entry = self.index.sheet(name).ok_or_else(|| {
+            Error::DatabaseError(DatabaseError::SheetNotFound {
+                sheet_name: name.to_string(),
+            })
+        })?;
+        Ok(SheetFile { file: self, entry })
+    }
+}
+
+/// One sheet of a `DatabaseFile`, nothing of which has been read yet
+pub(crate) struct SheetFile<'a> {
+    file: &'a DatabaseFile,
+    entry: &'a SheetEntry,
+}
+
+impl SheetFile<'_> {
+    pub(crate) fn name(&self) -> &str {
+        &self.entry.name
+    }
+
+    pub(crate) fn column_names(&self) -> Vec<&str> {
+        self.entry
+            .columns
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect()
+    }
+
+    /// Reads and decodes only this column
+    pub(crate) fn column(&self, name: &str) -> Result<Column, Error> {
+        let entry = self
+            .entry
+            .columns
+            .iter()
+            .find(|column| column.name == name)
+            .ok_or_else(|| {
+                Error::SheetError(SheetError::ColumnNotFound {
+                    column_name: name.to_string(),
+                })
+            })?;
+
+        let bytes = self.file.source.read_at(entry.range.clone())?;
+        let start = entry.range.start as usize;
+        let mut column =
+            Column::deserialize_bytes_with(&bytes, &self.file.format).map_err(|err| match err {
+                Error::ChecksumError(ChecksumError::Mismatch {
+                    region: Region::Column { sheet: None, name },
+                    stored,
+                    computed,
+                }) => Error::ChecksumError(ChecksumError::Mismatch {
+                    region: Region::Column {
+                        sheet: Some(self.entry.name.clone()),
+                        name,
+                    },
+                    stored,
+                    computed,
+                }),
+                err => err.offset_by(start),
+            })?;
+
+        for appended in self.appended() {
+            let Some(rows) = appended.get_column_by_id(&entry.id) else {
+                continue;
+            };
+            if rows.value_type != column.value_type {
+                return Err(Error::SegmentError(SegmentError::SchemaMismatch {
+                    sheet_name: self.entry.name.clone(),
+                }));
+            }
+            column.adopt_cells(rows.cells.clone());
+        }
+        Ok(column)
+    }
+
+    /// Reads and decodes the whole sheet
+    pub(crate) fn load(&self) -> Result<Sheet, Error> {
+        let bytes = self.file.source.read_at(self.entry.range.clone())?;
+        let mut sheet = Sheet::deserialize_bytes_with(&bytes, &self.file.format)
+            .map_err(|err| err.offset_by(self.entry.range.start as usize))?;
+        for appended in self.appended() {
+            segment::merge_sheet(&mut sheet, appended.clone(), &self.file.format)?;
+        }
+        Ok(sheet)
+    }
+
+    // this sheet's appended rows, oldest first
+    fn appended(&self) -> impl Iterator<Item = &Sheet> {
+        let id = self.entry.id;
+        self.file
+            .appended
+            .iter()
+            .filter(move |sheet| sheet.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::data_type::Type;
+    use crate::internal::data_value::Value;
+    use crate::internal::database::Database;
+    use std::io::Write;
+
+    fn orders() -> Database {
+        let mut sheet = Sheet::new("orders".to_string(), Vec::new(), None);
+        sheet.adopt_column(&Column::new("item".to_string(), Type::Str, None));
+        sheet.adopt_column(&Column::new("total".to_string(), Type::U32, None));
+        for (item, total) in [("tea", 4), ("cake", 12), ("scone", 7)] {
+            sheet
+                .insert_row(vec![Value::Str(item.to_string()), Value::Int(total)])
+                .unwrap();
+        }
+
+        let mut database = Database::new_empty();
+        database.adopt_sheet(&Sheet::new("customers".to_string(), Vec::new(), None));
+        database.adopt_sheet(&sheet);
+        database
+    }
+
+    fn write(bytes: &[u8]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        file
+    }
+
+    fn totals(file: &DatabaseFile) -> Vec<Value> {
+        let column = file.sheet("orders").unwrap().column("total").unwrap();
+        column.cells.iter().map(|cell| cell.value.clone()).collect()
+    }
+
+    #[test]
+    fn reads_one_column() {
+        let file = write(&orders().serialized_bytes());
+        let database = DatabaseFile::open(file.path()).unwrap();
+
+        assert_eq!(database.sheet_names(), vec!["customers", "orders"]);
+        let orders = database.sheet("orders").unwrap();
+        assert_eq!(orders.name(), "orders");
+        assert_eq!(orders.column_names(), vec!["item", "total"]);
+        assert_eq!(
+            totals(&database),
+            vec![Value::U32(4), Value::U32(12), Value::U32(7)]
+        );
+        assert!(matches!(
+            database.sheet("orders").unwrap().column("price"),
+            Err(Error::SheetError(SheetError::ColumnNotFound { .. }))
+        ));
+    }
+
+    #[test]
+    fn scans_files_without_an_index() {
+        let database = orders();
+        let expected = vec![Value::U32(4), Value::U32(12), Value::U32(7)];
+
+        let format = Format {
+            features: Format::current().features.without(Features::INDEX),
+            ..Format::current()
+        };
+        let file = write(&database.serialized_bytes_with(&format));
+        let opened = DatabaseFile::open(file.path()).unwrap();
+        assert_eq!(opened.format(), &format);
+        assert_eq!(totals(&opened), expected);
+
+        let file = write(&database.serialized_bytes_with(&Format::v1()));
+        let opened = DatabaseFile::open(file.path()).unwrap();
+        assert_eq!(opened.format(), &Format::v1());
+        assert_eq!(totals(&opened), expected);
+    }
+
+    #[test]
+    fn index_matches_a_scan() {
+        let bytes = orders().serialized_bytes();
+        let file = write(&bytes);
+        let indexed = DatabaseFile::open(file.path()).unwrap();
+
+        let mut deserializer = ByteDeserializer::new(&bytes);
+        let format = Format::read_header(&mut deserializer).unwrap();
+        let scanned = Index::locate(&bytes, deserializer.position(), &format).unwrap();
+        assert_eq!(indexed.index, scanned);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn reads_through_a_memory_map() {
+        let file = write(&orders().serialized_bytes());
+        let database = DatabaseFile::open_mapped(file.path()).unwrap();
+        assert_eq!(
+            totals(&database),
+            vec![Value::U32(4), Value::U32(12), Value::U32(7)]
+        );
+    }
+}
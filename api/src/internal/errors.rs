@@ -1,11 +1,27 @@
 use crate::internal::byte_deserializer::ByteError;
 use crate::internal::cell::CellError;
+use crate::internal::checksum::ChecksumError;
 use crate::internal::column::ColumnError;
+use crate::internal::computed::ComputedError;
 use crate::internal::data_type::TypeError;
 use crate::internal::data_value::ValueError;
 use crate::internal::database::DatabaseError;
+use crate::internal::expr::ExprError;
+use crate::internal::format::FormatError;
 use crate::internal::id::UuidError;
+use crate::internal::index::IndexError;
+use crate::internal::length_table::LengthTableError;
+use crate::internal::limits::LimitError;
+use crate::internal::pager::PagerError;
+use crate::internal::row_id::RowIdError;
+use crate::internal::segment::SegmentError;
 use crate::internal::sheet::SheetError;
+use crate::internal::sql::SqlError;
+use crate::internal::stream::StreamError;
+use crate::internal::transaction::TransactionError;
+use crate::internal::trigger::TriggerError;
+use crate::internal::view::ViewError;
+use crate::internal::wal::WalError;
 
 #[derive(Debug)]
 pub(crate) enum Error {
@@ -17,6 +33,23 @@ pub(crate) enum Error {
     SheetError(SheetError),
     DatabaseError(DatabaseError),
     ByteError(ByteError),
+    FormatError(FormatError),
+    ChecksumError(ChecksumError),
+    IndexError(IndexError),
+    StreamError(StreamError),
+    LimitError(LimitError),
+    LengthTableError(LengthTableError),
+    WalError(WalError),
+    SegmentError(SegmentError),
+    PagerError(PagerError),
+    TransactionError(TransactionError),
+    TriggerError(TriggerError),
+    ExprError(ExprError),
+    ComputedError(ComputedError),
+    SqlError(SqlError),
+    ViewError(ViewError),
+    RowIdError(RowIdError),
+    Io(std::io::Error),
 }
 
 impl std::fmt::Display for Error {
@@ -30,6 +63,39 @@ impl std::fmt::Display for Error {
             Error::SheetError(err) => write!(f, "{}", err),
             Error::DatabaseError(err) => write!(f, "{}", err),
             Error::ByteError(err) => write!(f, "{}", err),
+            Error::FormatError(err) => write!(f, "{}", err),
+            Error::ChecksumError(err) => write!(f, "{}", err),
+            Error::IndexError(err) => write!(f, "{}", err),
+            Error::StreamError(err) => write!(f, "{}", err),
+            Error::LimitError(err) => write!(f, "{}", err),
+            Error::LengthTableError(err) => write!(f, "{}", err),
+            Error::WalError(err) => write!(f, "{}", err),
+            Error::SegmentError(err) => write!(f, "{}", err),
+            Error::PagerError(err) => write!(f, "{}", err),
+            Error::TransactionError(err) => write!(f, "{}", err),
+            Error::TriggerError(err) => write!(f, "{}", err),
+            Error::ExprError(err) => write!(f, "{}", err),
+            Error::ComputedError(err) => write!(f, "{}", err),
+            Error::SqlError(err) => write!(f, "{}", err),
+            Error::ViewError(err) => write!(f, "{}", err),
+            Error::RowIdError(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error {
+    /// Adds `offset` to the position of an invalid string, for a decoder that
+    /// passed bytes starting at `offset` of its own to a nested decoder
+    pub(crate) fn offset_by(self, offset: usize) -> Error {
+        match self {
+            Error::ByteError(ByteError::InvalidUtf8 { pos, bytes }) => {
+                Error::ByteError(ByteError::InvalidUtf8 {
+                    pos: pos + offset,
+                    bytes,
+                })
+            }
+            err => err,
         }
     }
 }
@@ -0,0 +1,1378 @@
+use crate::internal::column::Column;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::{ArithOp, Number, Value};
+use crate::internal::errors::Error;
+use crate::internal::sheet::Sheet;
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// An expression over the columns of one row, e.g. `price * qty` or
+/// `lower(name) = "ada" and age is not null`. Parsed from text, and written
+/// back out the same way by Display, which is how it's stored.
+///
+/// - literals: `nil` (or `null`), `true`, `false`, "strings" (or 'strings'),
+///   and numbers, which are Int or Flt unless suffixed with their type, e.g.
+///   `5u8`, `-3i128` or `1.5f32`
+/// - columns, by name, in backticks if the name isn't a plain word (with any
+///   backtick in it doubled), and `rowid`, the row's id, in a sheet without a
+///   column of that name. A word, a dot and a name is one name with a dot in
+///   it, as triggers name the row before and after a change, e.g. `new.price`.
+/// - `+ - * / %` and unary `-`, following Value::arith: both sides are
+///   widened to a common type, and overflow or division by zero is an error
+/// - `= != < <= > >=` (also `==` and `<>`), comparing numbers by value
+/// - `and`, `or` and `not`, and `is null` / `is not null`
+/// - functions: lower, upper, length, substr(text, start, [count]) counting
+///   from 1, concat, abs, round, floor, ceil, sqrt, pow, min and max
+///
+/// Keywords are case-insensitive. Nil stands for a missing value, as NULL
+/// does in SQL: anything computed from it is nil, except that `false and nil`
+/// is false, `true or nil` is true, and `is null` is true.
+#[derive(Debug, Clone)]
+pub(crate) struct Expr {
+    pub(crate) kind: Kind,
+    // where in the source it was parsed from, for errors
+    pub(crate) span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Kind {
+    Literal(Value),
+    Column(String),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    IsNull {
+        inner: Box<Expr>,
+        negated: bool,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Call {
+        function: Function,
+        args: Vec<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Arith(ArithOp),
+    Compare(CompareOp),
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Function {
+    Lower,
+    Upper,
+    Length,
+    Substr,
+    Concat,
+    Abs,
+    Round,
+    Floor,
+    Ceil,
+    Sqrt,
+    Pow,
+    Min,
+    Max,
+}
+
+const FUNCTIONS: [(&str, Function); 13] = [
+    ("lower", Function::Lower),
+    ("upper", Function::Upper),
+    ("length", Function::Length),
+    ("substr", Function::Substr),
+    ("concat", Function::Concat),
+    ("abs", Function::Abs),
+    ("round", Function::Round),
+    ("floor", Function::Floor),
+    ("ceil", Function::Ceil),
+    ("sqrt", Function::Sqrt),
+    ("pow", Function::Pow),
+    ("min", Function::Min),
+    ("max", Function::Max),
+];
+
+// the suffixes of typed number literals
+const SUFFIXES: [(&str, Type); 11] = [
+    ("i8", Type::I8),
+    ("i16", Type::I16),
+    ("i32", Type::I32),
+    ("i64", Type::Int),
+    ("i128", Type::I128),
+    ("u8", Type::U8),
+    ("u16", Type::U16),
+    ("u32", Type::U32),
+    ("u64", Type::U64),
+    ("f32", Type::F32),
+    ("f64", Type::Flt),
+];
+
+// how tightly each kind of expression binds, see BinaryOp::precedence
+const NOT: u8 = 3;
+const IS: u8 = 4;
+const NEGATE: u8 = 7;
+const ATOM: u8 = 8;
+
+// how deeply expressions may nest, so parsing, checking, evaluating and
+// writing them, which all recurse, can't run out of stack
+const MAX_DEPTH: usize = 64;
+
+// parentheses nest the parser without nesting the expression. Writing an
+// expression adds at most two levels of parser nesting per level of depth.
+const MAX_NESTING: usize = 2 * MAX_DEPTH + 1;
+
+// the same expression, wherever it was parsed from
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Expr {
+    pub(crate) fn parse(source: &str) -> Result<Expr, Error> {
+        let mut parser = Parser::new(source, &[])?;
+        let expr = parser.expression()?;
+        parser.finish()?;
+        Ok(expr)
+    }
+
+    /// The names of the columns the expression reads
+    pub(crate) fn columns(&self) -> Vec<&str> {
+        match &self.kind {
+            Kind::Literal(_) => Vec::new(),
+            Kind::Column(name) => vec![name],
+            Kind::Negate(inner) | Kind::Not(inner) | Kind::IsNull { inner, .. } => inner.columns(),
+            Kind::Binary { left, right, .. } => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Kind::Call { args, .. } => args.iter().flat_map(Expr::columns).collect(),
+        }
+    }
+
+    /// The type of the expression's values, given the type of each column.
+    /// Unknown if it's always Nil.
+    pub(crate) fn check(&self, schema: &dyn Fn(&str) -> Option<Type>) -> Result<Type, Error> {
+        match &self.kind {
+            Kind::Literal(value) => Ok(value.get_type()),
+            Kind::Column(name) => schema(name).ok_or_else(|| self.unknown_column(name)),
+            Kind::Negate(inner) => match inner.check(schema)? {
+                found if found.is_signed() || found == Type::Unknown => Ok(found),
+                found => mismatch(inner.span.clone(), format!("can't negate {:?}", found)),
+            },
+            Kind::Not(inner) => {
+                inner.expect(inner.check(schema)?, Type::Bool)?;
+                Ok(Type::Bool)
+            }
+            Kind::IsNull { inner, .. } => {
+                inner.check(schema)?;
+                Ok(Type::Bool)
+            }
+            Kind::Binary { op, left, right } => {
+                let (left_type, right_type) = (left.check(schema)?, right.check(schema)?);
+                match op {
+                    BinaryOp::Arith(_) => {
+                        left.expect_number(left_type)?;
+                        right.expect_number(right_type)?;
+                        Ok(widen(left_type, right_type).unwrap_or(Type::Unknown))
+                    }
+                    BinaryOp::Compare(_) => {
+                        self.expect_comparable(left_type, right_type)?;
+                        Ok(Type::Bool)
+                    }
+                    BinaryOp::And | BinaryOp::Or => {
+                        left.expect(left_type, Type::Bool)?;
+                        right.expect(right_type, Type::Bool)?;
+                        Ok(Type::Bool)
+                    }
+                }
+            }
+            Kind::Call { function, args } => self.check_call(*function, args, schema),
+        }
+    }
+
+    fn check_call(
+        &self,
+        function: Function,
+        args: &[Expr],
+        schema: &dyn Fn(&str) -> Option<Type>,
+    ) -> Result<Type, Error> {
+        let types = args
+            .iter()
+            .map(|arg| arg.check(schema))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let (least, most) = function.arity();
+        if types.len() < least || types.len() > most {
+            return mismatch(
+                self.span.clone(),
+                format!("{} can't take {} arguments", function.name(), types.len()),
+            );
+        }
+
+        let each = |check: fn(&Expr, Type) -> Result<(), Error>| {
+            args.iter()
+                .zip(&types)
+                .try_for_each(|(arg, found)| check(arg, *found))
+        };
+        match function {
+            Function::Lower | Function::Upper | Function::Concat => {
+                each(|arg, found| arg.expect(found, Type::Str))?;
+                Ok(Type::Str)
+            }
+            Function::Length => {
+                args[0].expect(types[0], Type::Str)?;
+                Ok(Type::Int)
+            }
+            Function::Substr => {
+                args[0].expect(types[0], Type::Str)?;
+                for (arg, found) in args.iter().zip(&types).skip(1) {
+                    if !found.is_integer() && *found != Type::Unknown {
+                        return mismatch(
+                            arg.span.clone(),
+                            format!("expected an integer, not {:?}", found),
+                        );
+                    }
+                }
+                Ok(Type::Str)
+            }
+            Function::Abs | Function::Round | Function::Floor | Function::Ceil => {
+                args[0].expect_number(types[0])?;
+                Ok(types[0])
+            }
+            Function::Sqrt | Function::Pow => {
+                each(Expr::expect_number)?;
+                Ok(Type::Flt)
+            }
+            Function::Min | Function::Max => {
+                self.expect_comparable(types[0], types[1])?;
+                Ok(widen(types[0], types[1]).unwrap_or(types[0]))
+            }
+        }
+    }
+
+    fn expect(&self, found: Type, expected: Type) -> Result<(), Error> {
+        match found == expected || found == Type::Unknown {
+            true => Ok(()),
+            false => mismatch(
+                self.span.clone(),
+                format!("expected {:?}, not {:?}", expected, found),
+            ),
+        }
+    }
+
+    fn expect_number(&self, found: Type) -> Result<(), Error> {
+        match found.is_numeric() || found == Type::Unknown {
+            true => Ok(()),
+            false => mismatch(
+                self.span.clone(),
+                format!("expected a number, not {:?}", found),
+            ),
+        }
+    }
+
+    fn expect_comparable(&self, left: Type, right: Type) -> Result<(), Error> {
+        let comparable = left == right
+            || left == Type::Unknown
+            || right == Type::Unknown
+            || (left.is_numeric() && right.is_numeric());
+        match comparable {
+            true => Ok(()),
+            false => mismatch(
+                self.span.clone(),
+                format!("can't compare {:?} with {:?}", left, right),
+            ),
+        }
+    }
+
+    /// The value of the expression for a row, given the value of each column
+    pub(crate) fn evaluate(&self, row: &dyn Fn(&str) -> Option<Value>) -> Result<Value, Error> {
+        match &self.kind {
+            Kind::Literal(value) => Ok(value.clone()),
+            Kind::Column(name) => row(name).ok_or_else(|| self.unknown_column(name)),
+            Kind::Negate(inner) => negate(&inner.evaluate(row)?),
+            Kind::Not(inner) => Ok(match inner.truth(inner.evaluate(row)?)? {
+                Some(truth) => Value::Bool(!truth),
+                None => Value::Nil,
+            }),
+            Kind::IsNull { inner, negated } => Ok(Value::Bool(
+                (inner.evaluate(row)? == Value::Nil) != *negated,
+            )),
+            Kind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                left,
+                right,
+            } => {
+                // false and anything is false, true or anything is true, even nil
+                let decisive = *op == BinaryOp::Or;
+                let left = left.truth(left.evaluate(row)?)?;
+                if left == Some(decisive) {
+                    return Ok(Value::Bool(decisive));
+                }
+                Ok(match (left, right.truth(right.evaluate(row)?)?) {
+                    (_, Some(truth)) if truth == decisive => Value::Bool(decisive),
+                    (Some(_), Some(_)) => Value::Bool(!decisive),
+                    _ => Value::Nil,
+                })
+            }
+            Kind::Binary { op, left, right } => {
+                let (left, right) = (left.evaluate(row)?, right.evaluate(row)?);
+                match op {
+                    BinaryOp::Arith(op) => left.arith(*op, &right),
+                    BinaryOp::Compare(_) if left == Value::Nil || right == Value::Nil => {
+                        Ok(Value::Nil)
+                    }
+                    BinaryOp::Compare(op) => Ok(Value::Bool(op.holds(left.cmp(&right)))),
+                    BinaryOp::And | BinaryOp::Or => unreachable!("handled above"),
+                }
+            }
+            Kind::Call { function, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.evaluate(row))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                self.call(*function, &args)
+            }
+        }
+    }
+
+    fn call(&self, function: Function, args: &[Value]) -> Result<Value, Error> {
+        if args.contains(&Value::Nil) {
+            return Ok(Value::Nil);
+        }
+        let wrong = |index: usize| {
+            Error::ExprError(ExprError::TypeMismatch {
+                span: self.span.clone(),
+                reason: format!(
+                    "{} can't take {:?}",
+                    function.name(),
+                    args[index].get_type()
+                ),
+            })
+        };
+        let text = |index: usize| match &args[index] {
+            Value::Str(text) => Ok(text.as_str()),
+            _ => Err(wrong(index)),
+        };
+        let number = |index: usize| args[index].as_number().ok_or_else(|| wrong(index));
+        let count = |index: usize| match number(index)? {
+            Number::Int(int) => Ok(usize::try_from(int.max(0)).unwrap_or(usize::MAX)),
+            Number::Flt(_) => Err(wrong(index)),
+        };
+
+        Ok(match function {
+            Function::Lower => Value::Str(text(0)?.to_lowercase()),
+            Function::Upper => Value::Str(text(0)?.to_uppercase()),
+            Function::Length => Value::Int(text(0)?.chars().count() as i64),
+            Function::Substr => {
+                let chars = text(0)?.chars().skip(count(1)?.saturating_sub(1));
+                match args.len() {
+                    3 => Value::Str(chars.take(count(2)?).collect()),
+                    _ => Value::Str(chars.collect()),
+                }
+            }
+            Function::Concat => Value::Str((0..args.len()).map(text).collect::<Result<_, _>>()?),
+            Function::Abs => match number(0)? {
+                Number::Int(int) if int < 0 => negate(&args[0])?,
+                Number::Flt(float) if float < 0.0 => negate(&args[0])?,
+                _ => args[0].clone(),
+            },
+            Function::Round | Function::Floor | Function::Ceil => {
+                let rounded = |float: f64| match function {
+                    Function::Round => float.round(),
+                    Function::Floor => float.floor(),
+                    _ => float.ceil(),
+                };
+                match &args[0] {
+                    Value::Flt(float) => Value::Flt(rounded(*float)),
+                    Value::F32(float) => Value::F32(rounded(*float as f64) as f32),
+                    integer => {
+                        number(0)?;
+                        integer.clone()
+                    }
+                }
+            }
+            Function::Sqrt => Value::Flt(number(0)?.as_f64().sqrt()),
+            Function::Pow => Value::Flt(number(0)?.as_f64().powf(number(1)?.as_f64())),
+            Function::Min | Function::Max => {
+                let (a, b) = (&args[0], &args[1]);
+                let picked = match (a <= b) == (function == Function::Min) {
+                    true => a,
+                    false => b,
+                };
+                match widen(a.get_type(), b.get_type()) {
+                    Some(common) if common.is_numeric() => picked.cast_to(common)?,
+                    _ => picked.clone(),
+                }
+            }
+        })
+    }
+
+    // a value used as a condition: None for nil
+    fn truth(&self, value: Value) -> Result<Option<bool>, Error> {
+        match value {
+            Value::Bool(truth) => Ok(Some(truth)),
+            Value::Nil => Ok(None),
+            other => mismatch(
+                self.span.clone(),
+                format!("expected Bool, not {:?}", other.get_type()),
+            ),
+        }
+    }
+
+    fn unknown_column(&self, name: &str) -> Error {
+        Error::ExprError(ExprError::UnknownColumn {
+            span: self.span.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    /// Checks the expression against the columns of `sheet`, see check.
+    /// `rowid` is the row's id, see row_id.rs, unless there's a column of
+    /// that name.
+    pub(crate) fn check_in(&self, sheet: &Sheet) -> Result<Type, Error> {
+        self.check(&|name| match sheet.is_row_id(name) {
+            true => Some(Type::U64),
+            false => sheet
+                .get_column_by_name(name)
+                .map(|column| column.value_type),
+        })
+    }
+
+    /// The value of the expression for a row of `sheet`
+    pub(crate) fn evaluate_in(&self, sheet: &Sheet, row: usize) -> Result<Value, Error> {
+        self.evaluate(&|name| {
+            if sheet.is_row_id(name) {
+                return Some(sheet.row_id(row).map_or(Value::Nil, Value::U64));
+            }
+            let column = sheet.get_column_by_name(name)?;
+            Some(
+                column
+                    .get_cell(row)
+                    .map_or(Value::Nil, |cell| cell.get_value().clone()),
+            )
+        })
+    }
+}
+
+impl Sheet {
+    /// The rows `filter` is true for, in order. Rows it's false or nil for
+    /// are left out.
+    pub(crate) fn rows_where(&self, filter: &Expr) -> Result<Vec<usize>, Error> {
+        filter.expect(filter.check_in(self)?, Type::Bool)?;
+        if let Some(row) = self.row_picked_by(filter) {
+            return Ok(row.into_iter().collect());
+        }
+        let rows = self.columns.first().map_or(0, Column::get_row_count);
+        let mut matched = Vec::new();
+        for row in 0..rows {
+            if let Value::Bool(true) = filter.evaluate_in(self, row)? {
+                matched.push(row);
+            }
+        }
+        Ok(matched)
+    }
+}
+
+// the type two operands are widened to, where nil goes with anything
+fn widen(left: Type, right: Type) -> Option<Type> {
+    match (left, right) {
+        (Type::Unknown, other) | (other, Type::Unknown) => Some(other),
+        _ => Type::widen(left, right),
+    }
+}
+
+fn negate(value: &Value) -> Result<Value, Error> {
+    match value {
+        Value::Nil => Ok(Value::Nil),
+        // zero of the same type, or a float zero that widens to it
+        value => Value::from_i128(0, value.get_type())
+            .unwrap_or(Value::F32(0.0))
+            .arith(ArithOp::Sub, value),
+    }
+}
+
+fn syntax<T>(span: Range<usize>, reason: impl Into<String>) -> Result<T, Error> {
+    Err(Error::ExprError(ExprError::Syntax {
+        span,
+        reason: reason.into(),
+    }))
+}
+
+fn mismatch<T>(span: Range<usize>, reason: String) -> Result<T, Error> {
+    Err(Error::ExprError(ExprError::TypeMismatch { span, reason }))
+}
+
+impl BinaryOp {
+    // how tightly the operator binds: or, and, comparisons, + -, * / %
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Compare(_) => 4,
+            BinaryOp::Arith(ArithOp::Add | ArithOp::Sub) => 5,
+            BinaryOp::Arith(_) => 6,
+        }
+    }
+
+    fn of(token: &Token) -> Option<BinaryOp> {
+        let symbol = match token {
+            Token::Symbol(symbol) => *symbol,
+            token if keyword(token, "and") => "and",
+            token if keyword(token, "or") => "or",
+            _ => return None,
+        };
+        Some(match symbol {
+            "+" => BinaryOp::Arith(ArithOp::Add),
+            "-" => BinaryOp::Arith(ArithOp::Sub),
+            "*" => BinaryOp::Arith(ArithOp::Mul),
+            "/" => BinaryOp::Arith(ArithOp::Div),
+            "%" => BinaryOp::Arith(ArithOp::Rem),
+            "=" | "==" => BinaryOp::Compare(CompareOp::Eq),
+            "!=" | "<>" => BinaryOp::Compare(CompareOp::Ne),
+            "<" => BinaryOp::Compare(CompareOp::Lt),
+            "<=" => BinaryOp::Compare(CompareOp::Le),
+            ">" => BinaryOp::Compare(CompareOp::Gt),
+            ">=" => BinaryOp::Compare(CompareOp::Ge),
+            "and" => BinaryOp::And,
+            "or" => BinaryOp::Or,
+            _ => return None,
+        })
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Arith(ArithOp::Add) => "+",
+            BinaryOp::Arith(ArithOp::Sub) => "-",
+            BinaryOp::Arith(ArithOp::Mul) => "*",
+            BinaryOp::Arith(ArithOp::Div) => "/",
+            BinaryOp::Arith(ArithOp::Rem) => "%",
+            BinaryOp::Compare(CompareOp::Eq) => "=",
+            BinaryOp::Compare(CompareOp::Ne) => "!=",
+            BinaryOp::Compare(CompareOp::Lt) => "<",
+            BinaryOp::Compare(CompareOp::Le) => "<=",
+            BinaryOp::Compare(CompareOp::Gt) => ">",
+            BinaryOp::Compare(CompareOp::Ge) => ">=",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+        }
+    }
+}
+
+impl CompareOp {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl Function {
+    fn named(name: &str) -> Option<Function> {
+        FUNCTIONS
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|(_, function)| *function)
+    }
+
+    fn name(self) -> &'static str {
+        FUNCTIONS
+            .iter()
+            .find(|(_, function)| *function == self)
+            .map_or("", |(name, _)| name)
+    }
+
+    // how many arguments it takes, at least and at most
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Function::Substr => (2, 3),
+            Function::Concat => (1, usize::MAX),
+            Function::Pow | Function::Min | Function::Max => (2, 2),
+            _ => (1, 1),
+        }
+    }
+}
+
+/* -- WRITING -- */
+
+impl Expr {
+    // how tightly the expression binds, see BinaryOp::precedence
+    fn precedence(&self) -> u8 {
+        match &self.kind {
+            Kind::Binary { op, .. } => op.precedence(),
+            Kind::IsNull { .. } => IS,
+            Kind::Not(_) => NOT,
+            Kind::Negate(_) => NEGATE,
+            _ => ATOM,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut std::fmt::Formatter<'_>, parenthesise: bool) -> std::fmt::Result {
+        match parenthesise {
+            true => write!(f, "({})", self),
+            false => write!(f, "{}", self),
+        }
+    }
+}
+
+impl std::fmt::Display for Expr {
+    // parenthesised only where needed, and parses back to the same expression
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            Kind::Literal(value) => fmt_literal(value, f),
+            Kind::Column(name) => write!(f, "{}", Name(name)),
+            Kind::Negate(inner) => {
+                write!(f, "-")?;
+                // -5 would be read back as a negative literal
+                let number =
+                    matches!(&inner.kind, Kind::Literal(value) if value.as_number().is_some());
+                inner.fmt_operand(f, inner.precedence() < NEGATE || number)
+            }
+            Kind::Not(inner) => {
+                write!(f, "not ")?;
+                inner.fmt_operand(f, inner.precedence() < NOT)
+            }
+            Kind::IsNull { inner, negated } => {
+                inner.fmt_operand(f, inner.precedence() < IS)?;
+                match negated {
+                    true => write!(f, " is not null"),
+                    false => write!(f, " is null"),
+                }
+            }
+            Kind::Binary { op, left, right } => {
+                // operators of the same precedence group to the left
+                left.fmt_operand(f, left.precedence() < op.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                right.fmt_operand(f, right.precedence() <= op.precedence())
+            }
+            Kind::Call { function, args } => {
+                write!(f, "{}(", function.name())?;
+                for (n, arg) in args.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// A sheet or column name as it's written in expressions and statements:
+/// plain if it's a word that isn't a keyword, or such a word, a dot and a
+/// name, and otherwise in backticks
+pub(crate) struct Name<'a>(pub(crate) &'a str);
+
+impl std::fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let word = |name: &str| {
+            name.chars()
+                .next()
+                .is_some_and(|first| first.is_alphabetic() || first == '_')
+                && name
+                    .chars()
+                    .all(|char| char.is_alphanumeric() || char == '_')
+                && !KEYWORDS.iter().any(|word| word.eq_ignore_ascii_case(name))
+        };
+        let quoted = |name: &str| format!("`{}`", name.replace('`', "``"));
+        match self.0.split_once('.') {
+            _ if word(self.0) => write!(f, "{}", self.0),
+            Some((first, rest)) if word(first) => match word(rest) {
+                true => write!(f, "{}.{}", first, rest),
+                false => write!(f, "{}.{}", first, quoted(rest)),
+            },
+            _ => write!(f, "{}", quoted(self.0)),
+        }
+    }
+}
+
+fn fmt_literal(value: &Value, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match value {
+        Value::Nil => write!(f, "nil"),
+        Value::Bool(bool) => write!(f, "{}", bool),
+        Value::Str(string) => {
+            write!(
+                f,
+                "\"{}\"",
+                string.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        }
+        Value::Flt(float) if float.is_finite() => write!(f, "{:?}", float),
+        Value::F32(float) if float.is_finite() => write!(f, "{:?}f32", float),
+        // there's no literal for infinity or NaN, so divide by zero
+        Value::Flt(float) => fmt_non_finite(*float, "", f),
+        Value::F32(float) => fmt_non_finite(*float as f64, "f32", f),
+        Value::Int(int) => write!(f, "{}", int),
+        other => {
+            let suffix = SUFFIXES
+                .iter()
+                .find(|(_, value_type)| *value_type == other.get_type())
+                .map_or("", |(suffix, _)| suffix);
+            match other.as_number() {
+                Some(Number::Int(int)) => write!(f, "{}{}", int, suffix),
+                _ => write!(f, "{:?}", other),
+            }
+        }
+    }
+}
+
+fn fmt_non_finite(float: f64, suffix: &str, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let dividend = match float {
+        float if float.is_nan() => "0.0",
+        float if float > 0.0 => "1.0",
+        _ => "-1.0",
+    };
+    write!(f, "({}{} / 0.0{})", dividend, suffix, suffix)
+}
+
+/* -- PARSING -- */
+
+// words that aren't column names
+const KEYWORDS: [&str; 8] = ["nil", "null", "true", "false", "and", "or", "not", "is"];
+
+// longest first, so `<=` isn't read as `<` and `=`. `?` and `;` are only
+// used by statements, see sql.rs, and `:` by triggers, see trigger.rs.
+const SYMBOLS: [&str; 20] = [
+    "<=", ">=", "!=", "<>", "==", "+", "-", "*", "/", "%", "(", ")", ",", "=", "<", ">", "?", ";",
+    ":", ".",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    // in backticks, so never a keyword
+    Quoted(String),
+    Str(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+fn keyword(token: &Token, word: &str) -> bool {
+    matches!(token, Token::Word(found) if found.eq_ignore_ascii_case(word))
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, Range<usize>)>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, char)) = chars.peek() {
+        let token = if char.is_whitespace() {
+            chars.next();
+            continue;
+        } else if char.is_alphabetic() || char == '_' {
+            let mut word = String::new();
+            while let Some(&(_, char)) = chars
+                .peek()
+                .filter(|(_, c)| c.is_alphanumeric() || *c == '_')
+            {
+                word.push(char);
+                chars.next();
+            }
+            Token::Word(word)
+        } else if char.is_ascii_digit() {
+            let mut number = String::new();
+            // with an exponent or a type suffix, e.g. 1e-7 or 5u8
+            while let Some(&(_, char)) = chars.peek().filter(|(_, c)| {
+                c.is_ascii_alphanumeric()
+                    || *c == '.'
+                    || ("+-".contains(*c) && number.ends_with(['e', 'E']))
+            }) {
+                number.push(char);
+                chars.next();
+            }
+            Token::Number(number)
+        } else if char == '`' || char == '"' || char == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) if char != '`' => match chars.next() {
+                        Some((_, escaped)) => text.push(escaped),
+                        None => return syntax(start..source.len(), "unterminated string"),
+                    },
+                    // a backtick in a name is doubled
+                    Some((_, '`'))
+                        if char == '`' && chars.peek().is_some_and(|(_, c)| *c == '`') =>
+                    {
+                        chars.next();
+                        text.push('`');
+                    }
+                    Some((_, end)) if end == char => break,
+                    Some((_, other)) => text.push(other),
+                    None => return syntax(start..source.len(), "missing closing quote"),
+                }
+            }
+            match char {
+                '`' => Token::Quoted(text),
+                _ => Token::Str(text),
+            }
+        } else if let Some(symbol) = SYMBOLS
+            .iter()
+            .find(|symbol| source[start..].starts_with(**symbol))
+        {
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            Token::Symbol(symbol)
+        } else {
+            return syntax(start..start + char.len_utf8(), "unexpected character");
+        };
+        let end = chars.peek().map_or(source.len(), |(end, _)| *end);
+        tokens.push((token, start..end));
+    }
+    Ok(tokens)
+}
+
+// a number token, e.g. 12, 1.5e3 or 200u8, negated if it followed a `-`
+fn number(text: &str, negative: bool, span: Range<usize>) -> Result<Value, Error> {
+    let (digits, suffix) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, value_type)| {
+            text.strip_suffix(suffix)
+                .map(|digits| (digits, Some(*value_type)))
+        })
+        .unwrap_or((text, None));
+    let digits = match negative {
+        true => format!("-{}", digits),
+        false => digits.to_string(),
+    };
+    let float = digits.contains(['.', 'e', 'E']);
+    let value = match suffix {
+        None if float => digits.parse().ok().map(Value::Flt),
+        None => digits.parse().ok().map(Value::Int),
+        Some(Type::Flt) => digits.parse().ok().map(Value::Flt),
+        Some(Type::F32) => digits.parse().ok().map(Value::F32),
+        Some(_) if float => None,
+        Some(value_type) => digits
+            .parse()
+            .ok()
+            .and_then(|int| Value::from_i128(int, value_type)),
+    };
+    value.map_or_else(|| syntax(span, "not a number, or out of range"), Ok)
+}
+
+/// Reads expressions from a list of tokens, on its own or as part of a
+/// statement, see sql.rs. Each `?` stands for the next of `params`.
+pub(crate) struct Parser<'a> {
+    tokens: Vec<(Token, Range<usize>)>,
+    position: usize,
+    // length of the source, where an unexpected end is reported
+    end: usize,
+    params: &'a [Value],
+    bound: usize,
+    // calls to expr under way
+    nesting: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(source: &str, params: &'a [Value]) -> Result<Parser<'a>, Error> {
+        Ok(Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            end: source.len(),
+            params,
+            bound: 0,
+            nesting: 0,
+        })
+    }
+
+    pub(crate) fn expression(&mut self) -> Result<Expr, Error> {
+        Ok(self.expr(0)?.0)
+    }
+
+    /// Fails unless every token and every parameter has been used
+    pub(crate) fn finish(&self) -> Result<(), Error> {
+        if let Some((_, span)) = self.tokens.get(self.position) {
+            return syntax(span.clone(), "expected the end");
+        }
+        match self.bound < self.params.len() {
+            true => syntax(
+                self.end..self.end,
+                format!(
+                    "{} parameters given, but only {} used",
+                    self.params.len(),
+                    self.bound
+                ),
+            ),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether the next token is the keyword `word`, moving past it if so
+    pub(crate) fn eat(&mut self, word: &str) -> bool {
+        let found = self.peek().is_some_and(|token| keyword(token, word));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    /// Whether the next token is `symbol`, moving past it if so
+    pub(crate) fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(found)) if *found == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    /// Whether the next two tokens are the keywords `first` and `second`,
+    /// moving past them if so
+    pub(crate) fn eat_both(&mut self, first: &str, second: &str) -> bool {
+        let found = match self.tokens.get(self.position..self.position + 2) {
+            Some([(a, _), (b, _)]) => keyword(a, first) && keyword(b, second),
+            _ => false,
+        };
+        if found {
+            self.position += 2;
+        }
+        found
+    }
+
+    pub(crate) fn keyword(&mut self, word: &str) -> Result<(), Error> {
+        match self.next()? {
+            (token, _) if keyword(&token, word) => Ok(()),
+            (_, span) => syntax(span, format!("expected {}", word.to_uppercase())),
+        }
+    }
+
+    /// A syntax error at the next token
+    pub(crate) fn unexpected<T>(&self, reason: &str) -> Result<T, Error> {
+        match self.tokens.get(self.position) {
+            Some((_, span)) => syntax(span.clone(), reason),
+            None => syntax(self.end..self.end, reason),
+        }
+    }
+
+    /// The name of a sheet or column, a plain word or in backticks, or a word,
+    /// a dot and either
+    pub(crate) fn name(&mut self) -> Result<String, Error> {
+        match self.next()? {
+            (Token::Word(word), _) => self.rest_of_name(word),
+            (Token::Quoted(name), _) => Ok(name),
+            (_, span) => syntax(span, "expected a name"),
+        }
+    }
+
+    // a name that started with `word`
+    fn rest_of_name(&mut self, word: String) -> Result<String, Error> {
+        if !self.eat_symbol(".") {
+            return Ok(word);
+        }
+        match self.next()? {
+            (Token::Word(rest) | Token::Quoted(rest), _) => Ok(format!("{}.{}", word, rest)),
+            (_, span) => syntax(span, "expected a name"),
+        }
+    }
+
+    /// Whether every token has been read
+    pub(crate) fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<(Token, Range<usize>), Error> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token.map_or_else(|| syntax(self.end..self.end, "unexpected end"), Ok)
+    }
+
+    pub(crate) fn expect(&mut self, symbol: &str) -> Result<Range<usize>, Error> {
+        match self.next()? {
+            (Token::Symbol(found), span) if found == symbol => Ok(span),
+            (_, span) => syntax(span, format!("expected {}", symbol)),
+        }
+    }
+
+    // operators that bind tighter than `precedence`, see BinaryOp::precedence,
+    // and how deeply the expression nests
+    fn expr(&mut self, precedence: u8) -> Result<(Expr, usize), Error> {
+        if self.nesting == MAX_NESTING {
+            return self.unexpected("nested too deeply");
+        }
+        self.nesting += 1;
+        let parsed = self.operators(precedence);
+        self.nesting -= 1;
+        parsed
+    }
+
+    fn operators(&mut self, precedence: u8) -> Result<(Expr, usize), Error> {
+        let mut parsed = self.prefix()?;
+        while let Some(token) = self.peek() {
+            if keyword(token, "is") && IS > precedence {
+                parsed = self.is_null(parsed)?;
+                continue;
+            }
+            let Some(op) = BinaryOp::of(token).filter(|op| op.precedence() > precedence) else {
+                break;
+            };
+            self.position += 1;
+            parsed = self.binary(op, parsed)?;
+        }
+        Ok(parsed)
+    }
+
+    // `is [not] null` after an expression
+    fn is_null(&mut self, (inner, depth): (Expr, usize)) -> Result<(Expr, usize), Error> {
+        self.position += 1;
+        let negated = self.eat("not");
+        let (token, end) = self.next()?;
+        if !keyword(&token, "null") && !keyword(&token, "nil") {
+            return syntax(end, "expected null");
+        }
+        let expr = Expr {
+            span: inner.span.start..end.end,
+            kind: Kind::IsNull {
+                inner: Box::new(inner),
+                negated,
+            },
+        };
+        Ok((expr, nest(depth, &end)?))
+    }
+
+    // an operator's right operand, after the operator
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        (left, depth): (Expr, usize),
+    ) -> Result<(Expr, usize), Error> {
+        let (right, right_depth) = self.expr(op.precedence())?;
+        let depth = nest(depth.max(right_depth), &right.span)?;
+        let expr = Expr {
+            span: left.span.start..right.span.end,
+            kind: Kind::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+        };
+        Ok((expr, depth))
+    }
+
+    // a value, or an expression made of a prefix operator and what follows.
+    // Everything that doesn't recurse is left to `value`, to keep the stack
+    // each level of nesting takes small.
+    fn prefix(&mut self) -> Result<(Expr, usize), Error> {
+        let (token, span) = self.next()?;
+        let (end, kind, depth) = match token {
+            Token::Symbol("(") => {
+                let (inner, depth) = self.expr(0)?;
+                let end = self.expect(")")?;
+                let expr = Expr {
+                    span: span.start..end.end,
+                    kind: inner.kind,
+                };
+                return Ok((expr, depth));
+            }
+            // a negative number is one literal, so e.g. -128i8 fits
+            Token::Symbol("-") if !matches!(self.peek(), Some(Token::Number(_))) => {
+                // no operator binds tighter, so this is just the operand
+                let (inner, depth) = self.expr(NEGATE)?;
+                (inner.span.end, Kind::Negate(Box::new(inner)), depth)
+            }
+            Token::Word(word) if word.eq_ignore_ascii_case("not") => {
+                let (inner, depth) = self.expr(NOT)?;
+                (inner.span.end, Kind::Not(Box::new(inner)), depth)
+            }
+            Token::Word(word) if self.peek() == Some(&Token::Symbol("(")) => {
+                match Function::named(&word) {
+                    Some(function) => return self.call(function, span),
+                    None => return Ok((self.value(Token::Word(word), span)?, 0)),
+                }
+            }
+            token => return Ok((self.value(token, span)?, 0)),
+        };
+        let depth = nest(depth, &span)?;
+        let expr = Expr {
+            span: span.start..end,
+            kind,
+        };
+        Ok((expr, depth))
+    }
+
+    // a literal, a parameter or a column
+    fn value(&mut self, token: Token, span: Range<usize>) -> Result<Expr, Error> {
+        let kind = match token {
+            Token::Symbol("-") => {
+                let (text, end) = match self.next()? {
+                    (Token::Number(text), end) => (text, end),
+                    (_, span) => return syntax(span, "expected a number"),
+                };
+                return Ok(Expr {
+                    kind: Kind::Literal(number(&text, true, span.start..end.end)?),
+                    span: span.start..end.end,
+                });
+            }
+            Token::Symbol("?") => match self.params.get(self.bound) {
+                Some(value) => {
+                    self.bound += 1;
+                    Kind::Literal(value.clone())
+                }
+                None => return syntax(span, "no parameter given for this ?"),
+            },
+            Token::Number(text) => Kind::Literal(number(&text, false, span.clone())?),
+            Token::Str(string) => Kind::Literal(Value::Str(string)),
+            Token::Quoted(name) => Kind::Column(name),
+            Token::Word(word) => match word.to_lowercase().as_str() {
+                "nil" | "null" => Kind::Literal(Value::Nil),
+                "true" => Kind::Literal(Value::Bool(true)),
+                "false" => Kind::Literal(Value::Bool(false)),
+                "and" | "or" | "is" => return syntax(span, "expected a value"),
+                _ if self.peek() == Some(&Token::Symbol("(")) => {
+                    return syntax(span, format!("unknown function {}", word));
+                }
+                _ => {
+                    let name = self.rest_of_name(word)?;
+                    let end = self.tokens[self.position - 1].1.end;
+                    return Ok(Expr {
+                        kind: Kind::Column(name),
+                        span: span.start..end,
+                    });
+                }
+            },
+            Token::Symbol(_) => return syntax(span, "expected a value"),
+        };
+        Ok(Expr { kind, span })
+    }
+
+    // the arguments of a function, after its name
+    fn call(&mut self, function: Function, span: Range<usize>) -> Result<(Expr, usize), Error> {
+        self.expect("(")?;
+        let mut args = Vec::new();
+        let mut depth = 0;
+        let end = match self.peek() {
+            Some(Token::Symbol(")")) => self.next()?.1,
+            _ => loop {
+                let (arg, arg_depth) = self.expr(0)?;
+                args.push(arg);
+                depth = depth.max(arg_depth);
+                match self.next()? {
+                    (Token::Symbol(","), _) => continue,
+                    (Token::Symbol(")"), end) => break end,
+                    (_, span) => return syntax(span, "expected , or )"),
+                }
+            },
+        };
+        let expr = Expr {
+            span: span.start..end.end,
+            kind: Kind::Call { function, args },
+        };
+        Ok((expr, nest(depth, &span)?))
+    }
+}
+
+// the depth of an expression holding one `depth` deep
+fn nest(depth: usize, span: &Range<usize>) -> Result<usize, Error> {
+    match depth < MAX_DEPTH {
+        true => Ok(depth + 1),
+        false => syntax(span.clone(), "nested too deeply"),
+    }
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum ExprError {
+    Syntax { span: Range<usize>, reason: String },
+    UnknownColumn { span: Range<usize>, name: String },
+    TypeMismatch { span: Range<usize>, reason: String },
+}
+
+impl std::fmt::Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExprError::Syntax { span, reason } => write!(
+                f,
+                "Invalid expression at {}..{}: {}",
+                span.start, span.end, reason
+            ),
+            ExprError::UnknownColumn { span, name } => {
+                write!(f, "Unknown column {} at {}..{}", name, span.start, span.end)
+            }
+            ExprError::TypeMismatch { span, reason } => {
+                write!(f, "Type error at {}..{}: {}", span.start, span.end, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::id::Identifier;
+
+    #[test]
+    fn source_round_trips_and_errors_point_at_the_problem() {
+        for source in [
+            "price * qty",
+            "(a + b) * -c",
+            "a - (b - c) % 2",
+            r#"`unit price` / 1.5 + "say \"hi\"""#,
+            "-(a + 1) - nil",
+            "-128i8 + 255u8 * 1.5f32 - -(3)",
+            "not (a or b) and c is not null",
+            "a = b is null or `not` <= 1e-7",
+            "new.price * old.`unit price` + `a``b` + x.`y.z` + `not.a`",
+            "substr(lower(name), 2, length(name) - 1) != concat(\"x\", upper(name))",
+        ] {
+            assert_eq!(Expr::parse(source).unwrap().to_string(), source);
+        }
+        assert_eq!(
+            Expr::parse("((price)) * (qty)").unwrap(),
+            Expr::parse("price * qty").unwrap()
+        );
+        assert_eq!(
+            Expr::parse("NOT a IS NULL AND b <> 2").unwrap(),
+            Expr::parse("not a is null and b != 2").unwrap()
+        );
+
+        for (source, at) in [
+            ("a + * b", 4..5),
+            ("(a + b", 6..6),
+            ("a b", 2..3),
+            ("a # b", 2..3),
+            ("300u8", 0..5),
+            ("lower(a", 7..7),
+            ("frobnicate(a)", 0..10),
+            ("a is b", 5..6),
+            ("new. + 1", 5..6),
+        ] {
+            match Expr::parse(source) {
+                Err(Error::ExprError(ExprError::Syntax { span, .. })) => assert_eq!(span, at),
+                other => panic!("{} parsed as {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let deep = 60_000;
+        for source in [
+            format!("{}1{}", "(".repeat(deep), ")".repeat(deep)),
+            format!("{}x", "-".repeat(deep)),
+            format!("{}x", "not ".repeat(deep)),
+            format!("1{}", " + 1".repeat(deep)),
+            format!("{}1{}", "abs(".repeat(deep), ")".repeat(deep)),
+        ] {
+            assert!(matches!(
+                Expr::parse(&source),
+                Err(Error::ExprError(ExprError::Syntax { .. }))
+            ));
+        }
+
+        // as deep as allowed, and written back so it parses again
+        let longest = format!("a{}", " - a".repeat(MAX_DEPTH));
+        let nested = format!("{}a{}", "a - (".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        for source in [longest, nested] {
+            let expr = Expr::parse(&source).unwrap();
+            assert_eq!(Expr::parse(&expr.to_string()).unwrap(), expr);
+            assert_eq!(expr.check(&|_| Some(Type::Int)).unwrap(), Type::Int);
+            assert!(expr.evaluate(&|_| Some(Value::Int(1))).is_ok());
+            assert!(Expr::parse(&format!("-({})", source)).is_err());
+        }
+    }
+
+    #[test]
+    fn expressions_are_checked_and_evaluated_over_rows() {
+        let mut sheet =
+            Sheet::new_with_set_id(Identifier::new(), "people".to_string(), Vec::new(), None);
+        for (name, value_type) in [
+            ("name", Type::Str),
+            ("age", Type::U8),
+            ("admin", Type::Bool),
+        ] {
+            sheet.adopt_column(&Column::new(name.to_string(), value_type, None));
+        }
+        for (name, age, admin) in [
+            ("Ada", Value::Int(36), Value::Bool(true)),
+            ("alan", Value::Nil, Value::Bool(false)),
+            ("Grace", Value::Int(85), Value::Nil),
+        ] {
+            sheet
+                .insert_row(vec![Value::Str(name.to_string()), age, admin])
+                .unwrap();
+        }
+        let rows = |source: &str| sheet.rows_where(&Expr::parse(source).unwrap()).unwrap();
+        let value = |source: &str, row: usize| {
+            Expr::parse(source)
+                .unwrap()
+                .evaluate_in(&sheet, row)
+                .unwrap()
+        };
+
+        assert_eq!(rows("age > 40 or admin"), vec![0, 2]);
+        assert_eq!(rows("age is null"), vec![1]);
+        // nil is neither true nor false
+        assert_eq!(rows("not admin"), vec![1]);
+        assert_eq!(rows("admin or age < 100"), vec![0, 2]);
+        assert_eq!(rows("lower(name) = \"ada\""), vec![0]);
+
+        assert_eq!(value("age + 1", 0), Value::Int(37));
+        assert_eq!(value("age * 10", 1), Value::Nil);
+        assert_eq!(value("false and admin", 2), Value::Bool(false));
+        assert_eq!(
+            value("upper(substr(name, 2, 3))", 2),
+            Value::Str("RAC".to_string())
+        );
+        assert_eq!(
+            value("concat(name, \"!\")", 1),
+            Value::Str("alan!".to_string())
+        );
+        assert_eq!(value("length(name)", 2), Value::Int(5));
+        assert_eq!(value("abs(-3 - age)", 0), Value::Int(39));
+        assert_eq!(
+            value("round(2.5) + floor(-1.5) + ceil(0.2)", 0),
+            Value::Flt(2.0)
+        );
+        assert_eq!(value("sqrt(16) + pow(2, 10)", 0), Value::Flt(1028.0));
+        assert_eq!(value("max(age, 50u8)", 0), Value::U8(50));
+        assert!(matches!(value("max(age, 1.5f32)", 2), Value::F32(max) if max == 85.0));
+
+        let schema = |name: &str| {
+            sheet
+                .get_column_by_name(name)
+                .map(|column| column.value_type)
+        };
+        for (source, at) in [
+            ("age + name", 6..10),
+            ("admin and age", 10..13),
+            ("name < 3", 0..8),
+            ("substr(name, 1.5)", 13..16),
+            ("min(name)", 0..9),
+        ] {
+            match Expr::parse(source).unwrap().check(&schema) {
+                Err(Error::ExprError(ExprError::TypeMismatch { span, .. })) => assert_eq!(span, at),
+                other => panic!("{} checked as {:?}", source, other),
+            }
+        }
+        assert_eq!(
+            Expr::parse("age * 2").unwrap().check(&schema).unwrap(),
+            Type::Int
+        );
+        assert!(sheet.rows_where(&Expr::parse("age").unwrap()).is_err());
+    }
+}
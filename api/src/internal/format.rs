@@ -0,0 +1,236 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::database::DatabaseError;
+use crate::internal::errors::Error;
+use crate::internal::limits::DecodeLimits;
+
+// every file starts with "baseboredv" followed by the format version as an
+// ascii digit, so v1 files ("baseboredv1") are recognised by the same check
+pub(crate) const MAGIC: [u8; 10] = [98, 97, 115, 101, 98, 111, 114, 101, 100, 118];
+
+// magic + version + u16 header length + u32 feature flags
+const V2_HEADER_LENGTH: u16 = 17;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Version {
+    V1,
+    V2,
+}
+
+impl Version {
+    pub(crate) const LATEST: Version = Version::V2;
+
+    fn as_byte(&self) -> u8 {
+        match self {
+            Version::V1 => b'1',
+            Version::V2 => b'2',
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, Error> {
+        match byte {
+            b'1' => Ok(Version::V1),
+            b'2' => Ok(Version::V2),
+            _ => Err(Error::FormatError(FormatError::UnsupportedVersion {
+                version: byte,
+            })),
+        }
+    }
+}
+
+/// Feature flags stored in the v2 header. A reader refuses files that use
+/// features it doesn't know, since it couldn't decode them correctly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Features(pub(crate) u32);
+
+impl Features {
+    pub(crate) const NONE: Features = Features(0);
+    // CRC32C after every column, every sheet and the whole file
+    pub(crate) const CHECKSUMS: Features = Features(1 << 0);
+    // columns say how their cells are encoded, see columnar::Encoding
+    pub(crate) const COLUMNAR: Features = Features(1 << 1);
+    // columns may use the compressed encodings (run-length, delta, dictionary)
+    pub(crate) const COMPRESSION: Features = Features(1 << 2);
+    // an index of sheet and column offsets follows the sheets, see index::Index
+    pub(crate) const INDEX: Features = Features(1 << 3);
+    // rows may be appended after the file in segments, see segment.rs
+    pub(crate) const SEGMENTS: Features = Features(1 << 4);
+    // sheets carry their triggers after their name, see trigger.rs
+    pub(crate) const TRIGGERS: Features = Features(1 << 5);
+    // columns may be computed, and carry their expression, see computed.rs
+    pub(crate) const COMPUTED: Features = Features(1 << 6);
+    // the database's views follow the index, see view.rs
+    pub(crate) const VIEWS: Features = Features(1 << 7);
+    // sheets carry the ids of their rows after their triggers, see row_id.rs
+    pub(crate) const ROW_IDS: Features = Features(1 << 8);
+
+    // every flag this build knows how to read
+    pub(crate) const KNOWN: Features = Features(
+        Features::CHECKSUMS.0
+            | Features::COLUMNAR.0
+            | Features::COMPRESSION.0
+            | Features::INDEX.0
+            | Features::SEGMENTS.0
+            | Features::TRIGGERS.0
+            | Features::COMPUTED.0
+            | Features::VIEWS.0
+            | Features::ROW_IDS.0,
+    );
+
+    pub(crate) fn contains(&self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub(crate) fn without(&self, other: Features) -> Features {
+        Features(self.0 & !other.0)
+    }
+}
+
+/// Everything a reader needs to know about how the bytes after the header are
+/// laid out, and how much it may allocate decoding them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Format {
+    pub(crate) version: Version,
+    pub(crate) features: Features,
+    // not stored in the file
+    pub(crate) limits: DecodeLimits,
+    // decode sheet and column names that aren't valid UTF-8 with U+FFFD in
+    // place of the bad bytes, rather than failing. Not stored in the file.
+    pub(crate) lossy_names: bool,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::current()
+    }
+}
+
+impl Format {
+    /// The format new files are written in
+    pub(crate) fn current() -> Self {
+        Self {
+            version: Version::LATEST,
+            features: Features::KNOWN,
+            limits: DecodeLimits::default(),
+            lossy_names: false,
+        }
+    }
+
+    pub(crate) fn v1() -> Self {
+        Self {
+            version: Version::V1,
+            features: Features::NONE,
+            limits: DecodeLimits::default(),
+            lossy_names: false,
+        }
+    }
+
+    pub(crate) fn has(&self, feature: Features) -> bool {
+        self.features.contains(feature)
+    }
+
+    // v1 wrote length tables little-endian by mistake; everything else, and
+    // everything in v2, is big-endian
+    pub(crate) fn length_table_u32(&self, value: u32) -> [u8; 4] {
+        match self.version {
+            Version::V1 => value.to_le_bytes(),
+            Version::V2 => value.to_be_bytes(),
+        }
+    }
+
+    pub(crate) fn read_length_table_u32(&self, bytes: [u8; 4]) -> u32 {
+        match self.version {
+            Version::V1 => u32::from_le_bytes(bytes),
+            Version::V2 => u32::from_be_bytes(bytes),
+        }
+    }
+
+    // all numbers are BE
+    // u8[10] magic: "baseboredv"
+    // u8 version: ascii digit, '1' or '2'
+    // v2 and later only:
+    //   u16 header_length: total length of the header, including the magic
+    //   u32 features: feature flag bits
+    pub(crate) fn header_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(self.version.as_byte());
+
+        if self.version >= Version::V2 {
+            bytes.extend_from_slice(&V2_HEADER_LENGTH.to_be_bytes());
+            bytes.extend_from_slice(&self.features.0.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    /// Reads the header and leaves the deserializer at the first byte after it
+    pub(crate) fn read_header(deserializer: &mut ByteDeserializer) -> Result<Self, Error> {
+        let magic_bytes = deserializer.read_bytes(MAGIC.len())?;
+        if magic_bytes != MAGIC {
+            return Err(Error::DatabaseError(DatabaseError::InvalidMagicBytes));
+        }
+
+        let version = Version::from_byte(deserializer.read_u8()?)?;
+        if version == Version::V1 {
+            return Ok(Self::v1());
+        }
+
+        let header_length = deserializer.read_u16()?;
+        if header_length < V2_HEADER_LENGTH {
+            return Err(Error::FormatError(FormatError::InvalidHeaderLength {
+                got: header_length,
+            }));
+        }
+
+        let features = Features(deserializer.read_u32()?);
+        let unknown = features.without(Features::KNOWN);
+        if unknown != Features::NONE {
+            return Err(Error::FormatError(FormatError::UnsupportedFeatures {
+                flags: unknown.0,
+            }));
+        }
+
+        // later revisions of v2 may append fields to the header; skip them
+        deserializer.read_bytes((header_length - V2_HEADER_LENGTH) as usize)?;
+
+        Ok(Self {
+            version,
+            features,
+            limits: DecodeLimits::default(),
+            lossy_names: false,
+        })
+    }
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum FormatError {
+    UnsupportedVersion { version: u8 },
+    UnsupportedFeatures { flags: u32 },
+    InvalidHeaderLength { got: u16 },
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::UnsupportedVersion { version } if version.is_ascii_digit() => write!(
+                f,
+                "File format version {} is not supported, this build reads versions up to {}",
+                *version as char,
+                Version::LATEST.as_byte() as char
+            ),
+            FormatError::UnsupportedVersion { version } => {
+                write!(f, "Invalid file format version byte: {}", version)
+            }
+            FormatError::UnsupportedFeatures { flags } => {
+                write!(f, "File uses unsupported feature flags: {:#010x}", flags)
+            }
+            FormatError::InvalidHeaderLength { got } => {
+                write!(f, "Invalid header length: {}", got)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+
+use crate::internal::database::Database;
+use crate::internal::errors::Error;
+use crate::internal::transaction::{Changes, Transaction};
+use crate::internal::wal::Operation;
+
+/// How many steps are kept unless told otherwise
+pub(crate) const DEFAULT_DEPTH: usize = 100;
+
+/// A database with undo and redo, for interactive editing. Every edit is a
+/// transaction (see Database::transaction) and becomes one step, unless it
+/// is made between begin_group and end_group, where all of them become one.
+///
+/// Undo takes changes back by restoring what they replaced, so it only works
+/// if every change goes through here; editing the database some other way
+/// would leave the history describing a database that no longer exists.
+pub(crate) struct History {
+    database: Database,
+    undo: VecDeque<Vec<Changes>>,
+    redo: Vec<Vec<Operation>>,
+    group: Option<Vec<Changes>>,
+    groups: usize,
+    depth: usize,
+}
+
+impl History {
+    pub(crate) fn new(database: Database) -> History {
+        History {
+            database,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            group: None,
+            groups: 0,
+            depth: DEFAULT_DEPTH,
+        }
+    }
+
+    pub(crate) fn database(&self) -> &Database {
+        &self.database
+    }
+
+    pub(crate) fn into_database(self) -> Database {
+        self.database
+    }
+
+    /// Keeps at most `depth` steps to undo, forgetting the oldest ones
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.trim();
+    }
+
+    pub(crate) fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.group.as_ref().is_some_and(|group| !group.is_empty())
+    }
+
+    pub(crate) fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Applies `operation` as one step
+    pub(crate) fn apply(&mut self, operation: Operation) -> Result<(), Error> {
+        self.edit(|tx| tx.apply(operation))
+    }
+
+    /// Runs `f` in a transaction and records it as one step. Anything that
+    /// was undone can't be redone after this.
+    pub(crate) fn edit<T>(
+        &mut self,
+        f: impl FnOnce(&mut Transaction) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let (value, changes) = self.database.transaction_with_changes(f)?;
+        if changes.operations.is_empty() {
+            return Ok(value);
+        }
+        self.redo.clear();
+        match &mut self.group {
+            Some(group) => group.push(changes),
+            None => self.push(vec![changes]),
+        }
+        Ok(value)
+    }
+
+    /// Starts collecting edits into one step. Groups can be nested, only the
+    /// outermost one makes a step.
+    pub(crate) fn begin_group(&mut self) {
+        self.groups += 1;
+        self.group.get_or_insert_with(Vec::new);
+    }
+
+    pub(crate) fn end_group(&mut self) {
+        self.groups = self.groups.saturating_sub(1);
+        if self.groups == 0 {
+            self.close_group();
+        }
+    }
+
+    /// Takes back the last step, closing any open group first. Returns false
+    /// if there was nothing to undo.
+    pub(crate) fn undo(&mut self) -> bool {
+        self.groups = 0;
+        self.close_group();
+
+        let Some(step) = self.undo.pop_back() else {
+            return false;
+        };
+        let mut operations = Vec::new();
+        for changes in step.into_iter().rev() {
+            let mut applied = changes.operations.clone();
+            applied.append(&mut operations);
+            operations = applied;
+            changes.revert(&mut self.database);
+        }
+        self.redo.push(operations);
+        true
+    }
+
+    /// Applies the last undone step again. Returns false if there was nothing
+    /// to redo.
+    pub(crate) fn redo(&mut self) -> Result<bool, Error> {
+        let Some(operations) = self.redo.last() else {
+            return Ok(false);
+        };
+        let (_, changes) = self.database.transaction_with_changes(|tx| {
+            operations
+                .iter()
+                .try_for_each(|operation| tx.replay(operation.clone()))
+        })?;
+        self.redo.pop();
+        self.push(vec![changes]);
+        Ok(true)
+    }
+
+    fn close_group(&mut self) {
+        if let Some(group) = self.group.take() {
+            if !group.is_empty() {
+                self.push(group);
+            }
+        }
+    }
+
+    fn push(&mut self, step: Vec<Changes>) {
+        self.undo.push_back(step);
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::data_type::Type;
+    use crate::internal::data_value::Value;
+    use crate::internal::id::Identifier;
+    use crate::internal::traits::PrettyPrintable;
+
+    fn people() -> (History, Identifier, Identifier) {
+        let mut history = History::new(Database::new_empty());
+        let (sheet, columns) = history
+            .edit(|tx| tx.add_sheet("people", &[("name", Type::Str)]))
+            .unwrap();
+        (history, sheet, columns[0])
+    }
+
+    fn insert(sheet: Identifier, name: &str) -> Operation {
+        Operation::InsertRow {
+            sheet,
+            values: vec![Value::Str(name.to_string())],
+        }
+    }
+
+    #[test]
+    fn every_kind_of_edit_is_undone_and_redone() {
+        let (mut history, sheet, name) = people();
+        let edits = vec![
+            insert(sheet, "ada"),
+            insert(sheet, "grace"),
+            Operation::UpdateCell {
+                sheet,
+                column: name,
+                row: 0,
+                value: Value::Str("ada lovelace".to_string()),
+            },
+            Operation::AddColumn {
+                sheet,
+                id: Identifier::new(),
+                name: "age".to_string(),
+                value_type: Type::U8,
+            },
+            Operation::DeleteRow { sheet, row: 0 },
+            Operation::RemoveColumn {
+                sheet,
+                column: name,
+            },
+            Operation::RemoveSheet { sheet },
+        ];
+
+        let mut states = vec![history.database().pretty_print(0)];
+        for edit in edits {
+            history.apply(edit).unwrap();
+            states.push(history.database().pretty_print(0));
+        }
+        for state in states.iter().rev().skip(1) {
+            assert!(history.undo());
+            assert_eq!(&history.database().pretty_print(0), state);
+        }
+        // the sheet itself was made in people()
+        assert!(history.can_undo());
+        assert!(history.undo());
+        assert!(!history.can_undo());
+        assert!(!history.undo());
+        assert!(history.database().columns.is_empty());
+
+        assert!(history.can_redo());
+        assert!(history.redo().unwrap());
+        for state in &states[1..] {
+            assert!(history.redo().unwrap());
+            assert_eq!(&history.database().pretty_print(0), state);
+        }
+        assert!(!history.redo().unwrap());
+    }
+
+    #[test]
+    fn groups_are_one_step_and_depth_is_limited() {
+        let (mut history, sheet, _) = people();
+        let empty = history.database().pretty_print(0);
+
+        history.begin_group();
+        history.apply(insert(sheet, "ada")).unwrap();
+        // an open group can already be undone, closing it
+        assert!(history.can_undo());
+        history.begin_group();
+        history.apply(insert(sheet, "grace")).unwrap();
+        history.end_group();
+        history.apply(insert(sheet, "edsger")).unwrap();
+        history.end_group();
+        let grouped = history.database().pretty_print(0);
+
+        assert!(history.undo());
+        assert_eq!(history.database().pretty_print(0), empty);
+        assert!(history.redo().unwrap());
+        assert_eq!(history.database().pretty_print(0), grouped);
+
+        // a new edit drops what could have been redone
+        assert!(history.undo());
+        history.apply(insert(sheet, "barbara")).unwrap();
+        assert!(!history.can_redo());
+
+        history.set_depth(2);
+        for name in ["alan", "donald", "niklaus"] {
+            history.apply(insert(sheet, name)).unwrap();
+        }
+        assert!(history.undo());
+        assert!(history.undo());
+        assert!(!history.undo());
+        let rows = history.database().columns[0].columns[0].get_row_count();
+        assert_eq!(rows, 2);
+    }
+}
@@ -4,7 +4,7 @@ use uuid::Uuid;
 
 use crate::internal::traits::Serializable;
 
-#[derive(Debug, Clone, PartialEq, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
 pub(crate) struct Identifier {
     pub(crate) id: Uuid,
 }
@@ -0,0 +1,268 @@
+use crate::internal::byte_deserializer::{ByteDeserializer, ByteError};
+use crate::internal::checksum;
+use crate::internal::errors::Error;
+use crate::internal::format::{Features, Format};
+use crate::internal::id::Identifier;
+use crate::internal::length_table::{self, LengthTable};
+use crate::internal::traits::Serializable;
+use std::ops::Range;
+
+/// Where every sheet and column block sits in a file, so a reader can decode
+/// one of them without touching the rest. Offsets are from the start of the file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Index {
+    pub(crate) sheets: Vec<SheetEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SheetEntry {
+    pub(crate) id: Identifier,
+    pub(crate) name: String,
+    pub(crate) range: Range<u64>,
+    pub(crate) columns: Vec<ColumnEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ColumnEntry {
+    pub(crate) id: Identifier,
+    pub(crate) name: String,
+    pub(crate) range: Range<u64>,
+}
+
+impl Index {
+    pub(crate) fn sheet(&self, name: &str) -> Option<&SheetEntry> {
+        self.sheets.iter().find(|sheet| sheet.name == name)
+    }
+
+    /// Offset of the index block in a file with Features::INDEX: right after
+    /// the sheets table, whose length is the first field after the header
+    pub(crate) fn offset(header_length: usize, sheets_table_length: u32) -> u64 {
+        header_length as u64 + 8 + sheets_table_length as u64
+    }
+
+    /// Builds the index of a file written without Features::INDEX by walking
+    /// its length tables. Needs the whole file, but decodes no cells.
+    pub(crate) fn locate(
+        bytes: &[u8],
+        header_length: usize,
+        format: &Format,
+    ) -> Result<Index, Error> {
+        let mut sheets = Vec::new();
+        let sheets_start = header_length;
+
+        for sheet_range in LengthTable::entries(&bytes[sheets_start..], format)? {
+            let sheet_offset = sheets_start + sheet_range.start;
+            let sheet_bytes = &bytes[sheet_offset..sheets_start + sheet_range.end];
+            let body = match format.has(Features::CHECKSUMS) {
+                true => &sheet_bytes[..sheet_bytes.len().saturating_sub(4)],
+                false => sheet_bytes,
+            };
+
+            let (id, name, name_end) =
+                read_id_and_name(body, format).map_err(|err| err.offset_by(sheet_offset))?;
+            let triggers_end = name_end
+                + length_table::block_length(&body[name_end..], format.has(Features::TRIGGERS))
+                    .map_err(|err| err.offset_by(sheet_offset + name_end))?;
+            let columns_start = triggers_end
+                + length_table::block_length(
+                    body.get(triggers_end..).unwrap_or_default(),
+                    format.has(Features::ROW_IDS),
+                )
+                .map_err(|err| err.offset_by(sheet_offset + triggers_end))?;
+            if columns_start > body.len() {
+                return Err(Error::ByteError(ByteError::OutOfBoundsError {
+                    pos: sheet_offset + columns_start,
+                    len: bytes.len(),
+                }));
+            }
+            let mut columns = Vec::new();
+            for column_range in LengthTable::entries(&body[columns_start..], format)? {
+                let column_offset = sheet_offset + columns_start + column_range.start;
+                let column_bytes =
+                    &body[columns_start + column_range.start..columns_start + column_range.end];
+                let (id, name, _) = read_id_and_name(column_bytes, format)
+                    .map_err(|err| err.offset_by(column_offset))?;
+                columns.push(ColumnEntry {
+                    id,
+                    name,
+                    range: column_offset as u64..(column_offset + column_bytes.len()) as u64,
+                });
+            }
+
+            sheets.push(SheetEntry {
+                id,
+                name,
+                range: sheet_offset as u64..(sheet_offset + sheet_bytes.len()) as u64,
+                columns,
+            });
+        }
+
+        Ok(Index { sheets })
+    }
+
+    /// Checks that every entry lies inside a file of `file_length` bytes
+    pub(crate) fn check_bounds(&self, file_length: u64) -> Result<(), Error> {
+        let out_of_bounds = |name: &str, range: &Range<u64>| {
+            Err(Error::IndexError(IndexError::EntryOutOfBounds {
+                name: name.to_string(),
+                end: range.end,
+                file_length,
+            }))
+        };
+
+        for sheet in &self.sheets {
+            if sheet.range.start > sheet.range.end || sheet.range.end > file_length {
+                return out_of_bounds(&sheet.name, &sheet.range);
+            }
+            for column in &sheet.columns {
+                if column.range.start < sheet.range.start || column.range.end > sheet.range.end {
+                    return out_of_bounds(&column.name, &column.range);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// both sheet and column blocks start with a 16 byte id and a length-prefixed name;
+// returns the offset of the first byte after the name too
+fn read_id_and_name(bytes: &[u8], format: &Format) -> Result<(Identifier, String, usize), Error> {
+    let mut deserializer = ByteDeserializer::new(bytes);
+    let id = Identifier::deserialize_bytes(&deserializer.read_bytes(16)?)?;
+    let name_length = deserializer.read_u32()?;
+    let name = deserializer.read_name(name_length as usize, format)?;
+    Ok((id, name, deserializer.position()))
+}
+
+fn write_name(name: &str, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+fn read_range(deserializer: &mut ByteDeserializer) -> Result<Range<u64>, Error> {
+    let offset = deserializer.read_u64()?;
+    let length = deserializer.read_u64()?;
+    let end = offset
+        .checked_add(length)
+        .ok_or(Error::IndexError(IndexError::Malformed {
+            reason: "entry ends past u64::MAX".to_string(),
+        }))?;
+    Ok(offset..end)
+}
+
+impl Serializable<Index> for Index {
+    // all numbers are BE
+    // u32 sheet_count
+    // repeated sheet_count times:
+    //   u8[16] id, u32 name_length, u8[name_length] name
+    //   u64 offset, u64 length: the sheet block
+    //   u32 column_count
+    //   repeated column_count times:
+    //     u8[16] id, u32 name_length, u8[name_length] name
+    //     u64 offset, u64 length: the column block
+    // with Features::CHECKSUMS: u32 crc32c of the above
+
+    fn serialized_bytes(&self) -> Vec<u8> {
+        self.serialized_bytes_with(&Format::current())
+    }
+
+    fn deserialize_bytes(bytes: &[u8]) -> Result<Index, Error> {
+        Self::deserialize_bytes_with(bytes, &Format::current())
+    }
+
+    fn serialized_bytes_with(&self, format: &Format) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.sheets.len() as u32).to_be_bytes());
+
+        for sheet in &self.sheets {
+            bytes.extend_from_slice(&sheet.id.serialized_bytes());
+            write_name(&sheet.name, &mut bytes);
+            bytes.extend_from_slice(&sheet.range.start.to_be_bytes());
+            bytes.extend_from_slice(&(sheet.range.end - sheet.range.start).to_be_bytes());
+
+            bytes.extend_from_slice(&(sheet.columns.len() as u32).to_be_bytes());
+            for column in &sheet.columns {
+                bytes.extend_from_slice(&column.id.serialized_bytes());
+                write_name(&column.name, &mut bytes);
+                bytes.extend_from_slice(&column.range.start.to_be_bytes());
+                bytes.extend_from_slice(&(column.range.end - column.range.start).to_be_bytes());
+            }
+        }
+
+        if format.has(Features::CHECKSUMS) {
+            checksum::append(&mut bytes);
+        }
+
+        bytes
+    }
+
+    fn deserialize_bytes_with(bytes: &[u8], format: &Format) -> Result<Index, Error> {
+        let bytes = match format.has(Features::CHECKSUMS) {
+            true => checksum::checked_body(bytes, || checksum::Region::Index)?,
+            false => bytes,
+        };
+
+        let mut deserializer = ByteDeserializer::new(bytes);
+        let sheet_count = deserializer.read_u32()?;
+
+        let mut sheets = Vec::new();
+        for _ in 0..sheet_count {
+            let id = Identifier::deserialize_bytes(&deserializer.read_bytes(16)?)?;
+            let name_length = deserializer.read_u32()?;
+            let name = deserializer.read_name(name_length as usize, format)?;
+            let range = read_range(&mut deserializer)?;
+
+            let column_count = deserializer.read_u32()?;
+            let mut columns = Vec::new();
+            for _ in 0..column_count {
+                let id = Identifier::deserialize_bytes(&deserializer.read_bytes(16)?)?;
+                let name_length = deserializer.read_u32()?;
+                let name = deserializer.read_name(name_length as usize, format)?;
+                let range = read_range(&mut deserializer)?;
+                columns.push(ColumnEntry { id, name, range });
+            }
+
+            sheets.push(SheetEntry {
+                id,
+                name,
+                range,
+                columns,
+            });
+        }
+
+        Ok(Index { sheets })
+    }
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum IndexError {
+    Malformed {
+        reason: String,
+    },
+    EntryOutOfBounds {
+        name: String,
+        end: u64,
+        file_length: u64,
+    },
+}
+
+impl std::fmt::Display for IndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexError::Malformed { reason } => write!(f, "Malformed index: {}", reason),
+            IndexError::EntryOutOfBounds {
+                name,
+                end,
+                file_length,
+            } => write!(
+                f,
+                "Index entry '{}' ends at byte {}, past the end of the {} byte file",
+                name, end, file_length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IndexError {}
@@ -1,72 +1,245 @@
+use crate::internal::byte_deserializer::{ByteDeserializer, ByteError};
 use crate::internal::errors::Error;
+use crate::internal::format::Format;
+use crate::internal::limits::{Budget, Limited};
 use crate::internal::traits::Serializable;
-use std::convert::TryInto;
+use std::ops::Range;
 
 pub(crate) struct LengthTable;
 
 impl LengthTable {
-    pub(crate) fn serialize<T: Serializable<T>>(objects: &Vec<T>) -> Vec<u8> {
-        let mut bytes = Vec::new();
+    pub(crate) fn serialize<T: Serializable<T>>(objects: &[T]) -> Vec<u8> {
+        Self::serialize_with(objects, &Format::current())
+    }
+
+    pub(crate) fn serialize_with<T: Serializable<T>>(objects: &[T], format: &Format) -> Vec<u8> {
+        let serialized_objects: Vec<Vec<u8>> = objects
+            .iter()
+            .map(|object| object.serialized_bytes_with(format))
+            .collect();
+
+        Self::serialize_blocks(&serialized_objects, format)
+    }
 
-        // Serialize each object and store its length
-        let mut object_lengths = Vec::new();
-        let mut serialized_objects = Vec::new();
+    /// Builds a table from objects that are already serialized
+    pub(crate) fn serialize_blocks(blocks: &[Vec<u8>], format: &Format) -> Vec<u8> {
+        let lengths: Vec<usize> = blocks.iter().map(|block| block.len()).collect();
+        let mut bytes = Self::header_bytes(&lengths, format);
 
-        for object in objects {
-            let serialized_object = object.serialized_bytes();
-            object_lengths.push(serialized_object.len() as u32);
-            serialized_objects.extend(serialized_object);
+        // Serialize the objects
+        for block in blocks {
+            bytes.extend(block);
         }
 
+        bytes
+    }
+
+    /// Everything in a table before the objects, for objects of these lengths.
+    /// Lets a writer stream the objects afterwards instead of collecting them.
+    pub(crate) fn header_bytes(lengths: &[usize], format: &Format) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + lengths.len() * 4);
+
         // Serialize the length table length
-        let length_table_length = object_lengths.len() * 4 + serialized_objects.len();
-        bytes.extend(&(length_table_length as u32).to_le_bytes());
+        let length_table_length = Self::table_length(lengths) - 8;
+        bytes.extend(&format.length_table_u32(length_table_length as u32));
 
         // Serialize the number of objects
-        bytes.extend(&(objects.len() as u32).to_le_bytes());
+        bytes.extend(&format.length_table_u32(lengths.len() as u32));
 
         // Serialize the object lengths
-        for length in object_lengths {
-            bytes.extend(&length.to_le_bytes());
+        for length in lengths {
+            bytes.extend(&format.length_table_u32(*length as u32));
         }
 
-        // Serialize the objects
-        bytes.extend(&serialized_objects);
-
         bytes
     }
 
+    /// Total length of a table of objects with these lengths
+    pub(crate) fn table_length(lengths: &[usize]) -> usize {
+        8 + lengths.len() * 4 + lengths.iter().sum::<usize>()
+    }
+
+    /// Where each object of a table with these object lengths ends up, relative
+    /// to the start of the table
+    pub(crate) fn ranges(lengths: &[usize]) -> Vec<Range<usize>> {
+        let mut cursor = 8 + lengths.len() * 4;
+        lengths
+            .iter()
+            .map(|length| {
+                let range = cursor..cursor + length;
+                cursor += length;
+                range
+            })
+            .collect()
+    }
+
     pub(crate) fn deserialize<T: Serializable<T>>(bytes: &[u8]) -> Result<Vec<T>, Error> {
-        let mut cursor = 0;
+        Self::deserialize_with(bytes, &Format::current())
+    }
+
+    pub(crate) fn deserialize_with<T: Serializable<T>>(
+        bytes: &[u8],
+        format: &Format,
+    ) -> Result<Vec<T>, Error> {
+        let mut budget = Budget::new(&format.limits);
+        Self::deserialize_limited(bytes, format, None, &mut budget, |bytes, format, _| {
+            T::deserialize_bytes_with(bytes, format)
+        })
+    }
+
+    /// Like deserialize_with, but fails before decoding anything if the table
+    /// holds more objects than the format's limits allow for `what`. `decode`
+    /// charges `budget` for what each object allocates.
+    pub(crate) fn deserialize_limited<T>(
+        bytes: &[u8],
+        format: &Format,
+        what: Option<Limited>,
+        budget: &mut Budget,
+        decode: impl Fn(&[u8], &Format, &mut Budget) -> Result<T, Error>,
+    ) -> Result<Vec<T>, Error> {
+        let ranges = Self::entries(bytes, format)?;
+        if let Some(what) = what {
+            format.limits.check_count(what, ranges.len() as u64)?;
+        }
+
+        let mut objects = Vec::new();
+        for range in ranges {
+            let start = range.start;
+            let object =
+                decode(&bytes[range], format, budget).map_err(|err| err.offset_by(start))?;
+            objects.push(object);
+        }
+
+        Ok(objects)
+    }
 
-        // Read the length table length
-        let length_table_length =
-            u32::from_le_bytes(bytes[cursor..cursor + 4].try_into().unwrap()) as usize;
-        cursor += 4;
+    /// Locates each object of the table at the start of `bytes` without decoding
+    /// it. Ranges are relative to `bytes`.
+    pub(crate) fn entries(bytes: &[u8], format: &Format) -> Result<Vec<Range<usize>>, Error> {
+        let mut deserializer = ByteDeserializer::new(bytes);
+        let mut read_u32 = || -> Result<usize, Error> {
+            let raw = deserializer.read_bytes(4)?;
+            Ok(format.read_length_table_u32([raw[0], raw[1], raw[2], raw[3]]) as usize)
+        };
 
-        // Read the number of objects
-        let objects_count =
-            u32::from_le_bytes(bytes[cursor..cursor + 4].try_into().unwrap()) as usize;
-        cursor += 4;
+        let length_table_length = read_u32()?;
+        let objects_count = read_u32()?;
+
+        // every object needs a 4 byte length, so a count that couldn't fit in
+        // the bytes is rejected before reading (or allocating for) them
+        let lengths_end = objects_count
+            .checked_mul(4)
+            .and_then(|lengths| lengths.checked_add(8))
+            .filter(|end| *end <= bytes.len())
+            .ok_or(Error::ByteError(ByteError::OutOfBoundsError {
+                pos: 8,
+                len: bytes.len(),
+            }))?;
 
-        // Read the object lengths
         let mut object_lengths = Vec::with_capacity(objects_count);
         for _ in 0..objects_count {
-            let length = u32::from_le_bytes(bytes[cursor..cursor + 4].try_into().unwrap()) as usize;
-            cursor += 4;
-            object_lengths.push(length);
+            object_lengths.push(read_u32()?);
         }
 
-        // Read and deserialize the objects
-        let mut objects = Vec::with_capacity(objects_count);
+        let mut cursor = lengths_end;
+        let mut entries = Vec::with_capacity(object_lengths.len());
         for length in object_lengths {
-            let end = cursor + length;
-            let object_bytes = &bytes[cursor..end];
-            let object = T::deserialize_bytes(object_bytes)?;
-            objects.push(object);
+            let end = cursor
+                .checked_add(length)
+                .filter(|end| *end <= bytes.len())
+                .ok_or(Error::ByteError(ByteError::OutOfBoundsError {
+                    pos: cursor,
+                    len: bytes.len(),
+                }))?;
+            entries.push(cursor..end);
             cursor = end;
         }
 
-        Ok(objects)
+        if length_table_length != cursor - 8 {
+            return Err(Error::LengthTableError(LengthTableError::LengthMismatch {
+                stored: length_table_length,
+                computed: cursor - 8,
+            }));
+        }
+
+        Ok(entries)
+    }
+}
+
+// The triggers of a sheet and the views of a database are kept in blocks:
+//
+// all numbers are BE
+// u32 length: of everything after it, so readers can skip the block
+// u32 count
+// count entries, each texts written by write_text and whatever else it needs
+
+/// A block with an entry for each of `items`, written by `entry`
+pub(crate) fn block<T>(items: &[T], entry: impl Fn(&T, &mut Vec<u8>)) -> Vec<u8> {
+    let mut body = (items.len() as u32).to_be_bytes().to_vec();
+    for item in items {
+        entry(item, &mut body);
+    }
+    let mut bytes = (body.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(body);
+    bytes
+}
+
+/// Reads a block, leaving the deserializer after it. A count that couldn't
+/// fit, with every entry taking at least `entry_length` bytes, fails with
+/// `too_many(count, length)` before any entry is read.
+pub(crate) fn read_block<T>(
+    deserializer: &mut ByteDeserializer,
+    entry_length: usize,
+    too_many: impl FnOnce(usize, usize) -> Error,
+    mut entry: impl FnMut(&mut ByteDeserializer) -> Result<T, Error>,
+) -> Result<Vec<T>, Error> {
+    let length = deserializer.read_u32()? as usize;
+    let bytes = deserializer.read_bytes(length)?;
+    let mut body = ByteDeserializer::new(&bytes);
+    let count = body.read_u32()? as usize;
+    if count > length / entry_length {
+        return Err(too_many(count, length));
+    }
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        entries.push(entry(&mut body)?);
     }
+    Ok(entries)
 }
+
+/// Length of the block at the start of `bytes`, to skip over it, or 0 if it
+/// isn't `present`. Row ids (see row_id.rs) start with their length too.
+pub(crate) fn block_length(bytes: &[u8], present: bool) -> Result<usize, Error> {
+    if !present {
+        return Ok(0);
+    }
+    Ok(4 + ByteDeserializer::new(bytes).read_u32()? as usize)
+}
+
+/// Writes `text` into a block's entry: its u32 length, then its bytes
+pub(crate) fn write_text(bytes: &mut Vec<u8>, text: &str) {
+    bytes.extend_from_slice(&(text.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(text.as_bytes());
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum LengthTableError {
+    // the table's own length doesn't match the lengths of its objects
+    LengthMismatch { stored: usize, computed: usize },
+}
+
+impl std::fmt::Display for LengthTableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LengthTableError::LengthMismatch { stored, computed } => write!(
+                f,
+                "Length table says it is {} bytes long but its objects take {}",
+                stored, computed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LengthTableError {}
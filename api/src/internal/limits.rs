@@ -0,0 +1,172 @@
+use crate::internal::cell::Cell;
+use crate::internal::column::Column;
+use crate::internal::data_value::Value;
+use crate::internal::errors::Error;
+use crate::internal::sheet::Sheet;
+use std::mem::size_of;
+
+/// Caps on what decoding may allocate, so a truncated or hostile file fails
+/// with an error instead of exhausting memory. Counts are checked before
+/// anything is allocated for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DecodeLimits {
+    pub(crate) max_sheets: u64,
+    // per sheet
+    pub(crate) max_columns: u64,
+    // per column
+    pub(crate) max_cells: u64,
+    // in bytes, for names and Str values
+    pub(crate) max_string_length: u64,
+    // estimated in-memory size of everything decoded, charged against one
+    // running total for the whole decode before each object is allocated
+    pub(crate) max_total_bytes: u64,
+}
+
+// generous enough for any real file; callers reading untrusted bytes should
+// set tighter ones
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_sheets: 1 << 16,
+            max_columns: 1 << 16,
+            max_cells: u32::MAX as u64,
+            max_string_length: 1 << 30,
+            max_total_bytes: 1 << 34,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Limited {
+    Sheets,
+    Columns,
+    Cells,
+}
+
+impl DecodeLimits {
+    pub(crate) fn check_count(&self, what: Limited, count: u64) -> Result<(), Error> {
+        let limit = match what {
+            Limited::Sheets => self.max_sheets,
+            Limited::Columns => self.max_columns,
+            Limited::Cells => self.max_cells,
+        };
+        if count > limit {
+            return Err(Error::LimitError(LimitError::TooMany {
+                what,
+                count,
+                limit,
+            }));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_string(&self, length: usize) -> Result<(), Error> {
+        if length as u64 > self.max_string_length {
+            return Err(Error::LimitError(LimitError::StringTooLong {
+                length: length as u64,
+                limit: self.max_string_length,
+            }));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_strings(&self, values: &[Value]) -> Result<(), Error> {
+        for value in values {
+            if let Value::Str(s) = value {
+                self.check_string(s.len())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Running total of the estimated size of what has been decoded
+#[derive(Debug)]
+pub(crate) struct Budget {
+    limit: u64,
+    used: u64,
+}
+
+impl Budget {
+    pub(crate) fn new(limits: &DecodeLimits) -> Self {
+        Self {
+            limit: limits.max_total_bytes,
+            used: 0,
+        }
+    }
+
+    pub(crate) fn charge(&mut self, bytes: u64) -> Result<(), Error> {
+        self.used = self.used.saturating_add(bytes);
+        if self.used > self.limit {
+            return Err(Error::LimitError(LimitError::TooLarge {
+                limit: self.limit,
+            }));
+        }
+        Ok(())
+    }
+}
+
+// Estimated in-memory sizes of decoded objects, charged before each is
+// allocated: the structs themselves plus the strings they own
+
+pub(crate) fn cell_size(heap: usize) -> u64 {
+    (size_of::<Cell>() + heap) as u64
+}
+
+pub(crate) fn column_size(name_length: usize) -> u64 {
+    (size_of::<Column>() + name_length) as u64
+}
+
+pub(crate) fn sheet_size(name_length: usize) -> u64 {
+    (size_of::<Sheet>() + name_length) as u64
+}
+
+// each row id is kept in order and in the map from ids to positions
+pub(crate) fn row_ids_size(count: usize) -> u64 {
+    (count * 3 * size_of::<u64>()) as u64
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum LimitError {
+    TooMany {
+        what: Limited,
+        count: u64,
+        limit: u64,
+    },
+    StringTooLong {
+        length: u64,
+        limit: u64,
+    },
+    TooLarge {
+        limit: u64,
+    },
+}
+
+impl std::fmt::Display for LimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitError::TooMany { what, count, limit } => {
+                let what = match what {
+                    Limited::Sheets => "sheets",
+                    Limited::Columns => "columns in a sheet",
+                    Limited::Cells => "cells in a column",
+                };
+                write!(f, "Too many {}: {}, the limit is {}", what, count, limit)
+            }
+            LimitError::StringTooLong { length, limit } => write!(
+                f,
+                "String of {} bytes is longer than the limit of {}",
+                length, limit
+            ),
+            LimitError::TooLarge { limit } => write!(
+                f,
+                "Decoded data would take more than the limit of {} bytes",
+                limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
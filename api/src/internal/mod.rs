@@ -1,11 +1,34 @@
 pub(crate) mod byte_deserializer;
 pub(crate) mod cell;
+pub(crate) mod checksum;
+pub(crate) mod codec;
 pub(crate) mod column;
+pub(crate) mod columnar;
+pub(crate) mod computed;
 pub(crate) mod data_type;
 pub(crate) mod data_value;
 pub(crate) mod database;
+pub(crate) mod database_file;
 pub(crate) mod errors;
+pub(crate) mod expr;
+pub(crate) mod format;
+pub(crate) mod history;
 pub(crate) mod id;
+pub(crate) mod index;
 pub(crate) mod length_table;
+pub(crate) mod limits;
+pub(crate) mod mvcc;
+pub(crate) mod observer;
+pub(crate) mod pager;
+pub(crate) mod row_id;
+pub(crate) mod save;
+pub(crate) mod segment;
 pub(crate) mod sheet;
+pub(crate) mod sql;
+pub(crate) mod storage;
+pub(crate) mod stream;
 pub(crate) mod traits;
+pub(crate) mod transaction;
+pub(crate) mod trigger;
+pub(crate) mod view;
+pub(crate) mod wal;
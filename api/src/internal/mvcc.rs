@@ -0,0 +1,1149 @@
+use crate::internal::column::Column;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::Value;
+use crate::internal::database::{Database, DatabaseError};
+use crate::internal::errors::Error;
+use crate::internal::id::Identifier;
+use crate::internal::row_id::{RowIdError, RowIds};
+use crate::internal::sheet::{Sheet, SheetError};
+use crate::internal::sql::Query;
+use crate::internal::storage::{query_in_batches, Change, ColumnInfo, SheetInfo, Storage};
+use crate::internal::wal::WalError;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// every commit gets the next version, and a snapshot sees every version up to
+// and including its own
+type Version = u64;
+
+// removed at this version means not removed
+const LIVE: Version = Version::MAX;
+
+// sees everything that isn't removed, the commit in progress included
+const UNCOMMITTED: Version = LIVE - 1;
+
+fn visible(created: Version, removed: Version, at: Version) -> bool {
+    created <= at && at < removed
+}
+
+#[derive(Debug, Clone)]
+struct VersionedColumn {
+    id: Identifier,
+    name: String,
+    value_type: Type,
+    // index into every row version's values, never reused within a sheet
+    slot: usize,
+    created: Version,
+    removed: Version,
+}
+
+#[derive(Debug, Clone)]
+struct RowVersion {
+    created: Version,
+    // by column slot; slots added after the version was written are Nil
+    values: Vec<Value>,
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    // the row's id, see row_id.rs, so counting up in insertion order
+    key: u64,
+    inserted: Version,
+    // by DeleteRow, or when the sheet's last column was removed
+    removed: Version,
+    // oldest first, each created by a later commit than the one before
+    versions: Vec<RowVersion>,
+}
+
+#[derive(Debug)]
+struct VersionedSheet {
+    // tells apart sheets that reuse an id after one was removed
+    key: u64,
+    id: Identifier,
+    name: String,
+    created: Version,
+    removed: Version,
+    columns: Vec<VersionedColumn>,
+    slots: usize,
+    // in insertion order, so the rows inserted by any version are a prefix
+    rows: Vec<Row>,
+    // the id the next row gets
+    next_row: u64,
+    // how many rows are removed; while none are, a row's position is its
+    // index into rows
+    removed_rows: usize,
+    // while some are, version -> the index into rows of each row it sees, in
+    // order. Built on the first read at a version, and dropped once nothing
+    // reads at it or collecting removed rows moves the rest.
+    positions: Mutex<BTreeMap<Version, Arc<Vec<usize>>>>,
+}
+
+impl VersionedSheet {
+    fn columns_at(&self, at: Version) -> impl Iterator<Item = &VersionedColumn> {
+        self.columns
+            .iter()
+            .filter(move |column| visible(column.created, column.removed, at))
+    }
+
+    fn column_at(&self, id: &Identifier, at: Version) -> Result<&VersionedColumn, Error> {
+        self.columns_at(at)
+            .find(|column| column.id == *id)
+            .ok_or(Error::WalError(WalError::ColumnNotFound { id: *id }))
+    }
+
+    fn row_count_at(&self, at: Version) -> usize {
+        match self.removed_rows {
+            0 => self.rows.partition_point(|row| row.inserted <= at),
+            _ => self.positions_at(at).len(),
+        }
+    }
+
+    // the index into rows of the row at `row` as of `at`
+    fn row_index_at(&self, row: usize, at: Version) -> Option<usize> {
+        match self.removed_rows {
+            0 => (row < self.rows.partition_point(|row| row.inserted <= at)).then_some(row),
+            _ => self.positions_at(at).get(row).copied(),
+        }
+    }
+
+    // the index into rows of each row `at` sees. What a committed version
+    // sees doesn't change, so it's kept until the rows move.
+    fn positions_at(&self, at: Version) -> Arc<Vec<usize>> {
+        let mut positions = self
+            .positions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(found) = positions.get(&at) {
+            return Arc::clone(found);
+        }
+        let inserted = self.rows.partition_point(|row| row.inserted <= at);
+        let found = Arc::new(
+            (0..inserted)
+                .filter(|index| at < self.rows[*index].removed)
+                .collect::<Vec<_>>(),
+        );
+        if at < UNCOMMITTED {
+            positions.insert(at, Arc::clone(&found));
+        }
+        found
+    }
+
+    // the index into rows of the row with id `id`, if the commit in progress
+    // sees it
+    fn live_row(&self, id: u64) -> Result<usize, Error> {
+        match self.rows.binary_search_by_key(&id, |row| row.key) {
+            Ok(index) if self.rows[index].removed == LIVE => Ok(index),
+            _ => Err(Error::RowIdError(RowIdError::NotFound {
+                sheet_name: self.name.clone(),
+                id,
+            })),
+        }
+    }
+
+    // up to `count` rows from `row` on as of `at`, each its id and values by
+    // column
+    fn rows_at(&self, row: usize, count: usize, at: Version) -> Vec<(u64, Vec<Value>)> {
+        let slots = self
+            .columns_at(at)
+            .map(|column| column.slot)
+            .collect::<Vec<_>>();
+        let end = row.saturating_add(count);
+        let indices = match self.removed_rows {
+            0 => (row..end.min(self.row_count_at(at))).collect(),
+            _ => {
+                let positions = self.positions_at(at);
+                positions[row.min(positions.len())..end.min(positions.len())].to_vec()
+            }
+        };
+        indices
+            .into_iter()
+            .map(|index| &self.rows[index])
+            .map(|row| {
+                let version = row
+                    .versions
+                    .iter()
+                    .rev()
+                    .find(|version| version.created <= at);
+                let values = slots
+                    .iter()
+                    .map(|slot| {
+                        version
+                            .and_then(|version| version.values.get(*slot))
+                            .cloned()
+                            .unwrap_or(Value::Nil)
+                    })
+                    .collect();
+                (row.key, values)
+            })
+            .collect()
+    }
+
+    fn remove_row(&mut self, index: usize, version: Version) {
+        self.rows[index].removed = version;
+        self.removed_rows += 1;
+    }
+}
+
+// what a commit changed, so a failed one can be taken back
+enum Touched {
+    AddedSheet { key: u64 },
+    RemovedSheet { key: u64 },
+    AddedColumn { sheet: u64 },
+    RemovedColumn { sheet: u64, slot: usize },
+    RemovedRows { sheet: u64 },
+    Row { sheet: u64, row: usize },
+}
+
+// old versions to drop once no snapshot can see them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Garbage {
+    Sheet { key: u64 },
+    Column { sheet: u64, slot: usize },
+    Rows { sheet: u64 },
+    // by the row's key, which stays put when rows before it are dropped
+    Row { sheet: u64, row: u64 },
+}
+
+// the garbage not collected yet, looked at a batch at a time and in turn, so
+// what a snapshot still sees doesn't hold up the rest
+#[derive(Debug, Default)]
+struct Pile {
+    queued: HashSet<Garbage>,
+    queue: VecDeque<Garbage>,
+}
+
+impl Pile {
+    fn add(&mut self, garbage: Garbage) {
+        if self.queued.insert(garbage) {
+            self.queue.push_back(garbage);
+        }
+    }
+}
+
+// how much garbage is looked at when a snapshot is dropped, and at least
+// after each commit, so neither holds the lock for long
+const GC_BATCH: usize = 64;
+
+// how many rows a copy reads per lock
+const LOAD_BATCH: usize = 1024;
+
+#[derive(Debug, Default)]
+struct Store {
+    sheets: Vec<VersionedSheet>,
+    // the last committed version
+    version: Version,
+    // version -> how many snapshots are open at it
+    snapshots: BTreeMap<Version, usize>,
+    garbage: Pile,
+    next_key: u64,
+}
+
+impl Store {
+    fn sheet_at(&self, id: &Identifier, at: Version) -> Result<&VersionedSheet, Error> {
+        self.sheets
+            .iter()
+            .find(|sheet| sheet.id == *id && visible(sheet.created, sheet.removed, at))
+            .ok_or(Error::WalError(WalError::SheetNotFound { id: *id }))
+    }
+
+    // the index of the sheet as the commit in progress sees it
+    fn live_sheet(&self, id: &Identifier) -> Result<usize, Error> {
+        self.sheets
+            .iter()
+            .position(|sheet| sheet.id == *id && sheet.removed == LIVE)
+            .ok_or(Error::WalError(WalError::SheetNotFound { id: *id }))
+    }
+
+    fn sheets(&self, at: Version) -> Vec<SheetInfo> {
+        self.sheets
+            .iter()
+            .filter(|sheet| visible(sheet.created, sheet.removed, at))
+            .map(|sheet| SheetInfo {
+                id: sheet.id,
+                name: sheet.name.clone(),
+            })
+            .collect()
+    }
+
+    fn columns(&self, sheet: &Identifier, at: Version) -> Result<Vec<ColumnInfo>, Error> {
+        Ok(self
+            .sheet_at(sheet, at)?
+            .columns_at(at)
+            .map(|column| ColumnInfo {
+                id: column.id,
+                name: column.name.clone(),
+                value_type: column.value_type,
+            })
+            .collect())
+    }
+
+    fn row_count(&self, sheet: &Identifier, at: Version) -> Result<usize, Error> {
+        Ok(self.sheet_at(sheet, at)?.row_count_at(at))
+    }
+
+    fn cell(
+        &self,
+        sheet: &Identifier,
+        column: &Identifier,
+        row: usize,
+        at: Version,
+    ) -> Result<Value, Error> {
+        let sheet = self.sheet_at(sheet, at)?;
+        let column = sheet.column_at(column, at)?;
+        let Some(index) = sheet.row_index_at(row, at) else {
+            return Err(Error::WalError(WalError::RowOutOfRange {
+                row: row as u64,
+                rows: sheet.row_count_at(at),
+            }));
+        };
+        Ok(sheet.rows[index]
+            .versions
+            .iter()
+            .rev()
+            .find(|version| version.created <= at)
+            .and_then(|version| version.values.get(column.slot))
+            .cloned()
+            .unwrap_or(Value::Nil))
+    }
+
+    fn row_id(&self, sheet: &Identifier, row: usize, at: Version) -> Result<u64, Error> {
+        let sheet = self.sheet_at(sheet, at)?;
+        match sheet.row_index_at(row, at) {
+            Some(index) => Ok(sheet.rows[index].key),
+            None => Err(Error::WalError(WalError::RowOutOfRange {
+                row: row as u64,
+                rows: sheet.row_count_at(at),
+            })),
+        }
+    }
+
+    fn apply(
+        &mut self,
+        change: &Change,
+        version: Version,
+        touched: &mut Vec<Touched>,
+    ) -> Result<(), Error> {
+        match change {
+            Change::AddSheet { id, name } => {
+                let key = self.next_key;
+                self.next_key += 1;
+                self.sheets.push(VersionedSheet {
+                    key,
+                    id: *id,
+                    name: name.clone(),
+                    created: version,
+                    removed: LIVE,
+                    columns: Vec::new(),
+                    slots: 0,
+                    rows: Vec::new(),
+                    next_row: 0,
+                    removed_rows: 0,
+                    positions: Mutex::default(),
+                });
+                touched.push(Touched::AddedSheet { key });
+            }
+            Change::RemoveSheet { sheet } => {
+                self.live_sheet(sheet)?;
+                for existing in &mut self.sheets {
+                    if existing.id == *sheet && existing.removed == LIVE {
+                        existing.removed = version;
+                        touched.push(Touched::RemovedSheet { key: existing.key });
+                        self.garbage.add(Garbage::Sheet { key: existing.key });
+                    }
+                }
+            }
+            Change::AddColumn {
+                sheet,
+                id,
+                name,
+                value_type,
+            } => {
+                let index = self.live_sheet(sheet)?;
+                let sheet = &mut self.sheets[index];
+                sheet.columns.push(VersionedColumn {
+                    id: *id,
+                    name: name.clone(),
+                    value_type: *value_type,
+                    slot: sheet.slots,
+                    created: version,
+                    removed: LIVE,
+                });
+                sheet.slots += 1;
+                touched.push(Touched::AddedColumn { sheet: sheet.key });
+            }
+            Change::RemoveColumn { sheet, column } => {
+                let index = self.live_sheet(sheet)?;
+                let sheet = &mut self.sheets[index];
+                sheet.column_at(column, UNCOMMITTED)?;
+                for existing in &mut sheet.columns {
+                    if existing.id == *column && existing.removed == LIVE {
+                        existing.removed = version;
+                        let (sheet, slot) = (sheet.key, existing.slot);
+                        touched.push(Touched::RemovedColumn { sheet, slot });
+                        self.garbage.add(Garbage::Column { sheet, slot });
+                    }
+                }
+
+                // like an in-memory sheet, one without columns has no rows
+                if sheet.columns_at(UNCOMMITTED).next().is_none() {
+                    for index in 0..sheet.rows.len() {
+                        if sheet.rows[index].removed == LIVE {
+                            sheet.remove_row(index, version);
+                        }
+                    }
+                    touched.push(Touched::RemovedRows { sheet: sheet.key });
+                    self.garbage.add(Garbage::Rows { sheet: sheet.key });
+                }
+            }
+            Change::InsertRow { sheet, values } => {
+                let index = self.live_sheet(sheet)?;
+                let sheet = &mut self.sheets[index];
+                let columns = sheet.columns_at(UNCOMMITTED).collect::<Vec<_>>();
+                if values.len() != columns.len() {
+                    return Err(Error::SheetError(SheetError::InvalidRowLength {
+                        expected: columns.len(),
+                        got: values.len(),
+                    }));
+                }
+                if columns.is_empty() {
+                    return Ok(());
+                }
+
+                let mut row = vec![Value::Nil; sheet.slots];
+                for (value, column) in values.iter().zip(columns) {
+                    row[column.slot] = coerce(column, value.clone())?;
+                }
+                sheet.rows.push(Row {
+                    key: sheet.next_row,
+                    inserted: version,
+                    removed: LIVE,
+                    versions: vec![RowVersion {
+                        created: version,
+                        values: row,
+                    }],
+                });
+                sheet.next_row += 1;
+                touched.push(Touched::Row {
+                    sheet: sheet.key,
+                    row: sheet.rows.len() - 1,
+                });
+            }
+            Change::UpdateCell {
+                sheet,
+                column,
+                row,
+                value,
+            } => {
+                let index = self.live_sheet(sheet)?;
+                let sheet = &mut self.sheets[index];
+                let column = sheet.column_at(column, UNCOMMITTED)?;
+                let (slot, value) = (column.slot, coerce(column, value.clone())?);
+                let index = sheet.live_row(*row)?;
+                let slots = sheet.slots;
+                let versions = &mut sheet.rows[index].versions;
+                match versions.last_mut() {
+                    // changed earlier in the same commit, nobody can see it yet
+                    Some(latest) if latest.created == version => {
+                        latest.values.resize(slots, Value::Nil);
+                        latest.values[slot] = value;
+                    }
+                    latest => {
+                        let mut values =
+                            latest.map_or_else(Vec::new, |latest| latest.values.clone());
+                        values.resize(slots, Value::Nil);
+                        values[slot] = value;
+                        versions.push(RowVersion {
+                            created: version,
+                            values,
+                        });
+                        self.garbage.add(Garbage::Row {
+                            sheet: sheet.key,
+                            row: sheet.rows[index].key,
+                        });
+                    }
+                }
+                touched.push(Touched::Row {
+                    sheet: sheet.key,
+                    row: index,
+                });
+            }
+            Change::DeleteRow { sheet, row } => {
+                let index = self.live_sheet(sheet)?;
+                let sheet = &mut self.sheets[index];
+                let index = sheet.live_row(*row)?;
+                sheet.remove_row(index, version);
+                touched.push(Touched::RemovedRows { sheet: sheet.key });
+                self.garbage.add(Garbage::Rows { sheet: sheet.key });
+            }
+        }
+        Ok(())
+    }
+
+    // takes back everything a failed commit at `version` changed
+    fn abort(&mut self, version: Version, touched: Vec<Touched>) {
+        for change in touched.into_iter().rev() {
+            let key = match change {
+                Touched::AddedSheet { key } | Touched::RemovedSheet { key } => key,
+                Touched::AddedColumn { sheet }
+                | Touched::RemovedColumn { sheet, .. }
+                | Touched::RemovedRows { sheet }
+                | Touched::Row { sheet, .. } => sheet,
+            };
+            let Some(index) = self.sheets.iter().position(|sheet| sheet.key == key) else {
+                continue;
+            };
+            let sheet = &mut self.sheets[index];
+
+            match change {
+                Touched::AddedSheet { .. } => {
+                    self.sheets.remove(index);
+                }
+                Touched::RemovedSheet { .. } => sheet.removed = LIVE,
+                Touched::AddedColumn { .. } => {
+                    sheet.columns.pop();
+                    sheet.slots -= 1;
+                }
+                Touched::RemovedColumn { slot, .. } => {
+                    if let Some(column) = sheet.columns.iter_mut().find(|c| c.slot == slot) {
+                        column.removed = LIVE;
+                    }
+                }
+                Touched::RemovedRows { .. } => {
+                    for row in &mut sheet.rows {
+                        if row.removed == version {
+                            row.removed = LIVE;
+                            sheet.removed_rows -= 1;
+                        }
+                    }
+                }
+                Touched::Row { row, .. } => {
+                    // rows inserted by the commit are last, and taken back
+                    // last-first
+                    let Some(versions) = sheet.rows.get_mut(row).map(|row| &mut row.versions)
+                    else {
+                        continue;
+                    };
+                    versions.retain(|existing| existing.created != version);
+                    if versions.is_empty() {
+                        // and their ids given out again
+                        let key = sheet.rows.remove(row).key;
+                        sheet.next_row = sheet.next_row.min(key);
+                    }
+                }
+            }
+        }
+    }
+
+    // looks at up to `batch` pieces of garbage, dropping every version that
+    // isn't the latest and that no open snapshot can see any more
+    fn collect_garbage(&mut self, batch: usize) {
+        let oldest = self
+            .snapshots
+            .keys()
+            .next()
+            .copied()
+            .unwrap_or(self.version);
+        for _ in 0..batch.min(self.garbage.queue.len()) {
+            let Some(garbage) = self.garbage.queue.pop_front() else {
+                break;
+            };
+            match self.collect(garbage, oldest) {
+                true => self.garbage.queue.push_back(garbage),
+                false => {
+                    self.garbage.queued.remove(&garbage);
+                }
+            }
+        }
+
+        // only the latest version and the snapshots' are read at
+        for sheet in &mut self.sheets {
+            let positions = sheet
+                .positions
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner);
+            positions.retain(|at, _| *at == self.version || self.snapshots.contains_key(at));
+        }
+    }
+
+    // drops what `garbage` points at that's older than `oldest` and unseen,
+    // returning whether some of it has to wait for a snapshot to close
+    fn collect(&mut self, garbage: Garbage, oldest: Version) -> bool {
+        let key = match garbage {
+            Garbage::Sheet { key } => key,
+            Garbage::Column { sheet, .. }
+            | Garbage::Rows { sheet }
+            | Garbage::Row { sheet, .. } => sheet,
+        };
+        let Some(index) = self.sheets.iter().position(|sheet| sheet.key == key) else {
+            return false;
+        };
+        let sheet = &mut self.sheets[index];
+        match garbage {
+            Garbage::Sheet { .. } => {
+                if sheet.removed > oldest {
+                    return true;
+                }
+                self.sheets.remove(index);
+                false
+            }
+            Garbage::Column { slot, .. } => {
+                let Some(index) = sheet.columns.iter().position(|c| c.slot == slot) else {
+                    return false;
+                };
+                if sheet.columns[index].removed > oldest {
+                    return true;
+                }
+                sheet.columns.remove(index);
+                for version in sheet.rows.iter_mut().flat_map(|row| &mut row.versions) {
+                    if let Some(value) = version.values.get_mut(slot) {
+                        *value = Value::Nil;
+                    }
+                }
+                false
+            }
+            Garbage::Rows { .. } => {
+                let before = sheet.rows.len();
+                sheet.rows.retain(|row| row.removed > oldest);
+                if sheet.rows.len() < before {
+                    sheet.removed_rows -= before - sheet.rows.len();
+                    sheet.positions = Mutex::default();
+                }
+                sheet.removed_rows > 0
+            }
+            Garbage::Row { row, .. } => {
+                let Ok(index) = sheet.rows.binary_search_by_key(&row, |row| row.key) else {
+                    return false;
+                };
+                let versions = &mut sheet.rows[index].versions;
+
+                // keep the newest version, and any other that is the newest
+                // one some snapshot can see
+                let keep = (0..versions.len())
+                    .map(|i| match versions.get(i + 1) {
+                        Some(next) => self
+                            .snapshots
+                            .range(versions[i].created..next.created)
+                            .next()
+                            .is_some(),
+                        None => true,
+                    })
+                    .collect::<Vec<_>>();
+                let mut keep = keep.into_iter();
+                versions.retain(|_| keep.next().unwrap_or(true));
+                versions.len() > 1
+            }
+        }
+    }
+}
+
+fn coerce(column: &VersionedColumn, value: Value) -> Result<Value, Error> {
+    Column::new_with_set_id(column.id, column.name.clone(), column.value_type, None)
+        .coerce_value(value)
+}
+
+fn read(store: &RwLock<Store>) -> RwLockReadGuard<'_, Store> {
+    store.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write(store: &RwLock<Store>) -> RwLockWriteGuard<'_, Store> {
+    store.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+// copies `rows` of a sheet as of `at` into memory, with their ids, under a
+// single lock
+fn load_rows(
+    store: &RwLock<Store>,
+    info: &SheetInfo,
+    rows: Range<usize>,
+    at: Version,
+) -> Result<Sheet, Error> {
+    let store = read(store);
+    let versioned = store.sheet_at(&info.id, at)?;
+    let mut sheet = Sheet::new_with_set_id(info.id, info.name.clone(), Vec::new(), None);
+    for column in store.columns(&info.id, at)? {
+        sheet.adopt_column(&Column::new_with_set_id(
+            column.id,
+            column.name,
+            column.value_type,
+            None,
+        ));
+    }
+    let mut ids = Vec::new();
+    for (id, row) in versioned.rows_at(rows.start, rows.len(), at) {
+        for (column, value) in sheet.columns.iter_mut().zip(row) {
+            column.insert_value(value);
+        }
+        ids.push(id);
+    }
+    sheet.row_ids = RowIds::from_ids(ids, versioned.next_row);
+    Ok(sheet)
+}
+
+// copies the database as of `at` into memory, a batch of rows per lock so a
+// long copy doesn't hold up commits. What `at` sees can't change meanwhile.
+fn load(store: &RwLock<Store>, at: Version) -> Result<Database, Error> {
+    let mut database = Database::new_empty();
+    let sheets = read(store).sheets(at);
+    for info in sheets {
+        let mut sheet = load_rows(store, &info, 0..0, at)?;
+        let mut ids = Vec::new();
+        loop {
+            let start = ids.len();
+            let batch = load_rows(store, &info, start..start + LOAD_BATCH, at)?;
+            let rows = batch.row_ids.len();
+            if rows == 0 {
+                break;
+            }
+            for (column, from) in sheet.columns.iter_mut().zip(&batch.columns) {
+                for cell in &from.cells {
+                    column.insert_value(cell.value.clone());
+                }
+            }
+            ids.extend_from_slice(batch.row_ids.ids());
+        }
+        sheet.row_ids = RowIds::from_ids(ids, sheet.row_ids.next());
+        database.adopt_sheet(&sheet);
+    }
+    Ok(database)
+}
+
+/// A database that keeps old versions of its rows, so snapshots of it stay
+/// frozen at the moment they were taken while a single writer carries on
+/// committing. Snapshots can be sent to other threads.
+///
+/// Neither side waits on the other for longer than one read or one commit,
+/// since a snapshot holds no lock between reads, and a copy takes the lock
+/// for a batch of rows at a time. A version is dropped once it is neither the
+/// latest nor the newest one an open snapshot can see, by a later commit or
+/// dropped snapshot, which each look at a batch of old versions.
+pub(crate) struct VersionedDatabase {
+    store: Arc<RwLock<Store>>,
+}
+
+impl VersionedDatabase {
+    pub(crate) fn new(database: &Database) -> Result<VersionedDatabase, Error> {
+        let mut versioned = VersionedDatabase {
+            store: Arc::new(RwLock::new(Store::default())),
+        };
+        versioned.import(database)?;
+        Ok(versioned)
+    }
+
+    /// Applies `changes` as one commit: snapshots see all of them or none,
+    /// and if one fails none are applied
+    pub(crate) fn commit(&mut self, changes: &[Change]) -> Result<(), Error> {
+        let mut store = write(&self.store);
+        let version = store.version + 1;
+        let mut touched = Vec::new();
+        for change in changes {
+            if let Err(err) = store.apply(change, version, &mut touched) {
+                store.abort(version, touched);
+                return Err(err);
+            }
+        }
+        store.version = version;
+        // at least as much as the commit could have added
+        store.collect_garbage(GC_BATCH.max(changes.len()));
+        Ok(())
+    }
+
+    /// A read-only view of the database as of the last commit
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        let mut store = write(&self.store);
+        let version = store.version;
+        *store.snapshots.entry(version).or_default() += 1;
+        Snapshot {
+            store: Arc::clone(&self.store),
+            version,
+        }
+    }
+
+    /// How many row versions are kept, old ones included
+    pub(crate) fn row_versions(&self) -> usize {
+        read(&self.store)
+            .sheets
+            .iter()
+            .flat_map(|sheet| &sheet.rows)
+            .map(|row| row.versions.len())
+            .sum()
+    }
+}
+
+impl Storage for VersionedDatabase {
+    fn apply(&mut self, change: &Change) -> Result<(), Error> {
+        self.commit(std::slice::from_ref(change))
+    }
+
+    fn sheets(&mut self) -> Result<Vec<SheetInfo>, Error> {
+        let store = read(&self.store);
+        Ok(store.sheets(store.version))
+    }
+
+    fn columns(&mut self, sheet: &Identifier) -> Result<Vec<ColumnInfo>, Error> {
+        let store = read(&self.store);
+        store.columns(sheet, store.version)
+    }
+
+    fn row_count(&mut self, sheet: &Identifier) -> Result<usize, Error> {
+        let store = read(&self.store);
+        store.row_count(sheet, store.version)
+    }
+
+    fn cell(
+        &mut self,
+        sheet: &Identifier,
+        column: &Identifier,
+        row: usize,
+    ) -> Result<Value, Error> {
+        let store = read(&self.store);
+        store.cell(sheet, column, row, store.version)
+    }
+
+    fn row_id(&mut self, sheet: &Identifier, row: usize) -> Result<u64, Error> {
+        let store = read(&self.store);
+        store.row_id(sheet, row, store.version)
+    }
+
+    fn next_row_id(&mut self, sheet: &Identifier) -> Result<u64, Error> {
+        let store = read(&self.store);
+        Ok(store.sheet_at(sheet, store.version)?.next_row)
+    }
+
+    // ids only count up, so snapshots needn't see this
+    fn skip_row_ids(&mut self, sheet: &Identifier, next: u64) -> Result<(), Error> {
+        let mut store = write(&self.store);
+        let index = store.live_sheet(sheet)?;
+        let sheet = &mut store.sheets[index];
+        sheet.next_row = sheet.next_row.max(next);
+        Ok(())
+    }
+
+    fn load(&mut self) -> Result<Database, Error> {
+        let version = read(&self.store).version;
+        load(&self.store, version)
+    }
+}
+
+/// The database as it was when the snapshot was taken, whatever has been
+/// committed since. Dropping it lets the versions only it could see be
+/// collected.
+pub(crate) struct Snapshot {
+    store: Arc<RwLock<Store>>,
+    version: Version,
+}
+
+impl Snapshot {
+    pub(crate) fn sheets(&self) -> Vec<SheetInfo> {
+        read(&self.store).sheets(self.version)
+    }
+
+    pub(crate) fn find_sheet(&self, name: &str) -> Option<SheetInfo> {
+        self.sheets().into_iter().find(|sheet| sheet.name == name)
+    }
+
+    pub(crate) fn columns(&self, sheet: &Identifier) -> Result<Vec<ColumnInfo>, Error> {
+        read(&self.store).columns(sheet, self.version)
+    }
+
+    pub(crate) fn row_count(&self, sheet: &Identifier) -> Result<usize, Error> {
+        read(&self.store).row_count(sheet, self.version)
+    }
+
+    pub(crate) fn cell(
+        &self,
+        sheet: &Identifier,
+        column: &Identifier,
+        row: usize,
+    ) -> Result<Value, Error> {
+        read(&self.store).cell(sheet, column, row, self.version)
+    }
+
+    pub(crate) fn row(&self, sheet: &Identifier, row: usize) -> Result<Vec<Value>, Error> {
+        let store = read(&self.store);
+        store
+            .columns(sheet, self.version)?
+            .iter()
+            .map(|column| store.cell(sheet, &column.id, row, self.version))
+            .collect()
+    }
+
+    /// Runs a SELECT statement, see Query, on the sheet it names as the
+    /// snapshot sees it, a batch of rows at a time like Storage::query
+    pub(crate) fn query(&self, query: &Query) -> Result<Sheet, Error> {
+        let info = self.find_sheet(&query.sheet).ok_or_else(|| {
+            Error::DatabaseError(DatabaseError::SheetNotFound {
+                sheet_name: query.sheet.clone(),
+            })
+        })?;
+        let rows = self.row_count(&info.id)?;
+        query_in_batches(query, rows, |range| {
+            load_rows(&self.store, &info, range, self.version)
+        })
+    }
+
+    /// Copies the snapshot into an in-memory `Database`, e.g. to save it
+    pub(crate) fn load(&self) -> Result<Database, Error> {
+        load(&self.store, self.version)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut store = write(&self.store);
+        if let Some(count) = store.snapshots.get_mut(&self.version) {
+            *count -= 1;
+            if *count == 0 {
+                store.snapshots.remove(&self.version);
+            }
+        }
+        store.collect_garbage(GC_BATCH);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::traits::PrettyPrintable;
+
+    fn counters() -> (VersionedDatabase, Identifier, Identifier) {
+        let (sheet, column) = (Identifier::new(), Identifier::new());
+        let mut database = VersionedDatabase::new(&Database::new_empty()).unwrap();
+        database
+            .commit(&[
+                Change::AddSheet {
+                    id: sheet,
+                    name: "counters".to_string(),
+                },
+                Change::AddColumn {
+                    sheet,
+                    id: column,
+                    name: "n".to_string(),
+                    value_type: Type::Int,
+                },
+                Change::InsertRow {
+                    sheet,
+                    values: vec![Value::Int(0)],
+                },
+            ])
+            .unwrap();
+        (database, sheet, column)
+    }
+
+    #[test]
+    fn snapshots_stay_frozen_while_the_writer_commits() {
+        let (mut database, sheet, column) = counters();
+        let snapshot = database.snapshot();
+        let frozen = snapshot.load().unwrap().pretty_print(0);
+
+        // a reader on another thread sees the same thing throughout
+        let reader = std::thread::spawn(move || {
+            for _ in 0..1000 {
+                assert_eq!(snapshot.row_count(&sheet).unwrap(), 1);
+                assert_eq!(snapshot.row(&sheet, 0).unwrap(), vec![Value::Int(0)]);
+            }
+            snapshot
+        });
+        for n in 1..=1000 {
+            database
+                .commit(&[
+                    Change::UpdateCell {
+                        sheet,
+                        column,
+                        row: 0,
+                        value: Value::Int(n),
+                    },
+                    Change::InsertRow {
+                        sheet,
+                        values: vec![Value::Int(n)],
+                    },
+                ])
+                .unwrap();
+        }
+        let snapshot = reader.join().unwrap();
+        assert_eq!(snapshot.load().unwrap().pretty_print(0), frozen);
+        assert_eq!(snapshot.cell(&sheet, &column, 0).unwrap(), Value::Int(0));
+
+        // later changes to the schema don't reach it either
+        database
+            .commit(&[Change::RemoveColumn { sheet, column }])
+            .unwrap();
+        database.commit(&[Change::RemoveSheet { sheet }]).unwrap();
+        assert_eq!(snapshot.load().unwrap().pretty_print(0), frozen);
+        assert_eq!(snapshot.find_sheet("counters").unwrap().id, sheet);
+        assert_eq!(snapshot.columns(&sheet).unwrap()[0].name, "n");
+        assert!(database.find_sheet("counters").unwrap().is_none());
+
+        // a failed commit leaves no trace
+        let later = database.snapshot();
+        assert!(database
+            .commit(&[
+                Change::AddSheet {
+                    id: sheet,
+                    name: "counters".to_string(),
+                },
+                Change::InsertRow {
+                    sheet: Identifier::new(),
+                    values: Vec::new(),
+                },
+            ])
+            .is_err());
+        assert!(later.sheets().is_empty());
+        assert!(database.sheets().unwrap().is_empty());
+    }
+
+    #[test]
+    fn snapshots_find_rows_by_position_while_deleted_ones_await_collection() {
+        let (mut database, sheet, column) = counters();
+        let inserts = (1..=600)
+            .map(|n| Change::InsertRow {
+                sheet,
+                values: vec![Value::Int(n)],
+            })
+            .collect::<Vec<_>>();
+        database.commit(&inserts).unwrap();
+        let snapshot = database.snapshot();
+        // every even row, ids being the counters' starting values
+        let deletes = (0..=600)
+            .step_by(2)
+            .map(|row| Change::DeleteRow { sheet, row })
+            .collect::<Vec<_>>();
+        database.commit(&deletes).unwrap();
+
+        assert_eq!(database.row_count(&sheet).unwrap(), 300);
+        assert_eq!(database.cell(&sheet, &column, 0).unwrap(), Value::Int(1));
+        assert_eq!(database.row_id(&sheet, 299).unwrap(), 599);
+        assert_eq!(snapshot.row_count(&sheet).unwrap(), 601);
+        assert_eq!(snapshot.row(&sheet, 600).unwrap(), vec![Value::Int(600)]);
+
+        let query = Query::parse("select n from counters where n > 595 or rowid = 4").unwrap();
+        let picked = |sheet: Sheet| {
+            let cells = sheet.columns[0].cells.iter();
+            cells.map(|cell| cell.value.clone()).collect::<Vec<_>>()
+        };
+        let ints = |ns: &[i64]| ns.iter().map(|n| Value::Int(*n)).collect::<Vec<_>>();
+        assert_eq!(
+            picked(snapshot.query(&query).unwrap()),
+            ints(&[4, 596, 597, 598, 599, 600])
+        );
+        assert_eq!(
+            picked(Storage::query(&mut database, &query).unwrap()),
+            ints(&[597, 599])
+        );
+        assert!(matches!(
+            snapshot.query(&Query::parse("select * from nobody").unwrap()),
+            Err(Error::DatabaseError(DatabaseError::SheetNotFound { .. }))
+        ));
+
+        // both versions' positions are kept until the snapshot is dropped,
+        // which lets the deleted rows go
+        let cached = |database: &VersionedDatabase| {
+            let store = read(&database.store);
+            let positions = store.sheets[0].positions.lock().unwrap().len();
+            (positions, store.sheets[0].rows.len())
+        };
+        assert_eq!(cached(&database), (2, 601));
+        drop(snapshot);
+        assert_eq!(cached(&database), (0, 300));
+        assert_eq!(
+            database.cell(&sheet, &column, 299).unwrap(),
+            Value::Int(599)
+        );
+    }
+
+    #[test]
+    fn old_versions_are_collected_once_unseen() {
+        let (mut database, sheet, column) = counters();
+        let update = |n| Change::UpdateCell {
+            sheet,
+            column,
+            row: 0,
+            value: Value::Int(n),
+        };
+
+        // nobody is looking, so each update replaces the last
+        for n in 1..=10 {
+            database.commit(&[update(n)]).unwrap();
+        }
+        assert_eq!(database.row_versions(), 1);
+
+        let first = database.snapshot();
+        for n in 11..=20 {
+            database.commit(&[update(n)]).unwrap();
+        }
+        let second = database.snapshot();
+        for n in 21..=30 {
+            database.commit(&[update(n)]).unwrap();
+        }
+        // the latest, and the newest each snapshot can see
+        assert_eq!(database.row_versions(), 3);
+        assert_eq!(first.cell(&sheet, &column, 0).unwrap(), Value::Int(10));
+        assert_eq!(second.cell(&sheet, &column, 0).unwrap(), Value::Int(20));
+
+        drop(first);
+        assert_eq!(database.row_versions(), 2);
+        drop(second);
+        assert_eq!(database.row_versions(), 1);
+        assert_eq!(database.cell(&sheet, &column, 0).unwrap(), Value::Int(30));
+
+        // a deleted row lives on only while a snapshot can see it
+        let before = database.snapshot();
+        database
+            .commit(&[Change::DeleteRow { sheet, row: 0 }])
+            .unwrap();
+        assert_eq!(database.row_count(&sheet).unwrap(), 0);
+        assert_eq!(before.cell(&sheet, &column, 0).unwrap(), Value::Int(30));
+        assert_eq!(database.row_versions(), 1);
+        drop(before);
+        assert_eq!(database.row_versions(), 0);
+    }
+
+    #[test]
+    fn copies_and_collection_go_a_batch_at_a_time() {
+        let (mut database, sheet, column) = counters();
+        let rows = (1..3 * LOAD_BATCH as i64)
+            .map(|n| Change::InsertRow {
+                sheet,
+                values: vec![Value::Int(n)],
+            })
+            .collect::<Vec<_>>();
+        database.commit(&rows).unwrap();
+        let update = |row, n| Change::UpdateCell {
+            sheet,
+            column,
+            row,
+            value: Value::Int(n),
+        };
+
+        // more rows than a batch changed while this snapshot is open
+        let pinned = database.snapshot();
+        let expected = pinned.load().unwrap().pretty_print(0);
+        let updates = (0..2 * GC_BATCH as u64)
+            .map(|row| update(row, -1))
+            .collect::<Vec<_>>();
+        database.commit(&updates).unwrap();
+        database
+            .commit(&[Change::DeleteRow { sheet, row: 5 }])
+            .unwrap();
+
+        // old versions nobody sees go in turn with the pinned ones
+        for n in 0..100 {
+            database
+                .commit(&[update(3 * LOAD_BATCH as u64 - 2, n)])
+                .unwrap();
+        }
+        let pinned_versions = 3 * LOAD_BATCH + 2 * GC_BATCH;
+        assert!((pinned_versions..pinned_versions + 3).contains(&database.row_versions()));
+        assert_eq!(pinned.load().unwrap().pretty_print(0), expected);
+        assert_eq!(
+            database.load().unwrap().columns[0].columns[0].cells.len(),
+            3 * LOAD_BATCH - 1
+        );
+
+        drop(pinned);
+        for _ in 0..pinned_versions / GC_BATCH {
+            database.commit(&[update(0, 0)]).unwrap();
+        }
+        assert_eq!(database.row_versions(), 3 * LOAD_BATCH - 1);
+    }
+}
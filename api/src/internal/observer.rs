@@ -0,0 +1,355 @@
+use crate::internal::data_value::Value;
+use crate::internal::database::Database;
+use crate::internal::id::Identifier;
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+/// A change to one cell, of the row with id `row`, see row_id.rs. Adding or
+/// removing sheets and columns isn't reported, only the rows in them.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Event {
+    Inserted {
+        sheet: Identifier,
+        column: Identifier,
+        row: u64,
+        new: Value,
+    },
+    Updated {
+        sheet: Identifier,
+        column: Identifier,
+        row: u64,
+        old: Value,
+        new: Value,
+    },
+    Deleted {
+        sheet: Identifier,
+        column: Identifier,
+        row: u64,
+        old: Value,
+    },
+}
+
+impl Event {
+    pub(crate) fn sheet(&self) -> &Identifier {
+        match self {
+            Event::Inserted { sheet, .. }
+            | Event::Updated { sheet, .. }
+            | Event::Deleted { sheet, .. } => sheet,
+        }
+    }
+
+    /// The event that undoes this one
+    pub(crate) fn inverse(self) -> Event {
+        match self {
+            Event::Inserted {
+                sheet,
+                column,
+                row,
+                new,
+            } => Event::Deleted {
+                sheet,
+                column,
+                row,
+                old: new,
+            },
+            Event::Updated {
+                sheet,
+                column,
+                row,
+                old,
+                new,
+            } => Event::Updated {
+                sheet,
+                column,
+                row,
+                old: new,
+                new: old,
+            },
+            Event::Deleted {
+                sheet,
+                column,
+                row,
+                old,
+            } => Event::Inserted {
+                sheet,
+                column,
+                row,
+                new: old,
+            },
+        }
+    }
+}
+
+// defaults to every sheet, one event per call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct SubscribeOptions {
+    // only events on this sheet
+    pub(crate) sheet: Option<Identifier>,
+    // all events of a transaction in one call, rather than one call each
+    pub(crate) batch: bool,
+}
+
+type Callback = Rc<RefCell<dyn FnMut(&[Event])>>;
+
+struct Subscriber {
+    id: u64,
+    options: SubscribeOptions,
+    callback: Callback,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    subscribers: Vec<Subscriber>,
+}
+
+/// The subscribers of one database. A copy of the database starts without
+/// any, they only hear about the database they subscribed to.
+#[derive(Default)]
+pub(crate) struct Observers {
+    registry: Rc<RefCell<Registry>>,
+}
+
+impl Observers {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.registry.borrow().subscribers.is_empty()
+    }
+
+    /// Delivers `events` to every subscriber, in order, before returning. A
+    /// subscriber that is already being called, because it caused these
+    /// events, doesn't hear about them.
+    pub(crate) fn notify(&self, events: &[Event]) {
+        if events.is_empty() {
+            return;
+        }
+        // copied out, so callbacks can subscribe and unsubscribe
+        let subscribers = self
+            .registry
+            .borrow()
+            .subscribers
+            .iter()
+            .map(|subscriber| (subscriber.options, subscriber.callback.clone()))
+            .collect::<Vec<_>>();
+
+        for (options, callback) in subscribers {
+            let Ok(mut callback) = callback.try_borrow_mut() else {
+                continue;
+            };
+            let wanted = events
+                .iter()
+                .filter(|event| options.sheet.is_none_or(|sheet| *event.sheet() == sheet));
+            if options.batch {
+                let batch = wanted.cloned().collect::<Vec<_>>();
+                if !batch.is_empty() {
+                    callback(&batch);
+                }
+            } else {
+                for event in wanted {
+                    callback(std::slice::from_ref(event));
+                }
+            }
+        }
+    }
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Observers::default()
+    }
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Observers")
+            .field("subscribers", &self.registry.borrow().subscribers.len())
+            .finish()
+    }
+}
+
+/// Keeps a subscriber registered. Dropping it unsubscribes.
+pub(crate) struct Subscription {
+    registry: Weak<RefCell<Registry>>,
+    id: u64,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(registry) = self.registry.upgrade() {
+            registry
+                .borrow_mut()
+                .subscribers
+                .retain(|subscriber| subscriber.id != self.id);
+        }
+    }
+}
+
+impl Database {
+    /// Calls `callback` with the cells each committed transaction inserted,
+    /// updated or deleted, before the transaction returns. Undoing a change
+    /// through history::History reports the opposite change.
+    pub(crate) fn subscribe(
+        &self,
+        options: SubscribeOptions,
+        callback: impl FnMut(&[Event]) + 'static,
+    ) -> Subscription {
+        let mut registry = self.observers.registry.borrow_mut();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.subscribers.push(Subscriber {
+            id,
+            options,
+            callback: Rc::new(RefCell::new(callback)),
+        });
+        Subscription {
+            registry: Rc::downgrade(&self.observers.registry),
+            id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::data_type::Type;
+    use crate::internal::history::History;
+    use crate::internal::wal::Operation;
+
+    fn people() -> (Database, Identifier, Identifier) {
+        let mut database = Database::new_empty();
+        let (sheet, columns) = database
+            .transaction(|tx| tx.add_sheet("people", &[("name", Type::Str)]))
+            .unwrap();
+        (database, sheet, columns[0])
+    }
+
+    fn str(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+
+    #[test]
+    fn subscribers_hear_committed_changes_until_dropped() {
+        let (mut database, sheet, name) = people();
+        let (each, batches) = (Rc::new(RefCell::new(Vec::new())), Rc::new(RefCell::new(0)));
+        let seen = each.clone();
+        let subscription = database.subscribe(SubscribeOptions::default(), move |events| {
+            seen.borrow_mut().extend_from_slice(events)
+        });
+        let calls = batches.clone();
+        let batched = database.subscribe(
+            SubscribeOptions {
+                sheet: Some(sheet),
+                batch: true,
+            },
+            move |_| *calls.borrow_mut() += 1,
+        );
+
+        database
+            .transaction(|tx| {
+                tx.apply(Operation::InsertRow {
+                    sheet,
+                    values: vec![str("ada")],
+                })?;
+                tx.apply(Operation::UpdateCell {
+                    sheet,
+                    column: name,
+                    row: 0,
+                    value: str("ada lovelace"),
+                })?;
+                // rolled back, so never heard of
+                let _ = tx.savepoint(|tx| {
+                    tx.apply(Operation::InsertRow {
+                        sheet,
+                        values: vec![str("grace")],
+                    })?;
+                    tx.apply(Operation::InsertRow {
+                        sheet,
+                        values: Vec::new(),
+                    })
+                });
+                tx.apply(Operation::DeleteRow { sheet, row: 0 })
+            })
+            .unwrap();
+        assert_eq!(
+            *each.borrow(),
+            vec![
+                Event::Inserted {
+                    sheet,
+                    column: name,
+                    row: 0,
+                    new: str("ada"),
+                },
+                Event::Updated {
+                    sheet,
+                    column: name,
+                    row: 0,
+                    old: str("ada"),
+                    new: str("ada lovelace"),
+                },
+                Event::Deleted {
+                    sheet,
+                    column: name,
+                    row: 0,
+                    old: str("ada lovelace"),
+                },
+            ]
+        );
+        assert_eq!(*batches.borrow(), 1);
+
+        // a failed transaction reports nothing
+        assert!(database
+            .transaction(|tx| tx.apply(Operation::DeleteRow { sheet, row: 0 }))
+            .is_err());
+        assert_eq!(each.borrow().len(), 3);
+
+        drop(subscription);
+        drop(batched);
+        database
+            .transaction(|tx| {
+                tx.apply(Operation::InsertRow {
+                    sheet,
+                    values: vec![str("grace")],
+                })
+            })
+            .unwrap();
+        assert_eq!(each.borrow().len(), 3);
+        assert_eq!(*batches.borrow(), 1);
+    }
+
+    #[test]
+    fn undo_reports_the_opposite_change() {
+        let (database, sheet, name) = people();
+        let mut history = History::new(database);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let events = seen.clone();
+        let _subscription = history
+            .database()
+            .subscribe(SubscribeOptions::default(), move |batch| {
+                events.borrow_mut().extend_from_slice(batch)
+            });
+
+        history
+            .apply(Operation::InsertRow {
+                sheet,
+                values: vec![str("ada")],
+            })
+            .unwrap();
+        history.undo();
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                Event::Inserted {
+                    sheet,
+                    column: name,
+                    row: 0,
+                    new: str("ada"),
+                },
+                Event::Deleted {
+                    sheet,
+                    column: name,
+                    row: 0,
+                    old: str("ada"),
+                },
+            ]
+        );
+    }
+}
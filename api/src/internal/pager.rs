@@ -0,0 +1,1365 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::column::Column;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::Value;
+use crate::internal::errors::Error;
+use crate::internal::id::Identifier;
+use crate::internal::row_id::RowIdError;
+use crate::internal::sheet::SheetError;
+use crate::internal::storage::{Change, ColumnInfo, SheetInfo, Storage};
+use crate::internal::traits::Serializable;
+use crate::internal::wal::{self, WalError};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// every paged file starts with "baseboredpage" and a format version as an
+// ascii digit. Version 1 files have no row ids, and number their rows from 0.
+const PAGE_MAGIC: [u8; 14] = *b"baseboredpage2";
+const PAGE_MAGIC_V1: [u8; 14] = *b"baseboredpage1";
+
+/// Size of every page of a paged file, the header page included
+pub(crate) const PAGE_SIZE: usize = 4096;
+
+// u32 next page + u16 length
+const CATALOG_PAGE_HEADER: usize = 6;
+
+// u16 cell count + u16 length of the cells
+const DATA_PAGE_HEADER: usize = 4;
+
+// the largest cell that fits in a page
+const MAX_CELL: usize = PAGE_SIZE - DATA_PAGE_HEADER;
+
+pub(crate) type PageId = u32;
+
+fn page_offset(page: PageId) -> u64 {
+    page as u64 * PAGE_SIZE as u64
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PageOptions {
+    // how much memory the buffer pool may use for cached pages. At least one
+    // page is always cached. The catalog of sheets and columns is kept in
+    // memory on top of this.
+    pub(crate) memory_budget: usize,
+}
+
+impl Default for PageOptions {
+    fn default() -> Self {
+        Self {
+            memory_budget: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// The pages of a file cached in memory, up to a fixed number. When full, the
+/// least recently used page is evicted, and written back first if it changed.
+pub(crate) struct BufferPool {
+    file: File,
+    capacity: usize,
+    frames: HashMap<PageId, Frame>,
+    // time of last use -> page, least recent first
+    recency: BTreeMap<u64, PageId>,
+    clock: u64,
+}
+
+struct Frame {
+    bytes: Vec<u8>,
+    dirty: bool,
+    used: u64,
+}
+
+impl BufferPool {
+    pub(crate) fn new(file: File, memory_budget: usize) -> Self {
+        Self {
+            file,
+            capacity: (memory_budget / PAGE_SIZE).max(1),
+            frames: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// How many pages are cached
+    pub(crate) fn resident(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub(crate) fn read(&mut self, page: PageId) -> Result<&[u8], Error> {
+        if self.frames.contains_key(&page) {
+            self.touch(page);
+        } else {
+            let mut bytes = vec![0; PAGE_SIZE];
+            self.file
+                .seek(SeekFrom::Start(page_offset(page)))
+                .and_then(|_| self.file.read_exact(&mut bytes))
+                .map_err(Error::Io)?;
+            self.insert(page, bytes, false)?;
+        }
+        Ok(&self.frames[&page].bytes)
+    }
+
+    /// Replaces the contents of `page`, padding them to the page size. They
+    /// reach the file when the page is evicted or flushed.
+    pub(crate) fn write(&mut self, page: PageId, mut bytes: Vec<u8>) -> Result<(), Error> {
+        bytes.resize(PAGE_SIZE, 0);
+        match self.frames.get_mut(&page) {
+            Some(frame) => {
+                frame.bytes = bytes;
+                frame.dirty = true;
+                self.touch(page);
+                Ok(())
+            }
+            None => self.insert(page, bytes, true),
+        }
+    }
+
+    /// Writes every changed page back and syncs the file
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        let mut dirty = self
+            .frames
+            .iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(page, _)| *page)
+            .collect::<Vec<_>>();
+        dirty.sort_unstable();
+        for page in dirty {
+            self.write_back(page)?;
+        }
+        self.file.sync_data().map_err(Error::Io)
+    }
+
+    fn touch(&mut self, page: PageId) {
+        self.clock += 1;
+        if let Some(frame) = self.frames.get_mut(&page) {
+            self.recency.remove(&frame.used);
+            frame.used = self.clock;
+            self.recency.insert(self.clock, page);
+        }
+    }
+
+    fn insert(&mut self, page: PageId, bytes: Vec<u8>, dirty: bool) -> Result<(), Error> {
+        if self.frames.len() >= self.capacity {
+            if let Some((&used, &victim)) = self.recency.first_key_value() {
+                // write back before dropping the frame, so a failed write
+                // loses nothing
+                self.write_back(victim)?;
+                self.recency.remove(&used);
+                self.frames.remove(&victim);
+            }
+        }
+
+        self.clock += 1;
+        self.recency.insert(self.clock, page);
+        self.frames.insert(
+            page,
+            Frame {
+                bytes,
+                dirty,
+                used: self.clock,
+            },
+        );
+        Ok(())
+    }
+
+    fn write_back(&mut self, page: PageId) -> Result<(), Error> {
+        let Some(frame) = self.frames.get_mut(&page) else {
+            return Ok(());
+        };
+        if frame.dirty {
+            self.file
+                .seek(SeekFrom::Start(page_offset(page)))
+                .and_then(|_| self.file.write_all(&frame.bytes))
+                .map_err(Error::Io)?;
+            frame.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+// a page of a column's cells, holding rows first_row..first_row + rows
+#[derive(Debug, Clone, Copy)]
+struct PageRun {
+    page: PageId,
+    first_row: usize,
+    rows: usize,
+}
+
+#[derive(Debug, Clone)]
+struct PagedColumn {
+    id: Identifier,
+    name: String,
+    value_type: Type,
+    runs: Vec<PageRun>,
+    rows: usize,
+}
+
+impl PagedColumn {
+    fn new(id: Identifier, name: String, value_type: Type) -> Self {
+        Self {
+            id,
+            name,
+            value_type,
+            runs: Vec::new(),
+            rows: 0,
+        }
+    }
+
+    // the same checks and casts as an in-memory column
+    fn coerce_value(&self, value: Value) -> Result<Value, Error> {
+        Column::new_with_set_id(self.id, self.name.clone(), self.value_type, None)
+            .coerce_value(value)
+    }
+
+    // the run holding `row`, which must be in range
+    fn run_of(&self, row: usize) -> usize {
+        self.runs
+            .partition_point(|run| run.first_row + run.rows <= row)
+    }
+}
+
+// the ids first_id..first_id + rows, of the rows first_row..first_row + rows
+#[derive(Debug, Clone, Copy)]
+struct IdRun {
+    first_id: u64,
+    first_row: usize,
+    rows: usize,
+}
+
+#[derive(Debug, Clone)]
+struct PagedSheet {
+    id: Identifier,
+    name: String,
+    columns: Vec<PagedColumn>,
+    // the ids of the rows, see row_id.rs, as runs of consecutive ids, so
+    // they take room for each deleted row rather than each row
+    ids: Vec<IdRun>,
+    // the id the next row gets
+    next_id: u64,
+}
+
+impl PagedSheet {
+    fn new(id: Identifier, name: String) -> Self {
+        Self {
+            id,
+            name,
+            columns: Vec::new(),
+            ids: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    fn rows(&self) -> usize {
+        self.columns.first().map_or(0, |column| column.rows)
+    }
+
+    fn row_id(&self, row: usize) -> Option<u64> {
+        let run = self.ids[self
+            .ids
+            .partition_point(|run| run.first_row + run.rows <= row)..]
+            .first()?;
+        Some(run.first_id + (row - run.first_row) as u64)
+    }
+
+    // the position of the row with id `id`
+    fn find_row(&self, id: u64) -> Result<usize, Error> {
+        let runs = &self.ids[self
+            .ids
+            .partition_point(|run| run.first_id + run.rows as u64 <= id)..];
+        match runs.first() {
+            Some(run) if run.first_id <= id => Ok(run.first_row + (id - run.first_id) as usize),
+            _ => Err(Error::RowIdError(RowIdError::NotFound {
+                sheet_name: self.name.clone(),
+                id,
+            })),
+        }
+    }
+
+    // gives a row added at the end the next id
+    fn push_id(&mut self) {
+        let row = self.ids.last().map_or(0, |run| run.first_row + run.rows);
+        match self.ids.last_mut() {
+            Some(run) if run.first_id + run.rows as u64 == self.next_id => run.rows += 1,
+            _ => self.ids.push(IdRun {
+                first_id: self.next_id,
+                first_row: row,
+                rows: 1,
+            }),
+        }
+        self.next_id += 1;
+    }
+
+    // removes the id of the row at `row`, which must be in range, splitting
+    // its run in two
+    fn remove_id(&mut self, row: usize) {
+        let index = self
+            .ids
+            .partition_point(|run| run.first_row + run.rows <= row);
+        let run = self.ids[index];
+        let before = row - run.first_row;
+        let mut split = Vec::new();
+        if before > 0 {
+            split.push(IdRun {
+                rows: before,
+                ..run
+            });
+        }
+        if run.rows > before + 1 {
+            split.push(IdRun {
+                first_id: run.first_id + before as u64 + 1,
+                first_row: row + 1,
+                rows: run.rows - before - 1,
+            });
+        }
+        let kept = usize::from(before > 0);
+        self.ids.splice(index..=index, split);
+        for moved in &mut self.ids[index + kept..] {
+            moved.first_row -= 1;
+        }
+    }
+}
+
+fn encode_cell(value: &Value) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    wal::write_value(value, &mut bytes);
+    if bytes.len() > MAX_CELL {
+        return Err(Error::PagerError(PagerError::CellTooLarge {
+            size: bytes.len(),
+        }));
+    }
+    Ok(bytes)
+}
+
+// all numbers are BE
+// u16 cell_count
+// u16 length: bytes of cells that follow
+// u8[length] cells: each a u8 type followed by its payload, see Value::write_payload
+fn empty_data_page() -> Vec<u8> {
+    vec![0; DATA_PAGE_HEADER]
+}
+
+fn push_cell(page: &mut Vec<u8>, cell: &[u8]) {
+    let count = u16::from_be_bytes([page[0], page[1]]) + 1;
+    page.extend_from_slice(cell);
+    let length = (page.len() - DATA_PAGE_HEADER) as u16;
+    page[..2].copy_from_slice(&count.to_be_bytes());
+    page[2..4].copy_from_slice(&length.to_be_bytes());
+}
+
+// the used part of a data page
+fn data_page(bytes: &[u8], page: PageId) -> Result<Vec<u8>, Error> {
+    let length = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+    if length > MAX_CELL {
+        return Err(Error::PagerError(PagerError::CorruptPage { page }));
+    }
+    Ok(bytes[..DATA_PAGE_HEADER + length].to_vec())
+}
+
+fn read_cells(bytes: &[u8], page: PageId) -> Result<Vec<Value>, Error> {
+    let bytes = data_page(bytes, page)?;
+    let count = u16::from_be_bytes([bytes[0], bytes[1]]);
+    let mut deserializer = ByteDeserializer::new(&bytes[DATA_PAGE_HEADER..]);
+    let values = (0..count)
+        .map(|_| wal::read_value(&mut deserializer))
+        .collect::<Result<Vec<_>, Error>>()
+        .map_err(|_| Error::PagerError(PagerError::CorruptPage { page }))?;
+    if !deserializer.remaining_bytes().is_empty() {
+        return Err(Error::PagerError(PagerError::CorruptPage { page }));
+    }
+    Ok(values)
+}
+
+// pages in the file and the ones free for reuse
+struct Pages {
+    pool: BufferPool,
+    count: u32,
+    free: Vec<PageId>,
+}
+
+impl Pages {
+    fn allocate(&mut self) -> PageId {
+        self.free.pop().unwrap_or_else(|| {
+            self.count += 1;
+            self.count - 1
+        })
+    }
+
+    fn release(&mut self, column: &PagedColumn) {
+        self.free.extend(column.runs.iter().map(|run| run.page));
+    }
+
+    // appends cells to the end of the column, filling its last page first. On
+    // error the column is left as it was.
+    fn append(
+        &mut self,
+        column: &mut PagedColumn,
+        cells: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Result<(), Error> {
+        let (runs, rows) = (column.runs.len(), column.rows);
+        let last_rows = column.runs.last().map(|run| run.rows);
+        let result = self.append_cells(column, cells);
+        if result.is_err() {
+            let added = column.runs.split_off(runs);
+            self.free.extend(added.iter().map(|run| run.page));
+            if let (Some(run), Some(last_rows)) = (column.runs.last_mut(), last_rows) {
+                run.rows = last_rows;
+            }
+            column.rows = rows;
+        }
+        result
+    }
+
+    fn append_cells(
+        &mut self,
+        column: &mut PagedColumn,
+        cells: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Result<(), Error> {
+        let mut last = match column.runs.last() {
+            Some(run) => Some((run.page, data_page(self.pool.read(run.page)?, run.page)?)),
+            None => None,
+        };
+
+        for cell in cells {
+            let page = match last.take() {
+                Some((page, bytes)) if bytes.len() + cell.len() <= PAGE_SIZE => (page, bytes),
+                full => {
+                    if let Some((page, bytes)) = full {
+                        self.pool.write(page, bytes)?;
+                    }
+                    let page = self.allocate();
+                    column.runs.push(PageRun {
+                        page,
+                        first_row: column.rows,
+                        rows: 0,
+                    });
+                    (page, empty_data_page())
+                }
+            };
+            let (page, mut bytes) = page;
+            push_cell(&mut bytes, &cell);
+            if let Some(run) = column.runs.last_mut() {
+                run.rows += 1;
+            }
+            column.rows += 1;
+            last = Some((page, bytes));
+        }
+
+        if let Some((page, bytes)) = last {
+            self.pool.write(page, bytes)?;
+        }
+        Ok(())
+    }
+
+    // replaces the cells of a run, spilling into new pages after it if they
+    // no longer fit in one
+    fn rewrite(
+        &mut self,
+        column: &mut PagedColumn,
+        run: usize,
+        cells: &[Vec<u8>],
+    ) -> Result<(), Error> {
+        let mut pages = vec![(column.runs[run].page, empty_data_page(), 0)];
+        for cell in cells {
+            let needs_page = pages
+                .last()
+                .is_some_and(|(_, bytes, _)| bytes.len() + cell.len() > PAGE_SIZE);
+            if needs_page {
+                pages.push((self.allocate(), empty_data_page(), 0));
+            }
+            if let Some((_, bytes, rows)) = pages.last_mut() {
+                push_cell(bytes, cell);
+                *rows += 1;
+            }
+        }
+
+        let mut first_row = column.runs[run].first_row;
+        let mut runs = Vec::new();
+        for (page, bytes, rows) in pages {
+            self.pool.write(page, bytes)?;
+            runs.push(PageRun {
+                page,
+                first_row,
+                rows,
+            });
+            first_row += rows;
+        }
+        column.runs.splice(run..=run, runs);
+        Ok(())
+    }
+
+    // the cells of the run holding `row`, and where the row is among them
+    fn cells_around(
+        &mut self,
+        column: &PagedColumn,
+        row: usize,
+    ) -> Result<(Vec<Vec<u8>>, usize), Error> {
+        let PageRun {
+            page, first_row, ..
+        } = column.runs[column.run_of(row)];
+        let cells = read_cells(self.pool.read(page)?, page)?
+            .iter()
+            .map(encode_cell)
+            .collect::<Result<Vec<_>, Error>>()?;
+        if row - first_row >= cells.len() {
+            return Err(Error::PagerError(PagerError::CorruptPage { page }));
+        }
+        Ok((cells, row - first_row))
+    }
+
+    // replaces the cell of one row
+    fn update(&mut self, column: &mut PagedColumn, row: usize, cell: Vec<u8>) -> Result<(), Error> {
+        let (mut cells, at) = self.cells_around(column, row)?;
+        cells[at] = cell;
+        self.rewrite(column, column.run_of(row), &cells)
+    }
+
+    // puts a cell in at `row`, moving the rows from there on down one
+    fn insert(&mut self, column: &mut PagedColumn, row: usize, cell: Vec<u8>) -> Result<(), Error> {
+        if row == column.rows {
+            return self.append(column, [cell]);
+        }
+        let run = column.run_of(row);
+        let (mut cells, at) = self.cells_around(column, row)?;
+        cells.insert(at, cell);
+
+        let runs = column.runs.len();
+        self.rewrite(column, run, &cells)?;
+        // rewriting may have split the run into more
+        let later = run + 1 + column.runs.len() - runs;
+        for moved in &mut column.runs[later..] {
+            moved.first_row += 1;
+        }
+        column.rows += 1;
+        Ok(())
+    }
+
+    // removes the cell of one row, freeing its page if that empties it, and
+    // returns it
+    fn remove(&mut self, column: &mut PagedColumn, row: usize) -> Result<Vec<u8>, Error> {
+        let run = column.run_of(row);
+        let page = column.runs[run].page;
+        let (mut cells, at) = self.cells_around(column, row)?;
+        let removed = cells.remove(at);
+
+        let later = match cells.is_empty() {
+            true => {
+                column.runs.remove(run);
+                self.free.push(page);
+                run
+            }
+            false => {
+                self.rewrite(column, run, &cells)?;
+                run + 1
+            }
+        };
+        for moved in &mut column.runs[later..] {
+            moved.first_row -= 1;
+        }
+        column.rows -= 1;
+        Ok(removed)
+    }
+}
+
+/// Sheets kept in fixed-size pages of a file, with only as many pages in
+/// memory as the buffer pool's budget allows, so the database can be larger
+/// than memory. Each column's cells are stored in its own chain of pages.
+///
+/// Changes reach the file as pages are evicted, and the file is only
+/// consistent again after `flush` (also called on drop). For crash safety,
+/// `load` the database and save it, or keep it in a `LoggedDatabase`.
+///
+/// A change that fails part way is undone. If undoing it fails too, the
+/// storage is poisoned: its columns may no longer line up, so it refuses
+/// further changes and flushes rather than write out a catalog that doesn't
+/// match its pages.
+pub(crate) struct PagedStorage {
+    pages: Pages,
+    sheets: Vec<PagedSheet>,
+    // pages holding the catalog, in order; reused by the next flush
+    catalog: Vec<PageId>,
+    poisoned: bool,
+}
+
+impl PagedStorage {
+    /// Opens the paged file at `path`, creating an empty one if there's none
+    pub(crate) fn open(
+        path: impl AsRef<Path>,
+        options: PageOptions,
+    ) -> Result<PagedStorage, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(Error::Io)?;
+        let length = file.metadata().map_err(Error::Io)?.len();
+
+        let mut storage = PagedStorage {
+            pages: Pages {
+                pool: BufferPool::new(file, options.memory_budget),
+                count: 1,
+                free: Vec::new(),
+            },
+            sheets: Vec::new(),
+            catalog: Vec::new(),
+            poisoned: false,
+        };
+        if length == 0 {
+            storage.flush()?;
+            return Ok(storage);
+        }
+        if length < PAGE_SIZE as u64 {
+            return Err(Error::PagerError(PagerError::NotAPageFile));
+        }
+
+        // all numbers are BE
+        // u8[14] magic: "baseboredpage1"
+        // u32 page_size
+        // u32 page_count: pages in the file, the header included
+        // u32 catalog: first page of the catalog, 0 if it has none
+        let header = storage.pages.pool.read(0)?.to_vec();
+        let mut deserializer = ByteDeserializer::new(&header);
+        let has_ids = match deserializer.read_bytes(PAGE_MAGIC.len())? {
+            magic if magic == PAGE_MAGIC => true,
+            magic if magic == PAGE_MAGIC_V1 => false,
+            _ => return Err(Error::PagerError(PagerError::NotAPageFile)),
+        };
+        let page_size = deserializer.read_u32()?;
+        if page_size as usize != PAGE_SIZE {
+            return Err(Error::PagerError(PagerError::UnsupportedPageSize {
+                got: page_size,
+            }));
+        }
+        storage.pages.count = deserializer.read_u32()?;
+        let mut next = deserializer.read_u32()?;
+
+        // all numbers are BE, for each page of the catalog:
+        // u32 next: the next page of the catalog, 0 for the last
+        // u16 length, u8[length] the catalog's bytes
+        let mut bytes = Vec::new();
+        while next != 0 {
+            let page = next;
+            if page >= storage.pages.count || storage.catalog.contains(&page) {
+                return Err(Error::PagerError(PagerError::CorruptPage { page }));
+            }
+            let contents = storage.pages.pool.read(page)?;
+            next = u32::from_be_bytes([contents[0], contents[1], contents[2], contents[3]]);
+            let length = u16::from_be_bytes([contents[4], contents[5]]) as usize;
+            if length > PAGE_SIZE - CATALOG_PAGE_HEADER {
+                return Err(Error::PagerError(PagerError::CorruptPage { page }));
+            }
+            bytes.extend_from_slice(&contents[CATALOG_PAGE_HEADER..CATALOG_PAGE_HEADER + length]);
+            storage.catalog.push(page);
+        }
+        if !bytes.is_empty() {
+            storage.read_catalog(&bytes, has_ids)?;
+        }
+
+        Ok(storage)
+    }
+
+    /// How many pages the buffer pool holds in memory
+    pub(crate) fn resident_pages(&self) -> usize {
+        self.pages.pool.resident()
+    }
+
+    fn sheet_index(&self, id: &Identifier) -> Result<usize, Error> {
+        self.sheets
+            .iter()
+            .position(|sheet| sheet.id == *id)
+            .ok_or(Error::WalError(WalError::SheetNotFound { id: *id }))
+    }
+
+    fn column_index(&self, sheet: usize, id: &Identifier) -> Result<usize, Error> {
+        self.sheets[sheet]
+            .columns
+            .iter()
+            .position(|column| column.id == *id)
+            .ok_or(Error::WalError(WalError::ColumnNotFound { id: *id }))
+    }
+
+    // all numbers are BE
+    // u32 free_count, u32[free_count] free pages
+    // u32 sheet_count, then for each sheet:
+    //   u8[16] id, u32 name_length, u8[name_length] name,
+    //   u64 next_id: the id the next row gets,
+    //   u32 id_run_count, then for each run of consecutive row ids, in row
+    //   order: u64 first_id, u32 rows,
+    //   u32 column_count, then for each column:
+    //     u8[16] id, u32 name_length, u8[name_length] name, u8 value_type,
+    //     u32 page_count, then for each page: u32 page, u32 rows
+    fn catalog_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.pages.free.len() as u32).to_be_bytes().to_vec();
+        for page in &self.pages.free {
+            bytes.extend_from_slice(&page.to_be_bytes());
+        }
+        bytes.extend_from_slice(&(self.sheets.len() as u32).to_be_bytes());
+        for sheet in &self.sheets {
+            bytes.extend_from_slice(&sheet.id.serialized_bytes());
+            wal::write_name(&sheet.name, &mut bytes);
+            bytes.extend_from_slice(&sheet.next_id.to_be_bytes());
+            bytes.extend_from_slice(&(sheet.ids.len() as u32).to_be_bytes());
+            for run in &sheet.ids {
+                bytes.extend_from_slice(&run.first_id.to_be_bytes());
+                bytes.extend_from_slice(&(run.rows as u32).to_be_bytes());
+            }
+            bytes.extend_from_slice(&(sheet.columns.len() as u32).to_be_bytes());
+            for column in &sheet.columns {
+                bytes.extend_from_slice(&column.id.serialized_bytes());
+                wal::write_name(&column.name, &mut bytes);
+                bytes.extend_from_slice(&column.value_type.serialized_bytes());
+                bytes.extend_from_slice(&(column.runs.len() as u32).to_be_bytes());
+                for run in &column.runs {
+                    bytes.extend_from_slice(&run.page.to_be_bytes());
+                    bytes.extend_from_slice(&(run.rows as u32).to_be_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    // version 1 catalogs, without `has_ids`, have no row ids
+    fn read_catalog(&mut self, bytes: &[u8], has_ids: bool) -> Result<(), Error> {
+        let count = self.pages.count;
+        let corrupt = || Error::PagerError(PagerError::CorruptPage { page: 0 });
+        let mut deserializer = ByteDeserializer::new(bytes);
+        // every count is checked against the bytes left before allocating
+        let read_count = |deserializer: &mut ByteDeserializer, item: usize| {
+            let n = deserializer.read_u32()? as usize;
+            match n.saturating_mul(item) <= deserializer.remaining_bytes().len() {
+                true => Ok(n),
+                false => Err(corrupt()),
+            }
+        };
+        let read_page = |deserializer: &mut ByteDeserializer| match deserializer.read_u32()? {
+            page if page == 0 || page >= count => Err(corrupt()),
+            page => Ok(page),
+        };
+
+        let free = read_count(&mut deserializer, 4)?;
+        self.pages.free = (0..free)
+            .map(|_| read_page(&mut deserializer))
+            .collect::<Result<_, Error>>()?;
+
+        let sheets = read_count(&mut deserializer, 24)?;
+        for _ in 0..sheets {
+            let mut sheet = PagedSheet::new(
+                wal::read_id(&mut deserializer)?,
+                wal::read_name(&mut deserializer)?,
+            );
+            if has_ids {
+                sheet.next_id = deserializer.read_u64()?;
+                let runs = read_count(&mut deserializer, 12)?;
+                let mut rows = 0;
+                for _ in 0..runs {
+                    let run = IdRun {
+                        first_id: deserializer.read_u64()?,
+                        first_row: rows,
+                        rows: deserializer.read_u32()? as usize,
+                    };
+                    // each after the one before, and before the next id
+                    let after = sheet
+                        .ids
+                        .last()
+                        .map_or(0, |last: &IdRun| last.first_id + last.rows as u64);
+                    if run.rows == 0
+                        || run.first_id < after
+                        || run.first_id + run.rows as u64 > sheet.next_id
+                    {
+                        return Err(corrupt());
+                    }
+                    rows += run.rows;
+                    sheet.ids.push(run);
+                }
+            }
+            let columns = read_count(&mut deserializer, 25)?;
+            for _ in 0..columns {
+                let mut column = PagedColumn::new(
+                    wal::read_id(&mut deserializer)?,
+                    wal::read_name(&mut deserializer)?,
+                    Type::deserialize_bytes(&[deserializer.read_u8()?])?,
+                );
+                let runs = read_count(&mut deserializer, 8)?;
+                for _ in 0..runs {
+                    let page = read_page(&mut deserializer)?;
+                    let rows = deserializer.read_u32()? as usize;
+                    column.runs.push(PageRun {
+                        page,
+                        first_row: column.rows,
+                        rows,
+                    });
+                    column.rows += rows;
+                }
+                sheet.columns.push(column);
+            }
+            if !has_ids {
+                for _ in 0..sheet.rows() {
+                    sheet.push_id();
+                }
+            }
+            let ids = sheet.ids.last().map_or(0, |run| run.first_row + run.rows);
+            if sheet.columns.iter().any(|column| column.rows != ids) {
+                return Err(corrupt());
+            }
+            self.sheets.push(sheet);
+        }
+        Ok(())
+    }
+
+    fn write_catalog(&mut self) -> Result<(), Error> {
+        let bytes = self.catalog_bytes();
+        let chunks = bytes
+            .chunks(PAGE_SIZE - CATALOG_PAGE_HEADER)
+            .collect::<Vec<_>>();
+        // catalog pages aren't in the free list, so taking new ones can't
+        // change the bytes being written. A shorter catalog keeps its spare
+        // pages, left empty, for next time.
+        while self.catalog.len() < chunks.len() {
+            self.pages.count += 1;
+            self.catalog.push(self.pages.count - 1);
+        }
+
+        for (i, page) in self.catalog.iter().enumerate() {
+            let next = self.catalog.get(i + 1).copied().unwrap_or(0);
+            let chunk = chunks.get(i).copied().unwrap_or_default();
+            let mut contents = next.to_be_bytes().to_vec();
+            contents.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            contents.extend_from_slice(chunk);
+            self.pages.pool.write(*page, contents)?;
+        }
+
+        let mut header = PAGE_MAGIC.to_vec();
+        header.extend_from_slice(&(PAGE_SIZE as u32).to_be_bytes());
+        header.extend_from_slice(&self.pages.count.to_be_bytes());
+        header.extend_from_slice(&self.catalog.first().copied().unwrap_or(0).to_be_bytes());
+        self.pages.pool.write(0, header)
+    }
+}
+
+// the error a change failed with, and the one undoing it failed with
+fn rollback_failed(error: Error, rollback: Error) -> Error {
+    Error::PagerError(PagerError::RollbackFailed {
+        error: Box::new(error),
+        rollback: Box::new(rollback),
+    })
+}
+
+impl Storage for PagedStorage {
+    fn apply(&mut self, change: &Change) -> Result<(), Error> {
+        if self.poisoned {
+            return Err(Error::PagerError(PagerError::Poisoned));
+        }
+        match change {
+            Change::AddSheet { id, name } => {
+                self.sheets.push(PagedSheet::new(*id, name.clone()));
+            }
+            Change::RemoveSheet { sheet } => {
+                self.sheet_index(sheet)?;
+                let (removed, kept) = std::mem::take(&mut self.sheets)
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|existing| existing.id == *sheet);
+                self.sheets = kept;
+                for column in removed.iter().flat_map(|sheet| &sheet.columns) {
+                    self.pages.release(column);
+                }
+            }
+            Change::AddColumn {
+                sheet,
+                id,
+                name,
+                value_type,
+            } => {
+                let sheet = self.sheet_index(sheet)?;
+                let rows = self.sheets[sheet].rows();
+                let mut column = PagedColumn::new(*id, name.clone(), *value_type);
+                let nil = encode_cell(&Value::Nil)?;
+                if let Err(err) = self
+                    .pages
+                    .append(&mut column, (0..rows).map(|_| nil.clone()))
+                {
+                    self.pages.release(&column);
+                    return Err(err);
+                }
+                self.sheets[sheet].columns.push(column);
+            }
+            Change::RemoveColumn { sheet, column } => {
+                let sheet = self.sheet_index(sheet)?;
+                let index = self.column_index(sheet, column)?;
+                let column = self.sheets[sheet].columns.remove(index);
+                self.pages.release(&column);
+                // like an in-memory sheet, one without columns has no rows
+                if self.sheets[sheet].columns.is_empty() {
+                    self.sheets[sheet].ids.clear();
+                }
+            }
+            Change::InsertRow { sheet, values } => {
+                let index = self.sheet_index(sheet)?;
+                let sheet = &mut self.sheets[index];
+                if values.len() != sheet.columns.len() {
+                    return Err(Error::SheetError(SheetError::InvalidRowLength {
+                        expected: sheet.columns.len(),
+                        got: values.len(),
+                    }));
+                }
+                // check every value before writing any, so a bad one doesn't
+                // leave the row half inserted
+                let cells = values
+                    .iter()
+                    .zip(&sheet.columns)
+                    .map(|(value, column)| encode_cell(&column.coerce_value(value.clone())?))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                for (n, cell) in cells.into_iter().enumerate() {
+                    if let Err(err) = self.pages.append(&mut sheet.columns[n], [cell]) {
+                        // every column must keep the same number of rows
+                        for column in &mut sheet.columns[..n] {
+                            if let Err(rollback) = self.pages.remove(column, column.rows - 1) {
+                                self.poisoned = true;
+                                return Err(rollback_failed(err, rollback));
+                            }
+                        }
+                        return Err(err);
+                    }
+                }
+                if !sheet.columns.is_empty() {
+                    sheet.push_id();
+                }
+            }
+            Change::UpdateCell {
+                sheet,
+                column,
+                row,
+                value,
+            } => {
+                let sheet = self.sheet_index(sheet)?;
+                let index = self.column_index(sheet, column)?;
+                let row = self.sheets[sheet].find_row(*row)?;
+                let column = &mut self.sheets[sheet].columns[index];
+                let cell = encode_cell(&column.coerce_value(value.clone())?)?;
+                self.pages.update(column, row, cell)?;
+            }
+            Change::DeleteRow { sheet, row } => {
+                let index = self.sheet_index(sheet)?;
+                let sheet = &mut self.sheets[index];
+                let row = sheet.find_row(*row)?;
+                let mut removed = Vec::new();
+                for n in 0..sheet.columns.len() {
+                    match self.pages.remove(&mut sheet.columns[n], row) {
+                        Ok(cell) => removed.push(cell),
+                        Err(err) => {
+                            // put back what was removed, so every column
+                            // keeps the same rows
+                            for (column, cell) in sheet.columns.iter_mut().zip(removed) {
+                                if let Err(rollback) = self.pages.insert(column, row, cell) {
+                                    self.poisoned = true;
+                                    return Err(rollback_failed(err, rollback));
+                                }
+                            }
+                            return Err(err);
+                        }
+                    }
+                }
+                sheet.remove_id(row);
+            }
+        }
+        Ok(())
+    }
+
+    fn sheets(&mut self) -> Result<Vec<SheetInfo>, Error> {
+        Ok(self
+            .sheets
+            .iter()
+            .map(|sheet| SheetInfo {
+                id: sheet.id,
+                name: sheet.name.clone(),
+            })
+            .collect())
+    }
+
+    fn columns(&mut self, sheet: &Identifier) -> Result<Vec<ColumnInfo>, Error> {
+        Ok(self.sheets[self.sheet_index(sheet)?]
+            .columns
+            .iter()
+            .map(|column| ColumnInfo {
+                id: column.id,
+                name: column.name.clone(),
+                value_type: column.value_type,
+            })
+            .collect())
+    }
+
+    fn row_count(&mut self, sheet: &Identifier) -> Result<usize, Error> {
+        Ok(self.sheets[self.sheet_index(sheet)?].rows())
+    }
+
+    fn cell(
+        &mut self,
+        sheet: &Identifier,
+        column: &Identifier,
+        row: usize,
+    ) -> Result<Value, Error> {
+        let sheet = self.sheet_index(sheet)?;
+        let column = &self.sheets[sheet].columns[self.column_index(sheet, column)?];
+        if row >= column.rows {
+            return Err(Error::WalError(WalError::RowOutOfRange {
+                row: row as u64,
+                rows: column.rows,
+            }));
+        }
+
+        let run = column.runs[column.run_of(row)];
+        let mut cells = read_cells(self.pages.pool.read(run.page)?, run.page)?;
+        if row - run.first_row >= cells.len() {
+            return Err(Error::PagerError(PagerError::CorruptPage {
+                page: run.page,
+            }));
+        }
+        Ok(cells.swap_remove(row - run.first_row))
+    }
+
+    fn row_id(&mut self, sheet: &Identifier, row: usize) -> Result<u64, Error> {
+        let sheet = &self.sheets[self.sheet_index(sheet)?];
+        sheet
+            .row_id(row)
+            .ok_or(Error::WalError(WalError::RowOutOfRange {
+                row: row as u64,
+                rows: sheet.rows(),
+            }))
+    }
+
+    fn next_row_id(&mut self, sheet: &Identifier) -> Result<u64, Error> {
+        Ok(self.sheets[self.sheet_index(sheet)?].next_id)
+    }
+
+    fn skip_row_ids(&mut self, sheet: &Identifier, next: u64) -> Result<(), Error> {
+        let index = self.sheet_index(sheet)?;
+        let sheet = &mut self.sheets[index];
+        sheet.next_id = sheet.next_id.max(next);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.poisoned {
+            return Err(Error::PagerError(PagerError::Poisoned));
+        }
+        self.write_catalog()?;
+        self.pages.pool.flush()
+    }
+}
+
+impl Drop for PagedStorage {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum PagerError {
+    NotAPageFile,
+    UnsupportedPageSize {
+        got: u32,
+    },
+    CorruptPage {
+        page: PageId,
+    },
+    CellTooLarge {
+        size: usize,
+    },
+    RollbackFailed {
+        error: Box<Error>,
+        rollback: Box<Error>,
+    },
+    Poisoned,
+}
+
+impl std::fmt::Display for PagerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PagerError::NotAPageFile => write!(f, "Not a paged database file"),
+            PagerError::UnsupportedPageSize { got } => {
+                write!(
+                    f,
+                    "Page size {} is not supported, expected {}",
+                    got, PAGE_SIZE
+                )
+            }
+            PagerError::CorruptPage { page } => write!(f, "Page {} is corrupt", page),
+            PagerError::CellTooLarge { size } => write!(
+                f,
+                "A cell of {} bytes doesn't fit in a page, the most is {}",
+                size, MAX_CELL
+            ),
+            PagerError::RollbackFailed { error, rollback } => write!(
+                f,
+                "{}, and undoing the change failed too: {}",
+                error, rollback
+            ),
+            PagerError::Poisoned => write!(
+                f,
+                "A change couldn't be undone, so the paged storage takes no more"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PagerError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(pages: usize) -> PageOptions {
+        PageOptions {
+            memory_budget: pages * PAGE_SIZE,
+        }
+    }
+
+    #[test]
+    fn holds_more_than_the_memory_budget() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("numbers.pages");
+        let (sheet, column) = (Identifier::new(), Identifier::new());
+
+        let mut storage = PagedStorage::open(&path, options(4)).unwrap();
+        storage
+            .apply(&Change::AddSheet {
+                id: sheet,
+                name: "numbers".to_string(),
+            })
+            .unwrap();
+        storage
+            .apply(&Change::AddColumn {
+                sheet,
+                id: column,
+                name: "n".to_string(),
+                value_type: Type::Int,
+            })
+            .unwrap();
+        for n in 0..20_000 {
+            storage
+                .apply(&Change::InsertRow {
+                    sheet,
+                    values: vec![Value::Int(n)],
+                })
+                .unwrap();
+        }
+        // long since evicted
+        storage
+            .apply(&Change::UpdateCell {
+                sheet,
+                column,
+                row: 7,
+                value: Value::Int(-7),
+            })
+            .unwrap();
+        assert!(storage.resident_pages() <= 4);
+        drop(storage);
+
+        assert!(std::fs::metadata(&path).unwrap().len() > 40 * PAGE_SIZE as u64);
+        let mut storage = PagedStorage::open(&path, options(1)).unwrap();
+        assert_eq!(storage.row_count(&sheet).unwrap(), 20_000);
+        for n in (0..20_000).step_by(997) {
+            let expected = if n == 7 { -7 } else { n as i64 };
+            assert_eq!(
+                storage.cell(&sheet, &column, n).unwrap(),
+                Value::Int(expected)
+            );
+        }
+        assert_eq!(storage.resident_pages(), 1);
+    }
+
+    #[test]
+    fn growing_a_cell_splits_its_page() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("notes.pages");
+        let (sheet, column) = (Identifier::new(), Identifier::new());
+
+        let mut storage = PagedStorage::open(&path, options(2)).unwrap();
+        let mut database = crate::internal::database::Database::new_empty();
+        database.adopt_sheet(&crate::internal::sheet::Sheet::new_with_set_id(
+            sheet,
+            "notes".to_string(),
+            vec![Column::new_with_set_id(
+                column,
+                "text".to_string(),
+                Type::Str,
+                None,
+            )],
+            None,
+        ));
+        for i in 0..100 {
+            database.columns[0]
+                .insert_row(vec![Value::Str(format!("note {}", i))])
+                .unwrap();
+        }
+        storage.import(&database).unwrap();
+
+        let long = "x".repeat(3000);
+        for row in [10, 11, 12] {
+            storage
+                .apply(&Change::UpdateCell {
+                    sheet,
+                    column,
+                    row,
+                    value: Value::Str(long.clone()),
+                })
+                .unwrap();
+        }
+        assert!(matches!(
+            storage.apply(&Change::UpdateCell {
+                sheet,
+                column,
+                row: 0,
+                value: Value::Str("x".repeat(PAGE_SIZE)),
+            }),
+            Err(Error::PagerError(PagerError::CellTooLarge { .. }))
+        ));
+
+        let loaded = storage.load().unwrap();
+        let cells = &loaded.columns[0].columns[0].cells;
+        assert_eq!(cells.len(), 100);
+        assert_eq!(cells[11].value, Value::Str(long));
+        assert_eq!(cells[13].value, Value::Str("note 13".to_string()));
+        assert_eq!(cells[99].value, Value::Str("note 99".to_string()));
+    }
+
+    #[test]
+    fn failed_changes_leave_every_column_the_same_length() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("pairs.pages");
+        let (sheet, left, right) = (Identifier::new(), Identifier::new(), Identifier::new());
+
+        let mut storage = PagedStorage::open(&path, options(4)).unwrap();
+        storage
+            .apply(&Change::AddSheet {
+                id: sheet,
+                name: "pairs".to_string(),
+            })
+            .unwrap();
+        for id in [left, right] {
+            storage
+                .apply(&Change::AddColumn {
+                    sheet,
+                    id,
+                    name: id.to_string(),
+                    value_type: Type::Int,
+                })
+                .unwrap();
+        }
+        for n in 0..3 {
+            storage
+                .apply(&Change::InsertRow {
+                    sheet,
+                    values: vec![Value::Int(n), Value::Int(-n)],
+                })
+                .unwrap();
+        }
+
+        // a run claiming more rows than its page holds
+        storage.sheets[0].columns[0].runs[0].rows += 1;
+        storage.sheets[0].columns[0].rows += 1;
+        storage.sheets[0].ids[0].rows += 1;
+        assert!(matches!(
+            storage.apply(&Change::UpdateCell {
+                sheet,
+                column: left,
+                row: 3,
+                value: Value::Int(3),
+            }),
+            Err(Error::PagerError(PagerError::CorruptPage { .. }))
+        ));
+        storage.sheets[0].columns[0].runs[0].rows -= 1;
+        storage.sheets[0].columns[0].rows -= 1;
+        storage.sheets[0].ids[0].rows -= 1;
+
+        // the right column's page can't be read, so changing it fails after
+        // the left one has already changed
+        let page = storage.sheets[0].columns[1].runs[0].page;
+        let good = storage.pages.pool.read(page).unwrap().to_vec();
+        storage
+            .pages
+            .pool
+            .write(page, vec![0, 0, 0xff, 0xff])
+            .unwrap();
+        for operation in [
+            Change::InsertRow {
+                sheet,
+                values: vec![Value::Int(3), Value::Int(-3)],
+            },
+            Change::DeleteRow { sheet, row: 1 },
+        ] {
+            assert!(storage.apply(&operation).is_err());
+            assert_eq!(storage.sheets[0].columns[0].rows, 3);
+            let left = (0..3)
+                .map(|row| storage.cell(&sheet, &left, row).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(left, [Value::Int(0), Value::Int(1), Value::Int(2)]);
+        }
+
+        storage.pages.pool.write(page, good).unwrap();
+        storage.apply(&Change::DeleteRow { sheet, row: 1 }).unwrap();
+        assert_eq!(storage.cell(&sheet, &right, 1).unwrap(), Value::Int(-2));
+    }
+
+    #[test]
+    fn a_failed_rollback_poisons_the_storage() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("notes.pages");
+        let (sheet, text, n) = (Identifier::new(), Identifier::new(), Identifier::new());
+
+        let mut storage = PagedStorage::open(&path, options(4)).unwrap();
+        storage
+            .apply(&Change::AddSheet {
+                id: sheet,
+                name: "notes".to_string(),
+            })
+            .unwrap();
+        for (id, value_type) in [(text, Type::Str), (n, Type::Int)] {
+            storage
+                .apply(&Change::AddColumn {
+                    sheet,
+                    id,
+                    name: id.to_string(),
+                    value_type,
+                })
+                .unwrap();
+        }
+        // a page per note
+        for i in 0..3 {
+            storage
+                .apply(&Change::InsertRow {
+                    sheet,
+                    values: vec![Value::Str("x".repeat(3000)), Value::Int(i)],
+                })
+                .unwrap();
+        }
+        storage.flush().unwrap();
+
+        // deleting the first row empties its page, and putting it back after
+        // the other column fails has to read the next one, which can't be read
+        let second = storage.sheets[0].columns[0].runs[1].page;
+        let numbers = storage.sheets[0].columns[1].runs[0].page;
+        for page in [second, numbers] {
+            storage
+                .pages
+                .pool
+                .write(page, vec![0, 0, 0xff, 0xff])
+                .unwrap();
+        }
+        assert!(matches!(
+            storage.apply(&Change::DeleteRow { sheet, row: 0 }),
+            Err(Error::PagerError(PagerError::RollbackFailed { .. }))
+        ));
+        assert!(matches!(
+            storage.apply(&Change::RemoveSheet { sheet }),
+            Err(Error::PagerError(PagerError::Poisoned))
+        ));
+        assert!(matches!(
+            storage.flush(),
+            Err(Error::PagerError(PagerError::Poisoned))
+        ));
+    }
+}
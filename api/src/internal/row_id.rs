@@ -0,0 +1,585 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::Value;
+use crate::internal::database::Database;
+use crate::internal::errors::Error;
+use crate::internal::expr::{BinaryOp, CompareOp, Expr, Kind};
+use crate::internal::format::{Features, Format};
+use crate::internal::id::Identifier;
+use crate::internal::limits::{self, Budget, Limited};
+use crate::internal::sheet::Sheet;
+use crate::internal::wal::{Operation, WalError};
+use std::collections::HashMap;
+
+/// What expressions call a row's id, unless the sheet has a column of that name
+pub(crate) const ROWID: &str = "rowid";
+
+/// The ids of a sheet's rows, in row order. A row keeps its id however the
+/// rows before it move, and ids aren't given out again once their row is
+/// deleted, so an id is a stable handle on a row: operations, the log and
+/// events refer to rows by id, and so can another sheet. Ids count up from 0, so they're in
+/// order too. Looking a row up by id takes O(1).
+///
+/// Every `Storage` keeps the same ids for the same rows, and copying rows
+/// from one to another, or loading them into a `Database`, keeps their ids.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RowIds {
+    ids: Vec<u64>,
+    positions: HashMap<u64, usize>,
+    next: u64,
+}
+
+impl RowIds {
+    /// No ids yet, with the next row getting `next`
+    pub(crate) fn starting_at(next: u64) -> Self {
+        Self {
+            next,
+            ..Self::default()
+        }
+    }
+
+    /// `ids`, of rows in order, each above the one before and below `next`
+    pub(crate) fn from_ids(ids: Vec<u64>, next: u64) -> Self {
+        let positions = ids.iter().enumerate().map(|(row, id)| (*id, row)).collect();
+        Self {
+            ids,
+            positions,
+            next,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The id the next row gets
+    pub(crate) fn next(&self) -> u64 {
+        self.next
+    }
+
+    pub(crate) fn ids(&self) -> &[u64] {
+        &self.ids
+    }
+
+    /// The id of the row at position `row`
+    pub(crate) fn id(&self, row: usize) -> Option<u64> {
+        self.ids.get(row).copied()
+    }
+
+    /// The position of the row with id `id`
+    pub(crate) fn position(&self, id: u64) -> Option<usize> {
+        self.positions.get(&id).copied()
+    }
+
+    /// Makes the next row get `next` or a later id, so rows copied in from
+    /// elsewhere can keep theirs
+    pub(crate) fn skip_to(&mut self, next: u64) {
+        self.next = self.next.max(next);
+    }
+
+    /// Gives a row added at the end the next id, and returns it
+    pub(crate) fn push(&mut self) -> u64 {
+        let id = self.next;
+        self.positions.insert(id, self.ids.len());
+        self.ids.push(id);
+        self.next += 1;
+        id
+    }
+
+    /// Takes back the last push, so its id is given out again
+    pub(crate) fn pop(&mut self) {
+        if let Some(id) = self.ids.pop() {
+            self.positions.remove(&id);
+            self.next = id;
+        }
+    }
+
+    /// Removes the id of the row at position `row`, moving the rows after it
+    /// up by one
+    pub(crate) fn remove(&mut self, row: usize) -> Option<u64> {
+        if row >= self.ids.len() {
+            return None;
+        }
+        let id = self.ids.remove(row);
+        self.positions.remove(&id);
+        self.reposition(row);
+        Some(id)
+    }
+
+    /// Puts back an id `remove` took from position `row`
+    pub(crate) fn insert(&mut self, row: usize, id: u64) {
+        let row = row.min(self.ids.len());
+        self.ids.insert(row, id);
+        self.reposition(row);
+    }
+
+    /// Gives new ids to rows up to `rows`, e.g. those of files from before
+    /// row ids
+    pub(crate) fn fill(&mut self, rows: usize) {
+        while self.ids.len() < rows {
+            self.push();
+        }
+    }
+
+    /// Drops every id, when a sheet's last column goes and its rows with it.
+    /// They aren't given out again.
+    pub(crate) fn clear(&mut self) {
+        self.ids.clear();
+        self.positions.clear();
+    }
+
+    /// The ids from position `row` on
+    pub(crate) fn split_off(&self, row: usize) -> RowIds {
+        let mut rest = RowIds::starting_at(self.next);
+        for id in self.ids.get(row..).unwrap_or_default() {
+            rest.positions.insert(*id, rest.ids.len());
+            rest.ids.push(*id);
+        }
+        rest
+    }
+
+    /// Adds the ids of rows appended after these, which must come after them
+    pub(crate) fn append(&mut self, appended: RowIds) -> Result<(), Error> {
+        if appended.ids.first().is_some_and(|first| *first < self.next) {
+            return malformed(format!(
+                "appended row id {} isn't after the ids before it",
+                appended.ids[0]
+            ));
+        }
+        for id in appended.ids {
+            self.positions.insert(id, self.ids.len());
+            self.ids.push(id);
+        }
+        self.next = self.next.max(appended.next);
+        Ok(())
+    }
+
+    // the positions of the rows from `row` on, after they moved
+    fn reposition(&mut self, row: usize) {
+        for (position, id) in self.ids.iter().enumerate().skip(row) {
+            self.positions.insert(*id, position);
+        }
+    }
+}
+
+// all numbers are BE
+// u32 length: of everything after it, so readers can skip the ids
+// u64 next: the id the next row gets
+// (length - 8) / 8 times:
+//   u64 id: of each row, in order, each above the one before and below next
+// Only written with Features::ROW_IDS, after the sheet's triggers. There is
+// one id per row of the sheet's columns. Files without row ids number their
+// rows from 0.
+
+/// The serialized row ids of a sheet, empty if `format` has no row ids
+pub(crate) fn serialized_bytes(row_ids: &RowIds, format: &Format) -> Vec<u8> {
+    if !format.has(Features::ROW_IDS) {
+        return Vec::new();
+    }
+    let mut bytes = Vec::with_capacity(12 + 8 * row_ids.len());
+    bytes.extend_from_slice(&(8 + 8 * row_ids.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&row_ids.next.to_be_bytes());
+    for id in &row_ids.ids {
+        bytes.extend_from_slice(&id.to_be_bytes());
+    }
+    bytes
+}
+
+/// Reads the row ids of a sheet, leaving the deserializer after them. No
+/// ids if `format` has none, for Sheet to number the rows from 0.
+pub(crate) fn read(
+    deserializer: &mut ByteDeserializer,
+    format: &Format,
+    budget: &mut Budget,
+) -> Result<RowIds, Error> {
+    if !format.has(Features::ROW_IDS) {
+        return Ok(RowIds::default());
+    }
+    let length = deserializer.read_u32()? as usize;
+    if length < 8 || !length.is_multiple_of(8) {
+        return malformed(format!("{} bytes of row ids", length));
+    }
+    format
+        .limits
+        .check_count(Limited::Cells, (length as u64 - 8) / 8)?;
+    budget.charge(limits::row_ids_size((length - 8) / 8))?;
+    let bytes = deserializer.read_bytes(length)?;
+    let mut body = ByteDeserializer::new(&bytes);
+    let next = body.read_u64()?;
+    let mut row_ids = RowIds::starting_at(0);
+    for _ in 0..(length - 8) / 8 {
+        let id = body.read_u64()?;
+        if id < row_ids.next {
+            return malformed(format!("row id {} after {}", id, row_ids.next - 1));
+        }
+        // the row after it couldn't be given an id
+        if id == u64::MAX {
+            return malformed(format!("row id {} is the last there is", id));
+        }
+        row_ids.next = id;
+        row_ids.push();
+    }
+    if next < row_ids.next {
+        return malformed(format!(
+            "next row id {} isn't after row id {}",
+            next,
+            row_ids.next - 1
+        ));
+    }
+    if next == u64::MAX {
+        return malformed(format!("next row id {} is the last there is", next));
+    }
+    row_ids.next = next;
+    Ok(row_ids)
+}
+
+/// Checks that a sheet read from a file has as many row ids, `count`, as
+/// rows, `rows`
+pub(crate) fn check_count(count: usize, rows: usize, sheet_name: &str) -> Result<(), Error> {
+    if count != rows {
+        return malformed(format!(
+            "{} row ids for the {} rows of sheet '{}'",
+            count, rows, sheet_name
+        ));
+    }
+    Ok(())
+}
+
+impl Sheet {
+    /// The id of the row at position `row`, see RowIds
+    pub(crate) fn row_id(&self, row: usize) -> Option<u64> {
+        self.row_ids.id(row)
+    }
+
+    /// The position of the row with id `id`, in O(1)
+    pub(crate) fn row_position(&self, id: u64) -> Option<usize> {
+        self.row_ids.position(id)
+    }
+
+    /// The position of the row with id `id`, or an error naming the sheet
+    pub(crate) fn find_row(&self, id: u64) -> Result<u64, Error> {
+        self.row_position(id)
+            .map(|row| row as u64)
+            .ok_or(Error::RowIdError(RowIdError::NotFound {
+                sheet_name: self.name.clone(),
+                id,
+            }))
+    }
+
+    /// Whether `name` in an expression is the row's id rather than a column
+    pub(crate) fn is_row_id(&self, name: &str) -> bool {
+        name == ROWID && self.get_column_by_name(name).is_none()
+    }
+
+    /// The row `filter` picks out by id, if it's just `rowid = <number>`, so
+    /// it can be found without looking at every row. Some(None) if there is no
+    /// such row.
+    pub(crate) fn row_picked_by(&self, filter: &Expr) -> Option<Option<usize>> {
+        let Kind::Binary {
+            op: BinaryOp::Compare(CompareOp::Eq),
+            left,
+            right,
+        } = &filter.kind
+        else {
+            return None;
+        };
+        let ((Kind::Column(name), Kind::Literal(value))
+        | (Kind::Literal(value), Kind::Column(name))) = (&left.kind, &right.kind)
+        else {
+            return None;
+        };
+        if !self.is_row_id(name) || !value.get_type().is_integer() {
+            return None;
+        }
+        // ids are never negative, so no row has one
+        match value.cast_to(Type::U64) {
+            Ok(Value::U64(id)) => Some(self.row_position(id)),
+            _ => Some(None),
+        }
+    }
+}
+
+impl Database {
+    /// Inserts a row into a sheet, in a transaction, and returns its id
+    pub(crate) fn insert_row(
+        &mut self,
+        sheet: &Identifier,
+        values: Vec<Value>,
+    ) -> Result<u64, Error> {
+        let id = self.sheet_by_id(sheet)?.row_ids.next();
+        self.transaction(|tx| {
+            tx.apply(Operation::InsertRow {
+                sheet: *sheet,
+                values,
+            })
+        })?;
+        Ok(id)
+    }
+
+    /// Sets a cell of the row with id `row`, in a transaction
+    pub(crate) fn update_row(
+        &mut self,
+        sheet: &Identifier,
+        row: u64,
+        column: &Identifier,
+        value: Value,
+    ) -> Result<(), Error> {
+        self.transaction(|tx| {
+            tx.apply(Operation::UpdateCell {
+                sheet: *sheet,
+                column: *column,
+                row,
+                value,
+            })
+        })
+    }
+
+    /// Deletes the row with id `row`, in a transaction
+    pub(crate) fn delete_row(&mut self, sheet: &Identifier, row: u64) -> Result<(), Error> {
+        self.transaction(|tx| tx.apply(Operation::DeleteRow { sheet: *sheet, row }))
+    }
+
+    fn sheet_by_id(&self, id: &Identifier) -> Result<&Sheet, Error> {
+        self.columns
+            .iter()
+            .find(|sheet| sheet.id == *id)
+            .ok_or(Error::WalError(WalError::SheetNotFound { id: *id }))
+    }
+}
+
+fn malformed<T>(reason: String) -> Result<T, Error> {
+    Err(Error::RowIdError(RowIdError::Malformed { reason }))
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum RowIdError {
+    NotFound { sheet_name: String, id: u64 },
+    Malformed { reason: String },
+}
+
+impl std::fmt::Display for RowIdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RowIdError::NotFound { sheet_name, id } => {
+                write!(f, "Sheet '{}' has no row with id {}", sheet_name, id)
+            }
+            RowIdError::Malformed { reason } => write!(f, "Malformed row ids: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RowIdError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::format::Features;
+    use crate::internal::history::History;
+    use crate::internal::observer::{Event, SubscribeOptions};
+    use crate::internal::save::SaveOptions;
+    use crate::internal::stream::StreamReader;
+    use crate::internal::traits::Serializable;
+    use crate::internal::trigger;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn items(sheet: &Sheet) -> Vec<Value> {
+        let column = sheet.get_column_by_name("item").unwrap();
+        column.cells.iter().map(|cell| cell.value.clone()).collect()
+    }
+
+    fn text(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+
+    // orders, with a trigger logging the id of each one deleted
+    fn shop() -> Database {
+        let mut database = Database::new_empty();
+        for sql in [
+            "create sheet orders (item text, qty u8)",
+            "create sheet deleted (item text, `order` u64)",
+            "insert into orders values ('tea', 1), ('cake', 2), ('scone', 3)",
+        ] {
+            database.execute(sql, &[]).unwrap();
+        }
+        let orders = database.columns[0].id;
+        database
+            .add_trigger(&orders, "log", "on delete: append deleted (old.item, row)")
+            .unwrap();
+        database
+    }
+
+    #[test]
+    fn rows_keep_their_ids() {
+        let mut database = shop();
+        let orders = database.columns[0].id;
+        let qty = database.columns[0].columns[1].id;
+        assert_eq!(database.columns[0].row_ids.ids(), &[0, 1, 2]);
+
+        database.delete_row(&orders, 1).unwrap();
+        let bun = database.insert_row(&orders, vec![text("bun"), Value::U8(4)]);
+        assert_eq!(bun.unwrap(), 3);
+        // subscribers hear of the row by its id, not where it now sits
+        let heard = Rc::new(RefCell::new(Vec::new()));
+        let seen = heard.clone();
+        let subscription = database.subscribe(SubscribeOptions::default(), move |events| {
+            seen.borrow_mut().extend_from_slice(events)
+        });
+        database.update_row(&orders, 2, &qty, Value::U8(9)).unwrap();
+        drop(subscription);
+        assert_eq!(
+            heard.borrow()[..],
+            [Event::Updated {
+                sheet: orders,
+                column: qty,
+                row: 2,
+                old: Value::U8(3),
+                new: Value::U8(9),
+            }]
+        );
+        let sheet = &database.columns[0];
+        assert_eq!(items(sheet), vec![text("tea"), text("scone"), text("bun")]);
+        assert_eq!(sheet.row_ids.ids(), &[0, 2, 3]);
+        assert_eq!(sheet.row_position(2), Some(1));
+        assert_eq!(sheet.row_position(1), None);
+        assert!(matches!(
+            database.delete_row(&orders, 1),
+            Err(Error::RowIdError(RowIdError::NotFound { id: 1, .. }))
+        ));
+
+        // the trigger logged the deleted row's id
+        let logged = database
+            .execute("select `order` from deleted", &[])
+            .unwrap();
+        assert_eq!(logged.columns[0].cells[0].value, Value::U64(1));
+        let picked = database
+            .execute(
+                "select item, qty from orders where rowid = ?",
+                &[Value::Int(2)],
+            )
+            .unwrap();
+        assert_eq!(items(&picked), vec![text("scone")]);
+        assert_eq!(picked.columns[1].cells[0].value, Value::U8(9));
+        let ids = database
+            .execute(
+                "select rowid from orders where qty > 1 order by rowid desc",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(ids.columns[0].cells[0].value, Value::U64(3));
+        database
+            .execute("delete from orders where rowid = 0", &[])
+            .unwrap();
+        assert_eq!(database.columns[0].row_ids.ids(), &[2, 3]);
+
+        // undone and saved, ids stay as they were, and aren't given out again
+        let mut history = History::new(database);
+        history
+            .apply(Operation::DeleteRow {
+                sheet: orders,
+                row: 3,
+            })
+            .unwrap();
+        history.undo();
+        let database = history.into_database();
+        assert_eq!(database.columns[0].row_ids.ids(), &[2, 3]);
+        let bytes = database.serialized_bytes();
+        let loaded = Database::deserialize_bytes(&bytes).unwrap();
+        assert_eq!(loaded.columns[0].row_ids, database.columns[0].row_ids);
+        assert_eq!(loaded.columns[0].row_ids.next(), 4);
+        let mut stream = StreamReader::new(&bytes[..]);
+        let streamed = stream.next_sheet().unwrap().unwrap();
+        assert_eq!(streamed.row_ids, database.columns[0].row_ids);
+
+        // files without them number rows from 0
+        let v1 = Database::deserialize_bytes(&database.serialized_bytes_with(&Format::v1()));
+        assert_eq!(v1.unwrap().columns[0].row_ids.ids(), &[0, 1]);
+    }
+
+    #[test]
+    fn appended_rows_bring_their_ids() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("shop.basebored");
+        let mut database = shop();
+        let orders = database.columns[0].id;
+        database.save(&path, SaveOptions::default()).unwrap();
+
+        database
+            .insert_row(&orders, vec![text("pie"), Value::U8(1)])
+            .unwrap();
+        database.append(&path).unwrap();
+        // a row added and deleted again still used up its id
+        let bun = database
+            .insert_row(&orders, vec![text("bun"), Value::U8(1)])
+            .unwrap();
+        database
+            .transaction(|tx| {
+                tx.apply(Operation::DeleteRow {
+                    sheet: orders,
+                    row: 4,
+                })
+            })
+            .unwrap();
+        database.append(&path).unwrap();
+
+        let loaded = Database::deserialize_bytes(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(loaded.columns[0].row_ids.ids(), &[0, 1, 2, 3]);
+        assert_eq!(loaded.columns[0].row_ids.next(), bun + 1);
+
+        // a row deleted and another added keeps the count, but not the ids
+        database.delete_row(&orders, 0).unwrap();
+        database
+            .insert_row(&orders, vec![text("tart"), Value::U8(1)])
+            .unwrap();
+        assert!(matches!(
+            database.append(&path),
+            Err(Error::SegmentError(
+                crate::internal::segment::SegmentError::NeedsFullSave { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn malformed_ids_are_rejected() {
+        let unchecked = Format {
+            features: Features::KNOWN.without(Features::CHECKSUMS),
+            ..Format::current()
+        };
+        let sheet = shop().columns[0].clone();
+        let bytes = sheet.serialized_bytes_with(&unchecked);
+        // after the id, the name and the triggers
+        let triggers = trigger::serialized_bytes(&sheet.triggers, &unchecked).len();
+        let ids = 16 + 4 + sheet.name.len() + triggers + 4 + 8;
+
+        let mut swapped = bytes.clone();
+        swapped[ids + 7] = 1;
+        swapped[ids + 15] = 0;
+        let mut missing = sheet.clone();
+        missing.row_ids.pop();
+        let mut behind = sheet.clone();
+        behind.row_ids.next = 1;
+        let mut last = sheet.clone();
+        *last.row_ids.ids.last_mut().unwrap() = u64::MAX;
+        last.row_ids.next = u64::MAX;
+        let mut exhausted = sheet.clone();
+        exhausted.row_ids.next = u64::MAX;
+        for bytes in [
+            swapped,
+            missing.serialized_bytes_with(&unchecked),
+            behind.serialized_bytes_with(&unchecked),
+            last.serialized_bytes_with(&unchecked),
+            exhausted.serialized_bytes_with(&unchecked),
+        ] {
+            assert!(matches!(
+                Sheet::deserialize_bytes_with(&bytes, &unchecked),
+                Err(Error::RowIdError(RowIdError::Malformed { .. }))
+            ));
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use crate::internal::database::Database;
+use crate::internal::errors::Error;
+use crate::internal::format::Format;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// defaults to the current format and no backups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct SaveOptions {
+    pub(crate) format: Format,
+    // how many previous versions to keep next to the file, as <name>.bak.1
+    // (the newest) to <name>.bak.N. Older backups are removed on save.
+    pub(crate) backups: usize,
+}
+
+// tells apart temp files of saves running at the same time in one process
+static SAVES: AtomicU64 = AtomicU64::new(0);
+
+impl Database {
+    /// Saves the database to `path` so that a crash or error at any point
+    /// leaves either the old file or the new one there, never a mix.
+    ///
+    /// The new file is written to a temp file in the same directory and synced
+    /// to disk, then renamed over `path`, and the directory is synced so the
+    /// rename survives a crash too. Backups are only rotated once the new file
+    /// is in place; if that fails the save has still happened, and the error
+    /// says why the backups weren't.
+    pub(crate) fn save(&self, path: impl AsRef<Path>, options: SaveOptions) -> Result<(), Error> {
+        let path = path.as_ref();
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let temp = sibling(
+            path,
+            &format!(
+                ".{}.{}.tmp",
+                std::process::id(),
+                SAVES.fetch_add(1, Ordering::Relaxed)
+            ),
+            true,
+        );
+        let (length, fingerprint) = match self.write_synced(&temp, &options.format) {
+            Ok(written) => written,
+            Err(err) => {
+                let _ = fs::remove_file(&temp);
+                return Err(err);
+            }
+        };
+
+        // keep the old file reachable under a second name before replacing it
+        let staged = match options.backups > 0 && path.exists() {
+            true => {
+                let staged = sibling(path, ".bak.tmp", true);
+                let _ = fs::remove_file(&staged);
+                if let Err(err) = link_or_copy(path, &staged) {
+                    let _ = fs::remove_file(&temp);
+                    return Err(Error::Io(err));
+                }
+                Some(staged)
+            }
+            false => None,
+        };
+
+        if let Err(err) = fs::rename(&temp, path) {
+            let _ = fs::remove_file(&temp);
+            if let Some(staged) = staged {
+                let _ = fs::remove_file(staged);
+            }
+            return Err(Error::Io(err));
+        }
+        sync_directory(directory).map_err(Error::Io)?;
+        self.mark_saved(path, options.format, length, length, &fingerprint)?;
+
+        match staged {
+            Some(staged) => rotate(path, &staged, options.backups),
+            // backups kept under a higher setting are stale now
+            None => remove_backups_from(path, options.backups + 1),
+        }
+        .map_err(Error::Io)?;
+        sync_directory(directory).map_err(Error::Io)?;
+
+        Ok(())
+    }
+
+    // the length of the file written, and its last 4 bytes, see segment::Saved
+    fn write_synced(&self, temp: &Path, format: &Format) -> Result<(u64, [u8; 4]), Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(temp)
+            .map_err(Error::Io)?;
+        let mut file = self
+            .write_to(BufWriter::new(file), format)?
+            .into_inner()
+            .map_err(|err| Error::Io(err.into_error()))?;
+        file.sync_all().map_err(Error::Io)?;
+
+        let length = file.metadata().map_err(Error::Io)?.len();
+        let mut fingerprint = [0; 4];
+        file.seek(SeekFrom::End(-4)).map_err(Error::Io)?;
+        file.read_exact(&mut fingerprint).map_err(Error::Io)?;
+        Ok((length, fingerprint))
+    }
+}
+
+/// Path of the `n`th backup of the file at `path`
+pub(crate) fn backup_path(path: &Path, n: usize) -> PathBuf {
+    sibling(path, &format!(".bak.{}", n), false)
+}
+
+// `path` with `suffix` appended to its file name, hidden if `hidden`
+fn sibling(path: &Path, suffix: &str, hidden: bool) -> PathBuf {
+    let mut name = OsString::from(if hidden { "." } else { "" });
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+// moves every backup one step older, dropping the oldest and any left from
+// when more were kept, and makes `staged` the newest
+fn rotate(path: &Path, staged: &Path, backups: usize) -> io::Result<()> {
+    remove_backups_from(path, backups + 1)?;
+    for n in (1..backups).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(from, backup_path(path, n + 1))?;
+        }
+    }
+    fs::rename(staged, backup_path(path, 1))
+}
+
+// removes the `n`th backup and every older one
+fn remove_backups_from(path: &Path, mut n: usize) -> io::Result<()> {
+    loop {
+        match fs::remove_file(backup_path(path, n)) {
+            Ok(()) => n += 1,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    // not every file system supports hard links
+    if fs::hard_link(from, to).is_err() {
+        fs::copy(from, to)?;
+        File::open(to)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// makes renames in `directory` durable. Directories can't be opened (or
+// synced) like files on every platform, and there's nothing to do where they can't.
+pub(crate) fn sync_directory(directory: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(directory)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = directory;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::column::Column;
+    use crate::internal::data_type::Type;
+    use crate::internal::data_value::Value;
+    use crate::internal::sheet::Sheet;
+    use crate::internal::traits::Serializable;
+
+    fn database(version: i64) -> Database {
+        let mut sheet = Sheet::new("versions".to_string(), Vec::new(), None);
+        sheet.adopt_column(&Column::new("version".to_string(), Type::Int, None));
+        sheet.insert_row(vec![Value::Int(version)]).unwrap();
+
+        let mut database = Database::new_empty();
+        database.adopt_sheet(&sheet);
+        database
+    }
+
+    fn version(path: &Path) -> Value {
+        let database = Database::deserialize_bytes(&fs::read(path).unwrap()).unwrap();
+        database.columns[0].columns[0].cells[0].value.clone()
+    }
+
+    fn files(directory: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn keeps_the_last_versions_as_backups() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let options = SaveOptions {
+            backups: 2,
+            ..SaveOptions::default()
+        };
+
+        for n in 1..=4 {
+            database(n).save(&path, options).unwrap();
+        }
+
+        assert_eq!(version(&path), Value::Int(4));
+        assert_eq!(version(&backup_path(&path, 1)), Value::Int(3));
+        assert_eq!(version(&backup_path(&path, 2)), Value::Int(2));
+        assert_eq!(
+            files(directory.path()),
+            ["db.basebored", "db.basebored.bak.1", "db.basebored.bak.2"]
+        );
+
+        // keeping fewer drops the older ones
+        let options = SaveOptions {
+            backups: 1,
+            ..options
+        };
+        database(5).save(&path, options).unwrap();
+        assert_eq!(version(&backup_path(&path, 1)), Value::Int(4));
+        assert_eq!(
+            files(directory.path()),
+            ["db.basebored", "db.basebored.bak.1"]
+        );
+        database(6).save(&path, SaveOptions::default()).unwrap();
+        assert_eq!(files(directory.path()), ["db.basebored"]);
+    }
+
+    #[test]
+    fn failed_saves_leave_the_old_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let options = SaveOptions {
+            backups: 1,
+            ..SaveOptions::default()
+        };
+        database(1).save(&path, options).unwrap();
+        database(2).save(&path, options).unwrap();
+
+        // a directory where the old file would be staged stops the save after
+        // the temp file is written
+        let blocker = sibling(&path, ".bak.tmp", true);
+        fs::create_dir(&blocker).unwrap();
+        fs::write(blocker.join("keep"), b"").unwrap();
+        let before = files(directory.path());
+
+        assert!(matches!(
+            database(3).save(&path, options),
+            Err(Error::Io(_))
+        ));
+        assert_eq!(files(directory.path()), before);
+        assert_eq!(version(&path), Value::Int(2));
+        assert_eq!(version(&backup_path(&path, 1)), Value::Int(1));
+    }
+}
@@ -0,0 +1,823 @@
+use crate::internal::byte_deserializer::{ByteDeserializer, ByteError};
+use crate::internal::checksum::{self, ChecksumError, Region};
+use crate::internal::data_type::Type;
+use crate::internal::database::Database;
+use crate::internal::errors::Error;
+use crate::internal::format::{Features, Format};
+use crate::internal::id::Identifier;
+use crate::internal::length_table::LengthTable;
+use crate::internal::limits::{Budget, DecodeLimits, Limited};
+use crate::internal::sheet::Sheet;
+use crate::internal::trigger::Trigger;
+use crate::internal::view::{self, View};
+use crate::internal::wal::Operation;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// With Features::SEGMENTS, rows added after a file was written can be appended
+// to it as segments, without touching what is already there:
+//
+// all numbers are BE
+// u32 segment_length: of everything after it, checksum included
+// length_table<Sheet> sheets: each sheet with new rows, holding just those
+//   rows in every one of its columns, and with Features::ROW_IDS their ids
+// with Features::CHECKSUMS: u32 crc32c of the segment, segment_length included
+//
+// A segment that runs past the end of the file was cut short by a crash while
+// it was being appended, and is ignored. So is one that fails its checksum
+// with no intact segment after it, as when the crash left its bytes torn or
+// zeroed; with an intact one after it, it was damaged since.
+
+/// Length of a file as Database::write_to wrote it, before any segments.
+/// Worked out from the lengths stored in the file, read with `read_u32_at`.
+pub(crate) fn base_length(
+    header_length: usize,
+    format: &Format,
+    read_u32_at: impl Fn(u64) -> Result<[u8; 4], Error>,
+) -> Result<u64, Error> {
+    let mut length = view::offset(header_length, format, &read_u32_at)?;
+    if format.has(Features::VIEWS) {
+        length += 4 + u32::from_be_bytes(read_u32_at(length)?) as u64;
+    }
+    if format.has(Features::CHECKSUMS) {
+        length += 4;
+    }
+    Ok(length)
+}
+
+/// base_length of a file in memory, checked to lie within it
+pub(crate) fn base_length_of(
+    bytes: &[u8],
+    header_length: usize,
+    format: &Format,
+) -> Result<usize, Error> {
+    let out_of_bounds = |pos: u64| {
+        Error::ByteError(ByteError::OutOfBoundsError {
+            pos: pos as usize,
+            len: bytes.len(),
+        })
+    };
+    let length = base_length(header_length, format, |offset| {
+        let at = usize::try_from(offset).map_err(|_| out_of_bounds(offset))?;
+        match bytes.get(at..).and_then(|rest| rest.get(..4)) {
+            Some(raw) => Ok([raw[0], raw[1], raw[2], raw[3]]),
+            None => Err(out_of_bounds(offset)),
+        }
+    })?;
+    match usize::try_from(length) {
+        Ok(length) if length <= bytes.len() => Ok(length),
+        _ => Err(out_of_bounds(length)),
+    }
+}
+
+/// The sheets of every complete segment in `bytes`, in the order they were
+/// appended, and where the last complete segment ends. `start` is the offset
+/// of `bytes` in the file, for errors.
+pub(crate) fn read_segments(
+    bytes: &[u8],
+    start: usize,
+    format: &Format,
+    budget: &mut Budget,
+) -> Result<(Vec<Sheet>, usize), Error> {
+    let mut sheets = Vec::new();
+    let mut deserializer = ByteDeserializer::new(bytes);
+    let mut end = 0;
+
+    loop {
+        let offset = deserializer.position();
+        let Ok(length) = deserializer.read_u32() else {
+            break;
+        };
+        let Ok(segment) = deserializer.read_bytes(length as usize) else {
+            break;
+        };
+
+        let body = match format.has(Features::CHECKSUMS) {
+            true => match intact(&bytes[offset..offset + 4 + segment.len()]) {
+                Ok(body) => body,
+                Err(_) if !any_intact(&bytes[deserializer.position()..]) => break,
+                Err(err) => return Err(err),
+            },
+            false => &segment[..],
+        };
+        for sheet in LengthTable::deserialize_limited(
+            body,
+            format,
+            Some(Limited::Sheets),
+            budget,
+            Sheet::decode,
+        )
+        .map_err(|err| err.offset_by(start + offset + 4))?
+        {
+            sheets.push(sheet);
+        }
+        end = deserializer.position();
+    }
+
+    Ok((sheets, end))
+}
+
+/// The body of a segment, given with its length and checksum, if the checksum
+/// holds. A segment too short to have a body doesn't, zeroed bytes included.
+pub(crate) fn intact(segment: &[u8]) -> Result<&[u8], Error> {
+    if segment.len() < 8 {
+        return Err(Error::ChecksumError(ChecksumError::Mismatch {
+            region: Region::Segment,
+            stored: 0,
+            computed: crc32c::crc32c(segment),
+        }));
+    }
+    Ok(&checksum::checked_body(segment, || Region::Segment)?[4..])
+}
+
+/// Whether any of the segments in `bytes` is intact. If none is, the first was
+/// torn by an interrupted append and isn't part of the file.
+pub(crate) fn any_intact(bytes: &[u8]) -> bool {
+    let mut deserializer = ByteDeserializer::new(bytes);
+    loop {
+        let offset = deserializer.position();
+        let Ok(length) = deserializer.read_u32() else {
+            return false;
+        };
+        let Ok(segment) = deserializer.read_bytes(length as usize) else {
+            return false;
+        };
+        if intact(&bytes[offset..offset + 4 + segment.len()]).is_ok() {
+            return true;
+        }
+    }
+}
+
+/// Checks that `appended` holds rows for `sheet`: the same columns, in the same
+/// order and of the same types, each with the same number of rows
+pub(crate) fn check_schema(sheet: &Sheet, appended: &Sheet) -> Result<(), Error> {
+    let mismatch = || {
+        Err(Error::SegmentError(SegmentError::SchemaMismatch {
+            sheet_name: sheet.name.clone(),
+        }))
+    };
+
+    if sheet.columns.len() != appended.columns.len() {
+        return mismatch();
+    }
+    for (column, other) in sheet.columns.iter().zip(&appended.columns) {
+        if column.id != other.id || column.value_type != other.value_type {
+            return mismatch();
+        }
+    }
+    let rows = appended
+        .columns
+        .first()
+        .map_or(0, |column| column.cells.len());
+    if appended
+        .columns
+        .iter()
+        .any(|column| column.cells.len() != rows)
+    {
+        return mismatch();
+    }
+    Ok(())
+}
+
+/// Adds the rows of appended segment sheets to the sheets they belong to
+pub(crate) fn merge(
+    database: &mut Database,
+    appended: Vec<Sheet>,
+    format: &Format,
+) -> Result<(), Error> {
+    for appended in appended {
+        let sheet = database
+            .get_sheet_mut_by_id(&appended.id)
+            .ok_or(Error::SegmentError(SegmentError::UnknownSheet {
+                id: appended.id,
+            }))?;
+        merge_sheet(sheet, appended, format)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn merge_sheet(
+    sheet: &mut Sheet,
+    appended: Sheet,
+    format: &Format,
+) -> Result<(), Error> {
+    check_schema(sheet, &appended)?;
+    let rows = appended
+        .columns
+        .first()
+        .map_or(0, |column| column.cells.len());
+    for (column, appended) in sheet.columns.iter_mut().zip(appended.columns) {
+        column.adopt_cells(appended.cells);
+    }
+    // without stored ids, appended rows are numbered on from the rows before
+    match format.has(Features::ROW_IDS) {
+        true => sheet.row_ids.append(appended.row_ids),
+        false => {
+            sheet.row_ids.fill(sheet.row_ids.len() + rows);
+            Ok(())
+        }
+    }
+}
+
+/// What a database knows of the file it was last saved to or loaded from, so
+/// Database::append can add the rows since without reading the file back:
+/// where its segments end, and what it held of each sheet
+#[derive(Debug, Clone)]
+pub(crate) struct Saved {
+    // canonical, to compare with the path appended to
+    path: PathBuf,
+    format: Format,
+    // the length of the file as Database::write_to wrote it, and with its
+    // segments
+    base: u64,
+    end: u64,
+    // the 4 bytes before `base`, its checksum with Features::CHECKSUMS, to
+    // tell a file saved over since
+    fingerprint: [u8; 4],
+    sheets: Vec<SavedSheet>,
+    views: Vec<View>,
+    // the first sheet whose saved rows were updated or deleted since
+    changed: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct SavedSheet {
+    id: Identifier,
+    name: String,
+    columns: Vec<(Identifier, Type)>,
+    triggers: Vec<Trigger>,
+    rows: usize,
+    // every saved row has an id below this, and every row added since one
+    // from it on, as ids are handed out in order
+    next_id: u64,
+}
+
+impl SavedSheet {
+    fn of(sheet: &Sheet) -> Self {
+        Self {
+            id: sheet.id,
+            name: sheet.name.clone(),
+            columns: sheet
+                .columns
+                .iter()
+                .map(|column| (column.id, column.value_type))
+                .collect(),
+            triggers: sheet.triggers.clone(),
+            rows: sheet.row_ids.len(),
+            next_id: sheet.row_ids.next(),
+        }
+    }
+
+    // whether `sheet` is this one with only rows added, and not one of these
+    // rows deleted
+    fn only_added_to(&self, sheet: &Sheet) -> bool {
+        let columns = sheet
+            .columns
+            .iter()
+            .map(|column| (column.id, column.value_type));
+        let ids = sheet.row_ids.ids();
+        sheet.id == self.id
+            && sheet.triggers == self.triggers
+            && columns.eq(self.columns.iter().copied())
+            && ids.len() >= self.rows
+            // rows are in id order, so the saved ones are all still there if
+            // the row where the last of them was is one
+            && self.rows.checked_sub(1).is_none_or(|last| ids[last] < self.next_id)
+    }
+}
+
+impl Database {
+    /// Reads the database in the file at `path`, remembering the file so rows
+    /// added later can be appended to it, see Database::append
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Database, Error> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(Error::Io)?;
+        let (database, end) = Database::decode(&bytes, &DecodeLimits::default(), false)?;
+
+        let mut deserializer = ByteDeserializer::new(&bytes);
+        let format = Format::read_header(&mut deserializer)?;
+        let base = match format.has(Features::SEGMENTS) {
+            true => base_length_of(&bytes, deserializer.position(), &format)?,
+            false => bytes.len(),
+        };
+        let fingerprint = &bytes[base.saturating_sub(4)..base];
+        database.mark_saved(path, format, base as u64, end as u64, fingerprint)?;
+        Ok(database)
+    }
+
+    /// Remembers that the file at `path` holds this database, as written with
+    /// `format` to `base` bytes, then appended to up to `end`, see Saved
+    pub(crate) fn mark_saved(
+        &self,
+        path: &Path,
+        format: Format,
+        base: u64,
+        end: u64,
+        fingerprint: &[u8],
+    ) -> Result<(), Error> {
+        let mut mark = [0; 4];
+        mark[4 - fingerprint.len()..].copy_from_slice(fingerprint);
+        *self.saved.borrow_mut() = Some(Saved {
+            path: fs::canonicalize(path).map_err(Error::Io)?,
+            format,
+            base,
+            end,
+            fingerprint: mark,
+            sheets: self.columns.iter().map(SavedSheet::of).collect(),
+            views: self.views.clone(),
+            changed: None,
+        });
+        Ok(())
+    }
+
+    /// Notes saved rows that `operations` updated or deleted, which only
+    /// Database::save can save. Called as transactions commit or are reverted.
+    pub(crate) fn note_changes(&self, operations: &[Operation]) {
+        let mut saved = self.saved.borrow_mut();
+        let Some(saved) = saved.as_mut().filter(|saved| saved.changed.is_none()) else {
+            return;
+        };
+        saved.changed = operations.iter().find_map(|operation| match operation {
+            Operation::UpdateCell { sheet, row, .. } | Operation::DeleteRow { sheet, row } => saved
+                .sheets
+                .iter()
+                .find(|saved| saved.id == *sheet && *row < saved.next_id)
+                .map(|saved| saved.name.clone()),
+            _ => None,
+        });
+    }
+
+    /// Appends the rows added since the database was saved to `path` or loaded
+    /// from it, as one segment at the end of the file, leaving everything
+    /// already in the file as it is. Only the end of the file is read.
+    ///
+    /// Only new rows are saved: the sheets, columns and rows already in the
+    /// file must be unchanged. Anything else needs Database::save, which also
+    /// folds the segments back into the file. Needs a file written with
+    /// Features::SEGMENTS, and not written to since but by appends.
+    pub(crate) fn append(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let not_saved = || {
+            Error::SegmentError(SegmentError::NotSaved {
+                path: path.to_path_buf(),
+            })
+        };
+        let canonical = fs::canonicalize(path).map_err(Error::Io)?;
+        let mut saved = self.saved.borrow_mut();
+        let saved = saved
+            .as_mut()
+            .filter(|saved| saved.path == canonical)
+            .ok_or_else(not_saved)?;
+        let format = saved.format;
+        if !format.has(Features::SEGMENTS) {
+            return Err(Error::SegmentError(SegmentError::NotAppendable));
+        }
+        if let Some(sheet_name) = &saved.changed {
+            return Err(Error::SegmentError(SegmentError::NeedsFullSave {
+                sheet_name: sheet_name.clone(),
+            }));
+        }
+        let segment = self.new_rows(saved)?;
+        if segment.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(Error::Io)?;
+        let length = file.metadata().map_err(Error::Io)?.len();
+        if length < saved.end || saved.base < 4 {
+            return Err(not_saved());
+        }
+        let mut fingerprint = [0; 4];
+        file.seek(SeekFrom::Start(saved.base - 4))
+            .map_err(Error::Io)?;
+        file.read_exact(&mut fingerprint).map_err(Error::Io)?;
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(saved.end)).map_err(Error::Io)?;
+        file.read_to_end(&mut tail).map_err(Error::Io)?;
+        // anything past the end but an interrupted append was written since
+        let mut budget = Budget::new(&format.limits);
+        let (_, appended) = read_segments(&tail, saved.end as usize, &format, &mut budget)
+            .map_err(|_| not_saved())?;
+        if fingerprint != saved.fingerprint || appended > 0 {
+            return Err(not_saved());
+        }
+
+        let table = LengthTable::serialize_with(&segment, &format);
+        let checksum_length = if format.has(Features::CHECKSUMS) {
+            4
+        } else {
+            0
+        };
+        let mut block = ((table.len() + checksum_length) as u32)
+            .to_be_bytes()
+            .to_vec();
+        block.extend(table);
+        if format.has(Features::CHECKSUMS) {
+            checksum::append(&mut block);
+        }
+
+        // drop what's left of an append that was interrupted
+        file.set_len(saved.end).map_err(Error::Io)?;
+        file.seek(SeekFrom::End(0)).map_err(Error::Io)?;
+        file.write_all(&block).map_err(Error::Io)?;
+        file.sync_data().map_err(Error::Io)?;
+
+        saved.end += block.len() as u64;
+        saved.sheets = self.columns.iter().map(SavedSheet::of).collect();
+        Ok(())
+    }
+
+    // each sheet with rows added since `saved`, holding only those rows
+    fn new_rows(&self, saved: &Saved) -> Result<Vec<Sheet>, Error> {
+        let needs_save = |sheet_name: &str| {
+            Err(Error::SegmentError(SegmentError::NeedsFullSave {
+                sheet_name: sheet_name.to_string(),
+            }))
+        };
+        if let Some(removed) = saved.sheets.get(self.columns.len()) {
+            return needs_save(&removed.name);
+        }
+        // nor are views
+        let added = self.views.iter().find(|view| !saved.views.contains(view));
+        let changed = added.or_else(|| saved.views.iter().find(|view| !self.views.contains(view)));
+        if let Some(view) = changed {
+            return needs_save(&view.name);
+        }
+
+        let mut segment = Vec::new();
+        for (n, sheet) in self.columns.iter().enumerate() {
+            let Some(saved) = saved
+                .sheets
+                .get(n)
+                .filter(|saved| saved.only_added_to(sheet))
+            else {
+                return needs_save(&sheet.name);
+            };
+
+            let mut appended = sheet.without_columns();
+            appended.triggers.clear();
+            for column in &sheet.columns {
+                let mut new = column.without_cells();
+                new.computed = None;
+                new.adopt_cells(column.cells[saved.rows..].to_vec());
+                appended.adopt_column(&new);
+            }
+            appended.row_ids = sheet.row_ids.split_off(saved.rows);
+            // rows added and deleted again still used up their ids
+            if !appended.row_ids.is_empty() || appended.row_ids.next() != saved.next_id {
+                segment.push(appended);
+            }
+        }
+        Ok(segment)
+    }
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum SegmentError {
+    // the file was written without Features::SEGMENTS
+    NotAppendable,
+    UnknownSheet { id: Identifier },
+    SchemaMismatch { sheet_name: String },
+    NeedsFullSave { sheet_name: String },
+    // the database wasn't saved to or loaded from the file, or the file was
+    // written to since
+    NotSaved { path: PathBuf },
+}
+
+impl std::fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SegmentError::NotAppendable => {
+                write!(f, "The file's format doesn't allow appending rows")
+            }
+            SegmentError::UnknownSheet { id } => {
+                write!(f, "Appended rows belong to an unknown sheet, {}", id)
+            }
+            SegmentError::SchemaMismatch { sheet_name } => write!(
+                f,
+                "Appended rows don't match the columns of sheet '{}'",
+                sheet_name
+            ),
+            SegmentError::NeedsFullSave { sheet_name } => write!(
+                f,
+                "Sheet '{}' changed in more than new rows since it was saved",
+                sheet_name
+            ),
+            SegmentError::NotSaved { path } => write!(
+                f,
+                "The database wasn't saved to '{}', or the file changed since",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SegmentError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::column::Column;
+    use crate::internal::data_type::Type;
+    use crate::internal::data_value::Value;
+    use crate::internal::database_file::DatabaseFile;
+    use crate::internal::limits::{DecodeLimits, LimitError};
+    use crate::internal::save::SaveOptions;
+    use crate::internal::stream::{Event, StreamReader};
+    use crate::internal::traits::Serializable;
+
+    fn log() -> Database {
+        let mut sheet = Sheet::new("log".to_string(), Vec::new(), None);
+        sheet.adopt_column(&Column::new("level".to_string(), Type::Str, None));
+        sheet.adopt_column(&Column::new("code".to_string(), Type::U16, None));
+        sheet
+            .insert_row(vec![Value::Str("info".to_string()), Value::Int(0)])
+            .unwrap();
+
+        let mut database = Database::new_empty();
+        database.adopt_sheet(&sheet);
+        database
+    }
+
+    fn add(database: &mut Database, code: i64) {
+        database.columns[0]
+            .insert_row(vec![Value::Str("warn".to_string()), Value::Int(code)])
+            .unwrap();
+    }
+
+    fn codes(sheet: &Sheet) -> Vec<Value> {
+        let column = sheet.get_column_by_name("code").unwrap();
+        column.cells.iter().map(|cell| cell.value.clone()).collect()
+    }
+
+    #[test]
+    fn appends_rows_without_rewriting_the_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let mut database = log();
+        database.save(&path, SaveOptions::default()).unwrap();
+        let written = fs::read(&path).unwrap();
+
+        add(&mut database, 1);
+        database.append(&path).unwrap();
+        add(&mut database, 2);
+        add(&mut database, 3);
+        database.append(&path).unwrap();
+        // nothing new
+        database.append(&path).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(&bytes[..written.len()], &written[..]);
+        let expected = codes(&database.columns[0]);
+
+        let decoded = Database::deserialize_bytes(&bytes).unwrap();
+        assert_eq!(codes(&decoded.columns[0]), expected);
+        assert!(Database::verify(&bytes).unwrap().is_ok());
+
+        let file = DatabaseFile::open(&path).unwrap();
+        let sheet = file.sheet("log").unwrap();
+        assert_eq!(codes(&sheet.load().unwrap()), expected);
+        let column = sheet.column("code").unwrap();
+        let cells: Vec<Value> = column.cells.iter().map(|c| c.value.clone()).collect();
+        assert_eq!(cells, expected);
+
+        let code = database.columns[0].columns[1].id;
+        let mut column = None;
+        let mut streamed = Vec::new();
+        for event in StreamReader::new(&bytes[..]) {
+            match event.unwrap() {
+                Event::ColumnStart { id, .. } => column = Some(id),
+                Event::Cell { row, value } if column == Some(code) => {
+                    assert_eq!(row, streamed.len());
+                    streamed.push(value);
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn ignores_an_interrupted_append() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let mut database = log();
+        database.save(&path, SaveOptions::default()).unwrap();
+        add(&mut database, 1);
+        database.append(&path).unwrap();
+        let appended = fs::read(&path).unwrap();
+
+        add(&mut database, 2);
+        database.append(&path).unwrap();
+        let full = fs::read(&path).unwrap();
+
+        for end in appended.len()..full.len() {
+            let bytes = &full[..end];
+            let decoded = Database::deserialize_bytes(bytes).unwrap();
+            assert_eq!(codes(&decoded.columns[0]), [Value::U16(0), Value::U16(1)]);
+            assert!(Database::verify(bytes).unwrap().is_ok());
+            // the sheet, then the sheet again with its appended row
+            assert_eq!(StreamReader::new(bytes).count(), 2 * (1 + 2 * 3 + 1));
+        }
+
+        // the next append replaces what's left of the interrupted one
+        fs::write(&path, &full[..full.len() - 1]).unwrap();
+        let mut loaded = Database::load(&path).unwrap();
+        add(&mut loaded, 2);
+        loaded.append(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), full);
+    }
+
+    #[test]
+    fn ignores_an_append_torn_at_any_offset() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let mut database = log();
+        database.save(&path, SaveOptions::default()).unwrap();
+        add(&mut database, 1);
+        database.append(&path).unwrap();
+        let appended = fs::read(&path).unwrap();
+
+        add(&mut database, 2);
+        database.append(&path).unwrap();
+        let full = fs::read(&path).unwrap();
+
+        // the file grew to its full length, but the crash left the rest of the
+        // segment zeroed or holding whatever was there before
+        for fill in [0x00, 0xa5] {
+            for end in appended.len()..full.len() {
+                let mut bytes = full[..end].to_vec();
+                bytes.resize(full.len(), fill);
+                let decoded = Database::deserialize_bytes(&bytes).unwrap();
+                assert_eq!(codes(&decoded.columns[0]), [Value::U16(0), Value::U16(1)]);
+                assert!(Database::verify(&bytes).unwrap().is_ok());
+                let events: Result<Vec<Event>, Error> = StreamReader::new(&bytes[..]).collect();
+                assert_eq!(events.unwrap().len(), 2 * (1 + 2 * 3 + 1));
+
+                fs::write(&path, &bytes).unwrap();
+                let file = DatabaseFile::open(&path).unwrap();
+                let sheet = file.sheet("log").unwrap().load().unwrap();
+                assert_eq!(codes(&sheet), [Value::U16(0), Value::U16(1)]);
+            }
+        }
+
+        // the next append replaces the torn one
+        let mut bytes = full[..full.len() - 1].to_vec();
+        bytes.push(0);
+        fs::write(&path, &bytes).unwrap();
+        let mut loaded = Database::load(&path).unwrap();
+        add(&mut loaded, 2);
+        loaded.append(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), full);
+    }
+
+    #[test]
+    fn a_damaged_segment_before_an_intact_one_is_an_error() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let mut database = log();
+        database.save(&path, SaveOptions::default()).unwrap();
+        let saved = fs::metadata(&path).unwrap().len() as usize;
+        add(&mut database, 1);
+        database.append(&path).unwrap();
+        add(&mut database, 2);
+        database.append(&path).unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[saved + 8] ^= 0x20;
+        assert!(matches!(
+            Database::deserialize_bytes(&bytes),
+            Err(Error::ChecksumError(_))
+        ));
+        assert!(!Database::verify(&bytes).unwrap().is_ok());
+        let events: Result<Vec<Event>, Error> = StreamReader::new(&bytes[..]).collect();
+        assert!(matches!(events, Err(Error::ChecksumError(_))));
+    }
+
+    #[test]
+    fn appended_rows_count_against_the_files_limits() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let mut database = log();
+        database.save(&path, SaveOptions::default()).unwrap();
+        let limited = |bytes: &[u8], max_total_bytes| {
+            let limits = DecodeLimits {
+                max_total_bytes,
+                ..DecodeLimits::default()
+            };
+            Database::deserialize_bytes_limited(bytes, &limits)
+        };
+        let written = fs::read(&path).unwrap();
+        let enough = (0..).find(|n| limited(&written, *n).is_ok()).unwrap();
+
+        add(&mut database, 1);
+        database.append(&path).unwrap();
+        assert!(matches!(
+            limited(&fs::read(&path).unwrap(), enough),
+            Err(Error::LimitError(LimitError::TooLarge { .. }))
+        ));
+    }
+
+    #[test]
+    fn appends_only_to_the_file_last_saved_or_loaded() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let mut database = log();
+        add(&mut database, 1);
+        database
+            .write_to(fs::File::create(&path).unwrap(), &Format::current())
+            .unwrap();
+        let not_saved = |result| {
+            matches!(
+                result,
+                Err(Error::SegmentError(SegmentError::NotSaved { .. }))
+            )
+        };
+        assert!(not_saved(database.append(&path)));
+
+        let mut loaded = Database::load(&path).unwrap();
+        add(&mut loaded, 2);
+        let other = directory.path().join("other.basebored");
+        loaded.save(&other, SaveOptions::default()).unwrap();
+        assert!(not_saved(loaded.append(&path)));
+
+        // saved over by another database
+        let mut database = Database::load(&path).unwrap();
+        add(&mut database, 2);
+        let mut saver = log();
+        add(&mut saver, 3);
+        saver.save(&path, SaveOptions::default()).unwrap();
+        assert!(not_saved(database.append(&path)));
+
+        // or appended to
+        let mut database = Database::load(&path).unwrap();
+        add(&mut database, 4);
+        add(&mut saver, 5);
+        saver.append(&path).unwrap();
+        assert!(not_saved(database.append(&path)));
+
+        let mut loaded = Database::load(&path).unwrap();
+        add(&mut loaded, 6);
+        loaded.append(&path).unwrap();
+        let decoded = Database::deserialize_bytes(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(codes(&decoded.columns[0]), codes(&loaded.columns[0]));
+        assert_eq!(decoded.columns[0].row_ids, loaded.columns[0].row_ids);
+    }
+
+    #[test]
+    fn other_changes_need_a_full_save() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let mut database = log();
+        database.save(&path, SaveOptions::default()).unwrap();
+
+        let sheet = database.columns[0].id;
+        let code = database.columns[0].columns[1].id;
+        let mut updated = database.clone();
+        add(&mut updated, 1);
+        updated.update_row(&sheet, 0, &code, Value::Int(7)).unwrap();
+        assert!(matches!(
+            updated.append(&path),
+            Err(Error::SegmentError(SegmentError::NeedsFullSave { .. }))
+        ));
+        // rows added since can change before they are appended, but not after
+        let mut added = database.clone();
+        add(&mut added, 1);
+        added.update_row(&sheet, 1, &code, Value::Int(7)).unwrap();
+        added.append(&path).unwrap();
+        added.delete_row(&sheet, 1).unwrap();
+        assert!(matches!(
+            added.append(&path),
+            Err(Error::SegmentError(SegmentError::NeedsFullSave { .. }))
+        ));
+        database.save(&path, SaveOptions::default()).unwrap();
+
+        database.columns[0].adopt_column(&Column::new("source".to_string(), Type::Str, None));
+        assert!(matches!(
+            database.append(&path),
+            Err(Error::SegmentError(SegmentError::NeedsFullSave { .. }))
+        ));
+
+        let unsegmented = SaveOptions {
+            format: Format {
+                features: Features::KNOWN.without(Features::SEGMENTS),
+                ..Format::current()
+            },
+            ..SaveOptions::default()
+        };
+        let mut database = log();
+        database.save(&path, unsegmented).unwrap();
+        add(&mut database, 1);
+        assert!(matches!(
+            database.append(&path),
+            Err(Error::SegmentError(SegmentError::NotAppendable))
+        ));
+    }
+}
@@ -1,11 +1,19 @@
 use crate::internal::byte_deserializer::ByteDeserializer;
-use crate::internal::column::Column;
+use crate::internal::checksum::{self, ChecksumError, Region};
+use crate::internal::column::{Column, ColumnPlan};
 use crate::internal::data_value::Value;
 use crate::internal::database::Database;
 use crate::internal::errors::Error;
+use crate::internal::format::{Features, Format};
 use crate::internal::id::Identifier;
 use crate::internal::length_table::LengthTable;
+use crate::internal::limits::{self, Budget, Limited};
+use crate::internal::row_id::{self, RowIds};
+use crate::internal::stream::StreamWriter;
 use crate::internal::traits::{PrettyPrintable, Serializable};
+use crate::internal::trigger::{self, Trigger};
+use std::io::Write;
+use std::ops::Range;
 use std::rc::Rc;
 
 #[derive(Debug, Clone)]
@@ -13,6 +21,9 @@ pub(crate) struct Sheet {
     pub(crate) id: Identifier,
     pub(crate) name: String,
     pub(crate) columns: Vec<Column>,
+    pub(crate) triggers: Vec<Trigger>,
+    // one per row, see row_id.rs
+    pub(crate) row_ids: RowIds,
     pub(crate) database: Option<Rc<Database>>,
 }
 
@@ -22,6 +33,8 @@ impl Sheet {
             id: Identifier::new(),
             name,
             columns,
+            triggers: Vec::new(),
+            row_ids: RowIds::default(),
             database,
         }
     }
@@ -36,6 +49,8 @@ impl Sheet {
             id,
             name,
             columns,
+            triggers: Vec::new(),
+            row_ids: RowIds::default(),
             database,
         }
     }
@@ -43,6 +58,8 @@ impl Sheet {
     pub(crate) fn adopt_column(&mut self, column: &Column) {
         let mut column = column.clone();
         column.adopt(self);
+        // rows that came without ids get new ones
+        self.row_ids.fill(column.get_row_count());
         self.columns.push(column);
     }
 
@@ -53,7 +70,19 @@ impl Sheet {
     }
 
     pub(crate) fn adopt(&mut self, database: &Database) {
-        self.database = Some(Rc::new(database.clone()));
+        self.database = Some(Rc::new(database.without_sheets()));
+    }
+
+    /// A copy of everything but the columns, for columns to refer back to
+    pub(crate) fn without_columns(&self) -> Sheet {
+        Sheet {
+            id: self.id,
+            name: self.name.clone(),
+            columns: Vec::new(),
+            triggers: self.triggers.clone(),
+            row_ids: RowIds::starting_at(self.row_ids.next()),
+            database: self.database.clone(),
+        }
     }
 
     pub(crate) fn get_column_count(&self) -> usize {
@@ -92,6 +121,61 @@ impl Sheet {
         self.columns.iter().position(|column| column.id == *id)
     }
 
+    /// Works out the serialized length of the sheet and each of its columns
+    pub(crate) fn plan(&self, format: &Format) -> SheetPlan {
+        let columns: Vec<ColumnPlan> = self
+            .columns
+            .iter()
+            .map(|column| column.plan(format))
+            .collect();
+        let lengths: Vec<usize> = columns.iter().map(|column| column.length).collect();
+
+        let columns_start = 16
+            + 4
+            + self.name.len()
+            + trigger::serialized_bytes(&self.triggers, format).len()
+            + row_id::serialized_bytes(&self.row_ids, format).len();
+        let mut length = columns_start + LengthTable::table_length(&lengths);
+        if format.has(Features::CHECKSUMS) {
+            length += 4;
+        }
+
+        SheetPlan {
+            length,
+            columns_start,
+            columns,
+        }
+    }
+
+    /// Writes the sheet as laid out by `plan`, which must come from `self.plan(format)`
+    pub(crate) fn write_to<W: Write>(
+        &self,
+        writer: &mut StreamWriter<W>,
+        plan: &SheetPlan,
+        format: &Format,
+    ) -> Result<(), Error> {
+        if format.has(Features::CHECKSUMS) {
+            writer.begin_checksum();
+        }
+
+        writer.write(&self.id.serialized_bytes())?;
+        writer.write_u32(self.name.len() as u32)?;
+        writer.write(self.name.as_bytes())?;
+        writer.write(&trigger::serialized_bytes(&self.triggers, format))?;
+        writer.write(&row_id::serialized_bytes(&self.row_ids, format))?;
+
+        let lengths: Vec<usize> = plan.columns.iter().map(|column| column.length).collect();
+        writer.write(&LengthTable::header_bytes(&lengths, format))?;
+        for (column, column_plan) in self.columns.iter().zip(&plan.columns) {
+            column.write_to(writer, column_plan, format)?;
+        }
+
+        if format.has(Features::CHECKSUMS) {
+            writer.end_checksum()?;
+        }
+        Ok(())
+    }
+
     // insert_row takes in a vector of values and inserts them into the sheet
     pub(crate) fn insert_row(
         &mut self,
@@ -103,37 +187,150 @@ impl Sheet {
                 got: values.len(),
             }));
         }
-        for (i, value) in values.iter().enumerate() {
-            self.columns[i].insert_value(value.clone());
+        // coerce every value before inserting any, so a bad value doesn't leave
+        // the row half inserted. Computed columns are filled in afterwards.
+        let values = values
+            .into_iter()
+            .zip(&self.columns)
+            .map(|(value, column)| match column.computed {
+                Some(_) => Ok(Value::Nil),
+                None => column.coerce_value(value),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        for (i, value) in values.into_iter().enumerate() {
+            self.columns[i].insert_value(value);
+        }
+        // a sheet without columns has no rows to give an id
+        if self.columns.is_empty() {
+            return Ok(());
+        }
+        self.row_ids.push();
+        let row = self.columns.first().map_or(0, Column::get_row_count);
+        if let Err(err) = self.recompute(row.saturating_sub(1)) {
+            for column in &mut self.columns {
+                column.cells.pop();
+            }
+            self.row_ids.pop();
+            return Err(err);
         }
         Ok(())
     }
 }
 
+/// The serialized length of a sheet and its columns, see Sheet::plan
+#[derive(Debug, Clone)]
+pub(crate) struct SheetPlan {
+    pub(crate) length: usize,
+    // offset of the columns table from the start of the sheet
+    pub(crate) columns_start: usize,
+    pub(crate) columns: Vec<ColumnPlan>,
+}
+
+impl SheetPlan {
+    /// Where each column sits, relative to the start of the sheet
+    pub(crate) fn column_ranges(&self) -> Vec<Range<usize>> {
+        let lengths: Vec<usize> = self.columns.iter().map(|column| column.length).collect();
+        LengthTable::ranges(&lengths)
+            .into_iter()
+            .map(|range| self.columns_start + range.start..self.columns_start + range.end)
+            .collect()
+    }
+}
+
 impl Serializable<Sheet> for Sheet {
     // all numbers are BE
     // u128 id: 16 bytes, uuid of the sheet
     // u32 name_length: 4 bytes, length of the name of the sheet
     // [u8; name_length] name: name_length bytes, name of the sheet
+    // with Features::TRIGGERS: the sheet's triggers, see trigger.rs
+    // with Features::ROW_IDS: the ids of the sheet's rows, see row_id.rs
     // length_table<Column> columns: columns serialized
+    // with Features::CHECKSUMS: u32 crc32c of everything above
 
     fn serialized_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(&self.id.serialized_bytes());
-        bytes.extend_from_slice(&(self.name.len() as u32).to_be_bytes());
-        bytes.extend_from_slice(self.name.as_bytes());
-        let columns_bytes = LengthTable::serialize(&self.columns);
-        bytes.extend_from_slice(&columns_bytes);
-        bytes
+        self.serialized_bytes_with(&Format::current())
     }
 
     fn deserialize_bytes(bytes: &[u8]) -> Result<Self, crate::internal::errors::Error> {
+        Self::deserialize_bytes_with(bytes, &Format::current())
+    }
+
+    fn serialized_bytes_with(&self, format: &Format) -> Vec<u8> {
+        let plan = self.plan(format);
+        let mut writer = StreamWriter::new(Vec::with_capacity(plan.length));
+        self.write_to(&mut writer, &plan, format)
+            .expect("writing to a Vec can't fail");
+        writer.into_inner()
+    }
+
+    fn deserialize_bytes_with(
+        bytes: &[u8],
+        format: &Format,
+    ) -> Result<Self, crate::internal::errors::Error> {
+        Self::decode(bytes, format, &mut Budget::new(&format.limits))
+    }
+}
+
+impl Sheet {
+    /// Decodes a sheet, charging what it and its columns allocate to `budget`
+    pub(crate) fn decode(
+        bytes: &[u8],
+        format: &Format,
+        budget: &mut Budget,
+    ) -> Result<Self, Error> {
+        let bytes = if format.has(Features::CHECKSUMS) {
+            checksum::checked_body(bytes, || Region::Sheet {
+                name: checksum::peek_name(bytes),
+            })?
+        } else {
+            bytes
+        };
+
         let mut deserializer = ByteDeserializer::new(bytes);
         let id = Identifier::deserialize_bytes(&deserializer.read_bytes(16)?)?;
         let name_length = deserializer.read_u32()?;
-        let name = deserializer.read_string(name_length as usize)?;
-        let columns = LengthTable::deserialize(deserializer.remaining_bytes())?;
+        format.limits.check_string(name_length as usize)?;
+        budget.charge(limits::sheet_size(name_length as usize))?;
+        let name = deserializer.read_name(name_length as usize, format)?;
+        let triggers = trigger::read(&mut deserializer, format)?;
+        let row_ids = row_id::read(&mut deserializer, format, budget)?;
+
+        // a column doesn't know which sheet it is in, so name it in the error here
+        let columns_start = deserializer.position();
+        let columns = LengthTable::deserialize_limited(
+            deserializer.remaining_bytes(),
+            format,
+            Some(Limited::Columns),
+            budget,
+            Column::decode,
+        )
+        .map_err(|err| match err.offset_by(columns_start) {
+            Error::ChecksumError(ChecksumError::Mismatch {
+                region:
+                    Region::Column {
+                        sheet: None,
+                        name: column,
+                    },
+                stored,
+                computed,
+            }) => Error::ChecksumError(ChecksumError::Mismatch {
+                region: Region::Column {
+                    sheet: Some(name.clone()),
+                    name: column,
+                },
+                stored,
+                computed,
+            }),
+            err => err,
+        })?;
         let mut sheet = Sheet::new_with_set_id(id, name, Vec::new(), None);
+        sheet.triggers = triggers;
+        if format.has(Features::ROW_IDS) {
+            let rows = columns.first().map_or(0, Column::get_row_count);
+            row_id::check_count(row_ids.len(), rows, &sheet.name)?;
+        }
+        sheet.row_ids = row_ids;
         sheet.adopt_columns(columns);
         Ok(sheet)
     }
@@ -144,6 +341,12 @@ impl PrettyPrintable for Sheet {
         let mut result = String::new();
         let indent_str = " ".repeat(indent);
         result.push_str(&format!("{}Sheet: {}\n", indent_str, self.name));
+        for trigger in &self.triggers {
+            result.push_str(&format!(
+                "{}  Trigger {}: {}\n",
+                indent_str, trigger.name, trigger
+            ));
+        }
         for column in &self.columns {
             result.push_str(&column.pretty_print(indent + 2));
         }
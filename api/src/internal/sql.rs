@@ -0,0 +1,767 @@
+use crate::internal::column::Column;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::{Number, Value};
+use crate::internal::database::{Database, DatabaseError};
+use crate::internal::errors::Error;
+use crate::internal::expr::{Expr, Kind, Parser};
+use crate::internal::id::Identifier;
+use crate::internal::sheet::Sheet;
+use crate::internal::view::ViewError;
+use crate::internal::wal::Operation;
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+// A small subset of SQL, mapped onto sheets and columns:
+//
+//   CREATE SHEET name (column TYPE, ...)
+//   DROP SHEET name
+//   CREATE VIEW name AS SELECT ...
+//   DROP VIEW name
+//   INSERT INTO name [(column, ...)] VALUES (value, ...), ...
+//   SELECT * | value [AS name], ... FROM name [WHERE condition]
+//       [ORDER BY value [ASC | DESC], ...] [LIMIT count]
+//   UPDATE name SET column = value, ... [WHERE condition]
+//   DELETE FROM name [WHERE condition]
+//
+// Values and conditions are Exprs over the columns of the sheet, where
+// `rowid` is a row's id (see row_id.rs), and `?` stands for the next
+// parameter. Keywords are case-insensitive, and a
+// statement may end with `;`. Changes go through a transaction like any
+// other, so they're checked, logged and observed the same way. SELECT reads
+// views like sheets, see view.rs, but the other statements only change sheets.
+
+// type names for CREATE SHEET, with their usual SQL spellings
+const TYPES: [(&str, Type); 23] = [
+    ("bool", Type::Bool),
+    ("boolean", Type::Bool),
+    ("int", Type::Int),
+    ("integer", Type::Int),
+    ("bigint", Type::Int),
+    ("i64", Type::Int),
+    ("flt", Type::Flt),
+    ("float", Type::Flt),
+    ("double", Type::Flt),
+    ("real", Type::Flt),
+    ("f64", Type::Flt),
+    ("str", Type::Str),
+    ("text", Type::Str),
+    ("varchar", Type::Str),
+    ("i8", Type::I8),
+    ("i16", Type::I16),
+    ("i32", Type::I32),
+    ("i128", Type::I128),
+    ("u8", Type::U8),
+    ("u16", Type::U16),
+    ("u32", Type::U32),
+    ("u64", Type::U64),
+    ("f32", Type::F32),
+];
+
+/// A SELECT statement, which picks rows of a sheet or view into a new sheet.
+/// Written back out by Display, e.g. for views.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Query {
+    pub(crate) sheet: String,
+    // every column if None
+    values: Option<Vec<(Expr, Option<String>)>>,
+    filter: Option<Expr>,
+    // true for descending
+    order: Vec<(Expr, bool)>,
+    limit: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Statement {
+    CreateSheet {
+        name: String,
+        columns: Vec<(String, Type)>,
+    },
+    DropSheet {
+        name: String,
+    },
+    CreateView {
+        name: String,
+        query: Query,
+    },
+    DropView {
+        name: String,
+    },
+    Insert {
+        sheet: String,
+        // all of them, in order, if None
+        columns: Option<Vec<String>>,
+        rows: Vec<Vec<Expr>>,
+    },
+    Select(Query),
+    Update {
+        sheet: String,
+        set: Vec<(String, Expr)>,
+        filter: Option<Expr>,
+    },
+    Delete {
+        sheet: String,
+        filter: Option<Expr>,
+    },
+}
+
+impl Statement {
+    fn parse(source: &str, params: &[Value]) -> Result<Statement, Error> {
+        let mut parser = Parser::new(source, params)?;
+        let statement = if parser.eat_both("create", "view") {
+            let name = parser.name()?;
+            parser.keyword("as")?;
+            parser.keyword("select")?;
+            Statement::CreateView {
+                name,
+                query: Query::read(&mut parser)?,
+            }
+        } else if parser.eat_both("drop", "view") {
+            Statement::DropView {
+                name: parser.name()?,
+            }
+        } else if parser.eat("create") {
+            parser.keyword("sheet")?;
+            let name = parser.name()?;
+            parser.expect("(")?;
+            let mut columns = Vec::new();
+            loop {
+                columns.push((parser.name()?, type_named(&parser.name()?)?));
+                if !parser.eat_symbol(",") {
+                    break;
+                }
+            }
+            parser.expect(")")?;
+            Statement::CreateSheet { name, columns }
+        } else if parser.eat("drop") {
+            parser.keyword("sheet")?;
+            Statement::DropSheet {
+                name: parser.name()?,
+            }
+        } else if parser.eat("insert") {
+            parser.keyword("into")?;
+            let sheet = parser.name()?;
+            let columns = match parser.eat_symbol("(") {
+                true => Some(list(&mut parser, Parser::name)?),
+                false => None,
+            };
+            parser.keyword("values")?;
+            let mut rows = Vec::new();
+            loop {
+                parser.expect("(")?;
+                rows.push(list(&mut parser, Parser::expression)?);
+                if !parser.eat_symbol(",") {
+                    break;
+                }
+            }
+            Statement::Insert {
+                sheet,
+                columns,
+                rows,
+            }
+        } else if parser.eat("select") {
+            Statement::Select(Query::read(&mut parser)?)
+        } else if parser.eat("update") {
+            let sheet = parser.name()?;
+            parser.keyword("set")?;
+            let mut set = Vec::new();
+            loop {
+                let column = parser.name()?;
+                parser.expect("=")?;
+                set.push((column, parser.expression()?));
+                if !parser.eat_symbol(",") {
+                    break;
+                }
+            }
+            Statement::Update {
+                sheet,
+                set,
+                filter: filter(&mut parser)?,
+            }
+        } else if parser.eat("delete") {
+            parser.keyword("from")?;
+            Statement::Delete {
+                sheet: parser.name()?,
+                filter: filter(&mut parser)?,
+            }
+        } else {
+            return parser.unexpected("expected a statement");
+        };
+        parser.eat_symbol(";");
+        parser.finish()?;
+        Ok(statement)
+    }
+}
+
+impl Query {
+    /// Parses a SELECT statement without parameters
+    pub(crate) fn parse(source: &str) -> Result<Query, Error> {
+        match Statement::parse(source, &[])? {
+            Statement::Select(query) => Ok(query),
+            _ => Err(Error::SqlError(SqlError::NotAQuery)),
+        }
+    }
+
+    /// What the WHERE clause keeps, if there is one
+    pub(crate) fn filter(&self) -> Option<&Expr> {
+        self.filter.as_ref()
+    }
+
+    // the rest of a SELECT statement, after the keyword
+    fn read(parser: &mut Parser) -> Result<Query, Error> {
+        let values = match parser.eat_symbol("*") {
+            true => None,
+            false => {
+                let mut values = Vec::new();
+                loop {
+                    let value = parser.expression()?;
+                    let name = match parser.eat("as") {
+                        true => Some(parser.name()?),
+                        false => None,
+                    };
+                    values.push((value, name));
+                    if !parser.eat_symbol(",") {
+                        break;
+                    }
+                }
+                Some(values)
+            }
+        };
+        parser.keyword("from")?;
+        let sheet = parser.name()?;
+        let filter = filter(parser)?;
+        let mut order = Vec::new();
+        if parser.eat("order") {
+            parser.keyword("by")?;
+            loop {
+                let value = parser.expression()?;
+                let descending = parser.eat("desc");
+                if !descending {
+                    parser.eat("asc");
+                }
+                order.push((value, descending));
+                if !parser.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+        let limit = match parser.eat("limit") {
+            true => Some(parser.expression()?),
+            false => None,
+        };
+        Ok(Query {
+            sheet,
+            values,
+            filter,
+            order,
+            limit,
+        })
+    }
+}
+
+// items separated by commas, up to and including a `)`
+fn list<'a, T>(
+    parser: &mut Parser<'a>,
+    item: fn(&mut Parser<'a>) -> Result<T, Error>,
+) -> Result<Vec<T>, Error> {
+    let mut items = vec![item(parser)?];
+    while parser.eat_symbol(",") {
+        items.push(item(parser)?);
+    }
+    parser.expect(")")?;
+    Ok(items)
+}
+
+fn filter(parser: &mut Parser) -> Result<Option<Expr>, Error> {
+    match parser.eat("where") {
+        true => parser.expression().map(Some),
+        false => Ok(None),
+    }
+}
+
+fn type_named(name: &str) -> Result<Type, Error> {
+    TYPES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, value_type)| *value_type)
+        .ok_or_else(|| {
+            Error::SqlError(SqlError::UnknownType {
+                name: name.to_string(),
+            })
+        })
+}
+
+// the value of an expression that doesn't read any columns
+fn constant(expression: &Expr) -> Result<Value, Error> {
+    expression.evaluate(&|_| None)
+}
+
+impl Database {
+    /// Runs one SQL statement, see sql.rs, with `params` standing in for its
+    /// `?`s in order. SELECT returns the rows it picked as a new sheet, not
+    /// added to the database. INSERT, UPDATE and DELETE return a sheet with
+    /// one U64 column `rows` holding how many rows they changed, and the rest
+    /// return an empty sheet.
+    pub(crate) fn execute(&mut self, sql: &str, params: &[Value]) -> Result<Sheet, Error> {
+        match Statement::parse(sql, params)? {
+            Statement::CreateSheet { name, columns } => {
+                if self.sheet_names().contains(&name.as_str()) {
+                    return Err(Error::SqlError(SqlError::SheetExists { name }));
+                }
+                let id = Identifier::new();
+                self.transaction(|tx| {
+                    tx.apply(Operation::AddSheet {
+                        id,
+                        name: name.clone(),
+                    })?;
+                    for (column, value_type) in columns {
+                        tx.apply(Operation::AddColumn {
+                            sheet: id,
+                            id: Identifier::new(),
+                            name: column,
+                            value_type,
+                        })?;
+                    }
+                    Ok(())
+                })?;
+                Ok(result(&name, None))
+            }
+            Statement::DropSheet { name } => {
+                let sheet = self.sheet_named(&name)?.id;
+                self.transaction(|tx| tx.apply(Operation::RemoveSheet { sheet }))?;
+                Ok(result(&name, None))
+            }
+            Statement::CreateView { name, query } => {
+                self.add_view(&name, query)?;
+                Ok(result(&name, None))
+            }
+            Statement::DropView { name } => {
+                self.remove_view(&name)?;
+                Ok(result(&name, None))
+            }
+            Statement::Insert {
+                sheet,
+                columns,
+                rows,
+            } => {
+                let target = self.sheet_named(&sheet)?;
+                let indices = match columns {
+                    Some(names) => names
+                        .iter()
+                        .map(|name| column_index(target, name))
+                        .collect::<Result<Vec<_>, Error>>()?,
+                    None => (0..target.columns.len()).collect(),
+                };
+                let mut operations = Vec::new();
+                for row in &rows {
+                    if row.len() != indices.len() {
+                        return Err(Error::SqlError(SqlError::ValueCount {
+                            expected: indices.len(),
+                            got: row.len(),
+                        }));
+                    }
+                    let mut values = vec![Value::Nil; target.columns.len()];
+                    for (index, expression) in indices.iter().zip(row) {
+                        values[*index] = constant(expression)?;
+                    }
+                    operations.push(Operation::InsertRow {
+                        sheet: target.id,
+                        values,
+                    });
+                }
+                let changed = operations.len();
+                self.apply_all(operations)?;
+                Ok(result(&sheet, Some(changed)))
+            }
+            Statement::Select(query) => self.query(&query),
+            Statement::Update { sheet, set, filter } => {
+                let target = self.sheet_named(&sheet)?;
+                let mut columns = Vec::new();
+                for (name, expression) in &set {
+                    expression.check_in(target)?;
+                    let index = column_index(target, name)?;
+                    columns.push((target.columns[index].id, expression));
+                }
+                // worked out from the rows as they were, before any is set
+                let rows = target.rows_matching(filter.as_ref())?;
+                let mut operations = Vec::new();
+                for &row in &rows {
+                    let Some(id) = target.row_id(row) else {
+                        continue;
+                    };
+                    for (column, expression) in &columns {
+                        operations.push(Operation::UpdateCell {
+                            sheet: target.id,
+                            column: *column,
+                            row: id,
+                            value: expression.evaluate_in(target, row)?,
+                        });
+                    }
+                }
+                self.apply_all(operations)?;
+                Ok(result(&sheet, Some(rows.len())))
+            }
+            Statement::Delete { sheet, filter } => {
+                let target = self.sheet_named(&sheet)?;
+                let operations = target
+                    .rows_matching(filter.as_ref())?
+                    .into_iter()
+                    .filter_map(|row| target.row_id(row))
+                    .map(|row| Operation::DeleteRow {
+                        sheet: target.id,
+                        row,
+                    })
+                    .collect::<Vec<_>>();
+                let changed = operations.len();
+                self.apply_all(operations)?;
+                Ok(result(&sheet, Some(changed)))
+            }
+        }
+    }
+
+    /// Runs a SELECT statement, see Query
+    pub(crate) fn query(&self, query: &Query) -> Result<Sheet, Error> {
+        self.read(&query.sheet)?.select(query)
+    }
+
+    // a sheet to change, which a view can't be
+    fn sheet_named(&self, name: &str) -> Result<&Sheet, Error> {
+        if let Some(sheet) = self.columns.iter().find(|sheet| sheet.name == name) {
+            return Ok(sheet);
+        }
+        Err(match self.views.iter().any(|view| view.name == name) {
+            true => Error::ViewError(ViewError::ReadOnly {
+                name: name.to_string(),
+            }),
+            false => Error::DatabaseError(DatabaseError::SheetNotFound {
+                sheet_name: name.to_string(),
+            }),
+        })
+    }
+
+    // all or none of them
+    fn apply_all(&mut self, operations: Vec<Operation>) -> Result<(), Error> {
+        self.transaction(|tx| operations.into_iter().try_for_each(|op| tx.apply(op)))
+    }
+}
+
+impl Sheet {
+    fn rows_matching(&self, filter: Option<&Expr>) -> Result<Vec<usize>, Error> {
+        match filter {
+            Some(filter) => self.rows_where(filter),
+            None => Ok((0..self.columns.first().map_or(0, Column::get_row_count)).collect()),
+        }
+    }
+
+    /// The rows `query` picks from this sheet, whatever sheet it names
+    pub(crate) fn select(&self, query: &Query) -> Result<Sheet, Error> {
+        let values = match &query.values {
+            Some(values) => values.clone(),
+            None => self
+                .columns
+                .iter()
+                .map(|column| (column_expr(&column.name), Some(column.name.clone())))
+                .collect(),
+        };
+        let mut columns = Vec::new();
+        for (expression, name) in &values {
+            let name = match (name, &expression.kind) {
+                (Some(name), _) | (None, Kind::Column(name)) => name.clone(),
+                (None, _) => expression.to_string(),
+            };
+            columns.push(Column::new(name, expression.check_in(self)?, None));
+        }
+        let order = &query.order;
+        for (expression, _) in order {
+            expression.check_in(self)?;
+        }
+
+        let mut rows = self.rows_matching(query.filter.as_ref())?;
+        if !order.is_empty() {
+            let mut keyed = Vec::new();
+            for row in rows {
+                let keys = order
+                    .iter()
+                    .map(|(expression, _)| expression.evaluate_in(self, row))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                keyed.push((keys, row));
+            }
+            // stable, so rows that tie keep their order
+            keyed.sort_by(|(a, _), (b, _)| {
+                a.iter()
+                    .zip(b)
+                    .zip(order)
+                    .map(|((a, b), (_, descending))| match descending {
+                        true => b.cmp(a),
+                        false => a.cmp(b),
+                    })
+                    .find(|ordering| *ordering != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+            rows = keyed.into_iter().map(|(_, row)| row).collect();
+        }
+        if let Some(limit) = &query.limit {
+            match constant(limit)?.as_number() {
+                Some(Number::Int(count)) if count >= 0 => {
+                    rows.truncate(usize::try_from(count).unwrap_or(usize::MAX))
+                }
+                _ => {
+                    return Err(Error::SqlError(SqlError::InvalidLimit {
+                        limit: limit.to_string(),
+                    }))
+                }
+            }
+        }
+
+        for row in rows {
+            for ((expression, _), column) in values.iter().zip(&mut columns) {
+                column.push_value(expression.evaluate_in(self, row)?)?;
+            }
+        }
+        let mut selected = Sheet::new(self.name.clone(), Vec::new(), None);
+        selected.adopt_columns(columns);
+        Ok(selected)
+    }
+}
+
+fn column_expr(name: &str) -> Expr {
+    Expr {
+        kind: Kind::Column(name.to_string()),
+        span: 0..0,
+    }
+}
+
+fn column_index(sheet: &Sheet, name: &str) -> Result<usize, Error> {
+    sheet.get_column_index(name).ok_or_else(|| {
+        Error::SqlError(SqlError::ColumnNotFound {
+            sheet_name: sheet.name.clone(),
+            column_name: name.to_string(),
+        })
+    })
+}
+
+impl Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SELECT ")?;
+        match &self.values {
+            None => write!(f, "*")?,
+            Some(values) => {
+                for (n, (value, name)) in values.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                    if let Some(name) = name {
+                        write!(f, " AS {}", column_expr(name))?;
+                    }
+                }
+            }
+        }
+        write!(f, " FROM {}", column_expr(&self.sheet))?;
+        if let Some(filter) = &self.filter {
+            write!(f, " WHERE {}", filter)?;
+        }
+        for (n, (value, descending)) in self.order.iter().enumerate() {
+            match n {
+                0 => write!(f, " ORDER BY {}", value)?,
+                _ => write!(f, ", {}", value)?,
+            }
+            if *descending {
+                write!(f, " DESC")?;
+            }
+        }
+        if let Some(limit) = &self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+        Ok(())
+    }
+}
+
+// what a statement other than SELECT returns: how many rows it changed, if any
+fn result(sheet: &str, changed: Option<usize>) -> Sheet {
+    let mut result = Sheet::new(sheet.to_string(), Vec::new(), None);
+    if let Some(changed) = changed {
+        let mut rows = Column::new("rows".to_string(), Type::U64, None);
+        rows.insert_value(Value::U64(changed as u64));
+        result.adopt_column(&rows);
+    }
+    result
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum SqlError {
+    SheetExists {
+        name: String,
+    },
+    ColumnNotFound {
+        sheet_name: String,
+        column_name: String,
+    },
+    UnknownType {
+        name: String,
+    },
+    ValueCount {
+        expected: usize,
+        got: usize,
+    },
+    InvalidLimit {
+        limit: String,
+    },
+    NotAQuery,
+}
+
+impl std::fmt::Display for SqlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SqlError::SheetExists { name } => write!(f, "Sheet {} already exists", name),
+            SqlError::ColumnNotFound {
+                sheet_name,
+                column_name,
+            } => write!(
+                f,
+                "Column {} not found in sheet {}",
+                column_name, sheet_name
+            ),
+            SqlError::UnknownType { name } => write!(f, "Unknown type {}", name),
+            SqlError::ValueCount { expected, got } => {
+                write!(f, "Expected {} values, got {}", expected, got)
+            }
+            SqlError::InvalidLimit { limit } => {
+                write!(f, "LIMIT {} isn't a count of rows", limit)
+            }
+            SqlError::NotAQuery => write!(f, "Expected a SELECT statement"),
+        }
+    }
+}
+
+impl std::error::Error for SqlError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::expr::ExprError;
+
+    fn values(sheet: &Sheet, column: &str) -> Vec<Value> {
+        let column = sheet.get_column_by_name(column).unwrap();
+        column.cells.iter().map(|cell| cell.value.clone()).collect()
+    }
+
+    fn people() -> Database {
+        let mut database = Database::new_empty();
+        database
+            .execute(
+                "CREATE SHEET people (name TEXT, age u8, admin boolean)",
+                &[],
+            )
+            .unwrap();
+        let inserted = database
+            .execute(
+                "insert into people values ('Ada', 36, true), (?, ?, false), ('Grace', 85, nil);",
+                &[Value::Str("alan".to_string()), Value::Int(41)],
+            )
+            .unwrap();
+        assert_eq!(values(&inserted, "rows"), vec![Value::U64(3)]);
+        database
+    }
+
+    #[test]
+    fn statements_map_onto_sheets() {
+        let mut database = people();
+        assert_eq!(database.columns[0].columns[1].value_type, Type::U8);
+
+        let selected = database
+            .execute(
+                "SELECT upper(name) AS shout, age + 1 FROM people WHERE age > ? ORDER BY admin DESC, age LIMIT 2",
+                &[Value::Int(36)],
+            )
+            .unwrap();
+        assert_eq!(
+            values(&selected, "shout"),
+            vec![
+                Value::Str("ALAN".to_string()),
+                Value::Str("GRACE".to_string())
+            ]
+        );
+        assert_eq!(
+            values(&selected, "age + 1"),
+            vec![Value::Int(42), Value::Int(86)]
+        );
+
+        database
+            .execute("insert into people (age, name) values (7, 'Tim')", &[])
+            .unwrap();
+        assert_eq!(values(&database.columns[0], "admin")[3], Value::Nil);
+
+        let updated = database
+            .execute(
+                "UPDATE people SET age = age * 2, admin = not admin WHERE age < 40",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(values(&updated, "rows"), vec![Value::U64(2)]);
+        let deleted = database
+            .execute("delete from people where admin is null", &[])
+            .unwrap();
+        assert_eq!(values(&deleted, "rows"), vec![Value::U64(2)]);
+        let everyone = database.execute("select * from people", &[]).unwrap();
+        assert_eq!(values(&everyone, "age"), vec![Value::U8(72), Value::U8(41)]);
+        assert_eq!(
+            values(&everyone, "admin"),
+            vec![Value::Bool(false), Value::Bool(false)]
+        );
+
+        database.execute("DROP SHEET people", &[]).unwrap();
+        assert!(database.columns.is_empty());
+    }
+
+    #[test]
+    fn bad_statements_change_nothing() {
+        let mut database = people();
+        let before = database.columns[0].clone();
+        for (sql, params) in [
+            ("select * from nobody", vec![]),
+            ("select nobody from people", vec![]),
+            ("create sheet people (a int)", vec![]),
+            ("create sheet other (a money)", vec![]),
+            ("insert into people values ('Bob', 1)", vec![]),
+            ("insert into people values ('Bob', 300, true)", vec![]),
+            ("update people set age = age + 250", vec![]),
+            ("select * from people limit -1", vec![]),
+            ("select * from people where age = ?", vec![]),
+            ("delete from people", vec![Value::Nil]),
+            ("select * from people where name", vec![]),
+            ("frobnicate people", vec![]),
+        ] {
+            assert!(database.execute(sql, &params).is_err(), "{}", sql);
+        }
+        assert_eq!(values(&database.columns[0], "age"), values(&before, "age"));
+        assert_eq!(database.columns.len(), 1);
+
+        assert!(matches!(
+            database.execute("select name from people where age >", &[]),
+            Err(Error::ExprError(ExprError::Syntax { span, .. })) if span == (35..35)
+        ));
+
+        // far too deep to parse, rather than too deep for the stack
+        let deep = 100_000;
+        for sql in [
+            format!(
+                "select * from people where {}age{} > 1",
+                "(".repeat(deep),
+                ")".repeat(deep)
+            ),
+            format!("delete from people where {}true", "not ".repeat(deep)),
+            format!(
+                "update people set age = 1{} where true",
+                " + 1".repeat(deep)
+            ),
+        ] {
+            assert!(matches!(
+                database.execute(&sql, &[]),
+                Err(Error::ExprError(ExprError::Syntax { .. }))
+            ));
+        }
+        assert_eq!(values(&database.columns[0], "age"), values(&before, "age"));
+    }
+}
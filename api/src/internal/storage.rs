@@ -0,0 +1,555 @@
+use crate::internal::column::Column;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::Value;
+use crate::internal::database::{Database, DatabaseError};
+use crate::internal::errors::Error;
+use crate::internal::id::Identifier;
+use crate::internal::row_id::RowIds;
+use crate::internal::sheet::Sheet;
+use crate::internal::sql::Query;
+use crate::internal::wal::{Operation, WalError};
+use std::ops::Range;
+
+// how many rows a query reads into memory at a time
+const QUERY_BATCH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SheetInfo {
+    pub(crate) id: Identifier,
+    pub(crate) name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ColumnInfo {
+    pub(crate) id: Identifier,
+    pub(crate) name: String,
+    pub(crate) value_type: Type,
+}
+
+/// A change to the sheets, columns or rows of a `Storage`, with rows referred
+/// to by id, see row_id.rs. Triggers, computed columns and views are only
+/// kept by `Database`, and changed with `Operation`s.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Change {
+    AddSheet {
+        id: Identifier,
+        name: String,
+    },
+    RemoveSheet {
+        sheet: Identifier,
+    },
+    AddColumn {
+        sheet: Identifier,
+        id: Identifier,
+        name: String,
+        value_type: Type,
+    },
+    RemoveColumn {
+        sheet: Identifier,
+        column: Identifier,
+    },
+    InsertRow {
+        sheet: Identifier,
+        values: Vec<Value>,
+    },
+    UpdateCell {
+        sheet: Identifier,
+        column: Identifier,
+        row: u64,
+        value: Value,
+    },
+    DeleteRow {
+        sheet: Identifier,
+        row: u64,
+    },
+}
+
+/// Where a database's sheets are kept. `Database` holds everything in memory,
+/// `pager::PagedStorage` keeps it in pages on disk and caches only some of
+/// them, so a database can be larger than memory. `mvcc::VersionedDatabase`
+/// keeps old versions of rows around for snapshots.
+///
+/// They all make the same changes, through `Change`s, and fail the same way,
+/// and give rows the same ids. Reads take `&mut self` because a paged read
+/// may evict another page, and are by position. Queries run on any of them,
+/// reading a batch of rows at a time.
+pub(crate) trait Storage {
+    /// Applies `change`, leaving the storage untouched on error
+    fn apply(&mut self, change: &Change) -> Result<(), Error>;
+
+    fn sheets(&mut self) -> Result<Vec<SheetInfo>, Error>;
+
+    fn columns(&mut self, sheet: &Identifier) -> Result<Vec<ColumnInfo>, Error>;
+
+    fn row_count(&mut self, sheet: &Identifier) -> Result<usize, Error>;
+
+    fn cell(&mut self, sheet: &Identifier, column: &Identifier, row: usize)
+        -> Result<Value, Error>;
+
+    /// The id of the row at position `row`
+    fn row_id(&mut self, sheet: &Identifier, row: usize) -> Result<u64, Error>;
+
+    /// The id the next row inserted into `sheet` gets
+    fn next_row_id(&mut self, sheet: &Identifier) -> Result<u64, Error>;
+
+    /// Makes the next row inserted into `sheet` get `next` or a later id, so
+    /// `import` keeps the ids rows had
+    fn skip_row_ids(&mut self, sheet: &Identifier, next: u64) -> Result<(), Error>;
+
+    /// Writes any changes still held in memory to disk
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn find_sheet(&mut self, name: &str) -> Result<Option<SheetInfo>, Error> {
+        Ok(self.sheets()?.into_iter().find(|sheet| sheet.name == name))
+    }
+
+    fn row(&mut self, sheet: &Identifier, row: usize) -> Result<Vec<Value>, Error> {
+        self.columns(sheet)?
+            .iter()
+            .map(|column| self.cell(sheet, &column.id, row))
+            .collect()
+    }
+
+    /// Copies every sheet of `database` in, keeping the ids of its rows
+    fn import(&mut self, database: &Database) -> Result<(), Error> {
+        for sheet in &database.columns {
+            self.apply(&Change::AddSheet {
+                id: sheet.id,
+                name: sheet.name.clone(),
+            })?;
+            for column in &sheet.columns {
+                self.apply(&Change::AddColumn {
+                    sheet: sheet.id,
+                    id: column.id,
+                    name: column.name.clone(),
+                    value_type: column.value_type,
+                })?;
+            }
+            let rows = sheet.columns.first().map_or(0, Column::get_row_count);
+            for row in 0..rows {
+                let values = sheet
+                    .columns
+                    .iter()
+                    .map(|column| column.cells[row].value.clone())
+                    .collect();
+                if let Some(id) = sheet.row_id(row) {
+                    self.skip_row_ids(&sheet.id, id)?;
+                }
+                self.apply(&Change::InsertRow {
+                    sheet: sheet.id,
+                    values,
+                })?;
+            }
+            self.skip_row_ids(&sheet.id, sheet.row_ids.next())?;
+        }
+        Ok(())
+    }
+
+    /// Reads the rows `rows` of one sheet into memory, with their ids
+    fn load_rows(&mut self, info: &SheetInfo, rows: Range<usize>) -> Result<Sheet, Error> {
+        let mut sheet = Sheet::new_with_set_id(info.id, info.name.clone(), Vec::new(), None);
+        for column_info in self.columns(&info.id)? {
+            let mut column = Column::new_with_set_id(
+                column_info.id,
+                column_info.name,
+                column_info.value_type,
+                None,
+            );
+            for row in rows.clone() {
+                column.insert_value(self.cell(&info.id, &column_info.id, row)?);
+            }
+            sheet.adopt_column(&column);
+        }
+        let ids = match sheet.columns.is_empty() {
+            true => Vec::new(),
+            false => rows
+                .map(|row| self.row_id(&info.id, row))
+                .collect::<Result<_, Error>>()?,
+        };
+        sheet.row_ids = RowIds::from_ids(ids, self.next_row_id(&info.id)?);
+        Ok(sheet)
+    }
+
+    /// Reads one sheet into memory
+    fn load_sheet(&mut self, info: &SheetInfo) -> Result<Sheet, Error> {
+        let rows = self.row_count(&info.id)?;
+        self.load_rows(info, 0..rows)
+    }
+
+    /// Runs a SELECT statement, see Query, on the sheet it names. Only the
+    /// rows its filter picks are kept in memory, read a batch at a time.
+    fn query(&mut self, query: &Query) -> Result<Sheet, Error> {
+        let info = self.find_sheet(&query.sheet)?.ok_or_else(|| {
+            Error::DatabaseError(DatabaseError::SheetNotFound {
+                sheet_name: query.sheet.clone(),
+            })
+        })?;
+        let rows = self.row_count(&info.id)?;
+        query_in_batches(query, rows, |range| self.load_rows(&info, range))
+    }
+
+    /// Reads everything into an in-memory `Database`, e.g. to save it as a
+    /// regular file
+    fn load(&mut self) -> Result<Database, Error> {
+        let mut database = Database::new_empty();
+        for info in self.sheets()? {
+            database.adopt_sheet(&self.load_sheet(&info)?);
+        }
+        Ok(database)
+    }
+}
+
+/// Runs a SELECT statement over `rows` rows read by `load` a batch at a time,
+/// keeping only the rows its filter picks, for Storage::query and
+/// Snapshot::query
+pub(crate) fn query_in_batches(
+    query: &Query,
+    rows: usize,
+    mut load: impl FnMut(Range<usize>) -> Result<Sheet, Error>,
+) -> Result<Sheet, Error> {
+    let mut picked = load(0..0)?;
+    let mut ids = Vec::new();
+    for start in (0..rows).step_by(QUERY_BATCH) {
+        let end = rows.min(start + QUERY_BATCH);
+        let batch = load(start..end)?;
+        let matching = match query.filter() {
+            Some(filter) => batch.rows_where(filter)?,
+            None => (0..end - start).collect(),
+        };
+        for row in matching {
+            for (column, from) in picked.columns.iter_mut().zip(&batch.columns) {
+                column.insert_value(from.cells[row].value.clone());
+            }
+            ids.extend(batch.row_id(row));
+        }
+    }
+    picked.row_ids = RowIds::from_ids(ids, picked.row_ids.next());
+    picked.select(query)
+}
+
+impl Storage for Database {
+    // in a transaction, so subscribers hear about it
+    fn apply(&mut self, change: &Change) -> Result<(), Error> {
+        let operation = match change.clone() {
+            Change::AddSheet { id, name } => Operation::AddSheet { id, name },
+            Change::RemoveSheet { sheet } => Operation::RemoveSheet { sheet },
+            Change::AddColumn {
+                sheet,
+                id,
+                name,
+                value_type,
+            } => Operation::AddColumn {
+                sheet,
+                id,
+                name,
+                value_type,
+            },
+            Change::RemoveColumn { sheet, column } => Operation::RemoveColumn { sheet, column },
+            Change::InsertRow { sheet, values } => Operation::InsertRow { sheet, values },
+            Change::UpdateCell {
+                sheet,
+                column,
+                row,
+                value,
+            } => Operation::UpdateCell {
+                sheet,
+                column,
+                row,
+                value,
+            },
+            Change::DeleteRow { sheet, row } => Operation::DeleteRow { sheet, row },
+        };
+        self.transaction(|tx| tx.apply(operation))
+    }
+
+    fn sheets(&mut self) -> Result<Vec<SheetInfo>, Error> {
+        Ok(self
+            .columns
+            .iter()
+            .map(|sheet| SheetInfo {
+                id: sheet.id,
+                name: sheet.name.clone(),
+            })
+            .collect())
+    }
+
+    fn columns(&mut self, sheet: &Identifier) -> Result<Vec<ColumnInfo>, Error> {
+        Ok(sheet_by_id(self, sheet)?
+            .columns
+            .iter()
+            .map(|column| ColumnInfo {
+                id: column.id,
+                name: column.name.clone(),
+                value_type: column.value_type,
+            })
+            .collect())
+    }
+
+    fn row_count(&mut self, sheet: &Identifier) -> Result<usize, Error> {
+        Ok(sheet_by_id(self, sheet)?
+            .columns
+            .first()
+            .map_or(0, Column::get_row_count))
+    }
+
+    fn row_id(&mut self, sheet: &Identifier, row: usize) -> Result<u64, Error> {
+        let sheet = sheet_by_id(self, sheet)?;
+        sheet
+            .row_id(row)
+            .ok_or(Error::WalError(WalError::RowOutOfRange {
+                row: row as u64,
+                rows: sheet.row_ids.len(),
+            }))
+    }
+
+    fn next_row_id(&mut self, sheet: &Identifier) -> Result<u64, Error> {
+        Ok(sheet_by_id(self, sheet)?.row_ids.next())
+    }
+
+    // not a change to the sheet, so no operation
+    fn skip_row_ids(&mut self, sheet: &Identifier, next: u64) -> Result<(), Error> {
+        self.get_sheet_mut_by_id(sheet)
+            .ok_or(Error::WalError(WalError::SheetNotFound { id: *sheet }))?
+            .row_ids
+            .skip_to(next);
+        Ok(())
+    }
+
+    // views too
+    fn query(&mut self, query: &Query) -> Result<Sheet, Error> {
+        Database::query(self, query)
+    }
+
+    fn cell(
+        &mut self,
+        sheet: &Identifier,
+        column: &Identifier,
+        row: usize,
+    ) -> Result<Value, Error> {
+        let column = sheet_by_id(self, sheet)?
+            .get_column_by_id(column)
+            .ok_or(Error::WalError(WalError::ColumnNotFound { id: *column }))?;
+        column
+            .get_cell(row)
+            .map(|cell| cell.get_value().clone())
+            .ok_or(Error::WalError(WalError::RowOutOfRange {
+                row: row as u64,
+                rows: column.get_row_count(),
+            }))
+    }
+}
+
+fn sheet_by_id<'a>(database: &'a Database, id: &Identifier) -> Result<&'a Sheet, Error> {
+    database
+        .columns
+        .iter()
+        .find(|sheet| sheet.id == *id)
+        .ok_or(Error::WalError(WalError::SheetNotFound { id: *id }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::mvcc::VersionedDatabase;
+    use crate::internal::pager::{PageOptions, PagedStorage};
+    use crate::internal::row_id::RowIdError;
+
+    // the same changes and reads, whichever backend is underneath
+    fn exercise(storage: &mut impl Storage) {
+        let (sheet, name, age) = (Identifier::new(), Identifier::new(), Identifier::new());
+        storage
+            .apply(&Change::AddSheet {
+                id: sheet,
+                name: "people".to_string(),
+            })
+            .unwrap();
+        storage
+            .apply(&Change::AddColumn {
+                sheet,
+                id: name,
+                name: "name".to_string(),
+                value_type: Type::Str,
+            })
+            .unwrap();
+        for i in 0..500 {
+            storage
+                .apply(&Change::InsertRow {
+                    sheet,
+                    values: vec![Value::Str(format!("person {}", i))],
+                })
+                .unwrap();
+        }
+        storage
+            .apply(&Change::AddColumn {
+                sheet,
+                id: age,
+                name: "age".to_string(),
+                value_type: Type::U8,
+            })
+            .unwrap();
+        storage
+            .apply(&Change::UpdateCell {
+                sheet,
+                column: age,
+                row: 250,
+                value: Value::Int(36),
+            })
+            .unwrap();
+
+        // bad changes fail without touching anything
+        assert!(storage
+            .apply(&Change::InsertRow {
+                sheet,
+                values: vec![Value::Str("ada".to_string()), Value::Int(300)],
+            })
+            .is_err());
+        assert!(matches!(
+            storage.apply(&Change::UpdateCell {
+                sheet,
+                column: age,
+                row: 500,
+                value: Value::Int(1),
+            }),
+            Err(Error::RowIdError(RowIdError::NotFound { id: 500, .. }))
+        ));
+        assert!(matches!(
+            storage.apply(&Change::RemoveSheet {
+                sheet: Identifier::new()
+            }),
+            Err(Error::WalError(WalError::SheetNotFound { .. }))
+        ));
+
+        assert_eq!(storage.find_sheet("people").unwrap().unwrap().id, sheet);
+        assert_eq!(storage.row_count(&sheet).unwrap(), 500);
+        assert_eq!(
+            storage.row(&sheet, 250).unwrap(),
+            vec![Value::Str("person 250".to_string()), Value::U8(36)]
+        );
+        assert_eq!(storage.cell(&sheet, &age, 499).unwrap(), Value::Nil);
+
+        // rows keep their ids as the rows before them go
+        storage
+            .apply(&Change::DeleteRow { sheet, row: 100 })
+            .unwrap();
+        assert_eq!(storage.row_count(&sheet).unwrap(), 499);
+        assert_eq!(
+            storage.row(&sheet, 249).unwrap(),
+            vec![Value::Str("person 250".to_string()), Value::U8(36)]
+        );
+        assert_eq!(storage.row_id(&sheet, 249).unwrap(), 250);
+        storage
+            .apply(&Change::UpdateCell {
+                sheet,
+                column: age,
+                row: 499,
+                value: Value::Int(41),
+            })
+            .unwrap();
+        assert_eq!(storage.cell(&sheet, &age, 498).unwrap(), Value::U8(41));
+        assert!(matches!(
+            storage.apply(&Change::DeleteRow { sheet, row: 100 }),
+            Err(Error::RowIdError(RowIdError::NotFound { id: 100, .. }))
+        ));
+
+        // queries read the sheet from whatever holds it, a batch at a time
+        let query = Query::parse(
+            "select name, age + 1 as next from people where age > 40 or rowid = 0 \
+             or rowid = 250 order by name desc",
+        )
+        .unwrap();
+        let picked = storage.query(&query).unwrap();
+        assert_eq!(picked.columns[0].name, "name");
+        assert_eq!(picked.columns[1].name, "next");
+        let names: Vec<Value> = picked.columns[0]
+            .cells
+            .iter()
+            .map(|cell| cell.value.clone())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                Value::Str("person 499".to_string()),
+                Value::Str("person 250".to_string()),
+                Value::Str("person 0".to_string())
+            ]
+        );
+        assert!(matches!(
+            storage.query(&Query::parse("select * from nobody").unwrap()),
+            Err(Error::DatabaseError(DatabaseError::SheetNotFound { .. }))
+        ));
+
+        storage
+            .apply(&Change::RemoveColumn {
+                sheet,
+                column: name,
+            })
+            .unwrap();
+        let columns = storage.columns(&sheet).unwrap();
+        assert_eq!(columns.len(), 1);
+        assert_eq!(columns[0].name, "age");
+
+        let database = storage.load().unwrap();
+        assert_eq!(database.columns[0].columns[0].get_row_count(), 499);
+        assert_eq!(database.columns[0].row_id(249), Some(250));
+        assert_eq!(database.columns[0].row_ids.next(), 500);
+    }
+
+    // a copy of a sheet whose first and last rows were deleted keeps the ids
+    fn import(storage: &mut impl Storage) {
+        let mut database = Database::new_empty();
+        for sql in [
+            "create sheet people (name text)",
+            "insert into people values ('ada'), ('alan'), ('grace'), ('edsger')",
+            "delete from people where name = 'ada' or name = 'edsger'",
+        ] {
+            database.execute(sql, &[]).unwrap();
+        }
+        let sheet = database.columns[0].id;
+        storage.import(&database).unwrap();
+        assert_eq!(storage.row_id(&sheet, 0).unwrap(), 1);
+        assert_eq!(storage.next_row_id(&sheet).unwrap(), 4);
+        storage
+            .apply(&Change::InsertRow {
+                sheet,
+                values: vec![Value::Str("barbara".to_string())],
+            })
+            .unwrap();
+        assert_eq!(storage.row_id(&sheet, 2).unwrap(), 4);
+        assert_eq!(storage.load().unwrap().columns[0].row_ids.ids(), &[1, 2, 4]);
+    }
+
+    #[test]
+    fn in_memory_backend() {
+        exercise(&mut Database::new_empty());
+        import(&mut Database::new_empty());
+    }
+
+    #[test]
+    fn versioned_backend() {
+        exercise(&mut VersionedDatabase::new(&Database::new_empty()).unwrap());
+        import(&mut VersionedDatabase::new(&Database::new_empty()).unwrap());
+    }
+
+    #[test]
+    fn paged_backend() {
+        let directory = tempfile::tempdir().unwrap();
+        let options = PageOptions {
+            memory_budget: 2 * crate::internal::pager::PAGE_SIZE,
+        };
+        let path = directory.path().join("people.pages");
+        let mut storage = PagedStorage::open(&path, options).unwrap();
+        exercise(&mut storage);
+
+        // ids are kept in the file
+        drop(storage);
+        let mut storage = PagedStorage::open(&path, options).unwrap();
+        let sheet = storage.sheets().unwrap()[0].id;
+        assert_eq!(storage.row_id(&sheet, 249).unwrap(), 250);
+        assert_eq!(storage.next_row_id(&sheet).unwrap(), 500);
+
+        let mut storage = PagedStorage::open(directory.path().join("copy.pages"), options).unwrap();
+        import(&mut storage);
+    }
+}
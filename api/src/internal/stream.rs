@@ -0,0 +1,1059 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::cell::Cell;
+use crate::internal::checksum::{ChecksumError, Region};
+use crate::internal::column::{Column, ColumnError};
+use crate::internal::columnar::{self, Decoder, Encoding, Input, PINNED};
+use crate::internal::computed;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::Value;
+use crate::internal::errors::Error;
+use crate::internal::expr::Expr;
+use crate::internal::format::{Features, Format, MAGIC};
+use crate::internal::id::Identifier;
+use crate::internal::length_table::LengthTableError;
+use crate::internal::limits::{Budget, DecodeLimits, Limited};
+use crate::internal::row_id::{self, RowIds};
+use crate::internal::segment::{self, SegmentError};
+use crate::internal::sheet::Sheet;
+use crate::internal::traits::Serializable;
+use crate::internal::trigger::{self, Trigger};
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+
+/// Writes serialized bytes straight to a `Write`, keeping a running CRC32C of
+/// every checksummed block that's still open so nothing has to be buffered to
+/// checksum it. Does many small writes, so wrap files in a `BufWriter`.
+pub(crate) struct StreamWriter<W: Write> {
+    inner: W,
+    // one running checksum per open block, outermost first
+    checksums: Vec<u32>,
+    position: u64,
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            checksums: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.inner.write_all(bytes).map_err(Error::Io)?;
+        for checksum in &mut self.checksums {
+            *checksum = crc32c::crc32c_append(*checksum, bytes);
+        }
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) fn write_u32(&mut self, value: u32) -> Result<(), Error> {
+        self.write(&value.to_be_bytes())
+    }
+
+    /// Starts a block whose bytes get a trailing checksum, see `end_checksum`
+    pub(crate) fn begin_checksum(&mut self) {
+        self.checksums.push(0);
+    }
+
+    /// Writes the checksum of everything since the matching `begin_checksum`
+    pub(crate) fn end_checksum(&mut self) -> Result<(), Error> {
+        let checksum = self
+            .checksums
+            .pop()
+            .expect("end_checksum without begin_checksum");
+        self.write_u32(checksum)
+    }
+
+    /// Bytes written so far
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush().map_err(Error::Io)
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Something read by a StreamReader. Files are laid out column by column, so
+/// a sheet's cells arrive one column at a time, top to bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Event {
+    SheetStart {
+        id: Identifier,
+        name: String,
+        column_count: usize,
+        triggers: Vec<Trigger>,
+        // none in files without row ids, whose rows are numbered on from
+        // `row_ids.next()`
+        row_ids: RowIds,
+    },
+    ColumnStart {
+        id: Identifier,
+        name: String,
+        value_type: Type,
+        row_count: usize,
+        codec: Option<Encoding>,
+        computed: Option<Expr>,
+    },
+    Cell {
+        row: usize,
+        value: Value,
+    },
+    ColumnEnd,
+    SheetEnd,
+}
+
+/// Decodes a serialized database from a `Read` as a sequence of events,
+/// checking checksums as it goes. Cells are read one at a time, whatever the
+/// column's encoding. Of a column all that's held is its length table, or
+/// what columnar::Decoder keeps: a bit a row, and the offsets of packed
+/// strings or a dictionary's entries.
+///
+/// Cells are handed out before the column's checksum can be checked, so a
+/// mismatch is only reported at its end.
+///
+/// Rows appended to the file (see segment.rs) come after every sheet, as each
+/// sheet again with just those rows, numbered on from the rows before them.
+/// Each segment is decoded whole. One that fails its checksum ends them, unless
+/// an intact one comes after it.
+pub(crate) struct StreamReader<R: Read> {
+    source: Source<R>,
+    format: Format,
+    state: State,
+    sheet_lengths: std::vec::IntoIter<usize>,
+    column_lengths: std::vec::IntoIter<usize>,
+    sheet: Block,
+    column: Block,
+    cells: Cells,
+    row: usize,
+    // how many row ids the sheet being read has, one for each row of a column
+    row_ids: usize,
+    // every sheet read so far with its columns but no cells, to check appended
+    // rows against, and the row count of each column
+    layouts: Vec<Sheet>,
+    rows: HashMap<Identifier, usize>,
+    appended: VecDeque<Event>,
+    // why the first segment that failed its checksum did, reported only if an
+    // intact segment follows it; otherwise it was torn by an interrupted append
+    torn: Option<Error>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Start,
+    Sheets,
+    Columns,
+    Cells,
+    Segments,
+    Done,
+}
+
+// a sheet or column being read, to check it ends where its length table said
+#[derive(Debug, Default)]
+struct Block {
+    name: String,
+    start: u64,
+    length: u64,
+}
+
+enum Cells {
+    // Encoding::Cells: the length of each cell still to be read
+    Table(std::vec::IntoIter<usize>),
+    // every other encoding, with where its body ends and the budget for what
+    // the values expand to
+    Encoded {
+        decoder: Decoder,
+        end: u64,
+        budget: Budget,
+    },
+}
+
+impl<R: Read> StreamReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            source: Source {
+                inner: reader,
+                checksums: Vec::new(),
+                position: 0,
+            },
+            format: Format::current(),
+            state: State::Start,
+            sheet_lengths: Vec::new().into_iter(),
+            column_lengths: Vec::new().into_iter(),
+            sheet: Block::default(),
+            column: Block::default(),
+            cells: Cells::Table(Vec::new().into_iter()),
+            row: 0,
+            row_ids: 0,
+            layouts: Vec::new(),
+            rows: HashMap::new(),
+            appended: VecDeque::new(),
+            torn: None,
+        }
+    }
+
+    /// A reader that enforces `limits` and, if `lossy_names` is set, decodes
+    /// names that aren't valid UTF-8 with U+FFFD in place of the bad bytes
+    pub(crate) fn limited(reader: R, limits: DecodeLimits, lossy_names: bool) -> Self {
+        let mut stream = Self::new(reader);
+        stream.format.limits = limits;
+        stream.format.lossy_names = lossy_names;
+        stream
+    }
+
+    /// The format read from the header. Only meaningful after the first event.
+    pub(crate) fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /// The next event, or None once the whole file has been read. After an
+    /// error the reader stops and only returns None.
+    pub(crate) fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        let event = self.advance();
+        if !matches!(event, Ok(Some(_))) {
+            self.state = State::Done;
+        }
+        event
+    }
+
+    /// Reads the next sheet whole, for callers that want sheets rather than
+    /// events. Only valid between sheets: before the first event or after a SheetEnd.
+    pub(crate) fn next_sheet(&mut self) -> Result<Option<Sheet>, Error> {
+        if !matches!(self.state, State::Start | State::Sheets) {
+            return Err(Error::StreamError(StreamError::NotBetweenSheets));
+        }
+
+        let mut sheet = match self.next_event()? {
+            Some(Event::SheetStart {
+                id,
+                name,
+                triggers,
+                row_ids,
+                ..
+            }) => {
+                let mut sheet = Sheet::new_with_set_id(id, name, Vec::new(), None);
+                sheet.triggers = triggers;
+                sheet.row_ids = row_ids;
+                sheet
+            }
+            _ => return Ok(None),
+        };
+        let mut column = None;
+        while let Some(event) = self.next_event()? {
+            match event {
+                Event::ColumnStart {
+                    id,
+                    name,
+                    value_type,
+                    codec,
+                    computed,
+                    ..
+                } => {
+                    let mut new_column = Column::new_with_set_id(id, name, value_type, None);
+                    new_column.codec = codec;
+                    new_column.computed = computed;
+                    column = Some(new_column);
+                }
+                Event::Cell { value, .. } => {
+                    if let Some(column) = column.as_mut() {
+                        column.insert_value(value);
+                    }
+                }
+                Event::ColumnEnd => {
+                    if let Some(column) = column.take() {
+                        sheet.adopt_column(&column);
+                    }
+                }
+                Event::SheetEnd | Event::SheetStart { .. } => break,
+            }
+        }
+        Ok(Some(sheet))
+    }
+
+    fn advance(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            match self.state {
+                State::Start => self.read_header()?,
+                State::Sheets => return self.next_sheet_start(),
+                State::Columns => return self.next_column_start().map(Some),
+                State::Cells => return self.next_cell().map(Some),
+                State::Segments => return self.next_appended(),
+                State::Done => return Ok(None),
+            }
+        }
+    }
+
+    fn read_header(&mut self) -> Result<(), Error> {
+        // the file checksum covers the header too
+        self.source.begin_checksum();
+
+        // the first 11 bytes are a whole v1 header, and enough to reject bad magic
+        // or an unknown version; v2 headers go on to say how long they are
+        let mut header = self.source.read(MAGIC.len() + 1)?;
+        let format = match Format::read_header(&mut ByteDeserializer::new(&header)) {
+            Err(Error::ByteError(_)) => {
+                header.extend(self.source.read(2)?);
+                let header_length = u16::from_be_bytes([header[11], header[12]]) as usize;
+                header.extend(
+                    self.source
+                        .read(header_length.saturating_sub(header.len()))?,
+                );
+                Format::read_header(&mut ByteDeserializer::new(&header))?
+            }
+            format => format?,
+        };
+        // what the reader was asked to enforce isn't in the file
+        self.format = Format {
+            limits: self.format.limits,
+            lossy_names: self.format.lossy_names,
+            ..format
+        };
+
+        self.sheet_lengths = self
+            .source
+            .read_length_table(&self.format, Limited::Sheets)?
+            .into_iter();
+        self.state = State::Sheets;
+        Ok(())
+    }
+
+    fn next_sheet_start(&mut self) -> Result<Option<Event>, Error> {
+        let Some(length) = self.sheet_lengths.next() else {
+            self.finish()?;
+            return self.advance();
+        };
+
+        let start = self.source.position;
+        if self.format.has(Features::CHECKSUMS) {
+            self.source.begin_checksum();
+        }
+        let (id, name) = self.source.read_id_and_name(&self.format)?;
+        let triggers = match self.format.has(Features::TRIGGERS) {
+            true => {
+                let mut bytes = self.source.read(4)?;
+                let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                self.format.limits.check_string(length as usize)?;
+                bytes.extend(self.source.read(length as usize)?);
+                trigger::read(&mut ByteDeserializer::new(&bytes), &self.format)?
+            }
+            false => Vec::new(),
+        };
+        let row_ids = match self.format.has(Features::ROW_IDS) {
+            true => {
+                let mut bytes = self.source.read(4)?;
+                let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                self.format
+                    .limits
+                    .check_count(Limited::Cells, length as u64 / 8)?;
+                bytes.extend(self.source.read(length as usize)?);
+                // events aren't kept, so each is charged to a budget of its own
+                let mut budget = Budget::new(&self.format.limits);
+                row_id::read(
+                    &mut ByteDeserializer::new(&bytes),
+                    &self.format,
+                    &mut budget,
+                )?
+            }
+            false => RowIds::default(),
+        };
+        let column_lengths = self
+            .source
+            .read_length_table(&self.format, Limited::Columns)?;
+
+        self.sheet = Block {
+            name: name.clone(),
+            start,
+            length: length as u64,
+        };
+        self.state = State::Columns;
+        self.column_lengths = column_lengths.into_iter();
+        self.row_ids = row_ids.len();
+        self.layouts
+            .push(Sheet::new_with_set_id(id, name.clone(), Vec::new(), None));
+        Ok(Some(Event::SheetStart {
+            id,
+            name,
+            column_count: self.column_lengths.len(),
+            triggers,
+            row_ids,
+        }))
+    }
+
+    fn next_column_start(&mut self) -> Result<Event, Error> {
+        let Some(length) = self.column_lengths.next() else {
+            // a sheet without columns has no rows either
+            let columns = self.layouts.last().map_or(0, |layout| layout.columns.len());
+            if self.format.has(Features::ROW_IDS) && columns == 0 {
+                row_id::check_count(self.row_ids, 0, &self.sheet.name)?;
+            }
+            if self.format.has(Features::CHECKSUMS) {
+                self.source.end_checksum(|| Region::Sheet {
+                    name: Some(self.sheet.name.clone()),
+                })?;
+            }
+            self.source.check_end(&self.sheet, || Region::Sheet {
+                name: Some(self.sheet.name.clone()),
+            })?;
+            self.state = State::Sheets;
+            return Ok(Event::SheetEnd);
+        };
+
+        let start = self.source.position;
+        if self.format.has(Features::CHECKSUMS) {
+            self.source.begin_checksum();
+        }
+        let (id, name) = self.source.read_id_and_name(&self.format)?;
+        let value_type = Type::deserialize_bytes(&self.source.read(1)?)?;
+        let (encoding, pinned) = match self.format.has(Features::COLUMNAR) {
+            true => {
+                let byte = self.source.read(1)?[0];
+                (Encoding::from_byte(byte & !PINNED)?, byte & PINNED != 0)
+            }
+            false => (Encoding::Cells, false),
+        };
+        let computed = match self.format.has(Features::COMPUTED) {
+            true => {
+                let mut bytes = self.source.read(4)?;
+                let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                self.format.limits.check_string(length as usize)?;
+                bytes.extend(self.source.read(length as usize)?);
+                computed::read(&mut ByteDeserializer::new(&bytes), &self.format)?
+            }
+            false => None,
+        };
+        if encoding.is_compressed() && !self.format.has(Features::COMPRESSION) {
+            return Err(Error::ColumnError(ColumnError::UnknownEncoding {
+                encoding: encoding.as_byte(),
+            }));
+        }
+
+        self.column = Block {
+            name: name.clone(),
+            start,
+            length: length as u64,
+        };
+        let row_count = match encoding {
+            Encoding::Cells => {
+                let lengths = self
+                    .source
+                    .read_length_table(&self.format, Limited::Cells)?;
+                let row_count = lengths.len();
+                self.cells = Cells::Table(lengths.into_iter());
+                row_count
+            }
+            _ => {
+                // everything up to the checksum is the body
+                let trailer = if self.format.has(Features::CHECKSUMS) {
+                    4
+                } else {
+                    0
+                };
+                let read = self.source.position - start;
+                let body_length = (length as u64)
+                    .checked_sub(read + trailer)
+                    .ok_or_else(|| self.length_mismatch(read + trailer))?;
+                let end = self.source.position + body_length;
+                let mut budget = Budget::new(&self.format.limits);
+                let decoder = Decoder::new(
+                    &mut Body {
+                        source: &mut self.source,
+                        end,
+                    },
+                    value_type,
+                    encoding,
+                    &self.format.limits,
+                    &mut budget,
+                )?;
+                let row_count = decoder.rows();
+                self.cells = Cells::Encoded {
+                    decoder,
+                    end,
+                    budget,
+                };
+                row_count
+            }
+        };
+
+        if self.format.has(Features::ROW_IDS) {
+            row_id::check_count(self.row_ids, row_count, &self.sheet.name)?;
+        }
+        self.row = 0;
+        self.state = State::Cells;
+        if let Some(layout) = self.layouts.last_mut() {
+            layout
+                .columns
+                .push(Column::new_with_set_id(id, name.clone(), value_type, None));
+        }
+        self.rows.insert(id, row_count);
+        Ok(Event::ColumnStart {
+            id,
+            name,
+            value_type,
+            row_count,
+            codec: pinned.then_some(encoding),
+            computed,
+        })
+    }
+
+    fn next_cell(&mut self) -> Result<Event, Error> {
+        let value = match &mut self.cells {
+            Cells::Table(lengths) => match lengths.next() {
+                Some(length) => {
+                    let bytes = self.source.read(length)?;
+                    Some(Cell::deserialize_bytes_with(&bytes, &self.format)?.value)
+                }
+                None => None,
+            },
+            Cells::Encoded {
+                decoder,
+                end,
+                budget,
+            } => {
+                let mut body = Body {
+                    source: &mut self.source,
+                    end: *end,
+                };
+                let value = decoder.next(&mut body, budget)?;
+                if value.is_none() && body.remaining() > 0 {
+                    return Err(columnar::malformed("trailing bytes after the values"));
+                }
+                value
+            }
+        };
+
+        if let Some(value) = value {
+            self.format
+                .limits
+                .check_strings(std::slice::from_ref(&value))?;
+            self.row += 1;
+            return Ok(Event::Cell {
+                row: self.row - 1,
+                value,
+            });
+        }
+
+        let region = || Region::Column {
+            sheet: Some(self.sheet.name.clone()),
+            name: Some(self.column.name.clone()),
+        };
+        if self.format.has(Features::CHECKSUMS) {
+            self.source.end_checksum(region)?;
+        }
+        self.source.check_end(&self.column, region)?;
+        self.state = State::Columns;
+        Ok(Event::ColumnEnd)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.format.has(Features::INDEX) {
+            let index_length = self.source.read_u32()?;
+            self.source.skip(index_length as u64)?;
+        }
+        // views are rows of other sheets, so a stream of sheets passes them by
+        if self.format.has(Features::VIEWS) {
+            let views_length = self.source.read_u32()?;
+            self.source.skip(views_length as u64)?;
+        }
+        if self.format.has(Features::CHECKSUMS) {
+            self.source.end_checksum(|| Region::File)?;
+        }
+        self.state = match self.format.has(Features::SEGMENTS) {
+            true => State::Segments,
+            false => State::Done,
+        };
+        Ok(())
+    }
+
+    fn next_appended(&mut self) -> Result<Option<Event>, Error> {
+        while self.appended.is_empty() {
+            // a segment cut short is where an append was interrupted, and the end
+            let start = self.source.position;
+            let prefix = self.source.read_available(4)?;
+            if prefix.len() < 4 {
+                self.state = State::Done;
+                return Ok(None);
+            }
+            let length = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+            let mut segment = prefix;
+            segment.extend(self.source.read_available(length as usize)?);
+            if segment.len() < 4 + length as usize {
+                self.state = State::Done;
+                return Ok(None);
+            }
+            if self.format.has(Features::CHECKSUMS) {
+                if let Err(err) = segment::intact(&segment) {
+                    self.torn.get_or_insert(err);
+                    continue;
+                }
+                if let Some(err) = self.torn.take() {
+                    return Err(err);
+                }
+            }
+
+            let mut budget = Budget::new(&self.format.limits);
+            let (sheets, _) =
+                segment::read_segments(&segment, start as usize, &self.format, &mut budget)?;
+            for sheet in sheets {
+                self.queue_appended(sheet)?;
+            }
+        }
+        Ok(self.appended.pop_front())
+    }
+
+    fn queue_appended(&mut self, sheet: Sheet) -> Result<(), Error> {
+        let layout = self
+            .layouts
+            .iter()
+            .find(|layout| layout.id == sheet.id)
+            .ok_or(Error::SegmentError(SegmentError::UnknownSheet {
+                id: sheet.id,
+            }))?;
+        segment::check_schema(layout, &sheet)?;
+
+        let first = sheet
+            .columns
+            .first()
+            .and_then(|column| self.rows.get(&column.id))
+            .copied()
+            .unwrap_or_default();
+        self.appended.push_back(Event::SheetStart {
+            id: sheet.id,
+            name: sheet.name,
+            column_count: sheet.columns.len(),
+            triggers: sheet.triggers,
+            row_ids: match self.format.has(Features::ROW_IDS) {
+                true => sheet.row_ids,
+                false => RowIds::starting_at(first as u64),
+            },
+        });
+        for column in sheet.columns {
+            let first = self.rows.get(&column.id).copied().unwrap_or_default();
+            self.rows.insert(column.id, first + column.cells.len());
+            self.appended.push_back(Event::ColumnStart {
+                id: column.id,
+                name: column.name,
+                value_type: column.value_type,
+                row_count: column.cells.len(),
+                codec: column.codec,
+                computed: column.computed,
+            });
+            for (row, cell) in column.cells.into_iter().enumerate() {
+                self.appended.push_back(Event::Cell {
+                    row: first + row,
+                    value: cell.value,
+                });
+            }
+            self.appended.push_back(Event::ColumnEnd);
+        }
+        self.appended.push_back(Event::SheetEnd);
+        Ok(())
+    }
+
+    fn length_mismatch(&self, got: u64) -> Error {
+        Error::StreamError(StreamError::LengthMismatch {
+            region: Region::Column {
+                sheet: Some(self.sheet.name.clone()),
+                name: Some(self.column.name.clone()),
+            },
+            expected: self.column.length,
+            got,
+        })
+    }
+}
+
+impl<R: Read> Iterator for StreamReader<R> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+// a column body being decoded, which mustn't be read past
+struct Body<'a, R: Read> {
+    source: &'a mut Source<R>,
+    end: u64,
+}
+
+impl<R: Read> Input for Body<'_, R> {
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>, Error> {
+        if length > self.remaining() {
+            return Err(columnar::malformed("values run past the end of the column"));
+        }
+        self.source.read(length)
+    }
+
+    fn remaining(&self) -> usize {
+        (self.end - self.source.position) as usize
+    }
+}
+
+// the reading half of StreamWriter's checksum bookkeeping
+struct Source<R: Read> {
+    inner: R,
+    checksums: Vec<u32>,
+    position: u64,
+}
+
+impl<R: Read> Source<R> {
+    // reads exactly `length` bytes; the buffer grows as bytes arrive, so a
+    // corrupt length can't make it allocate more than the input holds
+    fn read(&mut self, length: usize) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        (&mut self.inner)
+            .take(length as u64)
+            .read_to_end(&mut bytes)
+            .map_err(Error::Io)?;
+        if bytes.len() < length {
+            return Err(Error::Io(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "stream ended at byte {}, {} bytes early",
+                    self.position + bytes.len() as u64,
+                    length - bytes.len()
+                ),
+            )));
+        }
+
+        for checksum in &mut self.checksums {
+            *checksum = crc32c::crc32c_append(*checksum, &bytes);
+        }
+        self.position += length as u64;
+        Ok(bytes)
+    }
+
+    // reads up to `length` bytes, fewer only if the stream ends first
+    fn read_available(&mut self, length: usize) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        (&mut self.inner)
+            .take(length as u64)
+            .read_to_end(&mut bytes)
+            .map_err(Error::Io)?;
+        for checksum in &mut self.checksums {
+            *checksum = crc32c::crc32c_append(*checksum, &bytes);
+        }
+        self.position += bytes.len() as u64;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        ByteDeserializer::new(&self.read(4)?).read_u32()
+    }
+
+    fn skip(&mut self, mut length: u64) -> Result<(), Error> {
+        while length > 0 {
+            let chunk = length.min(64 * 1024);
+            self.read(chunk as usize)?;
+            length -= chunk;
+        }
+        Ok(())
+    }
+
+    // sheets and columns both start with a 16 byte id and a length-prefixed name
+    fn read_id_and_name(&mut self, format: &Format) -> Result<(Identifier, String), Error> {
+        let id = Identifier::deserialize_bytes(&self.read(16)?)?;
+        let name_length = self.read_u32()? as usize;
+        format.limits.check_string(name_length)?;
+        let start = self.position as usize;
+        let name = ByteDeserializer::new(&self.read(name_length)?)
+            .read_name(name_length, format)
+            .map_err(|err| err.offset_by(start))?;
+        Ok((id, name))
+    }
+
+    // reads everything in a length table before the objects, returning the
+    // object lengths
+    fn read_length_table(&mut self, format: &Format, what: Limited) -> Result<Vec<usize>, Error> {
+        let mut read_u32 = || -> Result<usize, Error> {
+            let raw = self.read(4)?;
+            Ok(format.read_length_table_u32([raw[0], raw[1], raw[2], raw[3]]) as usize)
+        };
+
+        let length_table_length = read_u32()?;
+        let count = read_u32()?;
+        format.limits.check_count(what, count as u64)?;
+
+        let mut lengths = Vec::new();
+        for _ in 0..count {
+            lengths.push(read_u32()?);
+        }
+
+        let computed = lengths
+            .iter()
+            .fold(lengths.len() * 4, |sum, length| sum.saturating_add(*length));
+        if computed != length_table_length {
+            return Err(Error::LengthTableError(LengthTableError::LengthMismatch {
+                stored: length_table_length,
+                computed,
+            }));
+        }
+        Ok(lengths)
+    }
+
+    fn begin_checksum(&mut self) {
+        self.checksums.push(0);
+    }
+
+    // reads the stored checksum of the innermost open block and compares it
+    fn end_checksum(&mut self, region: impl FnOnce() -> Region) -> Result<(), Error> {
+        let computed = self
+            .checksums
+            .pop()
+            .expect("end_checksum without begin_checksum");
+        let stored = self.read_u32()?;
+        if stored != computed {
+            return Err(Error::ChecksumError(ChecksumError::Mismatch {
+                region: region(),
+                stored,
+                computed,
+            }));
+        }
+        Ok(())
+    }
+
+    fn check_end(&self, block: &Block, region: impl FnOnce() -> Region) -> Result<(), Error> {
+        let got = self.position - block.start;
+        if got != block.length {
+            return Err(Error::StreamError(StreamError::LengthMismatch {
+                region: region(),
+                expected: block.length,
+                got,
+            }));
+        }
+        Ok(())
+    }
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum StreamError {
+    // a block's contents don't add up to the length its table gave it
+    LengthMismatch {
+        region: Region,
+        expected: u64,
+        got: u64,
+    },
+    NotBetweenSheets,
+}
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamError::LengthMismatch {
+                region,
+                expected,
+                got,
+            } => write!(
+                f,
+                "The {} should be {} bytes long but its contents take {}",
+                region, expected, got
+            ),
+            StreamError::NotBetweenSheets => {
+                write!(f, "Can only read a whole sheet between sheets")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::database::Database;
+
+    fn orders() -> Database {
+        let mut sheet = Sheet::new("orders".to_string(), Vec::new(), None);
+        sheet.adopt_column(&Column::new("item".to_string(), Type::Str, None));
+        sheet.adopt_column(&Column::new("total".to_string(), Type::U16, None));
+        sheet
+            .insert_row(vec![Value::Str("tea".to_string()), Value::Int(4)])
+            .unwrap();
+        sheet
+            .insert_row(vec![Value::Str("cake".to_string()), Value::Nil])
+            .unwrap();
+
+        let mut database = Database::new_empty();
+        database.adopt_sheet(&sheet);
+        database.adopt_sheet(&Sheet::new("customers".to_string(), Vec::new(), None));
+        database
+    }
+
+    #[test]
+    fn reads_events_in_file_order() {
+        let database = orders();
+        for format in [Format::current(), Format::v1()] {
+            let bytes = database.serialized_bytes_with(&format);
+            let mut reader = StreamReader::new(&bytes[..]);
+            let events: Vec<Event> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+            assert_eq!(reader.format().features, format.features);
+
+            let sheet = &database.columns[0];
+            let column_start = |i: usize| Event::ColumnStart {
+                id: sheet.columns[i].id,
+                name: sheet.columns[i].name.clone(),
+                value_type: sheet.columns[i].value_type,
+                row_count: 2,
+                codec: None,
+                computed: None,
+            };
+            let cell = |row, value| Event::Cell { row, value };
+            assert_eq!(
+                events,
+                vec![
+                    Event::SheetStart {
+                        id: sheet.id,
+                        name: "orders".to_string(),
+                        column_count: 2,
+                        triggers: Vec::new(),
+                        row_ids: match format.has(Features::ROW_IDS) {
+                            true => sheet.row_ids.clone(),
+                            false => RowIds::default(),
+                        },
+                    },
+                    column_start(0),
+                    cell(0, Value::Str("tea".to_string())),
+                    cell(1, Value::Str("cake".to_string())),
+                    Event::ColumnEnd,
+                    column_start(1),
+                    cell(0, Value::U16(4)),
+                    cell(1, Value::Nil),
+                    Event::ColumnEnd,
+                    Event::SheetEnd,
+                    Event::SheetStart {
+                        id: database.columns[1].id,
+                        name: "customers".to_string(),
+                        column_count: 0,
+                        triggers: Vec::new(),
+                        row_ids: RowIds::default(),
+                    },
+                    Event::SheetEnd,
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reads_sheet_by_sheet() {
+        let database = orders();
+        let bytes = database.serialized_bytes();
+        let mut reader = StreamReader::new(&bytes[..]);
+
+        let mut sheets = Vec::new();
+        while let Some(sheet) = reader.next_sheet().unwrap() {
+            sheets.push(sheet);
+        }
+        let expected = Database::deserialize_bytes(&bytes).unwrap();
+        let names: Vec<&str> = sheets.iter().map(|sheet| sheet.name.as_str()).collect();
+        assert_eq!(names, vec!["orders", "customers"]);
+        assert_eq!(
+            sheets[0].serialized_bytes(),
+            expected.columns[0].serialized_bytes()
+        );
+    }
+
+    // a Read that counts how much has been read from it
+    struct Counted<'a> {
+        bytes: &'a [u8],
+        read: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl Read for Counted<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let read = self.bytes.read(buf)?;
+            self.read.set(self.read.get() + read);
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn reads_encoded_columns_a_cell_at_a_time() {
+        let rows = 20_000;
+        let columns = [
+            (Encoding::Columnar, Type::Str),
+            (Encoding::Columnar, Type::U32),
+            (Encoding::Columnar, Type::Bool),
+            (Encoding::RunLength, Type::Str),
+            (Encoding::Delta, Type::Int),
+            (Encoding::Dictionary, Type::Str),
+        ];
+        for (encoding, value_type) in columns {
+            let mut column = Column::new("values".to_string(), value_type, None);
+            for row in 0..rows {
+                column.insert_value(match (row % 7, value_type) {
+                    (0, _) => Value::Nil,
+                    (_, Type::Str) => Value::Str(format!("value {}", row / 100)),
+                    (_, Type::U32) => Value::U32(row as u32 * 3),
+                    (_, Type::Bool) => Value::Bool(row % 3 == 0),
+                    _ => Value::Int(row as i64 - 500),
+                });
+            }
+            column.set_codec(Some(encoding)).unwrap();
+            let mut sheet = Sheet::new("sheet".to_string(), Vec::new(), None);
+            sheet.adopt_column(&column);
+            let mut database = Database::new_empty();
+            database.adopt_sheet(&sheet);
+            let bytes = database.serialized_bytes();
+
+            let read = std::rc::Rc::new(std::cell::Cell::new(0));
+            let reader = StreamReader::new(Counted {
+                bytes: &bytes,
+                read: read.clone(),
+            });
+            let (mut values, mut sheet_start) = (Vec::new(), 0);
+            for event in reader {
+                match event.unwrap() {
+                    Event::SheetStart { .. } => sheet_start = read.get(),
+                    Event::ColumnStart { codec, .. } => assert_eq!(codec, Some(encoding)),
+                    Event::Cell { value, .. } => {
+                        // the first cell comes before most of the column is read
+                        if values.is_empty() {
+                            let column = bytes.len() - sheet_start;
+                            assert!(read.get() - sheet_start < column * 3 / 4, "{:?}", encoding);
+                        }
+                        values.push(value);
+                    }
+                    _ => {}
+                }
+            }
+            let expected: Vec<Value> = column.cells.iter().map(|cell| cell.value.clone()).collect();
+            assert_eq!(values, expected, "{:?} {:?}", encoding, value_type);
+        }
+    }
+
+    #[test]
+    fn stops_at_a_damaged_column() {
+        let bytes = orders().serialized_bytes();
+        let mut damaged = bytes.clone();
+        // still a string, so it's only caught by the checksum at the column's end
+        let at = bytes.windows(4).position(|w| w == b"cake").unwrap();
+        damaged[at] ^= 0x20;
+
+        let mut reader = StreamReader::new(&damaged[..]);
+        let error = reader.find_map(Result::err).unwrap();
+        match error {
+            Error::ChecksumError(ChecksumError::Mismatch { region, .. }) => assert_eq!(
+                region,
+                Region::Column {
+                    sheet: Some("orders".to_string()),
+                    name: Some("item".to_string())
+                }
+            ),
+            other => panic!("expected a checksum error, got {:?}", other),
+        }
+        assert!(reader.next().is_none());
+
+        let truncated = &bytes[..bytes.len() - 10];
+        assert!(matches!(
+            StreamReader::new(truncated).find_map(Result::err),
+            Some(Error::Io(_))
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::internal::errors::Error;
+use crate::internal::format::Format;
 
 pub(crate) trait PrettyPrintable {
     fn pretty_print(&self, indent: usize) -> String;
@@ -9,4 +10,17 @@ pub(crate) trait Serializable<T> {
     fn deserialize_bytes(bytes: &[u8]) -> Result<Self, Error>
     where
         Self: Sized;
+
+    // Types whose layout depends on the file format (anything containing a
+    // length table) override these. Leaf types encode the same way in every format.
+    fn serialized_bytes_with(&self, _format: &Format) -> Vec<u8> {
+        self.serialized_bytes()
+    }
+
+    fn deserialize_bytes_with(bytes: &[u8], _format: &Format) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        Self::deserialize_bytes(bytes)
+    }
 }
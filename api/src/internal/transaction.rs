@@ -0,0 +1,1053 @@
+use crate::internal::cell::Cell;
+use crate::internal::column::Column;
+use crate::internal::data_value::Value;
+use crate::internal::database::Database;
+use crate::internal::errors::Error;
+use crate::internal::id::Identifier;
+use crate::internal::observer::Event;
+use crate::internal::row_id::RowIds;
+use crate::internal::sheet::Sheet;
+use crate::internal::trigger::{self, Fired, Trigger, TriggerError};
+use crate::internal::view::{self, View};
+use crate::internal::wal::Operation;
+use std::collections::{HashMap, HashSet};
+
+/// A rule every committed state of a database must satisfy. Constraints are
+/// checked when a transaction commits, so a transaction may break one
+/// half-way as long as it's fixed again by the end. They're kept in memory
+/// only, not in the file. A constraint on a sheet or column that no longer
+/// exists is ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Constraint {
+    NotNull {
+        sheet: Identifier,
+        column: Identifier,
+    },
+    // Nil is exempt, like NULL in SQL
+    Unique {
+        sheet: Identifier,
+        column: Identifier,
+    },
+}
+
+impl Constraint {
+    fn sheet(&self) -> &Identifier {
+        match self {
+            Constraint::NotNull { sheet, .. } | Constraint::Unique { sheet, .. } => sheet,
+        }
+    }
+
+    // the sheet and column it's on
+    fn key(&self) -> (Identifier, Identifier) {
+        let (Constraint::NotNull { sheet, column } | Constraint::Unique { sheet, column }) = self;
+        (*sheet, *column)
+    }
+
+    // whether the column, as counted by ConstraintIndex, breaks it. Only the
+    // `changed` values can repeat, if they're known.
+    fn broken<'a>(
+        &self,
+        counts: &HashMap<Value, usize>,
+        mut changed: Option<impl Iterator<Item = &'a Value>>,
+    ) -> bool {
+        let repeated = |value: &Value| {
+            *value != Value::Nil && counts.get(value).is_some_and(|count| *count > 1)
+        };
+        match self {
+            Constraint::NotNull { .. } => counts.contains_key(&Value::Nil),
+            Constraint::Unique { .. } => match &mut changed {
+                Some(changed) => changed.any(repeated),
+                None => counts.keys().any(repeated),
+            },
+        }
+    }
+
+    // the first row that breaks it, found by going through the whole column
+    fn check(&self, database: &Database) -> Result<(), Error> {
+        let (Constraint::NotNull { sheet, column } | Constraint::Unique { sheet, column }) = self;
+        let Some(column) = database
+            .columns
+            .iter()
+            .find(|existing| existing.id == *sheet)
+            .and_then(|sheet| sheet.get_column_by_id(column))
+        else {
+            return Ok(());
+        };
+
+        let mut seen = HashSet::new();
+        for (row, cell) in column.cells.iter().enumerate() {
+            let violated = match self {
+                Constraint::NotNull { .. } => cell.value == Value::Nil,
+                Constraint::Unique { .. } => cell.value != Value::Nil && !seen.insert(&cell.value),
+            };
+            if violated {
+                return Err(Error::TransactionError(
+                    TransactionError::ConstraintViolated {
+                        constraint: self.clone(),
+                        column_name: column.name.clone(),
+                        row,
+                    },
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How many rows hold each value of the columns with constraints, so that a
+/// commit only checks the values it changed. Kept up to date with the events
+/// of each change, and counted again when the sheets or columns change.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConstraintIndex {
+    // by sheet and column, only for columns that exist
+    columns: HashMap<(Identifier, Identifier), HashMap<Value, usize>>,
+}
+
+impl ConstraintIndex {
+    // counted from scratch, for every constraint of `database`
+    fn of(database: &Database) -> ConstraintIndex {
+        let mut index = ConstraintIndex::default();
+        for constraint in &database.constraints {
+            index.add(database, constraint.key());
+        }
+        index
+    }
+
+    // starts counting a column, if it exists
+    fn add(&mut self, database: &Database, (sheet, column): (Identifier, Identifier)) {
+        let Some(cells) = database
+            .columns
+            .iter()
+            .find(|existing| existing.id == sheet)
+            .and_then(|sheet| sheet.get_column_by_id(&column))
+            .map(|column| &column.cells)
+        else {
+            return;
+        };
+        let counts = self.columns.entry((sheet, column)).or_default();
+        counts.clear();
+        for cell in cells {
+            *counts.entry(cell.value.clone()).or_default() += 1;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    fn apply(&mut self, event: &Event) {
+        let (sheet, column, old, new) = match event {
+            Event::Inserted {
+                sheet, column, new, ..
+            } => (sheet, column, None, Some(new)),
+            Event::Updated {
+                sheet,
+                column,
+                old,
+                new,
+                ..
+            } => (sheet, column, Some(old), Some(new)),
+            Event::Deleted {
+                sheet, column, old, ..
+            } => (sheet, column, Some(old), None),
+        };
+        let Some(counts) = self.columns.get_mut(&(*sheet, *column)) else {
+            return;
+        };
+        if let Some(old) = old {
+            if let Some(count) = counts.get_mut(old) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(old);
+                }
+            }
+        }
+        if let Some(new) = new {
+            *counts.entry(new.clone()).or_default() += 1;
+        }
+    }
+}
+
+// how to take back one applied operation
+#[derive(Debug)]
+enum Undo {
+    RemoveSheet {
+        index: usize,
+    },
+    RestoreSheets {
+        sheets: Vec<(usize, Sheet)>,
+    },
+    RemoveColumn {
+        sheet: Identifier,
+        index: usize,
+    },
+    RestoreColumns {
+        sheet: Identifier,
+        columns: Vec<(usize, Column)>,
+        // which go if the last column does
+        row_ids: RowIds,
+    },
+    PopRow {
+        sheet: Identifier,
+    },
+    RestoreRow {
+        sheet: Identifier,
+        row: usize,
+        id: u64,
+        values: Vec<Value>,
+    },
+    SetCell {
+        sheet: Identifier,
+        column: Identifier,
+        row: usize,
+        value: Value,
+        // the computed cells of the row, which change with it
+        computed: Vec<(Identifier, Value)>,
+    },
+    RemoveTrigger {
+        sheet: Identifier,
+        name: String,
+    },
+    RestoreTrigger {
+        sheet: Identifier,
+        index: usize,
+        trigger: Trigger,
+    },
+    RemoveView {
+        id: Identifier,
+    },
+    RestoreView {
+        index: usize,
+        view: View,
+    },
+}
+
+impl Undo {
+    // recorded before `operation` is applied to `database`
+    fn of(operation: &Operation, database: &Database) -> Undo {
+        let sheet_of = |id: &Identifier| database.columns.iter().find(|sheet| sheet.id == *id);
+        // of the row with id `row`. If there's none the operation fails, and
+        // the undo is never used.
+        let position = |sheet: &Identifier, row: &u64| {
+            sheet_of(sheet)
+                .and_then(|sheet| sheet.row_position(*row))
+                .unwrap_or_default()
+        };
+        match operation {
+            Operation::AddSheet { .. } => Undo::RemoveSheet {
+                index: database.columns.len(),
+            },
+            Operation::RemoveSheet { sheet } => Undo::RestoreSheets {
+                sheets: database
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, existing)| existing.id == *sheet)
+                    .map(|(index, existing)| (index, existing.clone()))
+                    .collect(),
+            },
+            Operation::AddColumn { sheet, .. } | Operation::AddComputedColumn { sheet, .. } => {
+                Undo::RemoveColumn {
+                    sheet: *sheet,
+                    index: sheet_of(sheet).map_or(0, |sheet| sheet.columns.len()),
+                }
+            }
+            Operation::RemoveColumn { sheet, column } => Undo::RestoreColumns {
+                sheet: *sheet,
+                columns: sheet_of(sheet)
+                    .map(|sheet| {
+                        sheet
+                            .columns
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, existing)| existing.id == *column)
+                            .map(|(index, existing)| (index, existing.clone()))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                row_ids: sheet_of(sheet)
+                    .map_or_else(RowIds::default, |sheet| sheet.row_ids.clone()),
+            },
+            Operation::InsertRow { sheet, .. } => Undo::PopRow { sheet: *sheet },
+            Operation::DeleteRow { sheet, row: id } => Undo::RestoreRow {
+                sheet: *sheet,
+                row: position(sheet, id),
+                id: *id,
+                values: sheet_of(sheet)
+                    .map(|sheet| {
+                        sheet
+                            .columns
+                            .iter()
+                            .filter_map(|column| column.get_cell(position(&sheet.id, id)))
+                            .map(|cell| cell.get_value().clone())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            },
+            Operation::UpdateCell {
+                sheet, column, row, ..
+            } => Undo::SetCell {
+                sheet: *sheet,
+                column: *column,
+                row: position(sheet, row),
+                value: sheet_of(sheet)
+                    .and_then(|sheet| sheet.get_column_by_id(column))
+                    .and_then(|column| column.get_cell(position(sheet, row)))
+                    .map_or(Value::Nil, |cell| cell.get_value().clone()),
+                computed: sheet_of(sheet)
+                    .map(|sheet| {
+                        sheet
+                            .columns
+                            .iter()
+                            .filter(|column| column.computed.is_some())
+                            .filter_map(|column| {
+                                let cell = column.get_cell(position(&sheet.id, row))?;
+                                Some((column.id, cell.get_value().clone()))
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            },
+            Operation::AddTrigger { sheet, trigger } => Undo::RemoveTrigger {
+                sheet: *sheet,
+                name: trigger.name.clone(),
+            },
+            Operation::RemoveTrigger { sheet, name } => {
+                let existing = sheet_of(sheet).and_then(|sheet| {
+                    sheet
+                        .triggers
+                        .iter()
+                        .position(|trigger| trigger.name == *name)
+                        .map(|index| (index, sheet.triggers[index].clone()))
+                });
+                match existing {
+                    Some((index, trigger)) => Undo::RestoreTrigger {
+                        sheet: *sheet,
+                        index,
+                        trigger,
+                    },
+                    // the operation will fail, so there's nothing to take back
+                    None => Undo::RemoveTrigger {
+                        sheet: *sheet,
+                        name: String::new(),
+                    },
+                }
+            }
+            Operation::AddView { id, .. } => Undo::RemoveView { id: *id },
+            Operation::RemoveView { view } => {
+                match database
+                    .views
+                    .iter()
+                    .position(|existing| existing.id == *view)
+                {
+                    Some(index) => Undo::RestoreView {
+                        index,
+                        view: database.views[index].clone(),
+                    },
+                    // the operation will fail, so there's nothing to take back
+                    None => Undo::RemoveView { id: *view },
+                }
+            }
+        }
+    }
+
+    fn revert(self, database: &mut Database) {
+        match self {
+            Undo::RemoveSheet { index } => {
+                if index < database.columns.len() {
+                    database.columns.remove(index);
+                }
+            }
+            Undo::RestoreSheets { sheets } => {
+                for (index, sheet) in sheets {
+                    debug_assert!(index <= database.columns.len());
+                    database.columns.insert(index, sheet);
+                }
+            }
+            Undo::RemoveColumn { sheet, index } => {
+                if let Some(sheet) = database.get_sheet_mut_by_id(&sheet) {
+                    if index < sheet.columns.len() {
+                        sheet.columns.remove(index);
+                    }
+                }
+            }
+            Undo::RestoreColumns {
+                sheet,
+                columns,
+                row_ids,
+            } => {
+                if let Some(sheet) = database.get_sheet_mut_by_id(&sheet) {
+                    for (index, column) in columns {
+                        debug_assert!(index <= sheet.columns.len());
+                        sheet.columns.insert(index, column);
+                    }
+                    sheet.row_ids = row_ids;
+                }
+            }
+            Undo::PopRow { sheet } => {
+                if let Some(sheet) = database.get_sheet_mut_by_id(&sheet) {
+                    for column in &mut sheet.columns {
+                        column.cells.pop();
+                    }
+                    sheet.row_ids.pop();
+                }
+            }
+            Undo::RestoreRow {
+                sheet,
+                row,
+                id,
+                values,
+            } => {
+                if let Some(sheet) = database.get_sheet_mut_by_id(&sheet) {
+                    for (column, value) in sheet.columns.iter_mut().zip(values) {
+                        let mut cell = Cell::new(value, None);
+                        cell.adopt(column);
+                        debug_assert!(row <= column.cells.len());
+                        column.cells.insert(row, cell);
+                    }
+                    sheet.row_ids.insert(row, id);
+                }
+            }
+            Undo::SetCell {
+                sheet,
+                column,
+                row,
+                value,
+                computed,
+            } => {
+                if let Some(sheet) = database.get_sheet_mut_by_id(&sheet) {
+                    for (column, value) in computed.into_iter().chain([(column, value)]) {
+                        if let Some(cell) = sheet
+                            .get_column_mut_by_id(&column)
+                            .and_then(|column| column.get_cell_mut(row))
+                        {
+                            cell.value = value;
+                        }
+                    }
+                }
+            }
+            Undo::RemoveTrigger { sheet, name } => {
+                if let Some(sheet) = database.get_sheet_mut_by_id(&sheet) {
+                    sheet.triggers.retain(|trigger| trigger.name != name);
+                }
+            }
+            Undo::RestoreTrigger {
+                sheet,
+                index,
+                trigger,
+            } => {
+                if let Some(sheet) = database.get_sheet_mut_by_id(&sheet) {
+                    debug_assert!(index <= sheet.triggers.len());
+                    sheet.triggers.insert(index, trigger);
+                }
+            }
+            Undo::RemoveView { id } => database.views.retain(|view| view.id != id),
+            Undo::RestoreView { index, view } => {
+                debug_assert!(index <= database.views.len());
+                database.views.insert(index, view);
+            }
+        }
+    }
+}
+
+// what `operation` did to cells, worked out once it has been applied, from
+// the database and from what `undo` says it replaced
+fn events(operation: &Operation, undo: &Undo, database: &Database) -> Vec<Event> {
+    let Some(sheet) = database
+        .columns
+        .iter()
+        .find(|sheet| sheet.id == *operation.sheet())
+    else {
+        return Vec::new();
+    };
+    match (operation, undo) {
+        (Operation::InsertRow { .. }, _) => {
+            let rows = sheet.columns.first().map_or(0, Column::get_row_count);
+            let row = rows.saturating_sub(1);
+            let Some(id) = sheet.row_id(row) else {
+                return Vec::new();
+            };
+            sheet
+                .columns
+                .iter()
+                .filter_map(|column| {
+                    column.get_cell(row).map(|cell| Event::Inserted {
+                        sheet: sheet.id,
+                        column: column.id,
+                        row: id,
+                        new: cell.get_value().clone(),
+                    })
+                })
+                .collect()
+        }
+        (
+            Operation::UpdateCell { column, row, .. },
+            Undo::SetCell {
+                row: position,
+                value,
+                computed,
+                ..
+            },
+        ) => {
+            let updated = |column: &Identifier, old: &Value| {
+                sheet
+                    .get_column_by_id(column)
+                    .and_then(|column| column.get_cell(*position))
+                    .map(|cell| Event::Updated {
+                        sheet: sheet.id,
+                        column: *column,
+                        row: *row,
+                        old: old.clone(),
+                        new: cell.get_value().clone(),
+                    })
+            };
+            // computed cells are only reported if they changed
+            let computed = computed
+                .iter()
+                .filter_map(|(column, old)| updated(column, old))
+                .filter(|event| !matches!(event, Event::Updated { old, new, .. } if old == new));
+            updated(column, value).into_iter().chain(computed).collect()
+        }
+        (Operation::DeleteRow { row, .. }, Undo::RestoreRow { values, .. }) => sheet
+            .columns
+            .iter()
+            .zip(values)
+            .map(|(column, value)| Event::Deleted {
+                sheet: sheet.id,
+                column: column.id,
+                row: *row,
+                old: value.clone(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// What a committed transaction did: its operations, e.g. to log or apply
+/// again, and how to take them back
+#[derive(Debug)]
+pub(crate) struct Changes {
+    pub(crate) operations: Vec<Operation>,
+    undo: Vec<Undo>,
+    // only worked out while someone is subscribed or a column has a
+    // constraint
+    events: Vec<Event>,
+}
+
+impl Changes {
+    /// Takes the changes back, and tells subscribers. Only meaningful while
+    /// the database is as the transaction left it, or has been returned to
+    /// that state.
+    pub(crate) fn revert(self, database: &mut Database) {
+        for undo in self.undo.into_iter().rev() {
+            undo.revert(database);
+        }
+        let events = self
+            .events
+            .into_iter()
+            .rev()
+            .map(Event::inverse)
+            .collect::<Vec<_>>();
+        // without events, the constraint may have come after the changes
+        if events.is_empty() || self.operations.iter().any(Operation::changes_schema) {
+            database.constrained = ConstraintIndex::of(database);
+        } else {
+            events
+                .iter()
+                .for_each(|event| database.constrained.apply(event));
+        }
+        database.observers.notify(&events);
+        database.note_changes(&self.operations);
+    }
+}
+
+/// Changes to a database that take effect together or not at all, see
+/// Database::transaction. Dropping a transaction that hasn't committed, as
+/// happens when its closure panics, rolls it back.
+pub(crate) struct Transaction<'a> {
+    database: &'a mut Database,
+    // one entry per applied operation, in order
+    undo: Vec<Undo>,
+    operations: Vec<Operation>,
+    events: Vec<Vec<Event>>,
+    // how many triggers deep the operation being applied is
+    depth: usize,
+    // the triggers running, by sheet, row and name, see Fired::by
+    running: Vec<(Identifier, u64, String)>,
+    committed: bool,
+}
+
+impl Transaction<'_> {
+    pub(crate) fn database(&self) -> &Database {
+        self.database
+    }
+
+    /// Applies `operation` and runs the triggers it sets off, see Trigger. If
+    /// it or a trigger fails nothing changes, and the transaction can carry on
+    /// or return the error to roll back.
+    pub(crate) fn apply(&mut self, operation: Operation) -> Result<(), Error> {
+        let Some(fired) = Fired::by(&operation, self.database, &self.running) else {
+            return self.replay(operation);
+        };
+        if self.depth >= trigger::MAX_DEPTH {
+            return Err(Error::TriggerError(TriggerError::TooDeep));
+        }
+        self.savepoint(|tx| {
+            tx.replay(operation)?;
+            tx.depth += 1;
+            let running = tx.running.len();
+            tx.running.extend(fired.running());
+            let result = fired.run(tx);
+            tx.running.truncate(running);
+            tx.depth -= 1;
+            result
+        })
+    }
+
+    /// Applies `operation` without running triggers, for operations that were
+    /// recorded along with what their triggers did, as in the log
+    pub(crate) fn replay(&mut self, operation: Operation) -> Result<(), Error> {
+        let undo = Undo::of(&operation, self.database);
+        operation.apply(self.database)?;
+        let events =
+            match self.database.observers.is_empty() && self.database.constrained.is_empty() {
+                true => Vec::new(),
+                false => events(&operation, &undo, self.database),
+            };
+        events
+            .iter()
+            .for_each(|event| self.database.constrained.apply(event));
+        self.events.push(events);
+        self.undo.push(undo);
+        self.operations.push(operation);
+        Ok(())
+    }
+
+    /// Runs `f` as a nested step: if it returns an error, only the changes it
+    /// made are rolled back, and the error is returned for the transaction to
+    /// handle or pass on.
+    pub(crate) fn savepoint<T>(
+        &mut self,
+        f: impl FnOnce(&mut Transaction) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let savepoint = self.undo.len();
+        let result = f(self);
+        if result.is_err() {
+            self.rollback_to(savepoint);
+        }
+        result
+    }
+
+    fn rollback_to(&mut self, savepoint: usize) {
+        while self.undo.len() > savepoint {
+            if let Some(undo) = self.undo.pop() {
+                undo.revert(self.database);
+            }
+        }
+        self.operations.truncate(savepoint);
+        let undone = self.events.drain(savepoint..).rev();
+        for event in undone.flat_map(|events| events.into_iter().rev()) {
+            self.database.constrained.apply(&event.inverse());
+        }
+    }
+
+    fn commit(&mut self) -> Result<(), Error> {
+        let touched = self
+            .operations
+            .iter()
+            .map(Operation::sheet)
+            .collect::<HashSet<_>>();
+        let changes_schema = self.operations.iter().any(Operation::changes_schema);
+        if changes_schema {
+            trigger::check(self.database)?;
+            view::check(self.database)?;
+        }
+        // counted again, as columns may have come or gone. Only kept once
+        // committed, as rolling back takes back events from the old counts.
+        let recounted = changes_schema.then(|| ConstraintIndex::of(self.database));
+        let index = recounted.as_ref().unwrap_or(&self.database.constrained);
+        for constraint in &self.database.constraints {
+            if !touched.contains(constraint.sheet()) {
+                continue;
+            }
+            let Some(counts) = index.columns.get(&constraint.key()) else {
+                continue;
+            };
+            let (sheet, column) = constraint.key();
+            let changed = self
+                .events
+                .iter()
+                .flatten()
+                .filter_map(|event| match event {
+                    Event::Inserted {
+                        sheet: changed_sheet,
+                        column: changed_column,
+                        new,
+                        ..
+                    }
+                    | Event::Updated {
+                        sheet: changed_sheet,
+                        column: changed_column,
+                        new,
+                        ..
+                    } if *changed_sheet == sheet && *changed_column == column => Some(new),
+                    _ => None,
+                });
+            if constraint.broken(counts, (!changes_schema).then_some(changed)) {
+                // which row, for the error
+                constraint.check(self.database)?;
+            }
+        }
+        if let Some(recounted) = recounted {
+            self.database.constrained = recounted;
+        }
+        self.database.note_changes(&self.operations);
+        self.committed = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Transaction<'_> {
+    /// Adds a sheet with `columns`, returning its id and theirs, for tests
+    pub(crate) fn add_sheet(
+        &mut self,
+        name: &str,
+        columns: &[(&str, crate::internal::data_type::Type)],
+    ) -> Result<(Identifier, Vec<Identifier>), Error> {
+        let sheet = Identifier::new();
+        self.apply(Operation::AddSheet {
+            id: sheet,
+            name: name.to_string(),
+        })?;
+        let mut ids = Vec::new();
+        for (name, value_type) in columns {
+            let id = Identifier::new();
+            self.apply(Operation::AddColumn {
+                sheet,
+                id,
+                name: name.to_string(),
+                value_type: *value_type,
+            })?;
+            ids.push(id);
+        }
+        Ok((sheet, ids))
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.rollback_to(0);
+        }
+    }
+}
+
+impl Operation {
+    // the sheet the operation changes, or the view
+    fn sheet(&self) -> &Identifier {
+        match self {
+            Operation::AddSheet { id, .. } | Operation::AddView { id, .. } => id,
+            Operation::RemoveView { view } => view,
+            Operation::RemoveSheet { sheet }
+            | Operation::AddColumn { sheet, .. }
+            | Operation::RemoveColumn { sheet, .. }
+            | Operation::InsertRow { sheet, .. }
+            | Operation::UpdateCell { sheet, .. }
+            | Operation::DeleteRow { sheet, .. }
+            | Operation::AddTrigger { sheet, .. }
+            | Operation::RemoveTrigger { sheet, .. }
+            | Operation::AddComputedColumn { sheet, .. } => sheet,
+        }
+    }
+
+    // whether it changes what sheets, columns or views there are, which the
+    // triggers and views have to be checked against again
+    fn changes_schema(&self) -> bool {
+        !matches!(
+            self,
+            Operation::InsertRow { .. }
+                | Operation::UpdateCell { .. }
+                | Operation::DeleteRow { .. }
+                | Operation::AddTrigger { .. }
+                | Operation::RemoveTrigger { .. }
+        )
+    }
+}
+
+impl Database {
+    /// Runs `f` in a transaction. If `f` returns an error or panics, or a
+    /// constraint fails at commit, every change it made is rolled back.
+    /// Subscribers hear about the changes once it has committed.
+    pub(crate) fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Transaction) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.transaction_with_changes(f).map(|(value, _)| value)
+    }
+
+    /// Like transaction, also returning what it changed
+    pub(crate) fn transaction_with_changes<T>(
+        &mut self,
+        f: impl FnOnce(&mut Transaction) -> Result<T, Error>,
+    ) -> Result<(T, Changes), Error> {
+        let mut transaction = Transaction {
+            database: self,
+            undo: Vec::new(),
+            operations: Vec::new(),
+            events: Vec::new(),
+            depth: 0,
+            running: Vec::new(),
+            committed: false,
+        };
+        let value = f(&mut transaction)?;
+        transaction.commit()?;
+        let changes = Changes {
+            operations: std::mem::take(&mut transaction.operations),
+            undo: std::mem::take(&mut transaction.undo),
+            events: transaction.events.drain(..).flatten().collect(),
+        };
+        drop(transaction);
+        self.observers.notify(&changes.events);
+        Ok((value, changes))
+    }
+
+    /// Adds a constraint, failing if the database already breaks it
+    pub(crate) fn add_constraint(&mut self, constraint: Constraint) -> Result<(), Error> {
+        constraint.check(self)?;
+        let mut constrained = std::mem::take(&mut self.constrained);
+        constrained.add(self, constraint.key());
+        self.constrained = constrained;
+        self.constraints.push(constraint);
+        Ok(())
+    }
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum TransactionError {
+    ConstraintViolated {
+        constraint: Constraint,
+        column_name: String,
+        row: usize,
+    },
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::ConstraintViolated {
+                constraint: Constraint::NotNull { .. },
+                column_name,
+                row,
+            } => write!(
+                f,
+                "Column {} can't be Nil, but is in row {}",
+                column_name, row
+            ),
+            TransactionError::ConstraintViolated {
+                constraint: Constraint::Unique { .. },
+                column_name,
+                row,
+            } => write!(
+                f,
+                "Column {} must be unique, but row {} repeats an earlier value",
+                column_name, row
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::data_type::Type;
+    use crate::internal::traits::PrettyPrintable;
+
+    // orders and their line items, as two sheets
+    fn shop() -> (Database, Identifier, Identifier, Identifier) {
+        let mut database = Database::new_empty();
+        let ((orders, ids), (items, _)) = database
+            .transaction(|tx| {
+                Ok((
+                    tx.add_sheet("orders", &[("id", Type::Int)])?,
+                    tx.add_sheet("items", &[("order", Type::Int)])?,
+                ))
+            })
+            .unwrap();
+        (database, orders, items, ids[0])
+    }
+
+    #[test]
+    fn errors_and_panics_roll_back_everything() {
+        let (mut database, orders, items, order_id) = shop();
+        database
+            .add_constraint(Constraint::Unique {
+                sheet: orders,
+                column: order_id,
+            })
+            .unwrap();
+        let before = database.pretty_print(0);
+
+        // the second insert fails, the first must not stick
+        let result = database.transaction(|tx| {
+            tx.apply(Operation::InsertRow {
+                sheet: orders,
+                values: vec![Value::Int(1)],
+            })?;
+            tx.apply(Operation::InsertRow {
+                sheet: items,
+                values: vec![Value::Str("one".to_string())],
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(database.pretty_print(0), before);
+
+        // every step succeeds, but the commit breaks the constraint
+        let result = database.transaction(|tx| {
+            for _ in 0..2 {
+                tx.apply(Operation::InsertRow {
+                    sheet: orders,
+                    values: vec![Value::Int(1)],
+                })?;
+            }
+            Ok(())
+        });
+        assert!(matches!(
+            result,
+            Err(Error::TransactionError(
+                TransactionError::ConstraintViolated { row: 1, .. }
+            ))
+        ));
+        assert_eq!(database.pretty_print(0), before);
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            database.transaction(|tx| {
+                tx.apply(Operation::RemoveSheet { sheet: items })?;
+                tx.apply(Operation::RemoveColumn {
+                    sheet: orders,
+                    column: order_id,
+                })?;
+                panic!("in the middle of a transaction");
+                #[allow(unreachable_code)]
+                Ok(())
+            })
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(database.pretty_print(0), before);
+    }
+
+    #[test]
+    fn savepoints_roll_back_only_their_own_changes() {
+        let (mut database, orders, _, order_id) = shop();
+        let insert = |n| Operation::InsertRow {
+            sheet: orders,
+            values: vec![Value::Int(n)],
+        };
+
+        let operations = database
+            .transaction_with_changes(|tx| {
+                tx.apply(insert(1))?;
+                let nested = tx.savepoint(|tx| {
+                    tx.apply(insert(2))?;
+                    tx.savepoint(|tx| {
+                        tx.apply(Operation::UpdateCell {
+                            sheet: orders,
+                            column: order_id,
+                            row: 0,
+                            value: Value::Int(10),
+                        })
+                    })?;
+                    tx.apply(insert(3))?;
+                    tx.apply(Operation::UpdateCell {
+                        sheet: orders,
+                        column: order_id,
+                        row: 9,
+                        value: Value::Int(0),
+                    })
+                });
+                assert!(nested.is_err());
+                tx.apply(insert(4))
+            })
+            .unwrap()
+            .1
+            .operations;
+
+        let cells = &database.columns[0].columns[0].cells;
+        let values = cells
+            .iter()
+            .map(|cell| cell.value.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![Value::Int(1), Value::Int(4)]);
+        assert_eq!(operations, vec![insert(1), insert(4)]);
+    }
+
+    #[test]
+    fn constraints_only_check_what_changed_and_keep_count() {
+        let (mut database, orders, _, order_id) = shop();
+        let insert = |n| Operation::InsertRow {
+            sheet: orders,
+            values: vec![Value::Int(n)],
+        };
+        let set = |row, n| Operation::UpdateCell {
+            sheet: orders,
+            column: order_id,
+            row,
+            value: Value::Int(n),
+        };
+        database.transaction(|tx| tx.apply(insert(1))).unwrap();
+        database.transaction(|tx| tx.apply(insert(2))).unwrap();
+        // counted from the rows already there
+        database
+            .add_constraint(Constraint::Unique {
+                sheet: orders,
+                column: order_id,
+            })
+            .unwrap();
+        // and a column with the constraint's id comes with Nil in every row
+        let later = Identifier::new();
+        database
+            .add_constraint(Constraint::NotNull {
+                sheet: orders,
+                column: later,
+            })
+            .unwrap();
+        let mut history = crate::internal::history::History::new(database);
+
+        assert!(history
+            .edit(|tx| {
+                tx.apply(insert(3))?;
+                tx.apply(set(2, 1))
+            })
+            .is_err());
+        // a swap repeats a value only half-way through
+        history
+            .edit(|tx| {
+                tx.apply(set(0, 2))?;
+                tx.apply(set(1, 1))
+            })
+            .unwrap();
+        history.apply(insert(5)).unwrap();
+        assert!(history.undo());
+        history.apply(insert(5)).unwrap();
+        assert!(history.apply(insert(1)).is_err());
+        assert!(history
+            .apply(Operation::AddColumn {
+                sheet: orders,
+                id: later,
+                name: "later".to_string(),
+                value_type: Type::Int,
+            })
+            .is_err());
+
+        let database = history.database();
+        assert_eq!(
+            database.constrained.columns,
+            ConstraintIndex::of(database).columns
+        );
+        assert_eq!(database.columns[0].columns[0].cells.len(), 3);
+    }
+}
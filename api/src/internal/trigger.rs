@@ -0,0 +1,882 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::Value;
+use crate::internal::database::Database;
+use crate::internal::errors::Error;
+use crate::internal::expr::{self, Expr};
+use crate::internal::format::{Features, Format};
+use crate::internal::id::Identifier;
+use crate::internal::length_table;
+use crate::internal::sheet::Sheet;
+use crate::internal::transaction::Transaction;
+use crate::internal::wal::{Operation, WalError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How deep triggers may set each other off before the change is refused, so
+/// a trigger that (indirectly) fires itself fails rather than running forever
+pub(crate) const MAX_DEPTH: usize = 16;
+
+/// Something to do whenever a row of a sheet is inserted, updated or deleted,
+/// stored with the sheet. Written in a small language, e.g.
+///
+/// ```text
+/// on insert: append audit (new.id, "insert", now)
+/// on update of price: set updated_at = now; set total = new.price * new.qty
+/// ```
+///
+/// A trigger is `on insert`, `on update`, `on update of <column>` or
+/// `on delete`, a colon, and actions separated by `;`:
+/// - `append <sheet> (<expr>, ...)` inserts a row into a sheet
+/// - `set <column> = <expr>` sets a cell of the row that fired the trigger.
+///   That doesn't set off triggers already running for the row, this one
+///   included, though it may others.
+///
+/// The values are expressions, see Expr, which read `new.<column>` and
+/// `old.<column>` (the row after and before the change), `row` (its id, as a
+/// U64, see row_id.rs) and `now` (seconds since the Unix epoch, as an Int).
+/// Keywords and these names are case-insensitive, and names that aren't plain
+/// words go in backticks, as in expressions.
+///
+/// Triggers run inside the transaction that fired them, and their changes are
+/// part of it: if one fails, the change that fired it fails too. A change
+/// that removes a sheet or column a trigger names fails, as it does for a
+/// view.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Trigger {
+    pub(crate) name: String,
+    pub(crate) on: On,
+    pub(crate) actions: Vec<Action>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum On {
+    Insert,
+    // any column, or only this one
+    Update { column: Option<String> },
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Action {
+    Append { sheet: String, values: Vec<Expr> },
+    Set { column: String, value: Expr },
+}
+
+// what a name in an action's expression stands for
+enum Bound<'a> {
+    New(&'a str),
+    Old(&'a str),
+    Row,
+    Now,
+}
+
+fn bound(name: &str) -> Option<Bound<'_>> {
+    if name.eq_ignore_ascii_case("row") {
+        return Some(Bound::Row);
+    }
+    if name.eq_ignore_ascii_case("now") {
+        return Some(Bound::Now);
+    }
+    match name.split_once('.') {
+        Some((row, column)) if row.eq_ignore_ascii_case("new") => Some(Bound::New(column)),
+        Some((row, column)) if row.eq_ignore_ascii_case("old") => Some(Bound::Old(column)),
+        _ => None,
+    }
+}
+
+impl Trigger {
+    pub(crate) fn parse(name: &str, source: &str) -> Result<Trigger, Error> {
+        let mut parser = expr::Parser::new(source, &[])?;
+        parser.keyword("on")?;
+        let on = if parser.eat("insert") {
+            On::Insert
+        } else if parser.eat("delete") {
+            On::Delete
+        } else if parser.eat("update") {
+            On::Update {
+                column: match parser.eat("of") {
+                    true => Some(parser.name()?),
+                    false => None,
+                },
+            }
+        } else {
+            return parser.unexpected("expected insert, update or delete");
+        };
+        parser.expect(":")?;
+
+        let mut actions = vec![action(&mut parser)?];
+        while parser.eat_symbol(";") && !parser.at_end() {
+            actions.push(action(&mut parser)?);
+        }
+        parser.finish()?;
+
+        let trigger = Trigger {
+            name: name.to_string(),
+            on,
+            actions,
+        };
+        trigger.check_terms()?;
+        Ok(trigger)
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Expr> {
+        self.actions.iter().flat_map(|action| match action {
+            Action::Append { values, .. } => values.iter().collect::<Vec<_>>(),
+            Action::Set { value, .. } => vec![value],
+        })
+    }
+
+    // old and new only exist where there is a row before and after
+    fn check_terms(&self) -> Result<(), Error> {
+        if self.on == On::Delete
+            && self
+                .actions
+                .iter()
+                .any(|action| matches!(action, Action::Set { .. }))
+        {
+            return syntax("set can't be used on delete".to_string());
+        }
+        for name in self.values().flat_map(Expr::columns) {
+            match (bound(name), &self.on) {
+                (None, _) => return syntax(format!("unknown name {}", expr::Name(name))),
+                (Some(Bound::Old(_)), On::Insert) => {
+                    return syntax("old can't be used on insert".to_string())
+                }
+                (Some(Bound::New(_)), On::Delete) => {
+                    return syntax("new can't be used on delete".to_string())
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // whether a change of `kind` sets off the trigger
+    fn fires_on(&self, kind: &On) -> bool {
+        match (&self.on, kind) {
+            (On::Insert, On::Insert) | (On::Delete, On::Delete) => true,
+            (On::Update { column: None }, On::Update { .. }) => true,
+            (
+                On::Update {
+                    column: Some(wanted),
+                },
+                On::Update { column },
+            ) => column.as_ref() == Some(wanted),
+            _ => false,
+        }
+    }
+
+    /// Checks that every sheet and column the trigger names exists, and that
+    /// its values can be worked out from them
+    pub(crate) fn check(&self, sheet: &Sheet, database: &Database) -> Result<(), Error> {
+        let column = |name: &str| match sheet.get_column_by_name(name) {
+            Some(column) => Ok(column.value_type),
+            None => Err(Error::TriggerError(TriggerError::UnknownColumn {
+                trigger: self.name.clone(),
+                column: name.to_string(),
+            })),
+        };
+        if let On::Update { column: Some(name) } = &self.on {
+            column(name)?;
+        }
+        for action in &self.actions {
+            match action {
+                Action::Append { sheet, .. } => {
+                    if !database
+                        .columns
+                        .iter()
+                        .any(|existing| existing.name == *sheet)
+                    {
+                        return Err(Error::TriggerError(TriggerError::UnknownSheet {
+                            trigger: self.name.clone(),
+                            sheet: sheet.clone(),
+                        }));
+                    }
+                }
+                Action::Set { column: name, .. } => {
+                    column(name)?;
+                }
+            }
+        }
+        for value in self.values() {
+            for name in value.columns() {
+                if let Some(Bound::New(name) | Bound::Old(name)) = bound(name) {
+                    column(name)?;
+                }
+            }
+            value.check(&|name| match bound(name)? {
+                Bound::New(name) | Bound::Old(name) => column(name).ok(),
+                Bound::Row => Some(Type::U64),
+                Bound::Now => Some(Type::Int),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Trigger {
+    // the source it was parsed from, give or take whitespace and parentheses
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.on {
+            On::Insert => write!(f, "on insert:")?,
+            On::Update { column: None } => write!(f, "on update:")?,
+            On::Update {
+                column: Some(column),
+            } => write!(f, "on update of {}:", expr::Name(column))?,
+            On::Delete => write!(f, "on delete:")?,
+        }
+        for (n, action) in self.actions.iter().enumerate() {
+            if n > 0 {
+                write!(f, ";")?;
+            }
+            match action {
+                Action::Append { sheet, values } => {
+                    write!(f, " append {} (", expr::Name(sheet))?;
+                    for (n, value) in values.iter().enumerate() {
+                        if n > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", value)?;
+                    }
+                    write!(f, ")")?;
+                }
+                Action::Set { column, value } => {
+                    write!(f, " set {} = {}", expr::Name(column), value)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/* -- PARSING -- */
+
+fn syntax<T>(reason: String) -> Result<T, Error> {
+    Err(Error::TriggerError(TriggerError::Syntax { reason }))
+}
+
+fn action(parser: &mut expr::Parser) -> Result<Action, Error> {
+    if parser.eat("append") {
+        let sheet = parser.name()?;
+        parser.expect("(")?;
+        let mut values = Vec::new();
+        if !parser.eat_symbol(")") {
+            loop {
+                values.push(parser.expression()?);
+                if parser.eat_symbol(")") {
+                    break;
+                }
+                parser.expect(",")?;
+            }
+        }
+        Ok(Action::Append { sheet, values })
+    } else if parser.eat("set") {
+        let column = parser.name()?;
+        parser.expect("=")?;
+        Ok(Action::Set {
+            column,
+            value: parser.expression()?,
+        })
+    } else {
+        parser.unexpected("expected append or set")
+    }
+}
+
+/* -- RUNNING -- */
+
+// a row as column names and values
+type Row = Vec<(String, Value)>;
+
+fn row_of(sheet: &Sheet, row: usize) -> Row {
+    sheet
+        .columns
+        .iter()
+        .filter_map(|column| {
+            column
+                .get_cell(row)
+                .map(|cell| (column.name.clone(), cell.get_value().clone()))
+        })
+        .collect()
+}
+
+/// The triggers an operation sets off, with what they need to know about the
+/// row from before it was applied
+pub(crate) struct Fired {
+    sheet: Identifier,
+    // of the row, see row_id.rs
+    id: u64,
+    kind: On,
+    old: Row,
+    triggers: Vec<Trigger>,
+}
+
+impl Fired {
+    /// Worked out before `operation` is applied to `database`. None if it
+    /// doesn't fire anything. Triggers in `running`, by sheet, row and name,
+    /// aren't set off again, so a `set` doesn't fire what's running for its row.
+    pub(crate) fn by(
+        operation: &Operation,
+        database: &Database,
+        running: &[(Identifier, u64, String)],
+    ) -> Option<Fired> {
+        let (id, kind, row) = match operation {
+            Operation::InsertRow { sheet, .. } => (sheet, On::Insert, None),
+            Operation::UpdateCell {
+                sheet, column, row, ..
+            } => (
+                sheet,
+                On::Update {
+                    column: database
+                        .columns
+                        .iter()
+                        .find(|existing| existing.id == *sheet)
+                        .and_then(|sheet| sheet.get_column_by_id(column))
+                        .map(|column| column.name.clone()),
+                },
+                Some(*row),
+            ),
+            Operation::DeleteRow { sheet, row } => (sheet, On::Delete, Some(*row)),
+            _ => return None,
+        };
+        let sheet = database.columns.iter().find(|sheet| sheet.id == *id)?;
+        let triggers = sheet
+            .triggers
+            .iter()
+            .filter(|trigger| trigger.fires_on(&kind))
+            .filter(|trigger| {
+                !running.iter().any(|(sheet, running, name)| {
+                    sheet == id && Some(*running) == row && *name == trigger.name
+                })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if triggers.is_empty() {
+            return None;
+        }
+
+        Some(Fired {
+            sheet: *id,
+            id: row.unwrap_or_else(|| sheet.row_ids.next()),
+            old: match row {
+                // if there's no such row, the operation fails before its
+                // triggers run
+                Some(row) => row_of(sheet, sheet.row_position(row)?),
+                None => Row::new(),
+            },
+            kind,
+            triggers,
+        })
+    }
+
+    /// Runs the triggers, once the operation that fired them has been applied
+    pub(crate) fn run(self, tx: &mut Transaction) -> Result<(), Error> {
+        let new = match self.kind {
+            On::Delete => Row::new(),
+            _ => tx
+                .database()
+                .columns
+                .iter()
+                .find(|sheet| sheet.id == self.sheet)
+                .and_then(|sheet| Some(row_of(sheet, sheet.row_position(self.id)?)))
+                .unwrap_or_default(),
+        };
+        for trigger in &self.triggers {
+            for action in &trigger.actions {
+                let operation = self.operation(trigger, action, &new, tx.database())?;
+                tx.apply(operation)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The triggers, by sheet, row and name, see by
+    pub(crate) fn running(&self) -> impl Iterator<Item = (Identifier, u64, String)> + '_ {
+        self.triggers
+            .iter()
+            .map(|trigger| (self.sheet, self.id, trigger.name.clone()))
+    }
+
+    fn operation(
+        &self,
+        trigger: &Trigger,
+        action: &Action,
+        new: &Row,
+        database: &Database,
+    ) -> Result<Operation, Error> {
+        let unknown_column = |column: &String| {
+            Error::TriggerError(TriggerError::UnknownColumn {
+                trigger: trigger.name.clone(),
+                column: column.clone(),
+            })
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+        let lookup = |row: &Row, column: &str| {
+            row.iter()
+                .find(|(name, _)| name == column)
+                .map(|(_, value)| value.clone())
+        };
+        let value = |value: &Expr| {
+            value.evaluate(&|name| match bound(name)? {
+                Bound::New(column) => lookup(new, column),
+                Bound::Old(column) => lookup(&self.old, column),
+                Bound::Row => Some(Value::U64(self.id)),
+                Bound::Now => Some(Value::Int(now)),
+            })
+        };
+
+        match action {
+            Action::Append { sheet, values } => Ok(Operation::InsertRow {
+                sheet: database
+                    .columns
+                    .iter()
+                    .find(|existing| existing.name == *sheet)
+                    .map(|sheet| sheet.id)
+                    .ok_or_else(|| {
+                        Error::TriggerError(TriggerError::UnknownSheet {
+                            trigger: trigger.name.clone(),
+                            sheet: sheet.clone(),
+                        })
+                    })?,
+                values: values.iter().map(value).collect::<Result<_, _>>()?,
+            }),
+            Action::Set { column, value: set } => Ok(Operation::UpdateCell {
+                sheet: self.sheet,
+                column: database
+                    .columns
+                    .iter()
+                    .find(|existing| existing.id == self.sheet)
+                    .and_then(|sheet| sheet.get_column_by_name(column))
+                    .map(|column| column.id)
+                    .ok_or_else(|| unknown_column(column))?,
+                row: self.id,
+                value: value(set)?,
+            }),
+        }
+    }
+}
+
+/* -- STORAGE -- */
+
+// all numbers are BE
+// u32 length: of everything after it, so readers can skip the triggers
+// u32 count
+// count times:
+//   u32 name_length, u8[name_length] name
+//   u32 source_length, u8[source_length] source: as Trigger's Display writes it
+// Only written with Features::TRIGGERS.
+
+/// The serialized triggers of a sheet, empty if `format` has no triggers
+pub(crate) fn serialized_bytes(triggers: &[Trigger], format: &Format) -> Vec<u8> {
+    if !format.has(Features::TRIGGERS) {
+        return Vec::new();
+    }
+    length_table::block(triggers, |trigger, bytes| {
+        length_table::write_text(bytes, &trigger.name);
+        length_table::write_text(bytes, &trigger.to_string());
+    })
+}
+
+/// Reads the triggers of a sheet, leaving the deserializer after them
+pub(crate) fn read(
+    deserializer: &mut ByteDeserializer,
+    format: &Format,
+) -> Result<Vec<Trigger>, Error> {
+    if !format.has(Features::TRIGGERS) {
+        return Ok(Vec::new());
+    }
+    let too_many = |count, length| {
+        Error::TriggerError(TriggerError::Syntax {
+            reason: format!("{} triggers in {} bytes", count, length),
+        })
+    };
+    // every trigger takes at least its two lengths
+    length_table::read_block(deserializer, 8, too_many, |body| {
+        let name_length = body.read_u32()? as usize;
+        format.limits.check_string(name_length)?;
+        let name = body.read_name(name_length, format)?;
+        let source_length = body.read_u32()? as usize;
+        format.limits.check_string(source_length)?;
+        let source = body.read_string(source_length)?;
+        Trigger::parse(&name, &source)
+    })
+}
+
+impl Database {
+    /// Adds a trigger to a sheet, see Trigger. Shorthand for applying
+    /// Operation::AddTrigger in a transaction.
+    pub(crate) fn add_trigger(
+        &mut self,
+        sheet: &Identifier,
+        name: &str,
+        source: &str,
+    ) -> Result<(), Error> {
+        let trigger = Trigger::parse(name, source)?;
+        self.transaction(|tx| {
+            tx.apply(Operation::AddTrigger {
+                sheet: *sheet,
+                trigger,
+            })
+        })
+    }
+}
+
+/// Checks every sheet's triggers against the sheets and columns they name,
+/// see Trigger::check
+pub(crate) fn check(database: &Database) -> Result<(), Error> {
+    for sheet in &database.columns {
+        for trigger in &sheet.triggers {
+            trigger.check(sheet, database)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn add(
+    database: &mut Database,
+    sheet: &Identifier,
+    trigger: &Trigger,
+) -> Result<(), Error> {
+    let existing = database
+        .columns
+        .iter()
+        .find(|existing| existing.id == *sheet)
+        .ok_or(Error::WalError(WalError::SheetNotFound { id: *sheet }))?;
+    if existing
+        .triggers
+        .iter()
+        .any(|existing| existing.name == trigger.name)
+    {
+        return Err(Error::TriggerError(TriggerError::AlreadyExists {
+            name: trigger.name.clone(),
+        }));
+    }
+    trigger.check(existing, database)?;
+    if let Some(sheet) = database.get_sheet_mut_by_id(sheet) {
+        sheet.triggers.push(trigger.clone());
+    }
+    Ok(())
+}
+
+pub(crate) fn remove(database: &mut Database, sheet: &Identifier, name: &str) -> Result<(), Error> {
+    let sheet = database
+        .get_sheet_mut_by_id(sheet)
+        .ok_or(Error::WalError(WalError::SheetNotFound { id: *sheet }))?;
+    let index = sheet
+        .triggers
+        .iter()
+        .position(|trigger| trigger.name == name)
+        .ok_or(Error::TriggerError(TriggerError::NotFound {
+            name: name.to_string(),
+        }))?;
+    sheet.triggers.remove(index);
+    Ok(())
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum TriggerError {
+    Syntax { reason: String },
+    AlreadyExists { name: String },
+    NotFound { name: String },
+    UnknownSheet { trigger: String, sheet: String },
+    UnknownColumn { trigger: String, column: String },
+    TooDeep,
+}
+
+impl std::fmt::Display for TriggerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggerError::Syntax { reason } => write!(f, "Invalid trigger: {}", reason),
+            TriggerError::AlreadyExists { name } => {
+                write!(f, "A trigger named {} already exists", name)
+            }
+            TriggerError::NotFound { name } => write!(f, "No trigger named {}", name),
+            TriggerError::UnknownSheet { trigger, sheet } => {
+                write!(f, "Trigger {} refers to unknown sheet {}", trigger, sheet)
+            }
+            TriggerError::UnknownColumn { trigger, column } => {
+                write!(f, "Trigger {} refers to unknown column {}", trigger, column)
+            }
+            TriggerError::TooDeep => write!(
+                f,
+                "Triggers set each other off more than {} deep",
+                MAX_DEPTH
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TriggerError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::expr::ExprError;
+    use crate::internal::save::SaveOptions;
+    use crate::internal::stream::StreamReader;
+    use crate::internal::traits::{PrettyPrintable, Serializable};
+    use crate::internal::wal::LoggedDatabase;
+
+    // orders with a price and when it last changed, and an audit log
+    fn shop() -> (Database, Identifier, Identifier) {
+        let mut database = Database::new_empty();
+        let ((orders, _), (audit, _)) = database
+            .transaction(|tx| {
+                Ok((
+                    tx.add_sheet(
+                        "orders",
+                        &[
+                            ("item", Type::Str),
+                            ("price", Type::U32),
+                            ("updated at", Type::Int),
+                        ],
+                    )?,
+                    tx.add_sheet("audit", &[("item", Type::Str), ("what", Type::Str)])?,
+                ))
+            })
+            .unwrap();
+        database
+            .add_trigger(
+                &orders,
+                "audit",
+                r#"on insert: append audit (new.item, "insert")"#,
+            )
+            .unwrap();
+        database
+            .add_trigger(
+                &orders,
+                "touch",
+                "on update of price: set `updated at` = now",
+            )
+            .unwrap();
+        (database, orders, audit)
+    }
+
+    fn order(orders: Identifier, item: &str) -> Operation {
+        Operation::InsertRow {
+            sheet: orders,
+            values: vec![Value::Str(item.to_string()), Value::Int(3), Value::Nil],
+        }
+    }
+
+    fn rows(database: &Database, sheet: usize) -> usize {
+        database.columns[sheet].columns[0].get_row_count()
+    }
+
+    #[test]
+    fn triggers_run_on_change_and_are_saved_with_the_sheet() {
+        let (mut database, orders, _) = shop();
+        database
+            .transaction(|tx| tx.apply(order(orders, "tea")))
+            .unwrap();
+        assert_eq!(rows(&database, 1), 1);
+        assert_eq!(
+            database.columns[1].columns[1].cells[0].value,
+            Value::Str("insert".to_string())
+        );
+
+        let price = database.columns[0].columns[1].id;
+        database
+            .transaction(|tx| {
+                tx.apply(Operation::UpdateCell {
+                    sheet: orders,
+                    column: price,
+                    row: 0,
+                    value: Value::Int(4),
+                })
+            })
+            .unwrap();
+        assert!(matches!(
+            database.columns[0].columns[2].cells[0].value,
+            Value::Int(seconds) if seconds > 0
+        ));
+
+        let bytes = database.serialized_bytes();
+        assert!(Database::verify(&bytes).unwrap().corruptions.is_empty());
+        let mut reader = StreamReader::new(&bytes[..]);
+        assert_eq!(
+            reader.next_sheet().unwrap().unwrap().triggers,
+            database.columns[0].triggers
+        );
+
+        let mut loaded = Database::deserialize_bytes(&bytes).unwrap();
+        assert_eq!(loaded.pretty_print(0), database.pretty_print(0));
+        loaded
+            .transaction(|tx| tx.apply(order(orders, "cake")))
+            .unwrap();
+        assert_eq!(rows(&loaded, 1), 2);
+    }
+
+    #[test]
+    fn failing_and_runaway_triggers_roll_back_the_change() {
+        let (mut database, orders, audit) = shop();
+        let before = database.pretty_print(0);
+
+        // audit has two columns, so this can never be appended
+        database
+            .add_trigger(&orders, "broken", "on insert: append audit (new.item)")
+            .unwrap();
+        assert!(database
+            .transaction(|tx| tx.apply(order(orders, "tea")))
+            .is_err());
+        database
+            .transaction(|tx| {
+                tx.apply(Operation::RemoveTrigger {
+                    sheet: orders,
+                    name: "broken".to_string(),
+                })
+            })
+            .unwrap();
+        assert_eq!(database.pretty_print(0), before);
+
+        database
+            .add_trigger(
+                &audit,
+                "echo",
+                "on insert: append audit (new.item, new.what)",
+            )
+            .unwrap();
+        assert!(matches!(
+            database.transaction(|tx| tx.apply(order(orders, "tea"))),
+            Err(Error::TriggerError(TriggerError::TooDeep))
+        ));
+        assert_eq!(rows(&database, 0), 0);
+        assert_eq!(rows(&database, 1), 0);
+
+        assert!(matches!(
+            database.add_trigger(&orders, "typo", "on delete: append audit (old.itme, nil)"),
+            Err(Error::TriggerError(TriggerError::UnknownColumn { .. }))
+        ));
+        assert!(matches!(
+            Trigger::parse("bad", "on insert: set price = old.price"),
+            Err(Error::TriggerError(TriggerError::Syntax { .. }))
+        ));
+    }
+
+    #[test]
+    fn what_triggers_name_cant_be_removed() {
+        let (mut database, orders, audit) = shop();
+        let before = database.pretty_print(0);
+        let updated_at = database.columns[0].columns[2].id;
+        assert!(matches!(
+            database.transaction(|tx| {
+                tx.apply(Operation::RemoveColumn {
+                    sheet: orders,
+                    column: updated_at,
+                })
+            }),
+            Err(Error::TriggerError(TriggerError::UnknownColumn { .. }))
+        ));
+        assert!(matches!(
+            database.transaction(|tx| tx.apply(Operation::RemoveSheet { sheet: audit })),
+            Err(Error::TriggerError(TriggerError::UnknownSheet { .. }))
+        ));
+        assert_eq!(database.pretty_print(0), before);
+
+        // a sheet goes with its own triggers
+        database
+            .transaction(|tx| tx.apply(Operation::RemoveSheet { sheet: orders }))
+            .unwrap();
+        database
+            .transaction(|tx| tx.apply(Operation::RemoveSheet { sheet: audit }))
+            .unwrap();
+    }
+
+    #[test]
+    fn source_round_trips() {
+        for source in [
+            r#"on insert: append audit (new.item, "say \"hi\"", -1.5e-7, 12, true, nil, row)"#,
+            "on update of `unit price`: set updated_at = now; append log ()",
+            "on delete: append audit (old.item, \"delete\")",
+            "on update of `a``b`: set ```` = 1.0; append `x``` ()",
+            "on update: set total = new.price * 2 + abs(old.`unit price`); set at = now",
+        ] {
+            let trigger = Trigger::parse("t", source).unwrap();
+            assert_eq!(trigger.to_string(), source);
+        }
+        assert_eq!(
+            Trigger::parse(
+                "t",
+                "ON Update OF price: SET at = NOW; Append log (New.price)"
+            )
+            .unwrap()
+            .to_string(),
+            "on update of price: set at = NOW; append log (New.price)"
+        );
+        for source in [
+            "on insert: set a = 1 +",
+            "on insert: set a = 1 2",
+            "on insert set a = 1",
+        ] {
+            assert!(matches!(
+                Trigger::parse("t", source),
+                Err(Error::ExprError(ExprError::Syntax { .. }))
+            ));
+        }
+        assert!(matches!(
+            Trigger::parse("t", "on insert: set a = price"),
+            Err(Error::TriggerError(TriggerError::Syntax { .. }))
+        ));
+    }
+
+    #[test]
+    fn a_set_does_not_set_off_its_own_trigger() {
+        let (mut database, orders, _) = shop();
+        database
+            .add_trigger(&orders, "double", "on update: set price = new.price * 2")
+            .unwrap();
+        database
+            .transaction(|tx| tx.apply(order(orders, "tea")))
+            .unwrap();
+        let price = database.columns[0].columns[1].id;
+        let row = database.columns[0].row_id(0).unwrap();
+        database
+            .transaction(|tx| {
+                tx.apply(Operation::UpdateCell {
+                    sheet: orders,
+                    column: price,
+                    row,
+                    value: Value::Int(4),
+                })
+            })
+            .unwrap();
+        assert_eq!(database.columns[0].columns[1].cells[0].value, Value::U32(8));
+        // but it still sets off the others
+        assert!(matches!(
+            database.columns[0].columns[2].cells[0].value,
+            Value::Int(seconds) if seconds > 0
+        ));
+
+        assert!(matches!(
+            database.add_trigger(&orders, "typed", "on update: set price = new.item * 2"),
+            Err(Error::ExprError(ExprError::TypeMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn replaying_the_log_does_not_fire_triggers_again() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("shop.basebored");
+        let (database, orders, _) = shop();
+        database.save(&path, SaveOptions::default()).unwrap();
+
+        let mut logged = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+        logged
+            .apply(Operation::AddTrigger {
+                sheet: orders,
+                trigger: Trigger::parse("again", r#"on insert: append audit ("", "again")"#)
+                    .unwrap(),
+            })
+            .unwrap();
+        logged.apply(order(orders, "tea")).unwrap();
+        assert_eq!(rows(logged.database(), 1), 2);
+        drop(logged);
+
+        let reopened = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+        assert_eq!(rows(reopened.database(), 1), 2);
+        assert_eq!(reopened.database().columns[0].triggers.len(), 3);
+    }
+}
@@ -0,0 +1,495 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::database::{Database, DatabaseError};
+use crate::internal::errors::Error;
+use crate::internal::format::{Features, Format};
+use crate::internal::id::Identifier;
+use crate::internal::index::Index;
+use crate::internal::length_table;
+use crate::internal::sheet::Sheet;
+use crate::internal::sql::Query;
+use crate::internal::traits::Serializable;
+use crate::internal::wal::Operation;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+// how many views deep a view can read, so reading one can't run away
+const MAX_DEPTH: usize = 32;
+
+/// A named SELECT statement, stored with the database and read like a sheet,
+/// e.g. `SELECT name, price * qty AS total FROM orders WHERE qty > 0`. Its
+/// rows are worked out from the sheet (or view) it reads each time it's
+/// read, so they're never out of date. A view reads one sheet or view:
+/// joins are out of scope, as they are for SELECT.
+///
+/// A view's name can't be taken by a sheet or another view. It's checked
+/// against the columns it reads when it's added, and again whenever a
+/// transaction changes what sheets or columns there are: a change that would
+/// break a view fails, as removing a column a computed column reads does.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct View {
+    pub(crate) id: Identifier,
+    pub(crate) name: String,
+    pub(crate) query: Query,
+}
+
+// all numbers are BE
+// u32 length: of everything after it, so readers can skip the views
+// u32 count
+// count times:
+//   u8[16] id
+//   u32 name_length, u8[name_length] name
+//   u32 query_length, u8[query_length] query: as Query's Display writes it
+// Only written with Features::VIEWS, after the index, see Database's layout.
+
+/// The serialized views of a database, empty if `format` has no views
+pub(crate) fn serialized_bytes(views: &[View], format: &Format) -> Vec<u8> {
+    if !format.has(Features::VIEWS) {
+        return Vec::new();
+    }
+    length_table::block(views, |view, bytes| {
+        bytes.extend_from_slice(&view.id.serialized_bytes());
+        length_table::write_text(bytes, &view.name);
+        length_table::write_text(bytes, &view.query.to_string());
+    })
+}
+
+/// Reads the views of a database, leaving the deserializer after them
+pub(crate) fn read(
+    deserializer: &mut ByteDeserializer,
+    format: &Format,
+) -> Result<Vec<View>, Error> {
+    if !format.has(Features::VIEWS) {
+        return Ok(Vec::new());
+    }
+    let too_many = |count, length| {
+        Error::ViewError(ViewError::Malformed {
+            reason: format!("{} views in {} bytes", count, length),
+        })
+    };
+    // every view takes at least its id and two lengths
+    length_table::read_block(deserializer, 24, too_many, |body| {
+        let id = Identifier::deserialize_bytes(&body.read_bytes(16)?)?;
+        let name_length = body.read_u32()? as usize;
+        format.limits.check_string(name_length)?;
+        let name = body.read_name(name_length, format)?;
+        let query_length = body.read_u32()? as usize;
+        format.limits.check_string(query_length)?;
+        let query = Query::parse(&body.read_string(query_length)?)?;
+        Ok(View { id, name, query })
+    })
+}
+
+/// Offset of the views in a file with Features::VIEWS: after the sheets and
+/// the index, if there is one. `read_u32_at` reads the 4 bytes at an offset.
+pub(crate) fn offset(
+    header_length: usize,
+    format: &Format,
+    read_u32_at: impl Fn(u64) -> Result<[u8; 4], Error>,
+) -> Result<u64, Error> {
+    let sheets_length = format.read_length_table_u32(read_u32_at(header_length as u64)?);
+    let mut offset = Index::offset(header_length, sheets_length);
+    if format.has(Features::INDEX) {
+        offset += 4 + u32::from_be_bytes(read_u32_at(offset)?) as u64;
+    }
+    Ok(offset)
+}
+
+impl Database {
+    /// The names of the sheets, then of the views
+    pub(crate) fn sheet_names(&self) -> Vec<&str> {
+        let sheets = self.columns.iter().map(|sheet| sheet.name.as_str());
+        sheets
+            .chain(self.views.iter().map(|view| view.name.as_str()))
+            .collect()
+    }
+
+    /// The sheet named `name`, or the rows of the view named `name` as a
+    /// sheet of that name
+    pub(crate) fn read(&self, name: &str) -> Result<Cow<'_, Sheet>, Error> {
+        self.read_before(name, self.views.len())
+    }
+
+    // like read, but only the first `count` views can be read. Views only
+    // read sheets or views before them, so following them one by one ends.
+    fn read_before(&self, name: &str, count: usize) -> Result<Cow<'_, Sheet>, Error> {
+        let mut chain = Vec::new();
+        let (mut name, mut count) = (name, count);
+        let sheet = loop {
+            if let Some(sheet) = self.columns.iter().find(|sheet| sheet.name == name) {
+                break sheet;
+            }
+            let n = self.views[..count]
+                .iter()
+                .position(|view| view.name == name)
+                .ok_or(Error::DatabaseError(DatabaseError::SheetNotFound {
+                    sheet_name: name.to_string(),
+                }))?;
+            if chain.len() == MAX_DEPTH {
+                return Err(Error::ViewError(ViewError::TooDeep {
+                    name: name.to_string(),
+                }));
+            }
+            chain.push(&self.views[n]);
+            name = &self.views[n].query.sheet;
+            count = n;
+        };
+        let mut rows = Cow::Borrowed(sheet);
+        for view in chain.into_iter().rev() {
+            let mut sheet = rows.select(&view.query)?;
+            sheet.name = view.name.clone();
+            rows = Cow::Owned(sheet);
+        }
+        Ok(rows)
+    }
+
+    /// Adds a view, see View, and returns its id. Shorthand for applying
+    /// Operation::AddView in a transaction.
+    pub(crate) fn add_view(&mut self, name: &str, query: Query) -> Result<Identifier, Error> {
+        let id = Identifier::new();
+        self.transaction(|tx| {
+            tx.apply(Operation::AddView {
+                id,
+                name: name.to_string(),
+                query,
+            })
+        })?;
+        Ok(id)
+    }
+
+    /// Removes the view named `name`, in a transaction
+    pub(crate) fn remove_view(&mut self, name: &str) -> Result<(), Error> {
+        let view = self
+            .views
+            .iter()
+            .find(|view| view.name == name)
+            .ok_or(Error::ViewError(ViewError::NotFound {
+                name: name.to_string(),
+            }))?
+            .id;
+        self.transaction(|tx| tx.apply(Operation::RemoveView { view }))
+    }
+
+    // the sheets with their columns but no rows, to check views against
+    fn schema(&self) -> Database {
+        let mut schema = Database::new_empty();
+        for sheet in &self.columns {
+            let mut empty = sheet.without_columns();
+            empty.adopt_columns(
+                sheet
+                    .columns
+                    .iter()
+                    .map(|column| column.without_cells())
+                    .collect(),
+            );
+            schema.columns.push(empty);
+        }
+        schema.views = self.views.clone();
+        schema
+    }
+}
+
+/// Checks what can be checked without the columns: every view's name is
+/// free, and it reads a sheet or an earlier view, at most MAX_DEPTH deep.
+/// `is_sheet` tells if there's a sheet of a name.
+pub(crate) fn check_names(views: &[View], is_sheet: impl Fn(&str) -> bool) -> Result<(), Error> {
+    // how many views deep each view reads
+    let mut depths: HashMap<&str, usize> = HashMap::with_capacity(views.len());
+    for view in views {
+        if is_sheet(&view.name) || depths.contains_key(view.name.as_str()) {
+            return Err(Error::ViewError(ViewError::NameTaken {
+                name: view.name.clone(),
+            }));
+        }
+        let broken = |reason: String| {
+            Error::ViewError(ViewError::Broken {
+                view_name: view.name.clone(),
+                reason,
+            })
+        };
+        let depth = match depths.get(view.query.sheet.as_str()) {
+            Some(depth) => depth + 1,
+            None if is_sheet(&view.query.sheet) => 1,
+            None => {
+                return Err(broken(
+                    DatabaseError::SheetNotFound {
+                        sheet_name: view.query.sheet.clone(),
+                    }
+                    .to_string(),
+                ))
+            }
+        };
+        if depth > MAX_DEPTH {
+            return Err(broken(
+                ViewError::TooDeep {
+                    name: view.name.clone(),
+                }
+                .to_string(),
+            ));
+        }
+        depths.insert(&view.name, depth);
+    }
+    Ok(())
+}
+
+/// Checks every view against the sheets and columns it reads, see View
+pub(crate) fn check(database: &Database) -> Result<(), Error> {
+    if database.views.is_empty() {
+        return Ok(());
+    }
+    let sheets: HashSet<&str> = database
+        .columns
+        .iter()
+        .map(|sheet| sheet.name.as_str())
+        .collect();
+    check_names(&database.views, |name| sheets.contains(name))?;
+    let schema = database.schema();
+    for (n, view) in database.views.iter().enumerate() {
+        // a view can only read what was there before it
+        schema
+            .read_before(&view.query.sheet, n)
+            .and_then(|sheet| sheet.select(&view.query))
+            .map_err(|err| {
+                Error::ViewError(ViewError::Broken {
+                    view_name: view.name.clone(),
+                    reason: err.to_string(),
+                })
+            })?;
+    }
+    Ok(())
+}
+
+/// Adds a view to the end of the database, for Operation::AddView
+pub(crate) fn add(
+    database: &mut Database,
+    id: &Identifier,
+    name: &str,
+    query: &Query,
+) -> Result<(), Error> {
+    database.views.push(View {
+        id: *id,
+        name: name.to_string(),
+        query: query.clone(),
+    });
+    if let Err(err) = check(database) {
+        database.views.pop();
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Removes a view, for Operation::RemoveView. Fails if a later view reads it.
+pub(crate) fn remove(database: &mut Database, id: &Identifier) -> Result<(), Error> {
+    let index = database
+        .views
+        .iter()
+        .position(|view| view.id == *id)
+        .ok_or(Error::ViewError(ViewError::UnknownId { id: *id }))?;
+    let view = database.views.remove(index);
+    if let Err(err) = check(database) {
+        database.views.insert(index, view);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum ViewError {
+    NameTaken { name: String },
+    NotFound { name: String },
+    UnknownId { id: Identifier },
+    Broken { view_name: String, reason: String },
+    ReadOnly { name: String },
+    Malformed { reason: String },
+    TooDeep { name: String },
+}
+
+impl std::fmt::Display for ViewError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ViewError::NameTaken { name } => {
+                write!(f, "A sheet or view named {} already exists", name)
+            }
+            ViewError::NotFound { name } => write!(f, "No view named {}", name),
+            ViewError::UnknownId { id } => write!(f, "No view with {}", id),
+            ViewError::Broken { view_name, reason } => {
+                write!(f, "View {} would no longer work: {}", view_name, reason)
+            }
+            ViewError::ReadOnly { name } => write!(f, "{} is a view and can't be changed", name),
+            ViewError::Malformed { reason } => write!(f, "Malformed views: {}", reason),
+            ViewError::TooDeep { name } => {
+                write!(f, "View {} reads views more than {} deep", name, MAX_DEPTH)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ViewError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::data_value::Value;
+    use crate::internal::database_file::DatabaseFile;
+    use crate::internal::history::History;
+    use crate::internal::stream::StreamReader;
+    use crate::internal::traits::PrettyPrintable;
+    use std::io::Write;
+
+    fn values(sheet: &Sheet, column: &str) -> Vec<Value> {
+        let column = sheet.get_column_by_name(column).unwrap();
+        column.cells.iter().map(|cell| cell.value.clone()).collect()
+    }
+
+    // orders, with a view of the big ones and a view of that
+    fn shop() -> Database {
+        let mut database = Database::new_empty();
+        for sql in [
+            "create sheet orders (item text, price u32, qty u8)",
+            "insert into orders values ('tea', 3, 2), ('cake', 5, 4), ('scone', 2, 1)",
+            "create view big as select item, price * qty as total from orders where price * qty > 5 order by price * qty desc",
+            "create view `top item` as select upper(item) as item from big limit 1",
+        ] {
+            database.execute(sql, &[]).unwrap();
+        }
+        database
+    }
+
+    #[test]
+    fn views_are_read_like_sheets_and_saved() {
+        let mut database = shop();
+        assert_eq!(database.sheet_names(), vec!["orders", "big", "top item"]);
+        let big = database.read("big").unwrap();
+        assert_eq!(big.name, "big");
+        assert_eq!(values(&big, "total"), vec![Value::U32(20), Value::U32(6)]);
+        let top = database.execute("select * from `top item`", &[]).unwrap();
+        assert_eq!(values(&top, "item"), vec![Value::Str("CAKE".to_string())]);
+
+        // always up to date
+        database
+            .execute("update orders set qty = 20 where item = 'scone'", &[])
+            .unwrap();
+        assert_eq!(values(&database.read("big").unwrap(), "total").len(), 3);
+
+        let bytes = database.serialized_bytes();
+        assert!(Database::verify(&bytes).unwrap().corruptions.is_empty());
+        let loaded = Database::deserialize_bytes(&bytes).unwrap();
+        assert_eq!(loaded.views, database.views);
+        assert_eq!(loaded.pretty_print(0), database.pretty_print(0));
+        let mut stream = StreamReader::new(&bytes[..]);
+        assert_eq!(stream.next_sheet().unwrap().unwrap().name, "orders");
+        assert!(stream.next_sheet().unwrap().is_none());
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&bytes).unwrap();
+        let file = DatabaseFile::open(file.path()).unwrap();
+        assert_eq!(file.sheet_names(), database.sheet_names());
+        assert_eq!(
+            values(&file.view("top item").unwrap(), "item"),
+            vec![Value::Str("SCONE".to_string())]
+        );
+
+        let mut history = History::new(loaded);
+        history
+            .apply(Operation::RemoveView {
+                view: database.views[1].id,
+            })
+            .unwrap();
+        assert_eq!(history.database().views.len(), 1);
+        history.undo();
+        assert_eq!(history.database().views, database.views);
+    }
+
+    #[test]
+    fn views_are_checked_when_what_they_read_changes() {
+        let mut database = shop();
+        for (sql, expected) in [
+            ("alter nothing", None),
+            ("drop sheet orders", Some("big")),
+            ("drop view big", Some("top item")),
+            ("create view big as select * from orders", None),
+            (
+                "create view broken as select nothing from orders",
+                Some("broken"),
+            ),
+            ("create view later as select * from missing", Some("later")),
+            ("create sheet big (a int)", None),
+            ("insert into big values ('pie', 1)", None),
+            (
+                "create view many as insert into orders values ('x', 1, 1)",
+                None,
+            ),
+        ] {
+            match (database.execute(sql, &[]), expected) {
+                (Err(Error::ViewError(ViewError::Broken { view_name, .. })), Some(expected)) => {
+                    assert_eq!(view_name, expected, "{}", sql)
+                }
+                (Err(_), None) => {}
+                (other, _) => panic!("{} gave {:?}", sql, other),
+            }
+        }
+        assert_eq!(database.sheet_names(), vec!["orders", "big", "top item"]);
+
+        // the column `total` reads from is renamed out from under it
+        let orders = database.columns[0].id;
+        let price = database.columns[0].columns[1].id;
+        assert!(matches!(
+            database.transaction(|tx| {
+                tx.apply(Operation::RemoveColumn {
+                    sheet: orders,
+                    column: price,
+                })?;
+                tx.apply(Operation::AddColumn {
+                    sheet: orders,
+                    id: Identifier::new(),
+                    name: "price".to_string(),
+                    value_type: crate::internal::data_type::Type::Str,
+                })
+            }),
+            Err(Error::ViewError(ViewError::Broken { .. }))
+        ));
+        assert_eq!(database.columns[0].columns[1].id, price);
+
+        database.execute("drop view `top item`", &[]).unwrap();
+        database.execute("drop view big", &[]).unwrap();
+        database.execute("drop sheet orders", &[]).unwrap();
+        assert!(database.sheet_names().is_empty());
+    }
+
+    #[test]
+    fn files_with_views_that_loop_or_nest_too_deeply_are_refused() {
+        let view = |name: &str, reads: &str| View {
+            id: Identifier::new(),
+            name: name.to_string(),
+            query: Query::parse(&format!("select * from `{}`", reads)).unwrap(),
+        };
+        let mut looping = shop();
+        looping.views.push(view("loop", "loop"));
+        // reading it can't go round and round either
+        assert!(looping.read("loop").is_err());
+        let mut deep = shop();
+        deep.views.push(view("0", "orders"));
+        for n in 1..=MAX_DEPTH {
+            deep.views.push(view(&n.to_string(), &(n - 1).to_string()));
+        }
+        assert!(matches!(
+            deep.read(&MAX_DEPTH.to_string()),
+            Err(Error::ViewError(ViewError::TooDeep { .. }))
+        ));
+
+        for database in [looping, deep] {
+            let bytes = database.serialized_bytes();
+            assert!(matches!(
+                Database::deserialize_bytes(&bytes),
+                Err(Error::ViewError(ViewError::Broken { .. }))
+            ));
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(&bytes).unwrap();
+            assert!(matches!(
+                DatabaseFile::open(file.path()),
+                Err(Error::ViewError(ViewError::Broken { .. }))
+            ));
+        }
+    }
+}
@@ -0,0 +1,848 @@
+use crate::internal::byte_deserializer::ByteDeserializer;
+use crate::internal::cell::Cell;
+use crate::internal::column::Column;
+use crate::internal::computed;
+use crate::internal::data_type::Type;
+use crate::internal::data_value::Value;
+use crate::internal::database::Database;
+use crate::internal::errors::Error;
+use crate::internal::expr::Expr;
+use crate::internal::id::Identifier;
+use crate::internal::save::{self, SaveOptions};
+use crate::internal::sheet::Sheet;
+use crate::internal::sql::Query;
+use crate::internal::traits::Serializable;
+use crate::internal::transaction::Transaction;
+use crate::internal::trigger::{self, Trigger};
+use crate::internal::view;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// every log starts with "baseboredlog" and a format version as an ascii digit
+const LOG_MAGIC: [u8; 13] = *b"baseboredlog1";
+
+// magic + u32 crc32c of the database file the log applies to
+const LOG_HEADER_LENGTH: usize = LOG_MAGIC.len() + 4;
+
+/// A logical change to a database, as recorded in the write-ahead log.
+/// Sheets and columns are referred to by id, so renaming one doesn't break the
+/// operations that follow, and rows by their id (see row_id.rs), so deleting
+/// one doesn't move the rows they refer to.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Operation {
+    AddSheet {
+        id: Identifier,
+        name: String,
+    },
+    RemoveSheet {
+        sheet: Identifier,
+    },
+    // existing rows get Nil in the new column
+    AddColumn {
+        sheet: Identifier,
+        id: Identifier,
+        name: String,
+        value_type: Type,
+    },
+    RemoveColumn {
+        sheet: Identifier,
+        column: Identifier,
+    },
+    InsertRow {
+        sheet: Identifier,
+        values: Vec<Value>,
+    },
+    UpdateCell {
+        sheet: Identifier,
+        column: Identifier,
+        row: u64,
+        value: Value,
+    },
+    DeleteRow {
+        sheet: Identifier,
+        row: u64,
+    },
+    AddTrigger {
+        sheet: Identifier,
+        trigger: Trigger,
+    },
+    RemoveTrigger {
+        sheet: Identifier,
+        name: String,
+    },
+    // computed for the existing rows, see computed.rs
+    AddComputedColumn {
+        sheet: Identifier,
+        id: Identifier,
+        name: String,
+        expression: Expr,
+    },
+    // checked against the sheets it reads, see view.rs
+    AddView {
+        id: Identifier,
+        name: String,
+        query: Query,
+    },
+    RemoveView {
+        view: Identifier,
+    },
+}
+
+impl Operation {
+    /// Applies the operation to `database`, leaving it untouched on error
+    pub(crate) fn apply(&self, database: &mut Database) -> Result<(), Error> {
+        match self {
+            Operation::AddSheet { id, name } => {
+                database.adopt_sheet(&Sheet::new_with_set_id(*id, name.clone(), Vec::new(), None));
+            }
+            Operation::RemoveSheet { sheet } => {
+                sheet_mut(database, sheet)?;
+                database.columns.retain(|existing| existing.id != *sheet);
+            }
+            Operation::AddColumn {
+                sheet,
+                id,
+                name,
+                value_type,
+            } => {
+                let sheet = sheet_mut(database, sheet)?;
+                let rows = sheet.columns.first().map_or(0, Column::get_row_count);
+                let mut column = Column::new_with_set_id(*id, name.clone(), *value_type, None);
+                for _ in 0..rows {
+                    column.insert_value(Value::Nil);
+                }
+                sheet.adopt_column(&column);
+            }
+            Operation::RemoveColumn { sheet, column } => {
+                let sheet = sheet_mut(database, sheet)?;
+                let name = column_mut(sheet, column)?.name.clone();
+                sheet.check_removable(&name)?;
+                sheet.columns.retain(|existing| existing.id != *column);
+                // the rows go with the last column
+                if sheet.columns.is_empty() {
+                    sheet.row_ids.clear();
+                }
+            }
+            Operation::InsertRow { sheet, values } => {
+                sheet_mut(database, sheet)?.insert_row(values.clone())?;
+            }
+            Operation::UpdateCell {
+                sheet,
+                column,
+                row,
+                value,
+            } => {
+                let sheet = sheet_mut(database, sheet)?;
+                let row = sheet.find_row(*row)?;
+                let cell = cell_mut(sheet, column, row)?;
+                let old = cell.get_value().clone();
+                cell.set_value(value.clone())?;
+                // the computed columns of the row follow, or the cell goes back
+                if let Err(err) = sheet.recompute(row as usize) {
+                    cell_mut(sheet, column, row)?.value = old;
+                    return Err(err);
+                }
+            }
+            Operation::DeleteRow { sheet, row } => {
+                let sheet = sheet_mut(database, sheet)?;
+                let row = sheet.find_row(*row)? as usize;
+                for column in &mut sheet.columns {
+                    column.cells.remove(row);
+                }
+                sheet.row_ids.remove(row);
+            }
+            Operation::AddTrigger { sheet, trigger } => trigger::add(database, sheet, trigger)?,
+            Operation::RemoveTrigger { sheet, name } => trigger::remove(database, sheet, name)?,
+            Operation::AddComputedColumn {
+                sheet,
+                id,
+                name,
+                expression,
+            } => computed::add(database, sheet, id, name, expression)?,
+            Operation::AddView { id, name, query } => view::add(database, id, name, query)?,
+            Operation::RemoveView { view } => view::remove(database, view)?,
+        }
+        Ok(())
+    }
+
+    fn kind(&self) -> u8 {
+        match self {
+            Operation::AddSheet { .. } => 0,
+            Operation::RemoveSheet { .. } => 1,
+            Operation::AddColumn { .. } => 2,
+            Operation::RemoveColumn { .. } => 3,
+            Operation::InsertRow { .. } => 4,
+            Operation::UpdateCell { .. } => 5,
+            Operation::DeleteRow { .. } => 6,
+            Operation::AddTrigger { .. } => 7,
+            Operation::RemoveTrigger { .. } => 8,
+            Operation::AddComputedColumn { .. } => 9,
+            Operation::AddView { .. } => 10,
+            Operation::RemoveView { .. } => 11,
+        }
+    }
+}
+
+fn sheet_mut<'a>(database: &'a mut Database, id: &Identifier) -> Result<&'a mut Sheet, Error> {
+    database
+        .get_sheet_mut_by_id(id)
+        .ok_or(Error::WalError(WalError::SheetNotFound { id: *id }))
+}
+
+fn column_mut<'a>(sheet: &'a mut Sheet, id: &Identifier) -> Result<&'a mut Column, Error> {
+    sheet
+        .get_column_mut_by_id(id)
+        .ok_or(Error::WalError(WalError::ColumnNotFound { id: *id }))
+}
+
+// a cell that may be set directly, i.e. not a computed one
+fn cell_mut<'a>(
+    sheet: &'a mut Sheet,
+    column: &Identifier,
+    row: u64,
+) -> Result<&'a mut Cell, Error> {
+    let column = column_mut(sheet, column)?;
+    column.check_settable()?;
+    let rows = column.get_row_count();
+    column
+        .get_cell_mut(row as usize)
+        .ok_or(Error::WalError(WalError::RowOutOfRange { row, rows }))
+}
+
+pub(crate) fn write_name(name: &str, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+pub(crate) fn write_value(value: &Value, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&value.get_type().serialized_bytes());
+    value.write_payload(bytes);
+}
+
+pub(crate) fn read_id(deserializer: &mut ByteDeserializer) -> Result<Identifier, Error> {
+    Identifier::deserialize_bytes(&deserializer.read_bytes(16)?)
+}
+
+pub(crate) fn read_name(deserializer: &mut ByteDeserializer) -> Result<String, Error> {
+    let length = deserializer.read_u32()?;
+    deserializer.read_string(length as usize)
+}
+
+pub(crate) fn read_value(deserializer: &mut ByteDeserializer) -> Result<Value, Error> {
+    let value_type = Type::deserialize_bytes(&[deserializer.read_u8()?])?;
+    Value::read_payload(value_type, deserializer)
+}
+
+impl Operation {
+    // reads one operation, leaving the deserializer at the byte after it
+    fn read(deserializer: &mut ByteDeserializer) -> Result<Operation, Error> {
+        let operation = match deserializer.read_u8()? {
+            0 => Operation::AddSheet {
+                id: read_id(deserializer)?,
+                name: read_name(deserializer)?,
+            },
+            1 => Operation::RemoveSheet {
+                sheet: read_id(deserializer)?,
+            },
+            2 => Operation::AddColumn {
+                sheet: read_id(deserializer)?,
+                id: read_id(deserializer)?,
+                name: read_name(deserializer)?,
+                value_type: Type::deserialize_bytes(&[deserializer.read_u8()?])?,
+            },
+            3 => Operation::RemoveColumn {
+                sheet: read_id(deserializer)?,
+                column: read_id(deserializer)?,
+            },
+            4 => {
+                let sheet = read_id(deserializer)?;
+                let count = deserializer.read_u32()?;
+                // every value takes at least its type byte
+                if count as usize > deserializer.remaining_bytes().len() {
+                    return Err(Error::WalError(WalError::Malformed {
+                        reason: format!(
+                            "{} values in a row with {} bytes left",
+                            count,
+                            deserializer.remaining_bytes().len()
+                        ),
+                    }));
+                }
+                let values = (0..count)
+                    .map(|_| read_value(deserializer))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                Operation::InsertRow { sheet, values }
+            }
+            5 => Operation::UpdateCell {
+                sheet: read_id(deserializer)?,
+                column: read_id(deserializer)?,
+                row: deserializer.read_u64()?,
+                value: read_value(deserializer)?,
+            },
+            6 => Operation::DeleteRow {
+                sheet: read_id(deserializer)?,
+                row: deserializer.read_u64()?,
+            },
+            7 => {
+                let sheet = read_id(deserializer)?;
+                let name = read_name(deserializer)?;
+                let source = read_name(deserializer)?;
+                Operation::AddTrigger {
+                    sheet,
+                    trigger: Trigger::parse(&name, &source)?,
+                }
+            }
+            8 => Operation::RemoveTrigger {
+                sheet: read_id(deserializer)?,
+                name: read_name(deserializer)?,
+            },
+            9 => Operation::AddComputedColumn {
+                sheet: read_id(deserializer)?,
+                id: read_id(deserializer)?,
+                name: read_name(deserializer)?,
+                expression: Expr::parse(&read_name(deserializer)?)?,
+            },
+            10 => Operation::AddView {
+                id: read_id(deserializer)?,
+                name: read_name(deserializer)?,
+                query: Query::parse(&read_name(deserializer)?)?,
+            },
+            11 => Operation::RemoveView {
+                view: read_id(deserializer)?,
+            },
+            kind => return Err(Error::WalError(WalError::UnknownOperation { kind })),
+        };
+        Ok(operation)
+    }
+}
+
+impl Serializable<Operation> for Operation {
+    // all numbers are BE
+    // u8 kind, then by kind:
+    // 0 AddSheet: u8[16] id, u32 name_length, u8[name_length] name
+    // 1 RemoveSheet: u8[16] sheet
+    // 2 AddColumn: u8[16] sheet, u8[16] id, u32 name_length, u8[name_length] name,
+    //   u8 value_type
+    // 3 RemoveColumn: u8[16] sheet, u8[16] column
+    // 4 InsertRow: u8[16] sheet, u32 value_count, then value_count values
+    // 5 UpdateCell: u8[16] sheet, u8[16] column, u64 row id, value
+    // 6 DeleteRow: u8[16] sheet, u64 row id
+    // 7 AddTrigger: u8[16] sheet, u32 name_length, u8[name_length] name,
+    //   u32 source_length, u8[source_length] source, see trigger.rs
+    // 8 RemoveTrigger: u8[16] sheet, u32 name_length, u8[name_length] name
+    // 9 AddComputedColumn: u8[16] sheet, u8[16] id, u32 name_length,
+    //   u8[name_length] name, u32 expression_length,
+    //   u8[expression_length] expression, see computed.rs
+    // 10 AddView: u8[16] id, u32 name_length, u8[name_length] name,
+    //   u32 query_length, u8[query_length] query, see view.rs
+    // 11 RemoveView: u8[16] view
+    // a value is its u8 type followed by its payload, see Value::write_payload
+
+    fn serialized_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.kind()];
+        match self {
+            Operation::AddSheet { id, name } => {
+                bytes.extend_from_slice(&id.serialized_bytes());
+                write_name(name, &mut bytes);
+            }
+            Operation::RemoveSheet { sheet } => {
+                bytes.extend_from_slice(&sheet.serialized_bytes());
+            }
+            Operation::AddColumn {
+                sheet,
+                id,
+                name,
+                value_type,
+            } => {
+                bytes.extend_from_slice(&sheet.serialized_bytes());
+                bytes.extend_from_slice(&id.serialized_bytes());
+                write_name(name, &mut bytes);
+                bytes.extend_from_slice(&value_type.serialized_bytes());
+            }
+            Operation::RemoveColumn { sheet, column } => {
+                bytes.extend_from_slice(&sheet.serialized_bytes());
+                bytes.extend_from_slice(&column.serialized_bytes());
+            }
+            Operation::InsertRow { sheet, values } => {
+                bytes.extend_from_slice(&sheet.serialized_bytes());
+                bytes.extend_from_slice(&(values.len() as u32).to_be_bytes());
+                for value in values {
+                    write_value(value, &mut bytes);
+                }
+            }
+            Operation::UpdateCell {
+                sheet,
+                column,
+                row,
+                value,
+            } => {
+                bytes.extend_from_slice(&sheet.serialized_bytes());
+                bytes.extend_from_slice(&column.serialized_bytes());
+                bytes.extend_from_slice(&row.to_be_bytes());
+                write_value(value, &mut bytes);
+            }
+            Operation::DeleteRow { sheet, row } => {
+                bytes.extend_from_slice(&sheet.serialized_bytes());
+                bytes.extend_from_slice(&row.to_be_bytes());
+            }
+            Operation::AddTrigger { sheet, trigger } => {
+                bytes.extend_from_slice(&sheet.serialized_bytes());
+                write_name(&trigger.name, &mut bytes);
+                write_name(&trigger.to_string(), &mut bytes);
+            }
+            Operation::RemoveTrigger { sheet, name } => {
+                bytes.extend_from_slice(&sheet.serialized_bytes());
+                write_name(name, &mut bytes);
+            }
+            Operation::AddComputedColumn {
+                sheet,
+                id,
+                name,
+                expression,
+            } => {
+                bytes.extend_from_slice(&sheet.serialized_bytes());
+                bytes.extend_from_slice(&id.serialized_bytes());
+                write_name(name, &mut bytes);
+                write_name(&expression.to_string(), &mut bytes);
+            }
+            Operation::AddView { id, name, query } => {
+                bytes.extend_from_slice(&id.serialized_bytes());
+                write_name(name, &mut bytes);
+                write_name(&query.to_string(), &mut bytes);
+            }
+            Operation::RemoveView { view } => {
+                bytes.extend_from_slice(&view.serialized_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn deserialize_bytes(bytes: &[u8]) -> Result<Operation, Error> {
+        let mut deserializer = ByteDeserializer::new(bytes);
+        let operation = Operation::read(&mut deserializer)?;
+
+        if !deserializer.remaining_bytes().is_empty() {
+            return Err(Error::WalError(WalError::Malformed {
+                reason: format!(
+                    "{} bytes left over after the operation",
+                    deserializer.remaining_bytes().len()
+                ),
+            }));
+        }
+        Ok(operation)
+    }
+}
+
+/// Path of the write-ahead log of the database file at `path`
+pub(crate) fn log_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".wal");
+    path.with_file_name(name)
+}
+
+fn log_header(base: u32) -> Vec<u8> {
+    let mut bytes = LOG_MAGIC.to_vec();
+    bytes.extend_from_slice(&base.to_be_bytes());
+    bytes
+}
+
+// all numbers are BE
+// u8[13] magic: "baseboredlog1"
+// u32 base: crc32c of the database file the log applies to
+// repeated until the end of the log:
+//   u32 length, u8[length] operations, u32 crc32c of the operations
+// a record holds the operations of one transaction, one after another
+//
+// Returns the transactions and the length of the log they take up. A record cut
+// short or failing its checksum is where a crash interrupted an append, so it
+// and everything after it is ignored. So is a log written against a different
+// database file: it was already folded in by a checkpoint.
+fn read_log(bytes: &[u8], base: u32) -> Result<(Vec<Vec<Operation>>, usize), Error> {
+    if bytes.len() < LOG_HEADER_LENGTH {
+        return Ok((Vec::new(), 0));
+    }
+    if bytes[..LOG_MAGIC.len()] != LOG_MAGIC {
+        return Err(Error::WalError(WalError::NotALog));
+    }
+    if bytes[..LOG_HEADER_LENGTH] != log_header(base) {
+        return Ok((Vec::new(), 0));
+    }
+
+    let mut transactions = Vec::new();
+    let mut deserializer = ByteDeserializer::new(&bytes[LOG_HEADER_LENGTH..]);
+    let mut end = LOG_HEADER_LENGTH;
+    while let Ok(length) = deserializer.read_u32() {
+        let Ok(record) = deserializer.read_bytes(length as usize) else {
+            break;
+        };
+        match deserializer.read_u32() {
+            Ok(stored) if stored == crc32c::crc32c(&record) => {}
+            _ => break,
+        }
+        let mut operations = ByteDeserializer::new(&record);
+        let mut transaction = Vec::new();
+        while !operations.remaining_bytes().is_empty() {
+            transaction.push(Operation::read(&mut operations)?);
+        }
+        transactions.push(transaction);
+        end = LOG_HEADER_LENGTH + deserializer.position();
+    }
+
+    Ok((transactions, end))
+}
+
+/// A database file with a write-ahead log beside it, see log_path. Changes are
+/// appended to the log and synced, which is far cheaper than rewriting the
+/// file; a checkpoint writes them into the file and empties the log.
+pub(crate) struct LoggedDatabase {
+    database: Database,
+    path: PathBuf,
+    log: File,
+    options: SaveOptions,
+}
+
+impl LoggedDatabase {
+    /// Opens the database at `path`, or starts an empty one if there's no file
+    /// yet, and replays its log. A log left cut short by a crash is trimmed
+    /// back to the last complete operation.
+    pub(crate) fn open(
+        path: impl AsRef<Path>,
+        options: SaveOptions,
+    ) -> Result<LoggedDatabase, Error> {
+        let path = path.as_ref().to_path_buf();
+        let bytes = read_if_exists(&path)?;
+        let mut database = match bytes.is_empty() {
+            true => Database::new_empty(),
+            false => Database::deserialize_bytes(&bytes)?,
+        };
+        let base = crc32c::crc32c(&bytes);
+
+        let log_path = log_path(&path);
+        let (transactions, end) = read_log(&read_if_exists(&log_path)?, base)?;
+        // what triggers did is in the log already, so they don't run again
+        for operations in transactions {
+            database.transaction(|tx| {
+                operations
+                    .into_iter()
+                    .try_for_each(|operation| tx.replay(operation))
+            })?;
+        }
+
+        let mut log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&log_path)
+            .map_err(Error::Io)?;
+        match end {
+            0 => reset(&mut log, base).map_err(Error::Io)?,
+            end => {
+                log.set_len(end as u64).map_err(Error::Io)?;
+                log.seek(SeekFrom::End(0)).map_err(Error::Io)?;
+            }
+        }
+        if let Some(directory) = log_path.parent() {
+            if !directory.as_os_str().is_empty() {
+                save::sync_directory(directory).map_err(Error::Io)?;
+            }
+        }
+
+        Ok(LoggedDatabase {
+            database,
+            path,
+            log,
+            options,
+        })
+    }
+
+    pub(crate) fn database(&self) -> &Database {
+        &self.database
+    }
+
+    /// Applies `operation` and appends it to the log, see transaction
+    pub(crate) fn apply(&mut self, operation: Operation) -> Result<(), Error> {
+        self.transaction(|tx| tx.apply(operation))
+    }
+
+    /// Runs `f` in a transaction (see Database::transaction) and appends its
+    /// operations to the log as one record, so after a crash it is replayed
+    /// whole or not at all. Once this returns Ok the changes survive a crash.
+    /// If writing the log fails they're taken back, and the error returned.
+    pub(crate) fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Transaction) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let (value, changes) = self.database.transaction_with_changes(f)?;
+        if changes.operations.is_empty() {
+            return Ok(value);
+        }
+
+        let record = changes
+            .operations
+            .iter()
+            .flat_map(Operation::serialized_bytes)
+            .collect::<Vec<_>>();
+        let mut bytes = (record.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&record);
+        bytes.extend_from_slice(&crc32c::crc32c(&record).to_be_bytes());
+
+        let end = self.log.stream_position().map_err(Error::Io)?;
+        if let Err(err) = self
+            .log
+            .write_all(&bytes)
+            .and_then(|_| self.log.sync_data())
+        {
+            // don't leave half a record for the next append to follow
+            let _ = self.log.set_len(end);
+            let _ = self.log.seek(SeekFrom::Start(end));
+            changes.revert(&mut self.database);
+            return Err(Error::Io(err));
+        }
+        Ok(value)
+    }
+
+    /// Saves the database over its file (see Database::save) and empties the log.
+    /// A crash in between leaves a log for the old file, which is ignored.
+    pub(crate) fn checkpoint(&mut self) -> Result<(), Error> {
+        self.database.save(&self.path, self.options)?;
+        let base = crc32c::crc32c(&fs::read(&self.path).map_err(Error::Io)?);
+        reset(&mut self.log, base).map_err(Error::Io)
+    }
+}
+
+fn read_if_exists(path: &Path) -> Result<Vec<u8>, Error> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(bytes),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(Error::Io(err)),
+    }
+}
+
+fn reset(log: &mut File, base: u32) -> io::Result<()> {
+    log.set_len(0)?;
+    log.seek(SeekFrom::Start(0))?;
+    log.write_all(&log_header(base))?;
+    log.sync_data()
+}
+
+/* -- ERRORS -- */
+
+#[derive(Debug)]
+pub(crate) enum WalError {
+    NotALog,
+    UnknownOperation { kind: u8 },
+    Malformed { reason: String },
+    SheetNotFound { id: Identifier },
+    ColumnNotFound { id: Identifier },
+    RowOutOfRange { row: u64, rows: usize },
+}
+
+impl std::fmt::Display for WalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalError::NotALog => write!(f, "Not a write-ahead log"),
+            WalError::UnknownOperation { kind } => {
+                write!(f, "Unknown operation {} in the write-ahead log", kind)
+            }
+            WalError::Malformed { reason } => write!(f, "Malformed operation: {}", reason),
+            WalError::SheetNotFound { id } => write!(f, "No sheet with {}", id),
+            WalError::ColumnNotFound { id } => write!(f, "No column with {}", id),
+            WalError::RowOutOfRange { row, rows } => {
+                write!(
+                    f,
+                    "Row {} is out of range, the column has {} rows",
+                    row, rows
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for WalError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::traits::PrettyPrintable;
+
+    fn people() -> (Database, Identifier, Identifier) {
+        let mut sheet = Sheet::new("people".to_string(), Vec::new(), None);
+        sheet.adopt_column(&Column::new("name".to_string(), Type::Str, None));
+        sheet
+            .insert_row(vec![Value::Str("ada".to_string())])
+            .unwrap();
+
+        let mut database = Database::new_empty();
+        database.adopt_sheet(&sheet);
+        let (sheet, column) = (sheet.id, sheet.columns[0].id);
+        (database, sheet, column)
+    }
+
+    fn operations(sheet: Identifier, name: Identifier) -> Vec<Operation> {
+        let age = Identifier::new();
+        let pets = Identifier::new();
+        vec![
+            Operation::AddColumn {
+                sheet,
+                id: age,
+                name: "age".to_string(),
+                value_type: Type::U8,
+            },
+            Operation::InsertRow {
+                sheet,
+                values: vec![Value::Str("alan".to_string()), Value::Int(41)],
+            },
+            Operation::UpdateCell {
+                sheet,
+                column: age,
+                row: 0,
+                value: Value::Int(36),
+            },
+            Operation::AddSheet {
+                id: pets,
+                name: "pets".to_string(),
+            },
+            Operation::RemoveColumn {
+                sheet,
+                column: name,
+            },
+            Operation::RemoveSheet { sheet: pets },
+        ]
+    }
+
+    #[test]
+    fn recovers_from_a_crash_at_every_offset() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let (database, sheet, column) = people();
+        database.save(&path, SaveOptions::default()).unwrap();
+
+        // the database after each operation, and where the log ends then
+        let mut logged = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+        let mut states = vec![logged.database().pretty_print(0)];
+        let mut ends = vec![fs::metadata(log_path(&path)).unwrap().len() as usize];
+        for operation in operations(sheet, column) {
+            logged.apply(operation).unwrap();
+            states.push(logged.database().pretty_print(0));
+            ends.push(fs::metadata(log_path(&path)).unwrap().len() as usize);
+        }
+        drop(logged);
+        let log = fs::read(log_path(&path)).unwrap();
+
+        for crash in 0..=log.len() {
+            fs::write(log_path(&path), &log[..crash]).unwrap();
+            let applied = ends[1..].iter().filter(|end| **end <= crash).count();
+
+            let recovered = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+            assert_eq!(recovered.database().pretty_print(0), states[applied]);
+            assert_eq!(
+                fs::metadata(log_path(&path)).unwrap().len() as usize,
+                ends[applied]
+            );
+        }
+    }
+
+    #[test]
+    fn checkpoints_fold_the_log_into_the_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let (database, sheet, column) = people();
+        database.save(&path, SaveOptions::default()).unwrap();
+
+        let mut logged = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+        for operation in operations(sheet, column) {
+            logged.apply(operation).unwrap();
+        }
+        let expected = logged.database().pretty_print(0);
+        let stale_log = fs::read(log_path(&path)).unwrap();
+        logged.checkpoint().unwrap();
+        drop(logged);
+
+        assert_eq!(
+            fs::metadata(log_path(&path)).unwrap().len() as usize,
+            LOG_HEADER_LENGTH
+        );
+        let saved = Database::deserialize_bytes(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved.pretty_print(0), expected);
+
+        // as if the checkpoint crashed after saving, before emptying the log
+        fs::write(log_path(&path), stale_log).unwrap();
+        let reopened = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+        assert_eq!(reopened.database().pretty_print(0), expected);
+    }
+
+    #[test]
+    fn failed_operations_are_not_logged() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let mut logged = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+
+        let result = logged.apply(Operation::InsertRow {
+            sheet: Identifier::new(),
+            values: Vec::new(),
+        });
+        assert!(matches!(
+            result,
+            Err(Error::WalError(WalError::SheetNotFound { .. }))
+        ));
+        assert_eq!(
+            fs::metadata(log_path(&path)).unwrap().len() as usize,
+            LOG_HEADER_LENGTH
+        );
+    }
+
+    #[test]
+    fn transactions_are_replayed_whole() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let (database, sheet, column) = people();
+        database.save(&path, SaveOptions::default()).unwrap();
+        let before = database.pretty_print(0);
+
+        let mut logged = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+        logged
+            .transaction(|tx| {
+                operations(sheet, column)
+                    .into_iter()
+                    .try_for_each(|operation| tx.apply(operation))
+            })
+            .unwrap();
+        let after = logged.database().pretty_print(0);
+        drop(logged);
+        let log = fs::read(log_path(&path)).unwrap();
+
+        fs::write(log_path(&path), &log[..log.len() - 1]).unwrap();
+        let recovered = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+        assert_eq!(recovered.database().pretty_print(0), before);
+        drop(recovered);
+
+        fs::write(log_path(&path), &log).unwrap();
+        let recovered = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+        assert_eq!(recovered.database().pretty_print(0), after);
+    }
+
+    #[test]
+    fn changes_that_cant_be_logged_are_taken_back() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("db.basebored");
+        let (database, sheet, column) = people();
+        database.save(&path, SaveOptions::default()).unwrap();
+        let before = database.pretty_print(0);
+
+        let mut logged = LoggedDatabase::open(&path, SaveOptions::default()).unwrap();
+        let log = fs::read(log_path(&path)).unwrap();
+        // opened read-only, so every write fails
+        logged.log = File::open(log_path(&path)).unwrap();
+        assert!(matches!(
+            logged.transaction(|tx| {
+                operations(sheet, column)
+                    .into_iter()
+                    .try_for_each(|operation| tx.apply(operation))
+            }),
+            Err(Error::Io(_))
+        ));
+        assert_eq!(logged.database().pretty_print(0), before);
+        assert_eq!(fs::read(log_path(&path)).unwrap(), log);
+    }
+}
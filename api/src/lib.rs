@@ -1,6 +1,8 @@
 mod column;
 mod data_row;
 mod database;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
 mod internal;
 mod row;
 mod type_;
@@ -6,6 +6,15 @@ pub enum DataType {
     Flt,
     Str,
     Bool,
+    I8,
+    I16,
+    I32,
+    U8,
+    U16,
+    U32,
+    U64,
+    I128,
+    F32,
 }
 
 impl DataType {
@@ -15,6 +24,15 @@ impl DataType {
             Type::Flt => DataType::Flt,
             Type::Str => DataType::Str,
             Type::Bool => DataType::Bool,
+            Type::I8 => DataType::I8,
+            Type::I16 => DataType::I16,
+            Type::I32 => DataType::I32,
+            Type::U8 => DataType::U8,
+            Type::U16 => DataType::U16,
+            Type::U32 => DataType::U32,
+            Type::U64 => DataType::U64,
+            Type::I128 => DataType::I128,
+            Type::F32 => DataType::F32,
             Type::Unknown => unreachable!(),
         }
     }
@@ -25,6 +43,15 @@ impl DataType {
             DataType::Flt => Type::Flt,
             DataType::Str => Type::Str,
             DataType::Bool => Type::Bool,
+            DataType::I8 => Type::I8,
+            DataType::I16 => Type::I16,
+            DataType::I32 => Type::I32,
+            DataType::U8 => Type::U8,
+            DataType::U16 => Type::U16,
+            DataType::U32 => Type::U32,
+            DataType::U64 => Type::U64,
+            DataType::I128 => Type::I128,
+            DataType::F32 => Type::F32,
         }
     }
 }
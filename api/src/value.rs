@@ -1,8 +1,16 @@
 use crate::internal::cell::Cell as InternalCell;
 use crate::internal::data_type::Type as InternalDataType;
 use crate::internal::data_value::Value as InternalValue;
+use crate::internal::errors::Error;
 use crate::type_::DataType;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
+/// A single cell value.
+///
+/// Values are totally ordered: `Nil` < `Bool` < numbers < `Str`. `Int` and
+/// `Flt` compare by numeric value, NaN sorts above every other number and is
+/// equal to itself, and strings compare bytewise.
 #[derive(Debug, Clone)]
 pub enum Value {
     Bool(bool),
@@ -10,19 +18,32 @@ pub enum Value {
     Flt(f64),
     Str(String),
     Nil,
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I128(i128),
+    F32(f32),
 }
 
 impl Value {
     pub fn conforms_to(&self, type_: &DataType) -> bool {
         match self {
-            Value::Bool(_) => *type_ == DataType::Bool,
-            Value::Int(_) => *type_ == DataType::Int,
-            Value::Flt(_) => *type_ == DataType::Flt,
-            Value::Str(_) => *type_ == DataType::Str,
             Value::Nil => true,
+            _ => self.as_internal_data_type() == type_.as_internal_data_type(),
         }
     }
 
+    /// Converts the value to `type_`, failing if it is incompatible or out of range.
+    pub fn cast_to(&self, type_: &DataType) -> Result<Value, Error> {
+        self.as_internal_value()
+            .cast_to(type_.as_internal_data_type())
+            .map(Value::from_internal_value)
+    }
+
     pub(crate) fn as_internal_value(&self) -> InternalValue {
         match self {
             Value::Bool(b) => InternalValue::Bool(*b),
@@ -30,6 +51,15 @@ impl Value {
             Value::Flt(f) => InternalValue::Flt(*f),
             Value::Str(s) => InternalValue::Str(s.clone()),
             Value::Nil => InternalValue::Nil,
+            Value::I8(i) => InternalValue::I8(*i),
+            Value::I16(i) => InternalValue::I16(*i),
+            Value::I32(i) => InternalValue::I32(*i),
+            Value::U8(i) => InternalValue::U8(*i),
+            Value::U16(i) => InternalValue::U16(*i),
+            Value::U32(i) => InternalValue::U32(*i),
+            Value::U64(i) => InternalValue::U64(*i),
+            Value::I128(i) => InternalValue::I128(*i),
+            Value::F32(f) => InternalValue::F32(*f),
         }
     }
 
@@ -40,6 +70,15 @@ impl Value {
             InternalValue::Flt(f) => Value::Flt(f),
             InternalValue::Str(s) => Value::Str(s),
             InternalValue::Nil => Value::Nil,
+            InternalValue::I8(i) => Value::I8(i),
+            InternalValue::I16(i) => Value::I16(i),
+            InternalValue::I32(i) => Value::I32(i),
+            InternalValue::U8(i) => Value::U8(i),
+            InternalValue::U16(i) => Value::U16(i),
+            InternalValue::U32(i) => Value::U32(i),
+            InternalValue::U64(i) => Value::U64(i),
+            InternalValue::I128(i) => Value::I128(i),
+            InternalValue::F32(f) => Value::F32(f),
         }
     }
 
@@ -58,6 +97,114 @@ impl Value {
             Value::Flt(_) => InternalDataType::Flt,
             Value::Str(_) => InternalDataType::Str,
             Value::Nil => InternalDataType::Unknown,
+            Value::I8(_) => InternalDataType::I8,
+            Value::I16(_) => InternalDataType::I16,
+            Value::I32(_) => InternalDataType::I32,
+            Value::U8(_) => InternalDataType::U8,
+            Value::U16(_) => InternalDataType::U16,
+            Value::U32(_) => InternalDataType::U32,
+            Value::U64(_) => InternalDataType::U64,
+            Value::I128(_) => InternalDataType::I128,
+            Value::F32(_) => InternalDataType::F32,
+        }
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_internal_value().cmp(&other.as_internal_value())
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_internal_value() == other.as_internal_value()
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_internal_value().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash(value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn values_are_ordered_by_kind_then_number() {
+        // each group is equal within itself, and less than the next one
+        let groups = vec![
+            vec![Value::Nil],
+            vec![Value::Bool(false)],
+            vec![Value::Bool(true)],
+            vec![Value::I128(i128::MIN)],
+            vec![Value::Int(-1), Value::I8(-1), Value::Flt(-1.0)],
+            vec![
+                Value::Int(1),
+                Value::U8(1),
+                Value::Flt(1.0),
+                Value::F32(1.0),
+            ],
+            vec![Value::F32(1.5), Value::Flt(1.5)],
+            vec![Value::U64(u64::MAX)],
+            vec![Value::Flt(f64::INFINITY)],
+            vec![Value::Flt(f64::NAN), Value::F32(f32::NAN)],
+            vec![Value::Str("".to_string())],
+            vec![Value::Str("a".to_string())],
+        ];
+        for (i, group) in groups.iter().enumerate() {
+            for (j, other) in groups.iter().enumerate() {
+                for a in group {
+                    for b in other {
+                        assert_eq!(a.cmp(b), i.cmp(&j), "{:?} vs {:?}", a, b);
+                        assert_eq!(a == b, i == j, "{:?} vs {:?}", a, b);
+                        if i == j {
+                            assert_eq!(hash(a), hash(b), "{:?} vs {:?}", a, b);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn casts_check_the_range_of_sized_types() {
+        let casts = [
+            (Value::Int(-300), DataType::I16, Some(Value::I16(-300))),
+            (Value::Int(70_000), DataType::I32, Some(Value::I32(70_000))),
+            (Value::U8(200), DataType::U16, Some(Value::U16(200))),
+            (
+                Value::Flt(4e9),
+                DataType::U32,
+                Some(Value::U32(4_000_000_000)),
+            ),
+            (Value::Int(40_000), DataType::I16, None),
+            (Value::Int(-1), DataType::U32, None),
+            (Value::Flt(1.5), DataType::U16, None),
+        ];
+        for (value, type_, expected) in casts {
+            let cast = value.cast_to(&type_).ok();
+            assert_eq!(cast, expected, "{:?} as {:?}", value, type_);
+            if let Some(cast) = cast {
+                assert!(cast.conforms_to(&type_));
+            }
         }
     }
 }